@@ -189,7 +189,14 @@ namespace sdk {
         if (!m_signer) {
             if (m_credential_data.contains("pin")) {
                 // Login with PIN. Fetch the mnemonic from the pin and pin data
-                m_credential_data = { { "mnemonic", m_session->mnemonic_from_pin_data(m_credential_data) } };
+                nlohmann::json credentials = { { "mnemonic", m_session->mnemonic_from_pin_data(m_credential_data) } };
+                for (const auto& key : { "store_secret", "seed_type" }) {
+                    const auto p = m_credential_data.find(key);
+                    if (p != m_credential_data.end()) {
+                        credentials[key] = *p;
+                    }
+                }
+                m_credential_data = std::move(credentials);
             }
 
             // Create our signer
@@ -223,6 +223,13 @@ namespace sdk {
         }
         auto details
             = nlohmann::json({ { "mnemonic", signer->get_mnemonic(std::string()) }, { "password", std::string() } });
+        const auto& credentials = signer->get_credentials();
+        for (const auto& key : { "store_secret", "seed_type" }) {
+            const auto p = credentials.find(key);
+            if (p != credentials.end()) {
+                details[key] = *p;
+            }
+        }
         return call_session("login", details);
     }
     std::string ga_rust::mnemonic_from_pin_data(const nlohmann::json& pin_data)
@@ -260,6 +267,12 @@ namespace sdk {
             { "subaccount", subaccount },
             { "name", details.at("name") },
         });
+        if (details.contains("multisig")) {
+            details_c["multisig"] = details.at("multisig");
+        }
+        if (details.contains("policy")) {
+            details_c["policy"] = details.at("policy");
+        }
         return call_session("create_subaccount", details_c);
     }
 
@@ -590,6 +590,13 @@ namespace sdk {
             set_override(defaults, "user_agent", user_overrides, std::string());
             set_override(defaults, "cert_expiry_threshold", user_overrides, 1);
             set_override(defaults, "proxy", user_overrides, std::string());
+            // Only set when given, singlesig sessions fall back to the default PIN server
+            for (const auto& key :
+                { "pin_server_url", "pin_server_onion_url", "pin_server_public_key", "offline_pin" }) {
+                if (user_overrides.contains(key)) {
+                    defaults[key] = user_overrides.at(key);
+                }
+            }
             defaults["state_dir"] = gdk_config().value("datadir", std::string{}) + "/state";
             return defaults;
         }
@@ -685,7 +692,12 @@ namespace sdk {
     int network_parameters::ct_bits() const { return m_details.at("ct_bits"); }
     bool network_parameters::is_main_net() const { return m_details.at("mainnet"); }
     bool network_parameters::is_liquid() const { return m_details.value("liquid", false); }
-    bool network_parameters::is_electrum() const { return m_details.value("server_type", std::string()) == "electrum"; }
+    bool network_parameters::is_electrum() const
+    {
+        // esplora networks are served by the same rust session as electrum ones
+        const auto server_type = m_details.value("server_type", std::string());
+        return server_type == "electrum" || server_type == "esplora";
+    }
     bool network_parameters::use_tor() const { return m_details.value("use_tor", false); }
     std::string network_parameters::socks5() const { return m_details.value("socks5", std::string()); }
     bool network_parameters::spv_enabled() const { return m_details.at("spv_enabled"); }
@@ -46,7 +46,17 @@ namespace sdk {
                         // Encrypted; decrypt it
                         mnemonic = decrypt_mnemonic(mnemonic, *password_p);
                     }
-                    return { { "mnemonic", mnemonic }, { "seed", b2h(bip39_mnemonic_to_seed(mnemonic)) } };
+                    nlohmann::json result
+                        = { { "mnemonic", mnemonic }, { "seed", b2h(bip39_mnemonic_to_seed(mnemonic)) } };
+                    // Options of singlesig sessions: the secret protecting the local
+                    // store and the seed type of a mnemonic also valid as an Electrum one
+                    for (const auto& key : { "store_secret", "seed_type" }) {
+                        const auto p = credentials.find(key);
+                        if (p != credentials.end()) {
+                            result[key] = *p;
+                        }
+                    }
+                    return result;
                 }
                 if (mnemonic.size() == 129u && mnemonic.back() == 'X') {
                     // Hex seed (a 512 bits bip32 seed encoding in hex with 'X' appended)
@@ -1,7 +1,7 @@
 use crate::be::*;
 use crate::error::Error;
 use crate::model::Balances;
-use crate::scripts::{p2pkh_script, ScriptType};
+use crate::scripts::{p2pkh_script, InputShape, ScriptType};
 use crate::NetworkId;
 use crate::{bail, ensure};
 use bitcoin::blockdata::script::Instruction;
@@ -32,6 +32,15 @@ const DEFAULT_RANGEPROOF_SIZE: usize = 4174;
 // 3-input ASP size
 const DEFAULT_SURJECTIONPROOF_SIZE: usize = 135;
 
+/// Block based timelocks an input must satisfy to be spent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timelocks {
+    /// Relative timelock in blocks (BIP68), set in the input nSequence
+    pub older: Option<u32>,
+    /// Absolute timelock as a block height, the transaction nLockTime must be at least this
+    pub after: Option<u32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 pub enum BETransaction {
     Bitcoin(bitcoin::Transaction),
@@ -323,18 +332,23 @@ impl BETransaction {
 
     /// estimates the fee of the final transaction given the `fee_rate`
     /// called when the tx is being built and miss things like signatures and changes outputs.
-    pub fn estimated_fee(&self, fee_rate: f64, more_changes: u8, script_type: ScriptType) -> u64 {
+    pub fn estimated_fee(
+        &self,
+        fee_rate: f64,
+        more_changes: u8,
+        shape: InputShape,
+    ) -> Result<u64, Error> {
         let dummy_tx = self.clone();
         match dummy_tx {
             BETransaction::Bitcoin(mut tx) => {
                 for input in tx.input.iter_mut() {
-                    input.witness = script_type.mock_witness();
-                    input.script_sig = script_type.mock_script_sig().into();
+                    input.witness = shape.mock_witness()?;
+                    input.script_sig = shape.mock_script_sig()?.into();
                 }
                 for _ in 0..more_changes {
                     tx.output.push(bitcoin::TxOut {
                         value: 0,
-                        script_pubkey: script_type.mock_script_pubkey().into(),
+                        script_pubkey: shape.mock_script_pubkey().into(),
                     })
                 }
                 let vbytes = tx.get_weight() as f64 / 4.0;
@@ -347,14 +361,14 @@ impl BETransaction {
                     vbytes,
                     fee_val
                 );
-                fee_val
+                Ok(fee_val)
             }
             BETransaction::Elements(mut tx) => {
                 for input in tx.input.iter_mut() {
                     let mut tx_wit = TxInWitness::default();
-                    tx_wit.script_witness = script_type.mock_witness();
+                    tx_wit.script_witness = shape.mock_witness()?;
                     input.witness = tx_wit;
-                    input.script_sig = script_type.mock_script_sig().into();
+                    input.script_sig = shape.mock_script_sig()?.into();
                 }
                 let mock_asset = confidential::Asset::Confidential(mock_asset());
                 let mock_value = confidential::Value::Confidential(mock_value());
@@ -364,7 +378,7 @@ impl BETransaction {
                         asset: mock_asset,
                         value: mock_value,
                         nonce: mock_nonce,
-                        script_pubkey: script_type.mock_script_pubkey().into(),
+                        script_pubkey: shape.mock_script_pubkey().into(),
                         ..Default::default()
                     };
                     tx.output.push(new_out);
@@ -387,7 +401,7 @@ impl BETransaction {
                     vbytes,
                     fee_val
                 );
-                fee_val
+                Ok(fee_val)
             }
         }
     }
@@ -427,8 +441,8 @@ impl BETransaction {
         policy_asset: Option<elements::issuance::AssetId>,
         all_txs: &BETransactions,
         unblinded: &HashMap<elements::OutPoint, elements::TxOutSecrets>,
-        script_type: ScriptType,
-    ) -> Vec<AssetValue> {
+        shape: InputShape,
+    ) -> Result<Vec<AssetValue>, Error> {
         match self {
             Self::Bitcoin(tx) => {
                 let sum_inputs = sum_inputs(tx, all_txs);
@@ -436,12 +450,12 @@ impl BETransaction {
                 let estimated_fee = self.estimated_fee(
                     fee_rate,
                     self.estimated_changes(no_change, all_txs, unblinded),
-                    script_type,
-                ); // send all does not create change
+                    shape,
+                )?; // send all does not create change
                 if sum_outputs + estimated_fee > sum_inputs {
-                    vec![AssetValue::new_bitcoin(sum_outputs + estimated_fee - sum_inputs)]
+                    Ok(vec![AssetValue::new_bitcoin(sum_outputs + estimated_fee - sum_inputs)])
                 } else {
-                    vec![]
+                    Ok(vec![])
                 }
             }
             Self::Elements(tx) => {
@@ -474,8 +488,8 @@ impl BETransaction {
                 let estimated_fee = self.estimated_fee(
                     fee_rate,
                     self.estimated_changes(no_change, all_txs, unblinded),
-                    script_type,
-                );
+                    shape,
+                )?;
                 *outputs.entry(policy_asset.clone()).or_insert(0) += estimated_fee;
 
                 let mut result = vec![];
@@ -493,7 +507,7 @@ impl BETransaction {
                         result.swap(index, last_index); // put the policy asset last
                     }
                 }
-                result
+                Ok(result)
             }
         }
     }
@@ -583,24 +597,35 @@ impl BETransaction {
         Ok(())
     }
 
-    pub fn add_input(&mut self, outpoint: BEOutPoint) {
+    /// Add an unsigned input, `timelocks` are the ones of the spending path of `outpoint`:
+    /// a relative timelock is set in its nSequence, an absolute one raises the nLockTime.
+    pub fn add_input(&mut self, outpoint: BEOutPoint, timelocks: Timelocks) {
         match (outpoint, self) {
             (BEOutPoint::Bitcoin(outpoint), BETransaction::Bitcoin(tx)) => {
+                if let Some(after) = timelocks.after {
+                    tx.lock_time = tx.lock_time.max(after);
+                }
                 let new_in = bitcoin::TxIn {
                     previous_output: outpoint,
                     script_sig: bitcoin::Script::default(),
-                    sequence: 0xffff_fffd, // nSequence is disabled, nLocktime is enabled, RBF is signaled.
+                    // A relative timelock enables nSequence and signals RBF, otherwise
+                    // nSequence is disabled, nLocktime is enabled, RBF is signaled.
+                    sequence: timelocks.older.unwrap_or(0xffff_fffd),
                     witness: vec![],
                 };
                 tx.input.push(new_in);
             }
             (BEOutPoint::Elements(outpoint), BETransaction::Elements(tx)) => {
+                if let Some(after) = timelocks.after {
+                    tx.lock_time = tx.lock_time.max(after);
+                }
                 let new_in = elements::TxIn {
                     previous_output: outpoint,
                     is_pegin: false,
                     has_issuance: false,
                     script_sig: elements::Script::default(),
-                    sequence: 0xffff_fffe, // nSequence is disabled, nLocktime is enabled, RBF is not signaled.
+                    // nSequence is disabled, nLocktime is enabled, RBF is not signaled.
+                    sequence: timelocks.older.unwrap_or(0xffff_fffe),
                     asset_issuance: Default::default(),
                     witness: TxInWitness::default(),
                 };
@@ -844,6 +869,8 @@ impl BETransaction {
                 Some(Ok(Instruction::PushBytes(sig))) => Ok(sig.to_vec()),
                 _ => Err(Error::InputValidationFailed),
             },
+            // multisig and miniscript inputs have script dependent witnesses
+            ScriptType::P2wsh | ScriptType::P2wshMiniscript => Err(Error::InputValidationFailed),
         }?;
 
         // We only ever create SIGHASH_ALL transactions
@@ -1,14 +1,279 @@
+use crate::wally;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha512, Hash, HashEngine, Hmac, HmacEngine};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use unicode_normalization::char::canonical_combining_class;
+use unicode_normalization::UnicodeNormalization;
 
-// This doesn't do validation, but we could?
+/// The BIP39 wordlists, as named by libwally
+pub const BIP39_LANGUAGES: &[&str] = &["en", "es", "fr", "it", "jp", "zhs", "zht"];
+
+/// The allowed number of words of a BIP39 mnemonic
+pub const BIP39_WORD_COUNTS: &[usize] = &[12, 15, 18, 21, 24];
+
+lazy_static! {
+    /// The words of every wordlist of `BIP39_LANGUAGES`, read once from libwally
+    static ref WORDLISTS: HashMap<&'static str, HashSet<String>> = BIP39_LANGUAGES
+        .iter()
+        .map(|language| (*language, wally::bip39_wordlist(language).into_iter().collect()))
+        .collect();
+}
+
+/// The prefix of the version hash of Electrum v2 "standard" seeds
+const ELECTRUM_STANDARD_PREFIX: &str = "01";
+
+/// The prefix of the version hash of Electrum v2 "segwit" seeds
+const ELECTRUM_SEGWIT_PREFIX: &str = "100";
+
+/// The ranges of the CJK characters, as listed by Electrum
+const CJK_INTERVALS: &[(u32, u32)] = &[
+    (0x4E00, 0x9FFF),   // CJK Unified Ideographs
+    (0x3400, 0x4DBF),   // CJK Unified Ideographs Extension A
+    (0x20000, 0x2A6DF), // CJK Unified Ideographs Extension B
+    (0x2A700, 0x2B73F), // CJK Unified Ideographs Extension C
+    (0x2B740, 0x2B81F), // CJK Unified Ideographs Extension D
+    (0xF900, 0xFAFF),   // CJK Compatibility Ideographs
+    (0x2F800, 0x2FA1F), // CJK Compatibility Ideographs Supplement
+    (0x3190, 0x319F),   // Kanbun
+    (0x3100, 0x312F),   // Bopomofo
+    (0x31A0, 0x31BF),   // Bopomofo Extended
+    (0x3040, 0x309F),   // Hiragana
+    (0x30A0, 0x30FF),   // Katakana
+    (0x31F0, 0x31FF),   // Katakana Phonetic Extensions
+    (0x3000, 0x303F),   // CJK Symbols and Punctuation
+    (0xFF00, 0xFFEF),   // Halfwidth and Fullwidth Forms
+    (0xAC00, 0xD7AF),   // Hangul Syllables
+    (0x1100, 0x11FF),   // Hangul Jamo
+    (0x3130, 0x318F),   // Hangul Compatibility Jamo
+    (0xA960, 0xA97F),   // Hangul Jamo Extended-A
+    (0xD7B0, 0xD7FF),   // Hangul Jamo Extended-B
+    (0x1D300, 0x1D35F), // Tai Xuan Jing Symbols
+    (0x1D360, 0x1D37F), // Counting Rod Numerals
+    (0x2FF0, 0x2FFF),   // Ideographic Description Characters
+    (0x2E80, 0x2EFF),   // CJK Radicals Supplement
+    (0x2F00, 0x2FDF),   // Kangxi Radicals
+    (0x3200, 0x32FF),   // Enclosed CJK Letters and Months
+    (0x3300, 0x33FF),   // CJK Compatibility
+    (0x4DC0, 0x4DFF),   // Yijing Hexagram Symbols
+    (0xA700, 0xA71F),   // Modifier Tone Letters
+    (0xFE10, 0xFE1F),   // Vertical Forms
+    (0xFE30, 0xFE4F),   // CJK Compatibility Forms
+    (0x1F200, 0x1F2FF), // Enclosed Ideographic Supplement
+    (0x1F000, 0x1F02F), // Mahjong Tiles
+    (0x1F0A0, 0x1F0FF), // Playing Cards
+    (0x1F100, 0x1F1FF), // Enclosed Alphanumeric Supplement
+    (0x1D000, 0x1D0FF), // Byzantine Musical Symbols
+];
+
+/// How the seed is derived from the mnemonic, and which accounts it is expected to have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedType {
+    Bip39,
+    /// Electrum v2 seed of a P2PKH wallet, derived at `m/`
+    ElectrumStandard,
+    /// Electrum v2 seed of a P2WPKH wallet, derived at `m/0'`
+    ElectrumSegwit,
+}
+
+// Validated only on demand, see `Mnemonic::validate`
 #[derive(Eq, Clone, PartialEq)]
 pub struct Mnemonic(String);
 
+/// Why a mnemonic is not a valid BIP39 mnemonic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum MnemonicError {
+    /// The number of words is not one of `BIP39_WORD_COUNTS`
+    InvalidWordCount {
+        words: usize,
+    },
+    /// The zero based positions of the words missing from the wordlist
+    UnknownWords {
+        positions: Vec<usize>,
+    },
+    /// Every word is in the wordlist but the checksum, encoded in the last word, does not match
+    InvalidChecksum,
+    UnsupportedLanguage {
+        language: String,
+    },
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MnemonicError::InvalidWordCount {
+                words,
+            } => write!(f, "invalid number of words {}", words),
+            MnemonicError::UnknownWords {
+                positions,
+            } => write!(f, "unknown words at positions {:?}", positions),
+            MnemonicError::InvalidChecksum => write!(f, "invalid checksum"),
+            MnemonicError::UnsupportedLanguage {
+                language,
+            } => write!(f, "unsupported language {}", language),
+        }
+    }
+}
+
 impl Mnemonic {
     // to_string would display REDACTED from the Display trait
     pub fn get_mnemonic_str(self) -> String {
         self.0
     }
+
+    /// Generate a mnemonic of `words` words from the `language` wordlist
+    pub fn generate(words: usize, language: &str) -> Result<Mnemonic, MnemonicError> {
+        if !BIP39_LANGUAGES.contains(&language) {
+            return Err(MnemonicError::UnsupportedLanguage {
+                language: language.to_string(),
+            });
+        }
+        if !BIP39_WORD_COUNTS.contains(&words) {
+            return Err(MnemonicError::InvalidWordCount {
+                words,
+            });
+        }
+        // every word encodes 11 bits, 1 bit every 33 is for the checksum
+        let mut entropy = vec![0u8; words * 4 / 3];
+        rand::thread_rng().fill_bytes(&mut entropy);
+        let mnemonic = wally::bip39_mnemonic_from_bytes(language, &entropy)
+            .expect("the entropy of a supported word count in a supported language");
+        Ok(Mnemonic(mnemonic))
+    }
+
+    /// Check the words and the checksum against the `language` wordlist
+    pub fn validate(&self, language: &str) -> Result<(), MnemonicError> {
+        if !BIP39_LANGUAGES.contains(&language) {
+            return Err(MnemonicError::UnsupportedLanguage {
+                language: language.to_string(),
+            });
+        }
+        let words: Vec<&str> = self.0.split_whitespace().collect();
+        if !BIP39_WORD_COUNTS.contains(&words.len()) {
+            return Err(MnemonicError::InvalidWordCount {
+                words: words.len(),
+            });
+        }
+        let wordlist = &WORDLISTS[language];
+        let positions: Vec<usize> = words
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| !wordlist.contains(**word))
+            .map(|(position, _)| position)
+            .collect();
+        if !positions.is_empty() {
+            return Err(MnemonicError::UnknownWords {
+                positions,
+            });
+        }
+        if !wally::bip39_mnemonic_validate(language, &words.join(" ")) {
+            return Err(MnemonicError::InvalidChecksum);
+        }
+        Ok(())
+    }
+
+    /// Validate against every wordlist, returning the language of the mnemonic or the error for
+    /// the language with the fewest unknown words
+    pub fn validate_any(&self) -> Result<&'static str, MnemonicError> {
+        let mut best_error: Option<(usize, MnemonicError)> = None;
+        for language in BIP39_LANGUAGES {
+            let error = match self.validate(language) {
+                Ok(()) => return Ok(language),
+                Err(
+                    error @ MnemonicError::InvalidWordCount {
+                        ..
+                    },
+                ) => return Err(error),
+                Err(error) => error,
+            };
+            let unknown = match &error {
+                MnemonicError::UnknownWords {
+                    positions,
+                } => positions.len(),
+                _ => 0,
+            };
+            if best_error.as_ref().map_or(true, |(best, _)| unknown < *best) {
+                best_error = Some((unknown, error));
+            }
+        }
+        Err(best_error.expect("at least one language").1)
+    }
+
+    /// The Electrum v2 seed type, None if this is not an Electrum seed or its type is not
+    /// supported (2FA seeds)
+    pub fn electrum_seed_type(&self) -> Option<SeedType> {
+        let mut engine = HmacEngine::<sha512::Hash>::new(b"Seed version");
+        engine.input(electrum_normalize(&self.0).as_bytes());
+        let version = Hmac::<sha512::Hash>::from_engine(engine).to_hex();
+        // the segwit prefix must be checked first, it would otherwise match the 2FA "101"
+        if version.starts_with(ELECTRUM_SEGWIT_PREFIX) {
+            Some(SeedType::ElectrumSegwit)
+        } else if version.starts_with(ELECTRUM_STANDARD_PREFIX) {
+            Some(SeedType::ElectrumStandard)
+        } else {
+            None
+        }
+    }
+
+    /// The seed type of the mnemonic, `requested` if given and valid for it.
+    ///
+    /// Without `requested` BIP39 takes precedence: every Electrum seed has a chance of 1/16 of
+    /// having a valid BIP39 checksum, while 1/256 of BIP39 mnemonics look like Electrum standard
+    /// seeds. The callers must check `ambiguous_seed_type` to tell the user about the other
+    /// interpretation.
+    pub fn seed_type(&self, requested: Option<SeedType>) -> Result<SeedType, MnemonicError> {
+        match requested {
+            Some(SeedType::Bip39) => self.validate_any().map(|_| SeedType::Bip39),
+            Some(electrum) => match self.electrum_seed_type() {
+                Some(seed_type) if seed_type == electrum => Ok(seed_type),
+                _ => Err(MnemonicError::InvalidChecksum),
+            },
+            None => match self.validate_any() {
+                Ok(_) => Ok(SeedType::Bip39),
+                Err(error) => self.electrum_seed_type().ok_or(error),
+            },
+        }
+    }
+
+    /// The Electrum seed type of a mnemonic that is also a valid BIP39 mnemonic
+    pub fn ambiguous_seed_type(&self) -> Option<SeedType> {
+        self.validate_any().ok().and(self.electrum_seed_type())
+    }
+
+    /// The seed of an Electrum v2 mnemonic, as derived by the Electrum KDF
+    pub fn electrum_seed(&self, passphrase: &str) -> [u8; 64] {
+        let salt = format!("electrum{}", electrum_normalize(passphrase));
+        wally::pbkdf2_hmac_sha512(electrum_normalize(&self.0).as_bytes(), salt.as_bytes(), 2048)
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    CJK_INTERVALS.iter().any(|(min, max)| (*min..=*max).contains(&(c as u32)))
+}
+
+/// The `normalize_text` of Electrum, applied to both the mnemonic and the passphrase: NFKD,
+/// lowercase, no accents, single spaces and no spaces between CJK characters
+fn electrum_normalize(text: &str) -> String {
+    let text: String = text
+        .nfkd()
+        .collect::<String>()
+        .to_lowercase()
+        .chars()
+        .filter(|c| canonical_combining_class(*c) == 0)
+        .collect();
+    let chars: Vec<char> = text.split_whitespace().collect::<Vec<_>>().join(" ").chars().collect();
+    // the joined spaces are never the first or the last character
+    chars
+        .iter()
+        .enumerate()
+        .filter(|(i, c)| !(**c == ' ' && is_cjk(chars[i - 1]) && is_cjk(chars[i + 1])))
+        .map(|(_, c)| c)
+        .collect()
 }
 
 impl serde::ser::Serialize for Mnemonic {
@@ -46,3 +311,104 @@ fn mnemonic_show_redacted() {
     assert_eq!(format, "Mnemonic(REDACTED)");
     assert_eq!(mnemonic.get_mnemonic_str(), "secret sauce");
 }
+
+#[test]
+fn mnemonic_generate_validate() {
+    for words in &[12, 24] {
+        for language in BIP39_LANGUAGES {
+            let mnemonic = Mnemonic::generate(*words, language).unwrap();
+            assert_eq!(mnemonic.0.split_whitespace().count(), *words);
+            assert_eq!(mnemonic.validate(language), Ok(()));
+        }
+    }
+    assert_eq!(
+        Mnemonic::generate(13, "en"),
+        Err(MnemonicError::InvalidWordCount {
+            words: 13
+        })
+    );
+    assert_eq!(
+        Mnemonic::generate(12, "xx"),
+        Err(MnemonicError::UnsupportedLanguage {
+            language: "xx".into()
+        })
+    );
+
+    let valid = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    assert_eq!(Mnemonic(valid.into()).validate_any(), Ok("en"));
+
+    let checksum = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon";
+    assert_eq!(Mnemonic(checksum.into()).validate("en"), Err(MnemonicError::InvalidChecksum));
+
+    let typo = "abandon abandon abandn abandon abandon abandon abandon abandon abandon abandon abandon abot";
+    assert_eq!(
+        Mnemonic(typo.into()).validate_any(),
+        Err(MnemonicError::UnknownWords {
+            positions: vec![2, 11]
+        })
+    );
+
+    assert_eq!(
+        Mnemonic("abandon about".into()).validate_any(),
+        Err(MnemonicError::InvalidWordCount {
+            words: 2
+        })
+    );
+}
+
+#[test]
+fn mnemonic_electrum_seed() {
+    // from the Electrum test suite
+    let standard = Mnemonic(
+        "cycle rocket west magnet parrot shuffle foot correct salt library feed song".into(),
+    );
+    assert_eq!(standard.electrum_seed_type(), Some(SeedType::ElectrumStandard));
+    assert_eq!(standard.electrum_seed("").to_hex(), "00302d7db162de47e6cd5074221aee6bbcb6be93982af90c04d0e7710dd26013aeb7848850a56a546e7955b360e561139d62805f2d5d3c940880b0dc91b60b29");
+
+    let segwit = Mnemonic(
+        " Bitter grass shiver impose acquire brush  forget axis eager alone wine silver".into(),
+    );
+    assert_eq!(segwit.electrum_seed_type(), Some(SeedType::ElectrumSegwit));
+    assert_eq!(segwit.electrum_seed("").to_hex(), "8ff3b1fa35d0bace7e80255253ee1ada21586eccd341bb90ae8ff5a7214e7d62b2be30df64807fd8716e604c66da392834853644bffdab8a7d9ac029f52a9a8c");
+
+    let bip39 = Mnemonic(
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+            .into(),
+    );
+    assert_eq!(bip39.electrum_seed_type(), None);
+    assert_eq!(bip39.seed_type(None), Ok(SeedType::Bip39));
+    assert_eq!(bip39.ambiguous_seed_type(), None);
+    assert!(bip39.seed_type(Some(SeedType::ElectrumStandard)).is_err());
+
+    assert_eq!(segwit.seed_type(None), Ok(SeedType::ElectrumSegwit));
+    assert!(segwit.seed_type(Some(SeedType::Bip39)).is_err());
+    assert!(segwit.seed_type(Some(SeedType::ElectrumStandard)).is_err());
+
+    // an Electrum segwit seed with a valid BIP39 checksum
+    let ambiguous = Mnemonic(
+        "abuse access absent absurd absent accident account about above about absurd accuse".into(),
+    );
+    assert_eq!(ambiguous.seed_type(None), Ok(SeedType::Bip39));
+    assert_eq!(ambiguous.ambiguous_seed_type(), Some(SeedType::ElectrumSegwit));
+    assert_eq!(ambiguous.seed_type(Some(SeedType::ElectrumSegwit)), Ok(SeedType::ElectrumSegwit));
+
+    // from the Electrum test suite, with a passphrase and with accents
+    let wild = Mnemonic(
+        "wild father tree among universe such mobile favorite target dynamic credit identify"
+            .into(),
+    );
+    assert_eq!(wild.electrum_seed_type(), Some(SeedType::ElectrumSegwit));
+    assert_eq!(wild.electrum_seed("Did you ever hear the tragedy of Darth Plagueis the Wise?").to_hex(), "4aa29f2aeb0127efb55138ab9e7be83b36750358751906f86c662b21a1ea1370f949e6d1a12fa56d3d93cadda93038c76ac8118597364e46f5156fde6183c82f");
+    let spanish = Mnemonic(
+        "almíbar tibio superar vencer hacha peatón príncipe matar consejo polen vehículo odisea"
+            .into(),
+    );
+    assert_eq!(spanish.electrum_seed_type(), Some(SeedType::ElectrumStandard));
+    assert_eq!(spanish.electrum_seed("").to_hex(), "18bffd573a960cc775bbd80ed60b7dc00bc8796a186edebe7fc7cf1f316da0fe937852a969c5c79ded8255cdf54409537a16339fbe33fb9161af793ea47faa7a");
+
+    // the passphrase is normalized too
+    let cafe = wild.electrum_seed("Café");
+    assert_eq!(cafe.to_hex(), "b126480f0178dfc46f2043240e7c1900f2cb5ac1d73e1cf59f713ff3281569f61959b0f75ac3c10c6c95ac3285d58ac43f56365028b91b58ddfd237106ce1cc5");
+    assert_eq!(wild.electrum_seed("CAFE\u{301}")[..], cafe[..]);
+    assert_eq!(electrum_normalize(" 眼 悲  叛 a b "), "眼悲叛 a b");
+}
@@ -2,9 +2,11 @@ use crate::be::{BEOutPoint, BEScript, BETransaction, BETransactionEntry, UTXOInf
 use crate::util::StringSerialized;
 use bitcoin::Network;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
 
 use crate::error::Error;
+use crate::mnemonic::{MnemonicError, SeedType};
 use crate::scripts::ScriptType;
 use bitcoin::hashes::hex::ToHex;
 use bitcoin::util::address::AddressType;
@@ -12,6 +14,7 @@ use bitcoin::util::bip32::{ChildNumber, DerivationPath};
 use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Display;
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub type Balances = HashMap<String, i64>;
@@ -91,6 +94,32 @@ impl AddressAmount {
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct LoginData {
     pub wallet_hash_id: String,
+    /// The Electrum seed type the mnemonic also matches when it was taken as BIP39 by default,
+    /// logging in again with this `seed_type` shows the funds of the Electrum wallet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ambiguous_seed_type: Option<SeedType>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+    Reconnecting,
+}
+
+/// Connectivity of the session to its server, timestamps are in seconds since the unix epoch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollSessionResult {
+    pub state: ConnectionState,
+    pub current_server: String,
+    /// Last time the wallet completed a sync with the server
+    pub last_sync_ts: Option<u64>,
+    pub tip_height: u32,
+    /// Seconds elapsed since the timestamp of the tip header
+    pub tip_age: Option<u64>,
+    /// When the next connection attempt happens, if disconnected
+    pub next_retry_ts: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
@@ -131,14 +160,130 @@ pub struct CreateTransaction {
     pub confidential_utxos_only: bool,
     #[serde(default)]
     pub utxo_strategy: UtxoStrategy,
+    /// When set, the transaction sweeps all the funds of this WIF private key to the subaccount
+    #[serde(default, skip_serializing)]
+    pub private_key: Option<SweepPrivateKey>,
+    /// The payjoin endpoint of the payment URI, as returned by `parse_uri`, the signed
+    /// transaction is then sent to the receiver before broadcasting
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payjoin: Option<PayjoinOpt>,
+}
+
+/// The BIP78 payjoin endpoint of a payment URI
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PayjoinOpt {
+    /// The `pj` parameter of the URI
+    pub url: String,
+    /// Set by `pjos=0`, the receiver must not change the payment output
+    #[serde(default)]
+    pub disable_output_substitution: bool,
+}
+
+/// The WIF private key of a sweep, it is never serialized back nor logged
+#[derive(Deserialize, Clone)]
+pub struct SweepPrivateKey(pub String);
+
+impl fmt::Debug for SweepPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SweepPrivateKey(REDACTED)")
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct GetTransactionsOpt {
+    /// Matching transactions to skip, after the `cursor` if given
     pub first: usize,
     pub count: usize,
     pub subaccount: u32,
     pub num_confs: Option<u32>,
+    /// The `cursor` of the last item of the previous page, pages are stable while new
+    /// transactions arrive
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub sort: TxSort,
+    /// Inclusive block height range, unconfirmed transactions are excluded by any bound
+    #[serde(default)]
+    pub min_height: Option<u32>,
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    /// Inclusive block time range in microseconds, unconfirmed transactions are excluded by any
+    /// bound
+    #[serde(default)]
+    pub min_ts: Option<u64>,
+    #[serde(default)]
+    pub max_ts: Option<u64>,
+    /// Any of `incoming`, `outgoing`, `redeposit` and `unblindable`
+    #[serde(default)]
+    pub types: Option<Vec<String>>,
+    /// Only the transactions changing the balance of this asset
+    #[serde(default)]
+    pub asset_id: Option<String>,
+    /// Inclusive range of the absolute balance change of `asset_id`, or of the policy asset
+    #[serde(default)]
+    pub min_satoshi: Option<u64>,
+    #[serde(default)]
+    pub max_satoshi: Option<u64>,
+    /// Case insensitive substring of the memo
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Only the transactions paying to or spending from this address
+    #[serde(default)]
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxSort {
+    /// Unconfirmed first, then by descending height
+    #[serde(rename = "newest")]
+    Newest,
+    #[serde(rename = "oldest")]
+    Oldest,
+}
+
+impl Default for TxSort {
+    fn default() -> Self {
+        TxSort::Newest
+    }
+}
+
+/// The position of a transaction in the history, `<height>:<txid>` or `unconfirmed:<txid>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxCursor {
+    pub height: Option<u32>,
+    pub txid: String,
+}
+
+impl Display for TxCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.height {
+            Some(height) => write!(f, "{}:{}", height, self.txid),
+            None => write!(f, "unconfirmed:{}", self.txid),
+        }
+    }
+}
+
+impl FromStr for TxCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let (height, txid) = match (parts.next(), parts.next()) {
+            (Some(height), Some(txid)) if !txid.is_empty() => (height, txid),
+            _ => return Err(Error::Generic(format!("invalid cursor {}", s))),
+        };
+        let height = match height {
+            "unconfirmed" => None,
+            height => {
+                Some(height.parse().map_err(|_| Error::Generic(format!("invalid cursor {}", s)))?)
+            }
+        };
+        Ok(TxCursor {
+            height,
+            txid: txid.to_string(),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -149,6 +294,47 @@ pub struct GetBalanceOpt {
     pub confidential_utxos_only: Option<bool>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetBalanceHistoryOpt {
+    pub subaccount: u32,
+    #[serde(default)]
+    pub interval: BalanceInterval,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BalanceInterval {
+    /// A sample for each block with transactions of the subaccount
+    #[serde(rename = "block")]
+    Block,
+    /// A sample for each UTC day with transactions of the subaccount
+    #[serde(rename = "day")]
+    Day,
+}
+
+impl Default for BalanceInterval {
+    fn default() -> Self {
+        BalanceInterval::Block
+    }
+}
+
+/// The confirmed balance of a subaccount over time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BalanceHistory {
+    pub interval: BalanceInterval,
+    /// Sorted by height
+    pub points: Vec<BalancePoint>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BalancePoint {
+    /// The last block of the sample
+    pub height: u32,
+    /// In microseconds, the block time or the start of the day, unknown without the header
+    pub timestamp: Option<u64>,
+    /// The balance per asset at the end of the sample
+    pub satoshi: Balances,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct GetUnspentOpt {
     pub subaccount: u32,
@@ -164,10 +350,72 @@ pub struct GetAddressOpt {
     pub address_type: Option<String>, // unused
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct CreateAccountOpt {
     pub subaccount: u32,
     pub name: String,
+    /// Required for multisig subaccounts
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultisigOpt>,
+    /// Required for miniscript subaccounts
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyOpt>,
+}
+
+/// A `threshold`-of-n P2WSH sortedmulti of our key and the keys of the `cosigners`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MultisigOpt {
+    pub threshold: u32,
+    /// The account xpubs of the other signers, optionally prefixed by their key origin as in
+    /// the descriptors, `[fingerprint/path]xpub`, which goes in the psbts
+    pub cosigners: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetMultisigXpubOpt {
+    pub subaccount: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetMultisigXpubResult {
+    /// Our xpub for the multisig or miniscript subaccount, to be shared with the cosigners
+    pub xpub: String,
+    pub path: String,
+}
+
+/// A P2WSH miniscript descriptor such as `wsh(or_d(pk(A),and_v(v:pk(B),older(52560))))`,
+/// where the keys are account xpubs and one of them is ours
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolicyOpt {
+    pub descriptor: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetExpiringUtxosOpt {
+    pub subaccount: u32,
+    /// Include the coins whose timelock expires within this many blocks
+    pub within_blocks: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetExpiringUtxosResult {
+    pub tip_height: u32,
+    /// Sorted by expiry, the coins already past their timelock come first
+    pub utxos: Vec<ExpiringUtxo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExpiringUtxo {
+    pub txhash: String,
+    pub pt_idx: u32,
+    pub satoshi: u64,
+    pub block_height: u32,
+    /// The first height at which a timelocked spending path of the coin can be used
+    pub expiry_height: u32,
+    /// Zero or negative once the timelock expired
+    pub blocks_left: i64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -212,6 +460,11 @@ pub struct SPVCommonParams {
     /// to enable the cache in the callee side.
     /// Encryption is needed to encrypt the cache content to avoid leaking the txids of the transactions
     pub encryption_key: Option<String>,
+
+    /// The backend of the session, "electrum" or "esplora", if not set esplora is used only if the
+    /// network has no electrum url
+    #[serde(default)]
+    pub server_type: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -247,6 +500,211 @@ pub struct SPVDownloadHeadersResult {
     pub reorg: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenerateMnemonicOpt {
+    /// 12 or 24, or any other BIP39 word count, it defaults to 24
+    #[serde(default = "default_mnemonic_words")]
+    pub words: usize,
+
+    /// One of `BIP39_LANGUAGES`, it defaults to english
+    #[serde(default = "default_mnemonic_language")]
+    pub language: String,
+}
+
+fn default_mnemonic_words() -> usize {
+    24
+}
+
+fn default_mnemonic_language() -> String {
+    "en".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenerateMnemonicResult {
+    pub mnemonic: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ValidateMnemonicOpt {
+    pub mnemonic: String,
+
+    /// Validate against this wordlist only, otherwise against every wordlist
+    pub language: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ValidateMnemonicResult {
+    pub valid: bool,
+
+    /// The wordlist of a valid mnemonic
+    pub language: Option<String>,
+
+    pub error: Option<MnemonicError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageSignatureFormat {
+    /// Recoverable signature with the address type in the header byte, as produced by most
+    /// wallets for P2PKH, P2SH-P2WPKH and P2WPKH addresses
+    Bip137,
+    /// BIP322 "simple" signature, the witness proving the address could spend a virtual output
+    Bip322,
+}
+
+impl Default for MessageSignatureFormat {
+    fn default() -> Self {
+        MessageSignatureFormat::Bip137
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignMessageOpt {
+    pub subaccount: u32,
+
+    /// A wallet address of the subaccount
+    pub address: String,
+
+    pub message: String,
+
+    #[serde(default)]
+    pub format: MessageSignatureFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignMessageResult {
+    /// Base64 encoded
+    pub signature: String,
+
+    pub format: MessageSignatureFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VerifyMessageOpt {
+    pub address: String,
+
+    pub message: String,
+
+    /// Base64 encoded
+    pub signature: String,
+
+    /// Inferred from the signature length if missing
+    pub format: Option<MessageSignatureFormat>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VerifyMessageResult {
+    pub valid: bool,
+
+    pub format: MessageSignatureFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProofOfReservesUtxo {
+    pub txhash: String,
+    pub pt_idx: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProofOfReservesOpt {
+    pub subaccount: u32,
+
+    /// The challenge the proof commits to, usually chosen by the auditor
+    pub message: String,
+
+    /// The utxos to include, all the utxos of the subaccount if missing
+    pub utxos: Option<Vec<ProofOfReservesUtxo>>,
+
+    /// Minimum number of confirmations of the included utxos
+    #[serde(default)]
+    pub num_confs: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProofOfReservesResult {
+    /// Base64 encoded, finalized but not broadcastable
+    pub psbt: String,
+
+    /// The sum of the utxos included
+    pub satoshi: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VerifyProofOfReservesOpt {
+    /// Base64 encoded
+    pub psbt: String,
+
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VerifyProofOfReservesResult {
+    pub valid: bool,
+
+    /// The sum of the unspent utxos proven, zero if the proof is not valid
+    pub satoshi: u64,
+
+    /// Why the proof is not valid
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ParseUriOpt {
+    /// A BIP21 payment URI, such as `bitcoin:<address>?amount=0.001`, or a plain address
+    pub uri: String,
+
+    /// The network of the URI, required by the `parse_uri` call without a session, the session
+    /// one uses the network of the session
+    #[serde(default)]
+    pub network: Option<crate::network::Network>,
+}
+
+/// The recipient of a payment URI, validated for the network of the session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ParseUriResult {
+    pub address: String,
+
+    /// The `amount` of the URI as given, in units of the asset
+    pub amount: Option<String>,
+
+    /// The `amount` converted to satoshi, missing if the precision of the asset is not known
+    pub satoshi: Option<u64>,
+
+    /// The lowercase `assetid` of a Liquid URI
+    pub asset_id: Option<String>,
+
+    pub label: Option<String>,
+    pub message: Option<String>,
+
+    /// Set if the receiver accepts payjoin, to be passed to `create_transaction`
+    pub payjoin: Option<PayjoinOpt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CreateUriOpt {
+    /// An address of the session network, usually returned by `get_receive_address`
+    pub address: String,
+
+    /// Converted to the `amount` with the precision of the asset, which must be known
+    #[serde(default)]
+    pub satoshi: Option<u64>,
+
+    /// The asset requested on Liquid, the policy asset if missing and `satoshi` is set
+    #[serde(default)]
+    pub asset_id: Option<String>,
+
+    #[serde(default)]
+    pub label: Option<String>,
+
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateUriResult {
+    pub uri: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum SPVVerifyTxResult {
@@ -288,6 +746,10 @@ pub struct TransactionMeta {
     pub vsize: usize,
     #[serde(rename = "transaction_size")]
     pub size: usize,
+    /// The base64 psbt with the signatures collected so far, multisig subaccounts only
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub psbt: Option<String>,
 }
 
 impl From<BETransaction> for TransactionMeta {
@@ -319,6 +781,7 @@ impl From<BETransaction> for TransactionMeta {
             weight,
             vsize: (weight as f32 / 4.0) as usize,
             size: transaction.get_size(),
+            psbt: None,
         }
     }
 }
@@ -433,6 +896,9 @@ pub struct TxListItem {
     pub transaction_size: usize,
     pub transaction_vsize: usize,
     pub transaction_weight: usize,
+    /// To pass to `get_transactions` for the page following this item
+    #[serde(default)]
+    pub cursor: String,
 }
 
 // Negative (sent) amounts are expected to be provided as positive numbers.
@@ -449,6 +915,125 @@ where
     balances_abs.serialize(serializer)
 }
 
+/// Content of the `transaction` and `confirmation` notifications
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TransactionNotification {
+    pub txhash: String,
+    pub subaccounts: Vec<u32>,
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// Net amount per asset, negative when the wallet is spending
+    pub satoshi: Balances,
+    /// 0 if unconfirmed
+    pub block_height: u32,
+    pub spv_verified: String,
+}
+
+/// Content of the `spv` notification, emitted when the SPV status of a confirmed transaction
+/// changes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SpvNotification {
+    pub txhash: String,
+    pub subaccounts: Vec<u32>,
+    pub block_height: u32,
+    pub spv_verified: String,
+}
+
+/// Content of the `network` notification, the `connected`, `login_required` and
+/// `heartbeat_timeout` fields follow the ones emitted by the green backend
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NetworkNotification {
+    pub connected: bool,
+    pub login_required: bool,
+    pub heartbeat_timeout: bool,
+    pub state: ConnectionState,
+    pub current_server: String,
+    pub next_retry_ts: Option<u64>,
+    /// Seconds until the next retry
+    pub waiting: Option<u64>,
+}
+
+/// A notification emitted by the session
+///
+/// Serialized with `to_value` as `{"event": <event>, <event>: <content>}`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    Block(u32),
+    Settings(Settings),
+    Network(NetworkNotification),
+    Transaction(TransactionNotification),
+    Confirmation(TransactionNotification),
+    Replacement(ReplacementNotification),
+    Spv(SpvNotification),
+    /// The history of the subaccount changed and must be reloaded, sent as a `transaction` event
+    /// with only the `subaccounts` field
+    SubaccountUpdated(u32),
+    Request(RequestNotification),
+}
+
+impl Notification {
+    pub fn event(&self) -> &'static str {
+        match self {
+            Notification::Block(_) => "block",
+            Notification::Settings(_) => "settings",
+            Notification::Network(_) => "network",
+            Notification::Transaction(_) | Notification::SubaccountUpdated(_) => "transaction",
+            Notification::Confirmation(_) => "confirmation",
+            Notification::Replacement(_) => "replacement",
+            Notification::Spv(_) => "spv",
+            Notification::Request(_) => "request",
+        }
+    }
+
+    pub fn to_value(&self) -> serde_json::Value {
+        let content = match self {
+            Notification::Block(height) => json!({ "block_height": height }),
+            Notification::Settings(settings) => json!(settings),
+            Notification::Network(network) => json!(network),
+            Notification::Transaction(tx) | Notification::Confirmation(tx) => json!(tx),
+            Notification::Replacement(replacement) => json!(replacement),
+            Notification::Spv(spv) => json!(spv),
+            Notification::SubaccountUpdated(account_num) => json!({ "subaccounts": [account_num] }),
+            Notification::Request(request) => json!(request),
+        };
+        let mut value = serde_json::Map::new();
+        value.insert(self.event().to_string(), content);
+        value.insert("event".to_string(), self.event().into());
+        value.into()
+    }
+}
+
+/// Content of the `replacement` notification, emitted when `txhash` is replaced by a conflicting
+/// transaction, for instance with RBF
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReplacementNotification {
+    pub txhash: String,
+    pub replaced_by: String,
+    pub subaccounts: Vec<u32>,
+}
+
+/// Content of the `request` notification, emitted by an asynchronous call while it progresses
+/// and once when it completes, with either `result` or `error` set
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RequestNotification {
+    pub id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<RequestProgress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RequestProgress {
+    /// The operation in progress, for instance `discover_accounts`
+    pub step: String,
+    pub done: u64,
+    /// Not known for open ended operations such as account discovery
+    pub total: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AccountInfo {
     #[serde(rename = "pointer")]
@@ -460,6 +1045,10 @@ pub struct AccountInfo {
     pub required_ca: u32,     // unused, always 0
     pub receiving_id: String, // unused, always ""
     pub bip44_discovered: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultisigOpt>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyOpt>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -469,7 +1058,7 @@ pub struct PinSetDetails {
     pub device_id: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PinGetDetails {
     pub salt: String,
     pub encrypted_data: String,
@@ -569,6 +1158,55 @@ impl RefreshAssets {
     }
 }
 
+/// {"assets_id":["ce091c99..."],"refresh":false}
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GetAssetsOpt {
+    /// The assets to look up, all the assets received by the wallet if missing
+    pub assets_id: Option<Vec<String>>,
+    /// Fetch the registry entries even if the cached ones didn't expire
+    pub refresh: bool,
+}
+
+/// The metadata of an asset defined by the user, such as a private asset missing from the
+/// registry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserAsset {
+    pub name: String,
+    #[serde(default)]
+    pub ticker: Option<String>,
+    #[serde(default)]
+    pub precision: u8,
+    /// The base64 encoded PNG icon
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+/// {"assets":{"ce091c99...":{"name":"Company Shares","ticker":"CSH","precision":0}}}
+/// The format used to export and import the user assets
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct UserAssets {
+    pub assets: HashMap<String, UserAsset>,
+}
+
+/// {"asset_id":"ce091c99...","asset":{"name":"Company Shares"}}, without `asset` to remove it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetUserAssetOpt {
+    pub asset_id: String,
+    #[serde(default)]
+    pub asset: Option<UserAsset>,
+}
+
+/// {"current_secret":"123456","new_secret":"654321"}, without `new_secret` to protect the store
+/// with the wallet xpub alone. Not `Debug`, to keep the secrets out of the logs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChangeStoreSecretOpt {
+    #[serde(default)]
+    pub current_secret: Option<String>,
+    #[serde(default)]
+    pub new_secret: Option<String>,
+}
+
 /// see comment for struct Settings
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Pricing {
@@ -759,8 +1397,35 @@ impl From<&BETransactionEntry> for TransactionDetails {
 
 #[cfg(test)]
 mod test {
-    use crate::model::{parse_path, GetUnspentOutputs};
+    use crate::model::{parse_path, GetUnspentOutputs, Notification, TxCursor};
     use bitcoin::util::bip32::DerivationPath;
+    use serde_json::json;
+
+    #[test]
+    fn test_notification_value() {
+        assert_eq!(
+            Notification::Block(101).to_value(),
+            json!({"event":"block","block":{"block_height":101}})
+        );
+        assert_eq!(
+            Notification::SubaccountUpdated(1).to_value(),
+            json!({"event":"transaction","transaction":{"subaccounts":[1]}})
+        );
+        let progress = RequestNotification {
+            id: 3,
+            progress: Some(RequestProgress {
+                step: "discover_accounts".into(),
+                done: 2,
+                total: None,
+            }),
+            result: None,
+            error: None,
+        };
+        assert_eq!(
+            Notification::Request(progress).to_value(),
+            json!({"event":"request","request":{"id":3,"progress":{"step":"discover_accounts","done":2,"total":null}}})
+        );
+    }
 
     #[test]
     fn test_path() {
@@ -770,6 +1435,25 @@ mod test {
         assert_eq!(parse_path(&path_internal).unwrap(), (true, 0u32));
     }
 
+    #[test]
+    fn test_tx_cursor() {
+        let txid = "08711d45d4867d7834b133a425da065b252eb6a9b206d57e2bbb226a344c5d13";
+        for height in [None, Some(0), Some(1806588)].iter() {
+            let cursor = TxCursor {
+                height: *height,
+                txid: txid.into(),
+            };
+            assert_eq!(cursor.to_string().parse::<TxCursor>().unwrap(), cursor);
+        }
+        assert_eq!(
+            format!("unconfirmed:{}", txid).parse::<TxCursor>().unwrap().height,
+            None
+        );
+        assert!("".parse::<TxCursor>().is_err());
+        assert!("12:".parse::<TxCursor>().is_err());
+        assert!(format!("-1:{}", txid).parse::<TxCursor>().is_err());
+    }
+
     #[test]
     fn test_unspent() {
         let json_str = r#"{"btc": [{"address_type": "p2wsh", "block_height": 1806588, "pointer": 3509, "pt_idx": 1, "satoshi": 3650144, "subaccount": 0, "txhash": "08711d45d4867d7834b133a425da065b252eb6a9b206d57e2bbb226a344c5d13", "is_internal": false, "confidential": false}, {"address_type": "p2wsh", "block_height": 1835681, "pointer": 3510, "pt_idx": 0, "satoshi": 5589415, "subaccount": 0, "txhash": "fbd00e5b9e8152c04214c72c791a78a65fdbab68b5c6164ff0d8b22a006c5221", "is_internal": false, "confidential": false}, {"address_type": "p2wsh", "block_height": 1835821, "pointer": 3511, "pt_idx": 0, "satoshi": 568158, "subaccount": 0, "txhash": "e5b358fb8366960130b97794062718d7f4fbe721bf274f47493a19326099b811", "is_internal": false, "confidential": false}]}"#;
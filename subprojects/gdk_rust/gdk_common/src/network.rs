@@ -5,6 +5,11 @@ use bitcoin::secp256k1::Secp256k1;
 use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey};
 use serde::{Deserialize, Serialize};
 
+/// The blind PIN server used when the network doesn't set one
+pub const PIN_SERVER_URL: &str = "https://jadepin.blockstream.com";
+pub const PIN_SERVER_PUBLIC_KEY: &str =
+    "0332b7b1348bde8ca4b46b9dcc30320e140ca26428160a27bdbfc30b34ec87c547";
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Network {
     pub name: String,
@@ -34,6 +39,13 @@ pub struct Network {
     pub spv_multi: Option<bool>,
     pub spv_servers: Option<Vec<String>>,
     pub taproot_enabled_at: Option<u32>,
+    pub esplora_url: Option<String>,
+    pub bip21_prefix: Option<String>,
+    pub use_tor: Option<bool>,
+    pub pin_server_url: Option<String>,
+    pub pin_server_onion_url: Option<String>,
+    pub pin_server_public_key: Option<String>,
+    pub offline_pin: Option<bool>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -110,6 +122,19 @@ impl Network {
             .ok_or_else(|| Error::Generic("asset regitry url not available".into()))
     }
 
+    /// The url of the blind PIN server, the onion one if available when using Tor
+    pub fn pin_server_url(&self) -> &str {
+        match (self.use_tor.unwrap_or(false), self.pin_server_onion_url.as_deref()) {
+            (true, Some(onion_url)) => onion_url,
+            _ => self.pin_server_url.as_deref().unwrap_or(PIN_SERVER_URL),
+        }
+    }
+
+    /// The hex public key authenticating the blind PIN server handshakes
+    pub fn pin_server_public_key(&self) -> &str {
+        self.pin_server_public_key.as_deref().unwrap_or(PIN_SERVER_PUBLIC_KEY)
+    }
+
     // Unique wallet identifier for the given xpub on this network. Used as part of the database
     // root path, any changes will result in the creation of a new separate database.
     pub fn wallet_hash_id(&self, master_xpub: &ExtendedPubKey) -> String {
@@ -120,6 +145,16 @@ impl Network {
         crate::wally::pbkdf2_hmac_sha512_256(password, salt, cost).to_hex()
     }
 
+    /// The scheme of the BIP21 payment URIs, such as `bitcoin` or `liquidnetwork`
+    pub fn bip21_prefix(&self) -> &str {
+        match (self.bip21_prefix.as_deref(), self.id()) {
+            (Some(prefix), _) => prefix,
+            (None, NetworkId::Bitcoin(_)) => "bitcoin",
+            (None, NetworkId::Elements(ElementsNetwork::LiquidTestnet)) => "liquidtestnet",
+            (None, NetworkId::Elements(_)) => "liquidnetwork",
+        }
+    }
+
     pub fn bip32_network(&self) -> bitcoin::network::constants::Network {
         if self.mainnet {
             bitcoin::network::constants::Network::Bitcoin
@@ -262,4 +297,17 @@ mod tests {
             "ca8f6b74e485133f441e01313682e6d5613cedbe479b2c472e017e21cc42a052"
         );
     }
+
+    #[test]
+    fn test_pin_server() {
+        let mut network = crate::Network::default();
+        assert_eq!(network.pin_server_url(), crate::network::PIN_SERVER_URL);
+        assert_eq!(network.pin_server_public_key(), crate::network::PIN_SERVER_PUBLIC_KEY);
+
+        network.pin_server_url = Some("http://127.0.0.1:8096".into());
+        network.pin_server_onion_url = Some("http://pin.onion".into());
+        assert_eq!(network.pin_server_url(), "http://127.0.0.1:8096");
+        network.use_tor = Some(true);
+        assert_eq!(network.pin_server_url(), "http://pin.onion");
+    }
 }
@@ -1,10 +1,13 @@
 use serde::{Deserialize, Serialize};
 
+use bitcoin::blockdata::opcodes;
 use bitcoin::blockdata::script::Builder;
 use bitcoin::hash_types::PubkeyHash;
 use bitcoin::hashes::Hash;
 use bitcoin::{Address, Network, PublicKey, Script};
 
+use crate::error::Error;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ScriptType {
     #[serde(rename = "p2sh-p2wpkh")]
@@ -13,10 +16,39 @@ pub enum ScriptType {
     P2wpkh = 1,
     #[serde(rename = "p2pkh")]
     P2pkh = 2,
+    /// Multisig accounts, 3 and 4 are taken by the Electrum seed accounts
+    #[serde(rename = "p2wsh")]
+    P2wsh = 5,
+    /// Accounts defined by a miniscript descriptor
+    #[serde(rename = "p2wsh-miniscript")]
+    P2wshMiniscript = 6,
+}
+
+/// The spending conditions of the outputs of an account, enough to estimate the size of the
+/// inputs spending them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputShape {
+    Single(ScriptType),
+    /// P2WSH `threshold`-of-`keys` sortedmulti
+    Multisig {
+        threshold: u8,
+        keys: u8,
+    },
+    /// P2WSH miniscript, satisfied by a witness of `elements` items serialized in `size` bytes
+    Satisfaction {
+        elements: u8,
+        size: u16,
+    },
 }
 
 const TYPES: [ScriptType; 3] = [ScriptType::P2shP2wpkh, ScriptType::P2wpkh, ScriptType::P2pkh];
 
+impl From<ScriptType> for InputShape {
+    fn from(script_type: ScriptType) -> Self {
+        InputShape::Single(script_type)
+    }
+}
+
 // The following scripts are always using regtest network,
 // it is always ok because I am not interested in the address just in the script
 
@@ -28,6 +60,17 @@ pub fn p2pkh_script(pk: &PublicKey) -> Script {
     Address::p2pkh(pk, Network::Regtest).script_pubkey()
 }
 
+/// The witness script of a `threshold`-of-`keys` sortedmulti, the keys are sorted as BIP67 requires
+pub fn sortedmulti_script(threshold: usize, keys: &[PublicKey]) -> Script {
+    let mut keys: Vec<Vec<u8>> = keys.iter().map(|k| k.to_bytes()).collect();
+    keys.sort();
+    let mut builder = Builder::new().push_int(threshold as i64);
+    for key in keys.iter() {
+        builder = builder.push_slice(key);
+    }
+    builder.push_int(keys.len() as i64).push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script()
+}
+
 pub fn p2shwpkh_script_sig(public_key: &PublicKey) -> Script {
     let internal = Builder::new()
         .push_int(0)
@@ -46,40 +89,93 @@ impl ScriptType {
     }
 
     pub fn is_segwit(self) -> bool {
-        matches!(self, ScriptType::P2wpkh | ScriptType::P2shP2wpkh)
+        matches!(
+            self,
+            ScriptType::P2wpkh
+                | ScriptType::P2shP2wpkh
+                | ScriptType::P2wsh
+                | ScriptType::P2wshMiniscript
+        )
     }
+}
 
+impl InputShape {
     /// Returns a mock witness with the expected size
-    pub fn mock_witness(self) -> Vec<Vec<u8>> {
-        match self {
+    pub fn mock_witness(self) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(match self {
             // signature (72) + compressed public key (33)
-            ScriptType::P2wpkh | ScriptType::P2shP2wpkh => vec![vec![0u8; 72], vec![0u8; 33]],
+            InputShape::Single(ScriptType::P2wpkh) | InputShape::Single(ScriptType::P2shP2wpkh) => {
+                vec![vec![0u8; 72], vec![0u8; 33]]
+            }
             // empty for non-witness inputs
-            ScriptType::P2pkh => vec![],
-        }
+            InputShape::Single(ScriptType::P2pkh) => vec![],
+            // the CHECKMULTISIG dummy + `threshold` signatures (72) + the witness script:
+            // OP_M <keys * (OP_PUSHBYTES <33 bytes compressed key>)> OP_N OP_CHECKMULTISIG
+            InputShape::Multisig {
+                threshold,
+                keys,
+            } => {
+                let mut witness = vec![vec![]];
+                witness.extend((0..threshold).map(|_| vec![0u8; 72]));
+                witness.push(vec![0u8; 3 + 34 * keys as usize]);
+                witness
+            }
+            // all the `size` bytes in the last item, which never underestimates the length prefixes
+            InputShape::Satisfaction {
+                elements,
+                size,
+            } => {
+                let mut witness = vec![vec![]; elements as usize - 1];
+                witness.push(vec![0u8; size as usize]);
+                witness
+            }
+            InputShape::Single(ScriptType::P2wsh)
+            | InputShape::Single(ScriptType::P2wshMiniscript) => {
+                return Err(Error::Generic("witness script shape unknown".into()))
+            }
+        })
     }
 
     /// Returns a mock script sig with the expected size
-    pub fn mock_script_sig(self) -> Vec<u8> {
-        match self {
+    pub fn mock_script_sig(self) -> Result<Vec<u8>, Error> {
+        Ok(match self {
             // empty for native segwit
-            ScriptType::P2wpkh => vec![],
+            InputShape::Single(ScriptType::P2wpkh)
+            | InputShape::Multisig {
+                ..
+            }
+            | InputShape::Satisfaction {
+                ..
+            } => vec![],
             // OP_PUSHBYTES <22 bytes>
-            ScriptType::P2shP2wpkh => vec![0u8; 23],
+            InputShape::Single(ScriptType::P2shP2wpkh) => vec![0u8; 23],
             // OP_PUSHBYTES <72 bytes sig> OP_PUSHBYTES <33 bytes compressed key>
-            ScriptType::P2pkh => vec![0u8; 107],
-        }
+            InputShape::Single(ScriptType::P2pkh) => vec![0u8; 107],
+            InputShape::Single(ScriptType::P2wsh)
+            | InputShape::Single(ScriptType::P2wshMiniscript) => {
+                return Err(Error::Generic("witness script shape unknown".into()))
+            }
+        })
     }
 
     /// Returns a mock scriptPubkey with the expected size
     pub fn mock_script_pubkey(self) -> Vec<u8> {
         match self {
             // OP_0 OP_PUSHBYTES <20 bytes hash>
-            ScriptType::P2wpkh => vec![0u8; 22],
+            InputShape::Single(ScriptType::P2wpkh) => vec![0u8; 22],
             // OP_HASH160 OP_PUSHBYTES <20 bytes hash> OP_EQUAL
-            ScriptType::P2shP2wpkh => vec![0u8; 23],
+            InputShape::Single(ScriptType::P2shP2wpkh) => vec![0u8; 23],
             // OP_DUP OP_HASH160 OP_PUSHBYTES <20 bytes hash> OP_EQUALVERIFY OP_CHECKSIG
-            ScriptType::P2pkh => vec![0u8; 25],
+            InputShape::Single(ScriptType::P2pkh) => vec![0u8; 25],
+            // OP_0 OP_PUSHBYTES <32 bytes hash>
+            InputShape::Multisig {
+                ..
+            }
+            | InputShape::Satisfaction {
+                ..
+            }
+            | InputShape::Single(ScriptType::P2wsh)
+            | InputShape::Single(ScriptType::P2wshMiniscript) => vec![0u8; 34],
         }
     }
 }
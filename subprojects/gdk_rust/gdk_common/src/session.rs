@@ -1,5 +1,5 @@
 use crate::be::BEBlockHash;
-use crate::mnemonic::Mnemonic;
+use crate::mnemonic::{Mnemonic, SeedType};
 use crate::model::*;
 use crate::password::Password;
 
@@ -8,15 +8,25 @@ use serde_json::Value;
 
 pub trait Session<E> {
     // fn create_session(network: Network) -> Result<Self::Value, E>;
-    fn poll_session(&self) -> Result<(), E>;
+    fn poll_session(&self) -> Result<PollSessionResult, E>;
     fn connect(&mut self, net_params: &Value) -> Result<(), E>;
     fn disconnect(&mut self) -> Result<(), E>;
-    fn login(&mut self, mnemonic: &Mnemonic, password: Option<Password>) -> Result<LoginData, E>;
+    /// `store_secret`, if given, protects the local store, which otherwise the xpub alone decrypts.
+    /// `seed_type` chooses how a mnemonic that is both a BIP39 and an Electrum one is read.
+    fn login(
+        &mut self,
+        mnemonic: &Mnemonic,
+        password: Option<Password>,
+        store_secret: Option<Password>,
+        seed_type: Option<SeedType>,
+    ) -> Result<LoginData, E>;
     fn mnemonic_from_pin_data(&mut self, pin: String, details: PinGetDetails) -> Result<String, E>;
     fn get_subaccounts(&mut self, refresh: bool) -> Result<Vec<AccountInfo>, E>;
     fn get_subaccount(&self, index: u32) -> Result<AccountInfo, E>;
     fn create_subaccount(&mut self, opt: CreateAccountOpt) -> Result<AccountInfo, E>;
     fn get_next_subaccount(&self, opt: GetNextAccountOpt) -> Result<u32, E>;
+    /// Our xpub for the multisig subaccount `opt.subaccount`, before its creation
+    fn get_multisig_xpub(&self, opt: &GetMultisigXpubOpt) -> Result<GetMultisigXpubResult, E>;
     /// Deprecated in favor of update_subaccount
     fn rename_subaccount(&mut self, opt: RenameAccountOpt) -> Result<(), E>;
     fn update_subaccount(&mut self, opt: UpdateAccountOpt) -> Result<(), E>;
@@ -25,22 +35,45 @@ pub trait Session<E> {
     fn get_transaction_hex(&self, txid: &str) -> Result<String, E>;
     fn get_transaction_details(&self, txid: &str) -> Result<TransactionDetails, E>;
     fn get_balance(&self, opt: &GetBalanceOpt) -> Result<Balances, E>;
+    fn get_balance_history(&self, opt: &GetBalanceHistoryOpt) -> Result<BalanceHistory, E>;
+    /// The coins of a miniscript subaccount approaching their timelock
+    fn get_expiring_utxos(&self, opt: &GetExpiringUtxosOpt) -> Result<GetExpiringUtxosResult, E>;
     fn set_transaction_memo(&self, txid: &str, memo: &str) -> Result<(), E>;
-    fn create_transaction(&mut self, details: &mut CreateTransaction)
-        -> Result<TransactionMeta, E>;
+    /// Takes `&self` as sweeping looks up the coins of the swept key on the backend
+    fn create_transaction(&self, details: &mut CreateTransaction) -> Result<TransactionMeta, E>;
     fn sign_transaction(&self, tx_detail_unsigned: &TransactionMeta) -> Result<TransactionMeta, E>;
-    fn send_transaction(
-        &mut self,
-        tx_detail_signed: &TransactionMeta,
-    ) -> Result<TransactionMeta, E>;
+    /// Takes `&self` as the payjoin requests may last a minute and must not block other calls
+    fn send_transaction(&self, tx_detail_signed: &TransactionMeta) -> Result<TransactionMeta, E>;
     fn broadcast_transaction(&mut self, tx_hex: &str) -> Result<String, E>;
     fn get_receive_address(&self, opt: &GetAddressOpt) -> Result<AddressPointer, E>;
+    fn sign_message(&self, opt: &SignMessageOpt) -> Result<SignMessageResult, E>;
+    fn create_proof_of_reserves(
+        &self,
+        opt: &ProofOfReservesOpt,
+    ) -> Result<ProofOfReservesResult, E>;
+    /// Doesn't need login, the proof is checked against the chain of the session network
+    fn verify_proof_of_reserves(
+        &self,
+        opt: &VerifyProofOfReservesOpt,
+    ) -> Result<VerifyProofOfReservesResult, E>;
+    /// Doesn't need login, the URI is validated for the session network
+    fn parse_uri(&self, opt: &ParseUriOpt) -> Result<ParseUriResult, E>;
+    fn create_uri(&self, opt: &CreateUriOpt) -> Result<CreateUriResult, E>;
     fn get_mnemonic(&self) -> Result<Mnemonic, E>;
     fn get_available_currencies(&self) -> Result<Value, E>;
     fn get_fee_estimates(&mut self) -> Result<Vec<FeeEstimate>, E>;
     fn get_settings(&self) -> Result<Settings, E>;
     fn change_settings(&mut self, value: &Value) -> Result<(), E>;
     fn refresh_assets(&self, details: &RefreshAssets) -> Result<Value, E>;
+    /// The registry entries of single assets, cached until they expire
+    fn get_assets(&self, opt: &GetAssetsOpt) -> Result<Value, E>;
+    /// The asset metadata defined by the user, overriding the registry ones
+    fn get_user_assets(&self) -> Result<UserAssets, E>;
+    fn set_user_asset(&self, opt: SetUserAssetOpt) -> Result<(), E>;
+    /// Add `assets` to the user assets, replacing the ones with the same asset id
+    fn import_user_assets(&self, assets: UserAssets) -> Result<(), E>;
+    /// Encrypt the local store again with a key derived from the new secret
+    fn change_store_secret(&self, opt: ChangeStoreSecretOpt) -> Result<(), E>;
     fn block_status(&self) -> Result<(u32, BEBlockHash), E>;
     fn tx_status(&self) -> Result<u64, E>;
     fn set_pin(&self, details: &PinSetDetails) -> Result<PinGetDetails, E>;
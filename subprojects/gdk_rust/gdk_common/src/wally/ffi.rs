@@ -43,6 +43,37 @@ extern "C" {
     //    const char *mnemonic);
     pub fn bip39_mnemonic_validate(word_list: *const c_void, mnemonic: *const c_char) -> c_int;
 
+    //WALLY_CORE_API int bip39_get_wordlist(
+    //    const char *lang,
+    //    struct words **output);
+    pub fn bip39_get_wordlist(lang: *const c_char, output: *mut *const c_void) -> c_int;
+
+    //WALLY_CORE_API int bip39_get_word(
+    //    const struct words *w,
+    //    size_t index,
+    //    char **output);
+    pub fn bip39_get_word(
+        word_list: *const c_void,
+        index: size_t,
+        output: *mut *mut c_char,
+    ) -> c_int;
+
+    //WALLY_CORE_API int bip39_mnemonic_from_bytes(
+    //    const struct words *w,
+    //    const unsigned char *bytes,
+    //    size_t bytes_len,
+    //    char **output);
+    pub fn bip39_mnemonic_from_bytes(
+        word_list: *const c_void,
+        bytes: *const c_uchar,
+        bytes_len: size_t,
+        output: *mut *mut c_char,
+    ) -> c_int;
+
+    //WALLY_CORE_API int wally_free_string(
+    //    char *str);
+    pub fn wally_free_string(str: *mut c_char) -> c_int;
+
     //WALLY_CORE_API int wally_tx_from_bytes(
     //    const unsigned char *bytes,
     //    size_t bytes_len,
@@ -114,6 +145,28 @@ extern "C" {
         len: size_t,
     ) -> c_int;
 
+    //WALLY_CORE_API int wally_scrypt(
+    //    const unsigned char *pass,
+    //    size_t pass_len,
+    //    const unsigned char *salt,
+    //    size_t salt_len,
+    //    uint32_t cost,
+    //    uint32_t block_size,
+    //    uint32_t parallelism,
+    //    unsigned char *bytes_out,
+    //    size_t len);
+    pub fn wally_scrypt(
+        pass: *const c_uchar,
+        pass_len: size_t,
+        salt: *const c_uchar,
+        salt_len: size_t,
+        cost: u32,
+        block_size: u32,
+        parallelism: u32,
+        bytes_out: *mut c_uchar,
+        len: size_t,
+    ) -> c_int;
+
     /**
      * Convert satoshi to an explicit confidential value representation.
      *
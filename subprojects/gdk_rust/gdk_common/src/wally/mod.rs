@@ -27,11 +27,63 @@ impl fmt::Debug for MasterBlindingKey {
 /// The size of BIP39-derived seeds in bytes.
 const BIP39_SEED_BYTES: usize = 64;
 
-/// Validate a BIP-39 mnemonic.
-fn bip39_mnemonic_validate(mnemonic: &str) -> bool {
+/// Number of words of the BIP-39 wordlists.
+pub const BIP39_WORDLIST_LEN: usize = 2048;
+
+/// The wally wordlist of `lang`, libwally falls back to english for unknown languages.
+fn bip39_get_wordlist(lang: &str) -> *const libc::c_void {
+    let c_lang = make_str(lang);
+    let mut word_list = ptr::null();
+    let ret = unsafe {
+        let ret = ffi::bip39_get_wordlist(c_lang, &mut word_list);
+        let _ = CString::from_raw(c_lang);
+        ret
+    };
+    assert_eq!(ret, ffi::WALLY_OK);
+    word_list
+}
+
+/// Take ownership of a string allocated by libwally.
+fn take_wally_str(s: *mut c_char) -> String {
+    let string = read_str(s);
+    unsafe {
+        ffi::wally_free_string(s);
+    }
+    string
+}
+
+/// The words of the BIP-39 wordlist of `lang`.
+pub fn bip39_wordlist(lang: &str) -> Vec<String> {
+    let word_list = bip39_get_wordlist(lang);
+    (0..BIP39_WORDLIST_LEN)
+        .map(|index| {
+            let mut word = ptr::null_mut();
+            let ret = unsafe { ffi::bip39_get_word(word_list, index, &mut word) };
+            assert_eq!(ret, ffi::WALLY_OK);
+            take_wally_str(word)
+        })
+        .collect()
+}
+
+/// Encode the entropy `bytes` as a BIP-39 mnemonic with the wordlist of `lang`.
+pub fn bip39_mnemonic_from_bytes(lang: &str, bytes: &[u8]) -> Option<String> {
+    let word_list = bip39_get_wordlist(lang);
+    let mut mnemonic = ptr::null_mut();
+    let ret = unsafe {
+        ffi::bip39_mnemonic_from_bytes(word_list, bytes.as_ptr(), bytes.len(), &mut mnemonic)
+    };
+    if ret != ffi::WALLY_OK {
+        return None;
+    }
+    Some(take_wally_str(mnemonic))
+}
+
+/// Validate a BIP-39 mnemonic against the wordlist of `lang`.
+pub fn bip39_mnemonic_validate(lang: &str, mnemonic: &str) -> bool {
+    let word_list = bip39_get_wordlist(lang);
     let c_mnemonic = make_str(mnemonic);
     let ret = unsafe {
-        let ret = ffi::bip39_mnemonic_validate(ptr::null(), c_mnemonic);
+        let ret = ffi::bip39_mnemonic_validate(word_list, c_mnemonic);
         let _ = CString::from_raw(c_mnemonic);
         ret
     };
@@ -40,7 +92,8 @@ fn bip39_mnemonic_validate(mnemonic: &str) -> bool {
 
 /// Convert the mnemonic phrase and passphrase to a binary seed.
 pub fn bip39_mnemonic_to_seed(mnemonic: &str, passphrase: &str) -> Option<[u8; BIP39_SEED_BYTES]> {
-    if !bip39_mnemonic_validate(mnemonic) {
+    if !crate::mnemonic::BIP39_LANGUAGES.iter().any(|lang| bip39_mnemonic_validate(lang, mnemonic))
+    {
         return None;
     }
 
@@ -115,9 +168,8 @@ pub fn ec_public_key_from_private_key(priv_key: secp256k1::SecretKey) -> secp256
     secp256k1::PublicKey::from_slice(&pub_key[..]).unwrap() // TODO return Result?
 }
 
-pub fn pbkdf2_hmac_sha512_256(password: Vec<u8>, salt: Vec<u8>, cost: u32) -> [u8; 32] {
-    let mut tmp = [0; 64];
-    let mut out = [0; 32];
+pub fn pbkdf2_hmac_sha512(password: &[u8], salt: &[u8], cost: u32) -> [u8; 64] {
+    let mut out = [0; 64];
     let ret = unsafe {
         ffi::wally_pbkdf2_hmac_sha512(
             password.as_ptr(),
@@ -126,12 +178,48 @@ pub fn pbkdf2_hmac_sha512_256(password: Vec<u8>, salt: Vec<u8>, cost: u32) -> [u
             salt.len(),
             0,
             cost,
-            tmp.as_mut_ptr(),
-            tmp.len(),
+            out.as_mut_ptr(),
+            out.len(),
+        )
+    };
+    assert_eq!(ret, ffi::WALLY_OK);
+    out
+}
+
+pub fn pbkdf2_hmac_sha512_256(password: Vec<u8>, salt: Vec<u8>, cost: u32) -> [u8; 32] {
+    let mut out = [0; 32];
+    out.copy_from_slice(&pbkdf2_hmac_sha512(&password, &salt, cost)[..32]);
+    out
+}
+
+// scrypt parameters of the keys derived from user secrets, using 32 MiB of memory
+pub const SCRYPT_COST: u32 = 1 << 15;
+pub const SCRYPT_BLOCK_SIZE: u32 = 8;
+pub const SCRYPT_PARALLELISM: u32 = 1;
+
+/// The 32 bytes scrypt key of `password`, using `cost * block_size * 128` bytes of memory
+pub fn scrypt(
+    password: &[u8],
+    salt: &[u8],
+    cost: u32,
+    block_size: u32,
+    parallelism: u32,
+) -> [u8; 32] {
+    let mut out = [0; 32];
+    let ret = unsafe {
+        ffi::wally_scrypt(
+            password.as_ptr(),
+            password.len(),
+            salt.as_ptr(),
+            salt.len(),
+            cost,
+            block_size,
+            parallelism,
+            out.as_mut_ptr(),
+            out.len(),
         )
     };
     assert_eq!(ret, ffi::WALLY_OK);
-    out.copy_from_slice(&tmp[..32]);
     out
 }
 
@@ -208,4 +296,14 @@ mod tests {
             "657a9de33d1f7753edbb86c90b0ba064bd1b986570f1a5019ed80459877b013b"
         );
     }
+
+    #[test]
+    fn test_scrypt() {
+        // test vector from RFC 7914, truncated to 32 bytes
+        let bytes = scrypt(b"password", b"NaCl", 1024, 8, 16);
+        assert_eq!(
+            bytes.to_hex(),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162"
+        );
+    }
 }
@@ -1,7 +1,10 @@
 use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::hash::{Hash as _, Hasher};
 use std::str::FromStr;
+use std::sync::Mutex;
 
 use log::{debug, info, trace, warn};
 
@@ -12,33 +15,55 @@ use bitcoin::secp256k1::{self, Message};
 use bitcoin::util::address::Payload;
 use bitcoin::util::bip143::SigHashCache;
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::util::psbt::PartiallySignedTransaction;
 use bitcoin::{PublicKey, SigHashType};
 use elements::confidential::Value;
 
 use gdk_common::be::{
-    BEAddress, BEOutPoint, BEScript, BEScriptConvert, BETransaction, BETxid, ScriptBatch, UTXOInfo,
-    Utxos, DUST_VALUE,
+    BEAddress, BEOutPoint, BEScript, BEScriptConvert, BETransaction, BETxid, ScriptBatch,
+    Timelocks, UTXOInfo, Utxos, DUST_VALUE,
 };
 use gdk_common::error::fn_err;
+use gdk_common::mnemonic::SeedType;
 use gdk_common::model::{
-    AccountInfo, AddressAmount, AddressPointer, Balances, CreateTransaction, GetTransactionsOpt,
-    SPVVerifyTxResult, TransactionMeta, UpdateAccountOpt, UtxoStrategy,
+    AccountInfo, AddressAmount, AddressPointer, BalanceHistory, BalanceInterval, Balances,
+    CreateTransaction, ExpiringUtxo, GetBalanceHistoryOpt, GetExpiringUtxosOpt,
+    GetExpiringUtxosResult, GetTransactionsOpt, PayjoinOpt, ProofOfReservesOpt,
+    ProofOfReservesResult, SPVVerifyTxResult, SignMessageOpt, SignMessageResult, TransactionMeta,
+    TransactionNotification, TxCursor, TxSort, UpdateAccountOpt, UtxoStrategy,
 };
-use gdk_common::scripts::{p2pkh_script, p2shwpkh_script_sig, ScriptType};
+use gdk_common::scripts::{p2pkh_script, p2shwpkh_script_sig, InputShape, ScriptType};
 use gdk_common::util::is_confidential_txoutsecrets;
 use gdk_common::wally::{
     asset_blinding_key_to_ec_private_key, ec_public_key_from_private_key, MasterBlindingKey,
 };
 use gdk_common::{ElementsNetwork, Network, NetworkId};
 
+use crate::chain::ChainSource;
 use crate::error::Error;
-use crate::interface::ElectrumUrl;
-use crate::store::{Store, BATCH_SIZE};
+use crate::history::{self, BlockChanges};
+use crate::message;
+use crate::multisig::{self, Multisig};
+use crate::payjoin;
+use crate::policy::Policy;
+use crate::request;
+use crate::reserves;
+use crate::store::{RawAccountCache, Store, StoreMeta, BATCH_SIZE};
 
 // The number of account types, including these reserved for future use.
-// Currently only 3 are used: P2SH-P2WPKH, P2WPKH and P2PKH
+// Currently 7 are used: P2SH-P2WPKH, P2WPKH, P2PKH, the 2 Electrum ones, P2WSH multisig and
+// P2WSH miniscript
 const NUM_RESERVED_ACCOUNT_TYPES: u32 = 16;
 
+/// The account of a wallet restored from an Electrum "segwit" seed, P2WPKH derived at `m/0'`.
+/// Electrum wallets have a single account, so only the first of the type is valid, and only for
+/// Electrum segwit seeds
+pub const ELECTRUM_SEGWIT_ACCOUNT: u32 = 3;
+
+/// The account of a wallet restored from an Electrum "standard" seed, P2PKH derived at `m/`,
+/// valid only for Electrum standard seeds
+pub const ELECTRUM_STANDARD_ACCOUNT: u32 = 4;
+
 pub struct Account {
     account_num: u32,
     script_type: ScriptType,
@@ -49,6 +74,13 @@ pub struct Account {
     store: Store,
     // elements only
     master_blinding: Option<MasterBlindingKey>,
+    // multisig accounts only
+    multisig: Option<Multisig>,
+    // miniscript accounts only
+    policy: Option<Policy>,
+    /// the last balance history of each interval, with the fingerprint of the data it was
+    /// computed from
+    balance_history: Mutex<HashMap<BalanceInterval, (u64, BalanceHistory)>>,
 
     /// When an account is discovered through `recover_accounts` is set to true, this is needed so
     /// that `bip44_discovered` in [`AccountInfo`] could be initialized correctly without needing the first sync.
@@ -67,9 +99,10 @@ impl Account {
         master_blinding: Option<MasterBlindingKey>,
         store: Store,
         account_num: u32,
+        seed_type: SeedType,
         discovered: bool,
     ) -> Result<Self, Error> {
-        let (script_type, path) = get_account_derivation(account_num, network.id())?;
+        let (script_type, path) = get_account_derivation(account_num, network.id(), seed_type)?;
 
         let xprv = master_xprv.derive_priv(&crate::EC, &path)?;
         let xpub = ExtendedPubKey::from_private(&crate::EC, &xprv);
@@ -77,6 +110,28 @@ impl Account {
         // cache internal/external chains
         let chains = [xpub.ckd_pub(&crate::EC, 0.into())?, xpub.ckd_pub(&crate::EC, 1.into())?];
 
+        let multisig = match script_type {
+            ScriptType::P2wsh => {
+                let store_read = store.read()?;
+                let opt = store_read
+                    .get_account_multisig(account_num)
+                    .ok_or_else(|| Error::InvalidMultisig("missing cosigners".into()))?;
+                Some(Multisig::new(opt, &xpub, master_xprv.fingerprint(&crate::EC), &path)?)
+            }
+            _ => None,
+        };
+
+        let policy = match script_type {
+            ScriptType::P2wshMiniscript => {
+                let store_read = store.read()?;
+                let opt = store_read
+                    .get_account_policy(account_num)
+                    .ok_or_else(|| Error::InvalidPolicy("missing descriptor".into()))?;
+                Some(Policy::new(opt, &xpub)?)
+            }
+            _ => None,
+        };
+
         store.write().unwrap().make_account(account_num);
 
         info!("initialized account #{} path={} type={:?}", account_num, path, script_type);
@@ -90,6 +145,9 @@ impl Account {
             chains,
             store,
             master_blinding,
+            multisig,
+            policy,
+            balance_history: Mutex::new(HashMap::new()),
             // currently unused, but seems useful to have around
             _path: path,
             discovered,
@@ -110,6 +168,8 @@ impl Account {
             required_ca: 0,
             receiving_id: "".to_string(),
             bip44_discovered: self.has_transactions(),
+            multisig: self.multisig.as_ref().map(|m| m.opt().clone()),
+            policy: self.policy.as_ref().map(|p| p.opt().clone()),
         })
     }
 
@@ -135,6 +195,14 @@ impl Account {
     }
 
     pub fn derive_address(&self, is_change: bool, index: u32) -> Result<BEAddress, Error> {
+        if let Some(multisig) = &self.multisig {
+            let network = self.network.id().get_bitcoin_network().expect("bitcoin only");
+            return Ok(BEAddress::Bitcoin(multisig.address(is_change, index, network)?));
+        }
+        if let Some(policy) = &self.policy {
+            let network = self.network.id().get_bitcoin_network().expect("bitcoin only");
+            return Ok(BEAddress::Bitcoin(policy.address(is_change, index, network)?));
+        }
         derive_address(
             &self.chains[is_change as usize],
             index,
@@ -158,12 +226,255 @@ impl Account {
         })
     }
 
+    /// The address `get_next_address` would return, without reserving it
+    pub fn peek_next_address(&self) -> Result<AddressPointer, Error> {
+        let pointer = self.store.read()?.account_cache(self.account_num)?.indexes.external + 1;
+        let address = self.derive_address(false, pointer)?.to_string();
+        Ok(AddressPointer {
+            address,
+            pointer,
+        })
+    }
+
+    /// Reserve `address` if it is the one returned by `peek_next_address`, so that the next
+    /// receive address is a new one
+    pub fn reserve_address(&self, address: &str) -> Result<(), Error> {
+        let store = &mut self.store.write()?;
+        let acc_store = store.account_cache_mut(self.account_num)?;
+        let pointer = acc_store.indexes.external + 1;
+        if self.derive_address(false, pointer)?.to_string() == address {
+            acc_store.indexes.external = pointer;
+        }
+        Ok(())
+    }
+
+    /// The spending conditions of the outputs of the account, for fee estimation
+    pub fn input_shape(&self) -> InputShape {
+        match (&self.multisig, &self.policy) {
+            (Some(multisig), _) => multisig.shape(),
+            (_, Some(policy)) => policy.shape(),
+            _ => self.script_type.into(),
+        }
+    }
+
+    /// The timelocks our inputs must set to be spendable
+    pub fn timelocks(&self) -> Timelocks {
+        self.policy.as_ref().map_or_else(Timelocks::default, Policy::timelocks)
+    }
+
+    /// Whether a coin confirmed at `height`, if any, can be spent in the next block
+    fn is_spendable(&self, height: Option<u32>, tip_height: u32) -> bool {
+        self.policy.as_ref().map_or(true, |p| p.is_spendable(height, tip_height))
+    }
+
+    /// Fail for the operations needing the single key of an address
+    fn ensure_single_sig(&self, operation: &str) -> Result<(), Error> {
+        if self.multisig.is_some() || self.policy.is_some() {
+            return Err(Error::Generic(format!(
+                "{} is not supported on multisig and miniscript subaccounts",
+                operation
+            )));
+        }
+        Ok(())
+    }
+
+    /// The confirmed coins of a miniscript subaccount whose earliest timelock expires within
+    /// `opt.within_blocks`, or already expired. Once expired, the coins can be spent through the
+    /// timelocked path, for instance by an heir, unless they are moved before.
+    pub fn expiring_utxos(
+        &self,
+        opt: &GetExpiringUtxosOpt,
+    ) -> Result<GetExpiringUtxosResult, Error> {
+        let policy = self.policy.as_ref().ok_or_else(|| {
+            Error::Generic("only miniscript subaccounts have timelocked coins".into())
+        })?;
+        let tip_height = self.store.read()?.cache.tip.0;
+        let mut utxos = vec![];
+        for (outpoint, info) in self.utxos(0, false)? {
+            let (height, expiry_height) = match info.height {
+                Some(height) => match policy.expiry_height(height) {
+                    Some(expiry_height) => (height, expiry_height),
+                    None => continue,
+                },
+                // the relative timelocks of unconfirmed coins did not start yet
+                None => continue,
+            };
+            let blocks_left = expiry_height as i64 - tip_height as i64;
+            if blocks_left > opt.within_blocks as i64 {
+                continue;
+            }
+            let outpoint = match outpoint {
+                BEOutPoint::Bitcoin(outpoint) => outpoint,
+                BEOutPoint::Elements(_) => {
+                    return Err(Error::Generic("miniscript is not supported on Liquid".into()))
+                }
+            };
+            utxos.push(ExpiringUtxo {
+                txhash: outpoint.txid.to_string(),
+                pt_idx: outpoint.vout,
+                satoshi: info.value,
+                block_height: height,
+                expiry_height,
+                blocks_left,
+            });
+        }
+        utxos.sort_by_key(|u| (u.expiry_height, u.txhash.clone(), u.pt_idx));
+        Ok(GetExpiringUtxosResult {
+            tip_height,
+            utxos,
+        })
+    }
+
+    /// Sign a message with the key of one of the addresses of the account
+    pub fn sign_message(&self, opt: &SignMessageOpt) -> Result<SignMessageResult, Error> {
+        if self.network.liquid {
+            return Err(Error::Generic("message signing is not supported on Liquid".into()));
+        }
+        self.ensure_single_sig("message signing")?;
+        let address =
+            bitcoin::Address::from_str(&opt.address).map_err(|_| Error::InvalidAddress)?;
+        let path = {
+            let store = self.store.read()?;
+            let acc_store = store.account_cache(self.account_num)?;
+            acc_store
+                .paths
+                .get(&address.script_pubkey().into_be())
+                .cloned()
+                .ok_or_else(|| Error::AddressNotFound(opt.address.clone()))?
+        };
+        let xprv = self.xprv.derive_priv(&crate::EC, &path)?;
+        let signature =
+            message::sign(&xprv.private_key, self.script_type, &opt.message, opt.format)?;
+        Ok(SignMessageResult {
+            signature,
+            format: opt.format,
+        })
+    }
+
+    /// A proof that the account controls its utxos, or the selected ones, see [`reserves`]
+    pub fn proof_of_reserves(
+        &self,
+        opt: &ProofOfReservesOpt,
+    ) -> Result<ProofOfReservesResult, Error> {
+        if self.network.liquid {
+            return Err(Error::Generic("proof of reserves is not supported on Liquid".into()));
+        }
+        self.ensure_single_sig("proof of reserves")?;
+        let mut utxos = self.utxos(opt.num_confs, false)?;
+        if let Some(selected) = &opt.utxos {
+            let mut outpoints = vec![];
+            for s in selected {
+                let outpoint =
+                    BEOutPoint::new_bitcoin(bitcoin::Txid::from_hex(&s.txhash)?, s.pt_idx);
+                if !utxos.iter().any(|(o, _)| *o == outpoint) {
+                    return Err(Error::Generic(format!(
+                        "utxo {}:{} not found",
+                        s.txhash, s.pt_idx
+                    )));
+                }
+                outpoints.push(outpoint);
+            }
+            utxos.retain(|(outpoint, _)| outpoints.contains(outpoint));
+        }
+        if utxos.is_empty() {
+            return Err(Error::InsufficientFunds);
+        }
+
+        let store = self.store.read()?;
+        let acc_store = store.account_cache(self.account_num)?;
+        let mut spent = vec![];
+        let mut paths = vec![];
+        for (outpoint, info) in utxos.iter() {
+            let outpoint = match outpoint {
+                BEOutPoint::Bitcoin(outpoint) => *outpoint,
+                BEOutPoint::Elements(_) => unreachable!("bitcoin only"),
+            };
+            let txout = bitcoin::TxOut {
+                value: info.value,
+                script_pubkey: info.script.clone().into_bitcoin(),
+            };
+            spent.push((outpoint, txout));
+            paths.push(info.path.clone());
+        }
+        let tx = reserves::proof_tx(&opt.message, &spent);
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx.clone())
+            .map_err(|e| Error::Generic(e.to_string()))?;
+
+        // the challenge input is signed by the key of the first utxo
+        let challenge = (
+            reserves::challenge_txin(&opt.message).previous_output,
+            reserves::challenge_txout(&spent[0].1),
+        );
+        spent.insert(0, challenge);
+        paths.insert(0, paths[0].clone());
+        for (index, ((outpoint, txout), path)) in spent.iter().zip(paths.iter()).enumerate() {
+            let (script_sig, witness) =
+                internal_sign_bitcoin(&tx, index, &self.xprv, path, txout.value, self.script_type)?;
+            let input = &mut psbt.inputs[index];
+            if self.script_type.is_segwit() || index == 0 {
+                input.witness_utxo = Some(txout.clone());
+            } else {
+                input.non_witness_utxo = Some(acc_store.get_bitcoin_tx(&outpoint.txid)?);
+            }
+            if !script_sig.is_empty() {
+                input.final_script_sig = Some(script_sig);
+            }
+            if !witness.is_empty() {
+                input.final_script_witness = Some(witness);
+            }
+        }
+        info!("proof of reserves of account {} with {} utxos", self.account_num, utxos.len());
+
+        Ok(ProofOfReservesResult {
+            psbt: base64::encode(&bitcoin::consensus::serialize(&psbt)),
+            satoshi: tx.output[0].value,
+        })
+    }
+
+    /// The transactions of the account matching the filters of `opt`. Only the height, block
+    /// time, memo and scripts of the transactions are looked at before the balance changes, and
+    /// the transaction details are built only for the requested page.
     pub fn list_tx(&self, opt: &GetTransactionsOpt) -> Result<Vec<TransactionMeta>, Error> {
         let store = self.store.read()?;
         let acc_store = store.account_cache(self.account_num)?;
 
         let tip_height = store.cache.tip.0;
         let num_confs = opt.num_confs.unwrap_or(0);
+        let in_range = |value: u64, min: Option<u64>, max: Option<u64>| {
+            min.map_or(true, |min| value >= min) && max.map_or(true, |max| value <= max)
+        };
+        let has_height_range = opt.min_height.is_some() || opt.max_height.is_some();
+        let min_height = opt.min_height.map(u64::from);
+        let max_height = opt.max_height.map(u64::from);
+
+        // unconfirmed transactions sort as the highest
+        let cursor = match &opt.cursor {
+            Some(cursor) => {
+                let cursor = TxCursor::from_str(cursor)?;
+                let txid = BETxid::from_hex(&cursor.txid, self.network.id())?;
+                Some((cursor.height.unwrap_or(std::u32::MAX), txid))
+            }
+            None => None,
+        };
+        let address_script = match &opt.address {
+            Some(address) => Some(match self.network.id() {
+                NetworkId::Bitcoin(_) => bitcoin::Address::from_str(address)
+                    .map_err(|_| Error::InvalidAddress)?
+                    .script_pubkey()
+                    .into_be(),
+                NetworkId::Elements(_) => elements::Address::from_str(address)
+                    .map_err(|_| Error::InvalidAddress)?
+                    .script_pubkey()
+                    .into_be(),
+            }),
+            None => None,
+        };
+        let amount_asset = opt
+            .asset_id
+            .clone()
+            .or_else(|| self.network.policy_asset.clone())
+            .unwrap_or_else(|| "btc".to_string());
+        let memo_search = opt.memo.as_ref().map(|m| m.to_lowercase());
 
         let mut txs = vec![];
         let mut my_txids: Vec<(&BETxid, &Option<u32>)> = acc_store
@@ -172,6 +483,20 @@ impl Account {
             .filter(|(_, height)| {
                 num_confs <= height.map_or(0, |height| (tip_height + 1).saturating_sub(height))
             })
+            .filter(|(_, height)| match height {
+                Some(height) => in_range(*height as u64, min_height, max_height),
+                None => !has_height_range,
+            })
+            .filter(|(txid, height)| match &cursor {
+                Some((cursor_height, cursor_txid)) => {
+                    let key = (height.unwrap_or(std::u32::MAX), *txid);
+                    match opt.sort {
+                        TxSort::Newest => key < (*cursor_height, cursor_txid),
+                        TxSort::Oldest => key > (*cursor_height, cursor_txid),
+                    }
+                }
+                None => true,
+            })
             .collect();
         my_txids.sort_by(|a, b| {
             let height_cmp = b.1.unwrap_or(std::u32::MAX).cmp(&a.1.unwrap_or(std::u32::MAX));
@@ -180,18 +505,78 @@ impl Account {
                 h @ _ => h,
             }
         });
+        if opt.sort == TxSort::Oldest {
+            my_txids.reverse();
+        }
 
-        for (tx_id, height) in my_txids.iter().skip(opt.first).take(opt.count) {
+        let mut skipped = 0;
+        for (tx_id, height) in my_txids.iter() {
+            if txs.len() >= opt.count {
+                break;
+            }
             trace!("tx_id {}", tx_id);
 
+            let header = height.map(|h| store.cache.headers.get(&h)).flatten();
+            trace!("tx_id {} header {:?}", tx_id, header);
+            let timestamp = header.map(|h| 1_000_000u64.saturating_mul(h.time() as u64)); // in microseconds
+            if opt.min_ts.is_some() || opt.max_ts.is_some() {
+                if !timestamp.map_or(false, |ts| in_range(ts, opt.min_ts, opt.max_ts)) {
+                    continue;
+                }
+            }
+
+            let memo = store.get_memo(tx_id).cloned();
+            if let Some(search) = &memo_search {
+                if !memo.as_ref().map_or(false, |m| m.to_lowercase().contains(search)) {
+                    continue;
+                }
+            }
+
             let txe = acc_store
                 .all_txs
                 .get(*tx_id)
                 .ok_or_else(fn_err(&format!("list_tx no tx {}", tx_id)))?;
             let tx = &txe.tx;
 
-            let header = height.map(|h| store.cache.headers.get(&h)).flatten();
-            trace!("tx_id {} header {:?}", tx_id, header);
+            if let Some(script) = &address_script {
+                let pays = (0..tx.output_len() as u32).any(|i| tx.output_script(i) == *script);
+                let spends = || {
+                    tx.previous_outputs().iter().any(|outpoint| {
+                        acc_store.all_txs.get_previous_output_script_pubkey(outpoint).as_ref()
+                            == Some(script)
+                    })
+                };
+                if !pays && !spends() {
+                    continue;
+                }
+            }
+
+            let satoshi =
+                tx.my_balance_changes(&acc_store.all_txs, &acc_store.paths, &acc_store.unblinded);
+            trace!("tx_id {} balances {:?}", tx_id, satoshi);
+            if let Some(asset_id) = &opt.asset_id {
+                if satoshi.get(asset_id).map_or(true, |v| *v == 0) {
+                    continue;
+                }
+            }
+            if opt.min_satoshi.is_some() || opt.max_satoshi.is_some() {
+                let amount = satoshi.get(&amount_asset).map_or(0, |v| v.abs() as u64);
+                if !in_range(amount, opt.min_satoshi, opt.max_satoshi) {
+                    continue;
+                }
+            }
+
+            let (type_, user_signed) = self.tx_type(tx, &satoshi, acc_store);
+            if let Some(types) = &opt.types {
+                if !types.iter().any(|t| t == type_) {
+                    continue;
+                }
+            }
+
+            if skipped < opt.first {
+                skipped += 1;
+                continue;
+            }
             let mut addressees = vec![];
             for i in 0..tx.output_len() as u32 {
                 let script = tx.output_script(i);
@@ -205,8 +590,6 @@ impl Account {
                     });
                 }
             }
-            let memo = store.get_memo(tx_id).cloned();
-
             let create_transaction = CreateTransaction {
                 addressees,
                 memo,
@@ -220,31 +603,7 @@ impl Account {
             )?;
             trace!("tx_id {} fee {}", tx_id, fee);
 
-            let satoshi =
-                tx.my_balance_changes(&acc_store.all_txs, &acc_store.paths, &acc_store.unblinded);
-            trace!("tx_id {} balances {:?}", tx_id, satoshi);
-
-            // We define an incoming txs if there are more assets received by the wallet than spent
-            // when they are equal it's an outgoing tx because the special asset liquid BTC
-            // is negative due to the fee being paid
-            // TODO how do we label issuance tx?
-            let negatives = satoshi.iter().filter(|(_, v)| **v < 0).count();
-            let positives = satoshi.iter().filter(|(_, v)| **v > 0).count();
-            let (type_, user_signed) = if satoshi.is_empty() && self.network.liquid {
-                ("unblindable", false)
-            } else if tx.is_redeposit(&acc_store.paths, &acc_store.all_txs) {
-                ("redeposit", true)
-            } else if positives > negatives {
-                ("incoming", false)
-            } else {
-                ("outgoing", true)
-            };
-
-            let spv_verified = if self.network.spv_enabled.unwrap_or(false) {
-                store.spv_verification_status(self.num(), tx_id)
-            } else {
-                SPVVerifyTxResult::Disabled
-            };
+            let spv_verified = self.spv_verification_status(&store, tx_id);
 
             trace!(
                 "tx_id {} type {} user_signed {} spv_verified {:?}",
@@ -257,7 +616,7 @@ impl Account {
             let tx_meta = TransactionMeta::new(
                 txe.clone(),
                 **height,
-                header.map(|h| 1_000_000u64.saturating_mul(h.time() as u64)), // in microseconds
+                timestamp,
                 satoshi,
                 fee,
                 self.network.id().get_bitcoin_network().unwrap_or(bitcoin::Network::Bitcoin),
@@ -274,6 +633,119 @@ impl Account {
         Ok(txs)
     }
 
+    /// The confirmed balance over time, recomputed only when the confirmed transactions of the
+    /// account, their block headers, or the known scripts and unblinded outputs change
+    pub fn balance_history(&self, opt: &GetBalanceHistoryOpt) -> Result<BalanceHistory, Error> {
+        let store = self.store.read()?;
+        let acc_store = store.account_cache(self.account_num)?;
+        let block_time = |height: u32| store.cache.headers.get(&height).map(|h| h.time());
+
+        let mut confirmed: Vec<(u32, &BETxid)> = acc_store
+            .heights
+            .iter()
+            .filter_map(|(txid, height)| height.map(|height| (height, txid)))
+            .collect();
+        confirmed.sort();
+
+        let mut hasher = DefaultHasher::new();
+        (acc_store.paths.len(), acc_store.unblinded.len()).hash(&mut hasher);
+        for (height, txid) in confirmed.iter() {
+            (height, txid, block_time(*height)).hash(&mut hasher);
+        }
+        let fingerprint = hasher.finish();
+        if let Some((cached, history)) = self.balance_history.lock().unwrap().get(&opt.interval) {
+            if *cached == fingerprint {
+                return Ok(history.clone());
+            }
+        }
+
+        let mut changes: Vec<BlockChanges> = vec![];
+        for (height, txid) in confirmed {
+            let txe = acc_store
+                .all_txs
+                .get(txid)
+                .ok_or_else(fn_err(&format!("balance_history no tx {}", txid)))?;
+            let satoshi = txe.tx.my_balance_changes(
+                &acc_store.all_txs,
+                &acc_store.paths,
+                &acc_store.unblinded,
+            );
+            match changes.last_mut() {
+                Some(last) if last.height == height => {
+                    for (asset, value) in satoshi {
+                        *last.satoshi.entry(asset).or_default() += value;
+                    }
+                }
+                _ => changes.push(BlockChanges {
+                    height,
+                    time: block_time(height),
+                    satoshi,
+                }),
+            }
+        }
+        let history = BalanceHistory {
+            interval: opt.interval,
+            points: history::balance_series(&changes, opt.interval),
+        };
+        self.balance_history.lock().unwrap().insert(opt.interval, (fingerprint, history.clone()));
+        Ok(history)
+    }
+
+    /// The type of the transaction from the point of view of this account, and whether it was
+    /// signed by the user
+    fn tx_type(
+        &self,
+        tx: &BETransaction,
+        satoshi: &Balances,
+        acc_store: &RawAccountCache,
+    ) -> (&'static str, bool) {
+        // We define an incoming txs if there are more assets received by the wallet than spent
+        // when they are equal it's an outgoing tx because the special asset liquid BTC
+        // is negative due to the fee being paid
+        // TODO how do we label issuance tx?
+        let negatives = satoshi.iter().filter(|(_, v)| **v < 0).count();
+        let positives = satoshi.iter().filter(|(_, v)| **v > 0).count();
+        if satoshi.is_empty() && self.network.liquid {
+            ("unblindable", false)
+        } else if tx.is_redeposit(&acc_store.paths, &acc_store.all_txs) {
+            ("redeposit", true)
+        } else if positives > negatives {
+            ("incoming", false)
+        } else {
+            ("outgoing", true)
+        }
+    }
+
+    fn spv_verification_status(&self, store: &StoreMeta, txid: &BETxid) -> SPVVerifyTxResult {
+        if self.network.spv_enabled.unwrap_or(false) {
+            store.spv_verification_status(self.num(), txid)
+        } else {
+            SPVVerifyTxResult::Disabled
+        }
+    }
+
+    /// Summary of the transaction for the notifications, `None` if it is not in the account
+    /// history
+    pub fn tx_notification(&self, txid: &BETxid) -> Result<Option<TransactionNotification>, Error> {
+        let store = self.store.read()?;
+        let acc_store = store.account_cache(self.account_num)?;
+        let (height, txe) = match (acc_store.heights.get(txid), acc_store.all_txs.get(txid)) {
+            (Some(height), Some(txe)) => (height, txe),
+            _ => return Ok(None),
+        };
+        let satoshi =
+            txe.tx.my_balance_changes(&acc_store.all_txs, &acc_store.paths, &acc_store.unblinded);
+        let (type_, _) = self.tx_type(&txe.tx, &satoshi, acc_store);
+        Ok(Some(TransactionNotification {
+            txhash: txid.to_hex(),
+            subaccounts: vec![self.account_num],
+            type_: type_.to_string(),
+            satoshi,
+            block_height: height.unwrap_or(0),
+            spv_verified: self.spv_verification_status(&store, txid).to_string(),
+        }))
+    }
+
     pub fn utxos(&self, num_confs: u32, confidential_utxos_only: bool) -> Result<Utxos, Error> {
         info!("start utxos");
         let store_read = self.store.read()?;
@@ -433,12 +905,23 @@ impl Account {
     pub fn sign(&self, request: &TransactionMeta) -> Result<TransactionMeta, Error> {
         info!("sign");
 
+        if request.is_sweep {
+            // the inputs of a sweep are not ours, they have been signed with the swept key
+            return Ok(request.clone());
+        }
+
         let be_tx =
             BETransaction::deserialize(&Vec::<u8>::from_hex(&request.hex)?, self.network.id())?;
         let store_read = self.store.read()?;
         let acc_store = store_read.account_cache(self.account_num)?;
 
         let mut betx: TransactionMeta = match be_tx {
+            BETransaction::Bitcoin(tx) if self.multisig.is_some() => {
+                self.sign_multisig(tx, request.psbt.as_deref(), acc_store)?
+            }
+            BETransaction::Bitcoin(tx) if self.policy.is_some() => {
+                self.sign_policy(tx, acc_store)?
+            }
             BETransaction::Bitcoin(tx) => {
                 let mut out_tx = tx.clone();
 
@@ -464,7 +947,7 @@ impl Account {
                         &derivation_path,
                         out.value,
                         self.script_type,
-                    );
+                    )?;
 
                     out_tx.input[i].script_sig = script_sig;
                     out_tx.input[i].witness = witness;
@@ -499,7 +982,7 @@ impl Account {
                         &derivation_path,
                         out.value,
                         self.script_type,
-                    );
+                    )?;
 
                     tx.input[i].script_sig = script_sig;
                     tx.input[i].witness.script_witness = witness;
@@ -545,6 +1028,218 @@ impl Account {
         Ok(betx)
     }
 
+    /// Check the payjoin `proposal` of the receiver for the `original` of `request` and sign
+    /// our inputs, `paths` being the ones returned by `payjoin_original`
+    pub fn sign_payjoin(
+        &self,
+        request: &TransactionMeta,
+        original: &payjoin::Original,
+        paths: &[DerivationPath],
+        proposal: &PartiallySignedTransaction,
+        opt: &PayjoinOpt,
+    ) -> Result<TransactionMeta, Error> {
+        let contribution = original.check_proposal(proposal, opt)?;
+
+        let mut tx = payjoin::extract(proposal);
+        let unsigned = proposal.global.unsigned_tx.clone();
+        for (i, txin) in unsigned.input.iter().enumerate() {
+            let index = match original
+                .psbt
+                .global
+                .unsigned_tx
+                .input
+                .iter()
+                .position(|o| o.previous_output == txin.previous_output)
+            {
+                Some(index) => index,
+                None => continue,
+            };
+            let value = original.psbt.inputs[index]
+                .witness_utxo
+                .as_ref()
+                .map(|o| o.value)
+                .or_else(|| {
+                    let tx = original.psbt.inputs[index].non_witness_utxo.as_ref()?;
+                    tx.output.get(txin.previous_output.vout as usize).map(|o| o.value)
+                })
+                .expect("set in the original");
+            let (script_sig, witness) = internal_sign_bitcoin(
+                &unsigned,
+                i,
+                &self.xprv,
+                &paths[index],
+                value,
+                self.script_type,
+            )?;
+            tx.input[i].script_sig = script_sig;
+            tx.input[i].witness = witness;
+        }
+        original.check_fee_rate(&tx, contribution.fee)?;
+        info!(
+            "payjoin proposal {} accepted, additional fee {}",
+            tx.txid(),
+            contribution.additional_fee
+        );
+
+        let signed: TransactionMeta = BETransaction::Bitcoin(tx).into();
+        let mut result = request.clone();
+        result.hex = signed.hex;
+        result.txid = signed.txid;
+        result.weight = signed.weight;
+        result.vsize = signed.vsize;
+        result.size = signed.size;
+        result.fee = contribution.fee;
+        if let Some(satoshi) = result.satoshi.get_mut("btc") {
+            *satoshi += contribution.additional_fee as i64;
+        }
+        Ok(result)
+    }
+
+    /// The original psbt of the signed `request`, with the paths of its inputs
+    pub fn payjoin_original(
+        &self,
+        request: &TransactionMeta,
+    ) -> Result<(payjoin::Original, Vec<DerivationPath>), Error> {
+        if self.network.liquid {
+            return Err(Error::Generic("payjoin is not supported on Liquid".into()));
+        }
+        self.ensure_single_sig("payjoin")?;
+        let addressees = request.create_transaction.as_ref().map(|c| &c.addressees[..]);
+        let payment_script = match addressees {
+            Some([addressee]) => bitcoin::Address::from_str(&addressee.address)
+                .map_err(|_| Error::InvalidAddress)?
+                .script_pubkey(),
+            _ => return Err(Error::Generic("payjoin requires a single recipient".into())),
+        };
+        let mut tx: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&Vec::<u8>::from_hex(&request.hex)?)?;
+        let finalized: Vec<_> = tx
+            .input
+            .iter_mut()
+            .map(|i| {
+                (
+                    std::mem::replace(&mut i.script_sig, bitcoin::Script::new()),
+                    std::mem::take(&mut i.witness),
+                )
+            })
+            .collect();
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx.clone())
+            .map_err(|e| Error::Generic(e.to_string()))?;
+
+        let store = self.store.read()?;
+        let acc_store = store.account_cache(self.account_num)?;
+        let mut paths = vec![];
+        for (i, (script_sig, witness)) in finalized.into_iter().enumerate() {
+            let outpoint = tx.input[i].previous_output;
+            let prev_tx = acc_store.get_bitcoin_tx(&outpoint.txid)?;
+            let txout = prev_tx.output[outpoint.vout as usize].clone();
+            let path = acc_store
+                .paths
+                .get(&txout.script_pubkey.clone().into())
+                .ok_or_else(|| Error::Generic("can't find derivation path".into()))?;
+            paths.push(path.clone());
+            let input = &mut psbt.inputs[i];
+            if self.script_type.is_segwit() {
+                input.witness_utxo = Some(txout);
+            } else {
+                input.non_witness_utxo = Some(prev_tx);
+            }
+            if script_sig.is_empty() && witness.is_empty() {
+                return Err(Error::Generic("payjoin requires a signed transaction".into()));
+            }
+            if !script_sig.is_empty() {
+                input.final_script_sig = Some(script_sig);
+            }
+            if !witness.is_empty() {
+                input.final_script_witness = Some(witness);
+            }
+        }
+        // our change, which pays the fee of the inputs of the receiver
+        let fee_output_index = tx.output.iter().position(|o| {
+            o.script_pubkey != payment_script
+                && acc_store.paths.contains_key(&o.script_pubkey.clone().into())
+        });
+
+        let original = payjoin::Original {
+            psbt,
+            fee_output_index,
+            payment_script,
+            script_type: self.script_type,
+        };
+        Ok((original, paths))
+    }
+
+    /// Add our signatures to the psbt of `tx`, completing the inputs with enough signatures
+    fn sign_multisig(
+        &self,
+        tx: bitcoin::Transaction,
+        psbt: Option<&str>,
+        acc_store: &RawAccountCache,
+    ) -> Result<TransactionMeta, Error> {
+        let multisig = self.multisig.as_ref().expect("multisig account");
+        let mut psbt = match psbt {
+            Some(psbt) => {
+                let psbt = multisig::psbt_from_base64(psbt)?;
+                if psbt.global.unsigned_tx.txid() != tx.txid() {
+                    return Err(Error::Generic("the psbt is of another transaction".into()));
+                }
+                psbt
+            }
+            None => PartiallySignedTransaction::from_unsigned_tx(tx.clone())
+                .map_err(|e| Error::Generic(e.to_string()))?,
+        };
+        for (i, txin) in tx.input.iter().enumerate() {
+            let prev_output = txin.previous_output;
+            let prev_tx = acc_store.get_bitcoin_tx(&prev_output.txid)?;
+            let out = prev_tx.output[prev_output.vout as usize].clone();
+            let derivation_path: DerivationPath = acc_store
+                .paths
+                .get(&out.script_pubkey.clone().into())
+                .ok_or_else(|| Error::Generic("can't find derivation path".into()))?
+                .clone();
+            if psbt.inputs[i].final_script_witness.is_some() {
+                continue;
+            }
+            if psbt.inputs[i].witness_script.is_none() {
+                multisig.fill_input(&mut psbt, i, out, &derivation_path)?;
+            }
+            multisig.sign_input(&mut psbt, i, &self.xprv, &derivation_path)?;
+        }
+        let complete = multisig::finalize(&mut psbt);
+        info!("multisig tx {} signed, complete: {}", tx.txid(), complete);
+        let tx = if complete {
+            psbt.clone().extract_tx()
+        } else {
+            tx
+        };
+        let mut betx: TransactionMeta = BETransaction::Bitcoin(tx).into();
+        betx.psbt = Some(multisig::psbt_to_base64(&psbt));
+        Ok(betx)
+    }
+
+    /// Satisfy the miniscript of every input of `tx` with our signature
+    fn sign_policy(
+        &self,
+        mut tx: bitcoin::Transaction,
+        acc_store: &RawAccountCache,
+    ) -> Result<TransactionMeta, Error> {
+        let policy = self.policy.as_ref().expect("miniscript account");
+        for i in 0..tx.input.len() {
+            let prev_output = tx.input[i].previous_output;
+            let prev_tx = acc_store.get_bitcoin_tx(&prev_output.txid)?;
+            let out = &prev_tx.output[prev_output.vout as usize];
+            let derivation_path: DerivationPath = acc_store
+                .paths
+                .get(&out.script_pubkey.clone().into())
+                .ok_or_else(|| Error::Generic("can't find derivation path".into()))?
+                .clone();
+            tx.input[i].witness =
+                policy.sign_input(&tx, i, &self.xprv, &derivation_path, out.value)?;
+        }
+        info!("miniscript tx {} signed", tx.txid());
+        Ok(BETransaction::Bitcoin(tx).into())
+    }
+
     pub fn get_script_batch(&self, is_change: bool, batch: u32) -> Result<ScriptBatch, Error> {
         let store = self.store.read()?;
         let acc_store = store.account_cache(self.account_num)?;
@@ -608,24 +1303,36 @@ impl Account {
                     .all_txs
                     .get_previous_output_script_pubkey(outpoint)
                     .expect("prevout to be indexed");
-                let public_key = match acc_store.paths.get(&script) {
-                    Some(path) => self.xpub.derive_pub(&crate::EC, path)?,
+                let path = match acc_store.paths.get(&script) {
+                    Some(path) => path,
                     // We only need to check wallet-owned inputs
                     None => continue,
-                }
-                .public_key;
+                };
                 let value = acc_store
                     .all_txs
                     .get_previous_output_value(&outpoint, &acc_store.unblinded)
                     .expect("own prevout to have known value");
-                if let Err(err) = tx.verify_input_sig(
-                    &crate::EC,
-                    &mut hashcache,
-                    vin,
-                    &public_key,
-                    value,
-                    self.script_type,
-                ) {
+                let verified = match (&self.multisig, &self.policy, tx) {
+                    (Some(multisig), _, BETransaction::Bitcoin(tx)) => {
+                        multisig.verify_input(tx, vin, path, value)
+                    }
+                    (_, Some(policy), BETransaction::Bitcoin(tx)) => {
+                        policy.verify_input(tx, vin, path, value)
+                    }
+                    _ => {
+                        let public_key = self.xpub.derive_pub(&crate::EC, path)?.public_key;
+                        tx.verify_input_sig(
+                            &crate::EC,
+                            &mut hashcache,
+                            vin,
+                            &public_key,
+                            value,
+                            self.script_type,
+                        )
+                        .map_err(Into::into)
+                    }
+                };
+                if let Err(err) = verified {
                     warn!("tx {} verification failed: {:?}", txid, err);
                     acc_store.all_txs.remove(txid);
                     acc_store.heights.remove(txid);
@@ -661,21 +1368,56 @@ pub fn get_account_script_purpose(account_num: u32) -> Result<(ScriptType, u32),
         0 => (ScriptType::P2shP2wpkh, 49),
         1 => (ScriptType::P2wpkh, 84),
         2 => (ScriptType::P2pkh, 44),
+        5 => (ScriptType::P2wsh, 48),
+        6 => (ScriptType::P2wshMiniscript, 87),
         _ => return Err(Error::InvalidSubaccount(account_num)),
     })
 }
 
+/// Our account xpub of the multisig or miniscript subaccount `account_num`, to share with the
+/// cosigners
+pub fn get_multisig_xpub(
+    master_xprv: &ExtendedPrivKey,
+    account_num: u32,
+    network_id: NetworkId,
+) -> Result<(ExtendedPubKey, DerivationPath), Error> {
+    // the Electrum accounts are not multisig, any seed type gives the same paths
+    match get_account_derivation(account_num, network_id, SeedType::Bip39)? {
+        (ScriptType::P2wsh, path) | (ScriptType::P2wshMiniscript, path) => {
+            let xprv = master_xprv.derive_priv(&crate::EC, &path)?;
+            Ok((ExtendedPubKey::from_private(&crate::EC, &xprv), path))
+        }
+        _ => Err(Error::InvalidSubaccount(account_num)),
+    }
+}
+
 fn get_account_derivation(
     account_num: u32,
     network_id: NetworkId,
+    seed_type: SeedType,
 ) -> Result<(ScriptType, DerivationPath), Error> {
+    match (account_num, seed_type) {
+        (ELECTRUM_SEGWIT_ACCOUNT, SeedType::ElectrumSegwit) => {
+            return Ok((ScriptType::P2wpkh, "m/0'".parse().unwrap()))
+        }
+        (ELECTRUM_STANDARD_ACCOUNT, SeedType::ElectrumStandard) => {
+            return Ok((ScriptType::P2pkh, "m".parse().unwrap()))
+        }
+        _ => (),
+    }
     let coin_type = get_coin_type(network_id);
     let (script_type, purpose) = get_account_script_purpose(account_num)?;
     let bip32_account_num = account_num / NUM_RESERVED_ACCOUNT_TYPES;
 
     // BIP44: m / purpose' / coin_type' / account' / change / address_index
-    let path: DerivationPath =
-        format!("m/{}'/{}'/{}'", purpose, coin_type, bip32_account_num).parse().unwrap();
+    // BIP48: m / purpose' / coin_type' / account' / script_type' / change / address_index
+    // with script_type 2' for P2WSH
+    let path: DerivationPath = match script_type {
+        ScriptType::P2wsh => format!("m/{}'/{}'/{}'/2'", purpose, coin_type, bip32_account_num),
+        _ => format!("m/{}'/{}'/{}'", purpose, coin_type, bip32_account_num),
+    }
+    .parse()
+    .unwrap();
 
     info!("derivation path for account {}: {}", account_num, path);
 
@@ -710,7 +1452,7 @@ fn derive_address(
     let child_key = xpub.ckd_pub(&crate::EC, index.into())?;
     match network_id {
         NetworkId::Bitcoin(network) => {
-            let address = bitcoin_address(&child_key.public_key, script_type, network);
+            let address = bitcoin_address(&child_key.public_key, script_type, network)?;
             Ok(BEAddress::Bitcoin(address))
         }
         NetworkId::Elements(network) => {
@@ -719,7 +1461,7 @@ fn derive_address(
                 master_blinding.expect("we are in elements but master blinding is None"),
                 script_type,
                 network,
-            );
+            )?;
             Ok(BEAddress::Elements(address))
         }
     }
@@ -729,12 +1471,20 @@ fn bitcoin_address(
     public_key: &PublicKey,
     script_type: ScriptType,
     net: bitcoin::Network,
-) -> bitcoin::Address {
+) -> Result<bitcoin::Address, Error> {
     use bitcoin::Address;
     match script_type {
-        ScriptType::P2shP2wpkh => Address::p2shwpkh(public_key, net).expect("no compressed keys"),
-        ScriptType::P2wpkh => Address::p2wpkh(public_key, net).expect("no compressed keys"),
-        ScriptType::P2pkh => Address::p2pkh(public_key, net),
+        ScriptType::P2shP2wpkh => {
+            Ok(Address::p2shwpkh(public_key, net).expect("no compressed keys"))
+        }
+        ScriptType::P2wpkh => Ok(Address::p2wpkh(public_key, net).expect("no compressed keys")),
+        ScriptType::P2pkh => Ok(Address::p2pkh(public_key, net)),
+        ScriptType::P2wsh => {
+            Err(Error::Generic("multisig addresses are derived by Multisig".into()))
+        }
+        ScriptType::P2wshMiniscript => {
+            Err(Error::Generic("miniscript addresses are derived by Policy".into()))
+        }
     }
 }
 
@@ -743,17 +1493,22 @@ fn elements_address(
     master_blinding_key: &MasterBlindingKey,
     script_type: ScriptType,
     net: ElementsNetwork,
-) -> elements::Address {
+) -> Result<elements::Address, Error> {
     let addr_params = net.address_params();
     let address = match script_type {
         ScriptType::P2pkh => elements::Address::p2pkh(public_key, None, addr_params),
         ScriptType::P2shP2wpkh => elements::Address::p2shwpkh(public_key, None, addr_params),
         ScriptType::P2wpkh => elements::Address::p2wpkh(public_key, None, addr_params),
+        ScriptType::P2wsh | ScriptType::P2wshMiniscript => {
+            return Err(Error::Generic(
+                "multisig and miniscript are not supported on Liquid".into(),
+            ))
+        }
     };
     let script_pubkey = address.script_pubkey();
     let blinding_prv = asset_blinding_key_to_ec_private_key(master_blinding_key, &script_pubkey);
     let blinding_pub = ec_public_key_from_private_key(blinding_prv);
-    address.to_confidential(blinding_pub)
+    Ok(address.to_confidential(blinding_pub))
 }
 
 // Discover all the available accounts as per BIP 44:
@@ -761,48 +1516,50 @@ fn elements_address(
 pub fn discover_accounts(
     master_xprv: &ExtendedPrivKey,
     network_id: NetworkId,
-    electrum_url: &ElectrumUrl,
-    proxy: Option<&str>,
+    source: &dyn ChainSource,
     master_blinding: Option<&MasterBlindingKey>,
     known_accounts: &[u32],
 ) -> Result<Vec<u32>, Error> {
-    use electrum_client::ElectrumApi;
-
-    // build our own client so that the subscriptions are dropped at the end
-    let client = electrum_url.build_client(proxy)?;
-
     // the batch size is the effective gap limit for our purposes. in reality it is a lower bound.
     let gap_limit = BATCH_SIZE;
     let num_types = NUM_RESERVED_ACCOUNT_TYPES as usize;
     let mut discovered_accounts: Vec<u32> = vec![];
+    let mut checked_accounts = 0;
 
     for script_type in ScriptType::types() {
         debug!("discovering script type {:?}", script_type);
-        'next_account: for account_num in (script_type.first_account_num()..).step_by(num_types) {
+        for account_num in (script_type.first_account_num()..).step_by(num_types) {
             if known_accounts.contains(&account_num) {
                 debug!("already known account_num: {}", account_num);
                 continue;
             }
+            request::check_cancelled()?;
+            request::progress("discover_accounts", checked_accounts, None);
+            checked_accounts += 1;
             debug!("account_num: {}", account_num);
-            let (_, path) = get_account_derivation(account_num, network_id).unwrap();
+            let (_, path) =
+                get_account_derivation(account_num, network_id, SeedType::Bip39).unwrap();
             let recv_xprv = master_xprv.derive_priv(&crate::EC, &path.child(0.into()))?;
             let recv_xpub = ExtendedPubKey::from_private(&crate::EC, &recv_xprv);
-            for child_code in 0..gap_limit {
-                let script = derive_address(
-                    &recv_xpub,
-                    child_code,
-                    *script_type,
-                    network_id,
-                    master_blinding,
-                )
-                .unwrap()
-                .script_pubkey();
+            let scripts: Vec<bitcoin::Script> = (0..gap_limit)
+                .map(|child_code| {
+                    derive_address(
+                        &recv_xpub,
+                        child_code,
+                        *script_type,
+                        network_id,
+                        master_blinding,
+                    )
+                    .unwrap()
+                    .script_pubkey()
+                    .into_bitcoin()
+                })
+                .collect();
 
-                if client.script_subscribe(&script.into_bitcoin())?.is_some() {
-                    debug!("found account {:?} #{}", script_type, account_num);
-                    discovered_accounts.push(account_num);
-                    continue 'next_account;
-                }
+            if source.scripts_history(&scripts)?.iter().any(|history| !history.is_empty()) {
+                debug!("found account {:?} #{}", script_type, account_num);
+                discovered_accounts.push(account_num);
+                continue;
             }
             debug!("no activity found for account {:?} #{}", script_type, account_num);
             break;
@@ -969,11 +1726,11 @@ pub fn create_tx(
             for address_amount in request.addressees.iter() {
                 if address_amount.satoshi <= DUST_VALUE {
                     match network.id() {
-                        NetworkId::Bitcoin(_) => return Err(Error::InvalidAmount),
+                        NetworkId::Bitcoin(_) => return Err(Error::AmountBelowDust),
                         NetworkId::Elements(_) => {
                             if address_amount.asset_id == network.policy_asset {
                                 // we apply dust rules for liquid bitcoin as elements do
-                                return Err(Error::InvalidAmount);
+                                return Err(Error::AmountBelowDust);
                             }
                         }
                     }
@@ -992,6 +1749,9 @@ pub fn create_tx(
     if request.confidential_utxos_only {
         utxos.retain(|(_, i)| i.confidential);
     }
+    // the coins of miniscript accounts may still be timelocked
+    let tip_height = account.store.read()?.cache.tip.0;
+    utxos.retain(|(_, i)| account.is_spendable(i.height, tip_height));
     info!("utxos len:{} utxos:{:?}", utxos.len(), utxos);
 
     if send_all {
@@ -1009,14 +1769,14 @@ pub fn create_tx(
         let to_send = if asset == network.policy_asset_id().ok() {
             let mut dummy_tx = BETransaction::new(network.id());
             for utxo in all_utxos.iter() {
-                dummy_tx.add_input(utxo.0.clone());
+                dummy_tx.add_input(utxo.0.clone(), account.timelocks());
             }
             let out = &request.addressees[0]; // safe because we checked we have exactly one recipient
             dummy_tx
                 .add_output(&out.address, out.satoshi, out.asset_id(), network.id())
                 .map_err(|_| Error::InvalidAddress)?;
             // estimating 2 satoshi more as estimating less would later result in InsufficientFunds
-            let estimated_fee = dummy_tx.estimated_fee(fee_rate, 0, account.script_type) + 2;
+            let estimated_fee = dummy_tx.estimated_fee(fee_rate, 0, account.input_shape())? + 2;
             total_amount_utxos.checked_sub(estimated_fee).ok_or_else(|| Error::InsufficientFunds)?
         } else {
             total_amount_utxos
@@ -1060,8 +1820,8 @@ pub fn create_tx(
                     network.policy_asset_id().ok(),
                     &acc_store.all_txs,
                     &acc_store.unblinded,
-                    account.script_type,
-                ); // "policy asset" is last, in bitcoin max 1 element
+                    account.input_shape(),
+                )?; // "policy asset" is last, in bitcoin max 1 element
                 info!("needs: {:?}", needs);
                 if needs.is_empty() {
                     // SUCCESS tx doesn't need other inputs
@@ -1085,7 +1845,7 @@ pub fn create_tx(
                         for other_utxo in utxos.iter() {
                             if (other_utxo.1).script == (utxo.1).script {
                                 used_utxo.insert(other_utxo.0.clone());
-                                tx.add_input(other_utxo.0.clone());
+                                tx.add_input(other_utxo.0.clone(), account.timelocks());
                             }
                         }
                     }
@@ -1096,14 +1856,14 @@ pub fn create_tx(
                         // While blinded address are required and not public knowledge,
                         // they are still available to whom transacted with us in the past
                         used_utxo.insert(utxo.0.clone());
-                        tx.add_input(utxo.0.clone());
+                        tx.add_input(utxo.0.clone(), account.timelocks());
                     }
                 }
             }
         }
         UtxoStrategy::Manual => {
             for utxo in utxos.iter() {
-                tx.add_input(utxo.0.clone());
+                tx.add_input(utxo.0.clone(), account.timelocks());
             }
             let needs = tx.needs(
                 fee_rate,
@@ -1111,8 +1871,8 @@ pub fn create_tx(
                 network.policy_asset_id().ok(),
                 &acc_store.all_txs,
                 &acc_store.unblinded,
-                account.script_type,
-            );
+                account.input_shape(),
+            )?;
             if !needs.is_empty() {
                 return Err(Error::InsufficientFunds);
             }
@@ -1123,8 +1883,8 @@ pub fn create_tx(
     let estimated_fee = tx.estimated_fee(
         fee_rate,
         tx.estimated_changes(send_all, &acc_store.all_txs, &acc_store.unblinded),
-        account.script_type,
-    );
+        account.input_shape(),
+    )?;
     let changes = tx.changes(
         estimated_fee,
         network.policy_asset_id().ok(),
@@ -1182,16 +1942,27 @@ pub fn create_tx(
     Ok(created_tx)
 }
 
-fn internal_sign_bitcoin(
+pub(crate) fn internal_sign_bitcoin(
     tx: &bitcoin::Transaction,
     input_index: usize,
     xprv: &ExtendedPrivKey,
     path: &DerivationPath,
     value: u64,
     script_type: ScriptType,
-) -> (bitcoin::Script, Vec<Vec<u8>>) {
+) -> Result<(bitcoin::Script, Vec<Vec<u8>>), Error> {
     let xprv = xprv.derive_priv(&crate::EC, &path).unwrap();
-    let private_key = &xprv.private_key;
+    sign_bitcoin_input(tx, input_index, &xprv.private_key, value, script_type)
+}
+
+/// Sign the input `input_index` of `tx`, spending an output of `value` locked by the
+/// `script_type` script of `private_key`
+pub(crate) fn sign_bitcoin_input(
+    tx: &bitcoin::Transaction,
+    input_index: usize,
+    private_key: &bitcoin::PrivateKey,
+    value: u64,
+    script_type: ScriptType,
+) -> Result<(bitcoin::Script, Vec<Vec<u8>>), Error> {
     let public_key = &PublicKey::from_private_key(&crate::EC, private_key);
     let script_code = p2pkh_script(public_key);
 
@@ -1217,7 +1988,7 @@ fn internal_sign_elements(
     path: &DerivationPath,
     value: Value,
     script_type: ScriptType,
-) -> (elements::Script, Vec<Vec<u8>>) {
+) -> Result<(elements::Script, Vec<Vec<u8>>), Error> {
     let xprv = xprv.derive_priv(&crate::EC, &path).unwrap();
     let private_key = &xprv.private_key;
     let public_key = &PublicKey::from_private_key(&crate::EC, private_key);
@@ -1242,8 +2013,8 @@ fn internal_sign_elements(
     let mut signature = signature.serialize_der().to_vec();
     signature.push(SigHashType::All as u8);
 
-    let (script_sig, witness) = prepare_input(&public_key, signature, script_type);
-    (script_sig.into_elements(), witness)
+    let (script_sig, witness) = prepare_input(&public_key, signature, script_type)?;
+    Ok((script_sig.into_elements(), witness))
 }
 
 // Get the input's script sig and witness data
@@ -1251,19 +2022,23 @@ fn prepare_input(
     public_key: &PublicKey,
     signature: Vec<u8>,
     script_type: ScriptType,
-) -> (bitcoin::Script, Vec<Vec<u8>>) {
+) -> Result<(bitcoin::Script, Vec<Vec<u8>>), Error> {
     let pk = public_key.to_bytes();
 
     match script_type {
-        ScriptType::P2shP2wpkh => (p2shwpkh_script_sig(public_key), vec![signature, pk]),
-        ScriptType::P2wpkh => (bitcoin::Script::new(), vec![signature, pk]),
-        ScriptType::P2pkh => (
+        ScriptType::P2shP2wpkh => Ok((p2shwpkh_script_sig(public_key), vec![signature, pk])),
+        ScriptType::P2wpkh => Ok((bitcoin::Script::new(), vec![signature, pk])),
+        ScriptType::P2pkh => Ok((
             script::Builder::new()
                 .push_slice(signature.as_slice())
                 .push_slice(pk.as_slice())
                 .into_script(),
             vec![],
-        ),
+        )),
+        ScriptType::P2wsh => Err(Error::Generic("multisig inputs are signed in psbts".into())),
+        ScriptType::P2wshMiniscript => {
+            Err(Error::Generic("miniscript inputs are signed by Policy".into()))
+        }
     }
 }
 
@@ -1307,13 +2082,22 @@ mod test {
     const NETWORK: NetworkId = NetworkId::Bitcoin(bitcoin::Network::Regtest);
 
     fn test_derivation(account_num: u32, expected_type: ScriptType, expected_path: &str) {
-        let (script_type, path) = get_account_derivation(account_num, NETWORK).unwrap();
+        test_seed_derivation(account_num, SeedType::Bip39, expected_type, expected_path);
+    }
+
+    fn test_seed_derivation(
+        account_num: u32,
+        seed_type: SeedType,
+        expected_type: ScriptType,
+        expected_path: &str,
+    ) {
+        let (script_type, path) = get_account_derivation(account_num, NETWORK, seed_type).unwrap();
         assert_eq!(script_type, expected_type);
         assert_eq!(path, DerivationPath::from_str(expected_path).unwrap());
     }
 
     fn test_derivation_fails(account_num: u32) {
-        assert!(get_account_derivation(account_num, NETWORK).is_err());
+        assert!(get_account_derivation(account_num, NETWORK, SeedType::Bip39).is_err());
     }
 
     #[test]
@@ -1322,15 +2106,29 @@ mod test {
         test_derivation(1, ScriptType::P2wpkh, "m/84'/1'/0'");
         test_derivation(2, ScriptType::P2pkh, "m/44'/1'/0'");
 
+        test_derivation(5, ScriptType::P2wsh, "m/48'/1'/0'/2'");
+        test_derivation(6, ScriptType::P2wshMiniscript, "m/87'/1'/0'");
+
         // reserved for future use, currently rejected
-        for n in 3..=15 {
+        for n in (3..=15).filter(|n| *n != 5 && *n != 6) {
             test_derivation_fails(n);
         }
 
+        // the Electrum accounts exist only for their seed type
+        test_seed_derivation(3, SeedType::ElectrumSegwit, ScriptType::P2wpkh, "m/0'");
+        test_seed_derivation(4, SeedType::ElectrumStandard, ScriptType::P2pkh, "m");
+        assert!(get_account_derivation(4, NETWORK, SeedType::ElectrumSegwit).is_err());
+        assert!(get_account_derivation(3, NETWORK, SeedType::ElectrumStandard).is_err());
+        assert!(get_account_derivation(19, NETWORK, SeedType::ElectrumSegwit).is_err());
+        test_seed_derivation(1, SeedType::ElectrumSegwit, ScriptType::P2wpkh, "m/84'/1'/0'");
+
         test_derivation(16, ScriptType::P2shP2wpkh, "m/49'/1'/1'");
         test_derivation(17, ScriptType::P2wpkh, "m/84'/1'/1'");
         test_derivation(18, ScriptType::P2pkh, "m/44'/1'/1'");
         test_derivation_fails(19);
+        test_derivation_fails(20);
+        test_derivation(21, ScriptType::P2wsh, "m/48'/1'/1'/2'");
+        test_derivation(22, ScriptType::P2wshMiniscript, "m/87'/1'/1'");
 
         test_derivation(160, ScriptType::P2shP2wpkh, "m/49'/1'/10'");
         test_derivation(161, ScriptType::P2wpkh, "m/84'/1'/10'");
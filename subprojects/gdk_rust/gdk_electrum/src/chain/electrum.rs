@@ -0,0 +1,61 @@
+use crate::chain::ChainSource;
+use crate::error::Error;
+use electrum_client::{Client, ElectrumApi, GetHistoryRes, GetMerkleRes};
+use gdk_common::model::FeeEstimate;
+
+impl ChainSource for Client {
+    fn scripts_history(
+        &self,
+        scripts: &[bitcoin::Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error> {
+        Ok(self.batch_script_get_history(scripts.iter())?)
+    }
+
+    fn transactions_raw(&self, txids: &[bitcoin::Txid]) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(self.batch_transaction_get_raw(txids.iter())?)
+    }
+
+    fn headers_raw(&self, heights: &[u32]) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(self.batch_block_header_raw(heights.iter().cloned())?)
+    }
+
+    fn headers(&self, start_height: u32, count: usize) -> Result<Vec<bitcoin::BlockHeader>, Error> {
+        Ok(self.block_headers(start_height as usize, count)?.headers)
+    }
+
+    fn merkle_proof(&self, txid: &bitcoin::Txid, height: u32) -> Result<GetMerkleRes, Error> {
+        Ok(self.transaction_get_merkle(txid, height as usize)?)
+    }
+
+    fn broadcast(&self, raw_tx: &[u8]) -> Result<bitcoin::Txid, Error> {
+        self.transaction_broadcast_raw(raw_tx).map_err(|e| match e {
+            // the server forwards the node reject reason as the error message
+            electrum_client::Error::Protocol(value) => Error::TxRejected(
+                value["message"]
+                    .as_str()
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| value.to_string()),
+            ),
+            e => e.into(),
+        })
+    }
+
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error> {
+        let header = self.block_headers_subscribe_raw()?;
+        Ok((header.height as u32, header.header))
+    }
+
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error> {
+        let relay_fee = (self.relay_fee()? * 100_000_000.0) as u64;
+        let blocks: Vec<usize> = (1..25).collect();
+        // max is covering a rounding errors in production electrs which sometimes cause a fee
+        // estimates lower than relay fee
+        let mut estimates: Vec<FeeEstimate> = self
+            .batch_estimate_fee(blocks)?
+            .iter()
+            .map(|e| FeeEstimate(relay_fee.max((*e * 100_000_000.0) as u64)))
+            .collect();
+        estimates.insert(0, FeeEstimate(relay_fee));
+        Ok(estimates)
+    }
+}
@@ -0,0 +1,348 @@
+use crate::chain::ChainSource;
+use crate::error::Error;
+use crate::request;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{sha256, Hash};
+use electrum_client::{GetHistoryRes, GetMerkleRes};
+use gdk_common::model::FeeEstimate;
+use log::trace;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const TIMEOUT_MS: u64 = 15_000;
+
+/// Confirmed transactions returned by esplora in every page of the script history
+const CHAIN_TXS_PER_PAGE: usize = 25;
+
+/// Unconfirmed transactions returned by esplora with the script history, there are no further
+/// pages of them
+const MEMPOOL_TXS_PER_PAGE: usize = 50;
+
+/// Blocks returned by esplora in every page of `/blocks/:start_height`
+const BLOCKS_PER_PAGE: u32 = 10;
+
+/// A client for the Esplora HTTP API, see https://github.com/Blockstream/esplora/blob/master/API.md
+///
+/// The API has no batch calls, so every element of a batch is a separate request. Bitcoin headers
+/// are rebuilt from the block pages, 10 per request, the raw ones need two requests each.
+pub struct EsploraClient {
+    base_url: String,
+    agent: ureq::Agent,
+}
+
+#[derive(Deserialize)]
+struct EsploraTx {
+    txid: bitcoin::Txid,
+    status: EsploraTxStatus,
+    fee: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct EsploraTxStatus {
+    confirmed: bool,
+    block_height: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct EsploraBlock {
+    id: bitcoin::BlockHash,
+    height: u32,
+    version: i32,
+    timestamp: u32,
+    merkle_root: bitcoin::TxMerkleNode,
+    previousblockhash: Option<bitcoin::BlockHash>,
+    bits: u32,
+    nonce: u32,
+}
+
+impl EsploraBlock {
+    /// The bitcoin header of the block, checked against its hash
+    fn header(&self) -> Result<bitcoin::BlockHeader, Error> {
+        let header = bitcoin::BlockHeader {
+            version: self.version,
+            prev_blockhash: self.previousblockhash.unwrap_or_default(),
+            merkle_root: self.merkle_root,
+            time: self.timestamp,
+            bits: self.bits,
+            nonce: self.nonce,
+        };
+        if header.block_hash() != self.id {
+            return Err(Error::Generic(format!("esplora returned an invalid block {}", self.id)));
+        }
+        Ok(header)
+    }
+}
+
+impl From<EsploraTx> for GetHistoryRes {
+    fn from(tx: EsploraTx) -> Self {
+        // unconfirmed txs have height 0, like in electrum
+        let height = match (tx.status.confirmed, tx.status.block_height) {
+            (true, Some(height)) => height as i32,
+            _ => 0,
+        };
+        GetHistoryRes {
+            height,
+            tx_hash: tx.txid,
+            fee: tx.fee,
+        }
+    }
+}
+
+impl EsploraClient {
+    pub fn new(base_url: &str, proxy: Option<&str>) -> Result<Self, Error> {
+        let mut agent = ureq::agent();
+        if let Some(proxy) = proxy {
+            agent.set_proxy(ureq::Proxy::new(proxy)?);
+        }
+        Ok(EsploraClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            agent,
+        })
+    }
+
+    fn get(&self, path: &str) -> Result<ureq::Response, Error> {
+        let url = format!("{}{}", self.base_url, path);
+        trace!("esplora GET {}", url);
+        let response =
+            self.agent.get(&url).timeout_connect(TIMEOUT_MS).timeout_read(TIMEOUT_MS).call();
+        check_response(&url, response)
+    }
+
+    fn get_text(&self, path: &str) -> Result<String, Error> {
+        Ok(self.get(path)?.into_string()?.trim().to_string())
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        Ok(self.get(path)?.into_json_deserialize()?)
+    }
+
+    fn script_history(&self, script: &bitcoin::Script) -> Result<Vec<GetHistoryRes>, Error> {
+        let scripthash = sha256::Hash::hash(script.as_bytes()).into_inner().to_hex();
+        let mut history = vec![];
+        let mut path = format!("/scripthash/{}/txs", scripthash);
+        loop {
+            let txs: Vec<EsploraTx> = self.get_json(&path)?;
+            let confirmed = txs.iter().filter(|tx| tx.status.confirmed).count();
+            if txs.len() - confirmed >= MEMPOOL_TXS_PER_PAGE {
+                return Err(Error::Generic(format!(
+                    "esplora returns at most {} unconfirmed transactions of {}",
+                    MEMPOOL_TXS_PER_PAGE, scripthash
+                )));
+            }
+            let last_confirmed = txs.iter().rev().find(|tx| tx.status.confirmed).map(|tx| tx.txid);
+            history.extend(txs.into_iter().map(GetHistoryRes::from));
+            match last_confirmed {
+                Some(txid) if confirmed >= CHAIN_TXS_PER_PAGE => {
+                    path = format!("/scripthash/{}/txs/chain/{}", scripthash, txid)
+                }
+                _ => break,
+            }
+        }
+        Ok(history)
+    }
+
+    fn header_raw(&self, height: u32) -> Result<Vec<u8>, Error> {
+        let hash = self.get_text(&format!("/block-height/{}", height))?;
+        Ok(Vec::<u8>::from_hex(&self.get_text(&format!("/block/{}/header", hash))?)?)
+    }
+
+    /// The bitcoin headers from `start_height` to `end_height` included
+    fn headers_range(
+        &self,
+        start_height: u32,
+        end_height: u32,
+    ) -> Result<Vec<bitcoin::BlockHeader>, Error> {
+        let mut headers = vec![];
+        let mut next = start_height;
+        let total = u64::from(end_height.saturating_add(1).saturating_sub(start_height));
+        while next <= end_height {
+            request::check_cancelled()?;
+            request::progress("esplora_headers", headers.len() as u64, Some(total));
+            // a page has the blocks from its height down
+            let top = end_height.min(next.saturating_add(BLOCKS_PER_PAGE - 1));
+            let blocks: Vec<EsploraBlock> = self.get_json(&format!("/blocks/{}", top))?;
+            for height in next..=top {
+                let block = blocks.iter().find(|b| b.height == height).ok_or_else(|| {
+                    Error::Generic(format!("esplora returned no block at height {}", height))
+                })?;
+                headers.push(block.header()?);
+            }
+            next = top + 1;
+        }
+        Ok(headers)
+    }
+
+    fn tip_height(&self) -> Result<u32, Error> {
+        self.get_text("/blocks/tip/height")?
+            .parse()
+            .map_err(|_| Error::Generic("esplora returned an invalid tip height".into()))
+    }
+}
+
+fn check_response(url: &str, response: ureq::Response) -> Result<ureq::Response, Error> {
+    if let Some(err) = response.synthetic_error() {
+        return Err(Error::ConnectionFailed(format!("esplora {} failed: {}", url, err)));
+    }
+    if response.ok() {
+        Ok(response)
+    } else {
+        let status = response.status();
+        let body = response.into_string().unwrap_or_default();
+        Err(Error::Generic(format!("esplora {} returned {}: {}", url, status, body.trim())))
+    }
+}
+
+impl ChainSource for EsploraClient {
+    fn scripts_history(
+        &self,
+        scripts: &[bitcoin::Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error> {
+        scripts.iter().map(|script| self.script_history(script)).collect()
+    }
+
+    fn transactions_raw(&self, txids: &[bitcoin::Txid]) -> Result<Vec<Vec<u8>>, Error> {
+        txids
+            .iter()
+            .map(|txid| -> Result<Vec<u8>, Error> {
+                Ok(Vec::<u8>::from_hex(&self.get_text(&format!("/tx/{}/hex", txid))?)?)
+            })
+            .collect()
+    }
+
+    fn headers_raw(&self, heights: &[u32]) -> Result<Vec<Vec<u8>>, Error> {
+        heights.iter().map(|height| self.header_raw(*height)).collect()
+    }
+
+    fn headers(&self, start_height: u32, count: usize) -> Result<Vec<bitcoin::BlockHeader>, Error> {
+        let tip_height = self.tip_height()?;
+        if count == 0 || start_height > tip_height {
+            return Ok(vec![]);
+        }
+        let end_height =
+            tip_height.min(start_height.saturating_add(count as u32).saturating_sub(1));
+        self.headers_range(start_height, end_height)
+    }
+
+    fn merkle_proof(&self, txid: &bitcoin::Txid, height: u32) -> Result<GetMerkleRes, Error> {
+        // same format of the electrum protocol
+        let proof: GetMerkleRes = self.get_json(&format!("/tx/{}/merkle-proof", txid))?;
+        if proof.block_height != height as usize {
+            return Err(Error::Generic(format!(
+                "merkle proof of {} is for height {} instead of {}",
+                txid, proof.block_height, height
+            )));
+        }
+        Ok(proof)
+    }
+
+    fn broadcast(&self, raw_tx: &[u8]) -> Result<bitcoin::Txid, Error> {
+        let url = format!("{}/tx", self.base_url);
+        let response = self
+            .agent
+            .post(&url)
+            .timeout_connect(TIMEOUT_MS)
+            .timeout_read(TIMEOUT_MS)
+            .send_string(&raw_tx.to_hex());
+        if response.synthetic_error().is_none() && response.status() == 400 {
+            // the body is the node reject reason
+            let body = response.into_string().unwrap_or_default();
+            return Err(Error::TxRejected(body.trim().to_string()));
+        }
+        let txid = check_response(&url, response)?.into_string()?;
+        Ok(bitcoin::Txid::from_hex(txid.trim())?)
+    }
+
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error> {
+        let height = self.tip_height()?;
+        Ok((height, self.header_raw(height)?))
+    }
+
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error> {
+        convert_fee_estimates(self.get_json("/fee-estimates")?)
+    }
+}
+
+/// Convert the esplora estimates, in sat/vbyte for a set of confirmation targets, in the format
+/// returned by `ChainSource::fee_estimates`
+fn convert_fee_estimates(estimates: HashMap<String, f64>) -> Result<Vec<FeeEstimate>, Error> {
+    let mut estimates: Vec<(usize, u64)> = estimates
+        .into_iter()
+        .filter_map(|(target, rate)| Some((target.parse().ok()?, (rate * 1000.0) as u64)))
+        .collect();
+    estimates.sort();
+
+    // the relay fee is not exposed, the lowest estimate is the best approximation we have
+    let relay_fee = estimates
+        .iter()
+        .map(|(_, rate)| *rate)
+        .min()
+        .ok_or_else(|| Error::Generic("esplora returned no fee estimates".into()))?;
+
+    let mut result = vec![FeeEstimate(relay_fee)];
+    for block in 1..25 {
+        // targets missing from the response use the closest lower target, or the first one
+        let rate = estimates
+            .iter()
+            .rev()
+            .find(|(target, _)| *target <= block)
+            .or_else(|| estimates.first())
+            .map(|(_, rate)| *rate)
+            .unwrap_or(relay_fee);
+        result.push(FeeEstimate(relay_fee.max(rate)));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_fee_estimates() {
+        let estimates: HashMap<String, f64> =
+            serde_json::from_str(r#"{"1":20.5,"2":10.0,"6":5.0,"144":1.0,"1008":1.0}"#).unwrap();
+        let converted = convert_fee_estimates(estimates).unwrap();
+        assert_eq!(converted.len(), 25);
+        assert_eq!(converted[0].0, 1000);
+        assert_eq!(converted[1].0, 20500);
+        assert_eq!(converted[2].0, 10000);
+        assert_eq!(converted[5].0, 10000);
+        assert_eq!(converted[6].0, 5000);
+        assert_eq!(converted[24].0, 5000);
+
+        assert!(convert_fee_estimates(HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_header_from_esplora() {
+        // block 1 of mainnet
+        let json = r#"{"id":"00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048","height":1,"version":1,"timestamp":1231469665,"tx_count":1,"size":215,"weight":860,"merkle_root":"0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098","previousblockhash":"000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f","mediantime":1231469665,"nonce":2573394689,"bits":486604799,"difficulty":1}"#;
+        let mut block: EsploraBlock = serde_json::from_str(json).unwrap();
+        let header = block.header().unwrap();
+        assert_eq!(header.block_hash(), block.id);
+        assert_eq!(header.prev_blockhash, block.previousblockhash.unwrap());
+
+        block.nonce += 1;
+        assert!(block.header().is_err());
+    }
+
+    #[test]
+    fn test_history_from_esplora() {
+        let txs: Vec<EsploraTx> = serde_json::from_str(
+            r#"[
+            {"txid":"4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b","status":{"confirmed":false},"fee":141},
+            {"txid":"0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098","status":{"confirmed":true,"block_height":1,"block_hash":"00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"},"fee":0}
+        ]"#,
+        )
+        .unwrap();
+        let history: Vec<GetHistoryRes> = txs.into_iter().map(GetHistoryRes::from).collect();
+        assert_eq!(history[0].height, 0);
+        assert_eq!(history[0].fee, Some(141));
+        assert_eq!(history[1].height, 1);
+        assert_eq!(
+            history[1].tx_hash.to_string(),
+            "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098"
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use crate::chain::ChainSource;
+use crate::error::Error;
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use electrum_client::{GetHistoryRes, GetMerkleRes};
+use gdk_common::be::{BEScriptConvert, BETransaction, BETxidConvert};
+use gdk_common::model::FeeEstimate;
+use gdk_common::NetworkId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A chain kept in memory, to exercise the wallet logic without an electrum or esplora server.
+///
+/// Blocks are made by the headers pushed with `push_header` and by the transactions inserted at
+/// their height, in insertion order. Broadcasted transactions end up in the mempool.
+pub struct MemoryChainSource {
+    network: NetworkId,
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    headers: Vec<Vec<u8>>,
+    txs: HashMap<bitcoin::Txid, (BETransaction, Option<u32>)>,
+    // keep the insertion order, the position in a block is relevant for merkle proofs
+    txs_order: Vec<bitcoin::Txid>,
+    fee_estimates: Option<Vec<FeeEstimate>>,
+}
+
+impl MemoryChainSource {
+    pub fn new(network: NetworkId) -> Self {
+        MemoryChainSource {
+            network,
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+
+    /// Append the raw `header` at the next height, starting from 0
+    pub fn push_header(&self, header: Vec<u8>) {
+        self.state.lock().unwrap().headers.push(header);
+    }
+
+    /// Insert `tx` in the block at `height`, or in the mempool if `None`. Inserting an existing
+    /// transaction moves it to the given height.
+    pub fn insert_tx(&self, tx: BETransaction, height: Option<u32>) {
+        let mut state = self.state.lock().unwrap();
+        let txid = tx.txid().into_bitcoin();
+        if state.txs.insert(txid, (tx, height)).is_none() {
+            state.txs_order.push(txid);
+        }
+    }
+
+    /// Drop the transaction, as it happens for replaced or reorged ones
+    pub fn remove_tx(&self, txid: &bitcoin::Txid) {
+        let mut state = self.state.lock().unwrap();
+        state.txs.remove(txid);
+        state.txs_order.retain(|t| t != txid);
+    }
+
+    pub fn set_fee_estimates(&self, fee_estimates: Vec<FeeEstimate>) {
+        self.state.lock().unwrap().fee_estimates = Some(fee_estimates);
+    }
+}
+
+impl MemoryState {
+    fn involves_script(&self, tx: &BETransaction, script: &bitcoin::Script) -> bool {
+        let in_outputs = (0..tx.output_len() as u32)
+            .any(|vout| &tx.output_script(vout).into_bitcoin() == script);
+        let in_inputs = tx.previous_outputs().iter().any(|outpoint| {
+            self.txs.get(&outpoint.txid().into_bitcoin()).map_or(false, |(prev_tx, _)| {
+                &prev_tx.output_script(outpoint.vout()).into_bitcoin() == script
+            })
+        });
+        in_outputs || in_inputs
+    }
+
+    fn block_txids(&self, height: u32) -> Vec<bitcoin::Txid> {
+        self.txs_order.iter().filter(|t| self.txs[*t].1 == Some(height)).cloned().collect()
+    }
+}
+
+impl ChainSource for MemoryChainSource {
+    fn scripts_history(
+        &self,
+        scripts: &[bitcoin::Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(scripts
+            .iter()
+            .map(|script| {
+                let mut history: Vec<GetHistoryRes> = state
+                    .txs_order
+                    .iter()
+                    .filter(|txid| state.involves_script(&state.txs[*txid].0, script))
+                    .map(|txid| GetHistoryRes {
+                        height: state.txs[txid].1.map_or(0, |h| h as i32),
+                        tx_hash: *txid,
+                        fee: None,
+                    })
+                    .collect();
+                // confirmed first, ordered by height, like electrum does
+                history.sort_by_key(|h| {
+                    if h.height == 0 {
+                        i32::max_value()
+                    } else {
+                        h.height
+                    }
+                });
+                history
+            })
+            .collect())
+    }
+
+    fn transactions_raw(&self, txids: &[bitcoin::Txid]) -> Result<Vec<Vec<u8>>, Error> {
+        let state = self.state.lock().unwrap();
+        txids
+            .iter()
+            .map(|txid| {
+                state
+                    .txs
+                    .get(txid)
+                    .map(|(tx, _)| tx.serialize())
+                    .ok_or_else(|| Error::TxNotFound(txid.to_string()))
+            })
+            .collect()
+    }
+
+    fn headers_raw(&self, heights: &[u32]) -> Result<Vec<Vec<u8>>, Error> {
+        let state = self.state.lock().unwrap();
+        heights
+            .iter()
+            .map(|height| {
+                state
+                    .headers
+                    .get(*height as usize)
+                    .cloned()
+                    .ok_or_else(|| Error::Generic(format!("missing header at {}", height)))
+            })
+            .collect()
+    }
+
+    fn headers(&self, start_height: u32, count: usize) -> Result<Vec<bitcoin::BlockHeader>, Error> {
+        let state = self.state.lock().unwrap();
+        state
+            .headers
+            .iter()
+            .skip(start_height as usize)
+            .take(count)
+            .map(|header| -> Result<bitcoin::BlockHeader, Error> {
+                Ok(bitcoin::consensus::deserialize(header)?)
+            })
+            .collect()
+    }
+
+    fn merkle_proof(&self, txid: &bitcoin::Txid, height: u32) -> Result<GetMerkleRes, Error> {
+        let state = self.state.lock().unwrap();
+        let block_txids = state.block_txids(height);
+        let pos = block_txids
+            .iter()
+            .position(|t| t == txid)
+            .ok_or_else(|| Error::TxNotFound(txid.to_string()))?;
+        Ok(GetMerkleRes {
+            block_height: height as usize,
+            pos,
+            merkle: merkle_branch(block_txids.iter().map(|t| t.into_inner()).collect(), pos),
+        })
+    }
+
+    fn broadcast(&self, raw_tx: &[u8]) -> Result<bitcoin::Txid, Error> {
+        let tx = BETransaction::deserialize(raw_tx, self.network)?;
+        let txid = tx.txid().into_bitcoin();
+        self.insert_tx(tx, None);
+        Ok(txid)
+    }
+
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error> {
+        let state = self.state.lock().unwrap();
+        let header = state.headers.last().ok_or_else(|| Error::Generic("empty chain".into()))?;
+        Ok((state.headers.len() as u32 - 1, header.clone()))
+    }
+
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error> {
+        let state = self.state.lock().unwrap();
+        let min_fee = match self.network {
+            NetworkId::Bitcoin(_) => 1000,
+            NetworkId::Elements(_) => 100,
+        };
+        Ok(state.fee_estimates.clone().unwrap_or_else(|| vec![FeeEstimate(min_fee); 25]))
+    }
+}
+
+/// The merkle path of the tx at `pos`, in electrum format (with the hashes reversed)
+fn merkle_branch(mut level: Vec<[u8; 32]>, mut pos: usize) -> Vec<[u8; 32]> {
+    let mut branch = vec![];
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(*level.last().unwrap());
+        }
+        let mut sibling = level[pos ^ 1];
+        sibling.reverse();
+        branch.push(sibling);
+        level = level.chunks(2).map(|pair| hash_pair(&pair[0], &pair[1])).collect();
+        pos /= 2;
+    }
+    branch
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(left);
+    engine.input(right);
+    sha256d::Hash::from_engine(engine).into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::compute_merkle_root;
+    use bitcoin::{OutPoint, Script, Transaction, TxIn, TxMerkleNode, TxOut};
+
+    const NETWORK: NetworkId = NetworkId::Bitcoin(bitcoin::Network::Regtest);
+
+    fn tx(previous_output: OutPoint, script_pubkey: Script) -> BETransaction {
+        BETransaction::Bitcoin(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: Script::default(),
+                sequence: 0xffff_fffe,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_memory_history() {
+        let source = MemoryChainSource::new(NETWORK);
+        let script_a = Script::from(vec![0x51]);
+        let script_b = Script::from(vec![0x52]);
+
+        let funding = tx(OutPoint::null(), script_a.clone());
+        let funding_txid = funding.txid().into_bitcoin();
+        source.insert_tx(funding, Some(1));
+        let spending = tx(OutPoint::new(funding_txid, 0), script_b.clone());
+        let spending_txid = source.broadcast(&spending.serialize()).unwrap();
+
+        let history = source.scripts_history(&[script_a, script_b, Script::new()]).unwrap();
+        assert_eq!(
+            history[0].iter().map(|h| h.tx_hash).collect::<Vec<_>>(),
+            vec![funding_txid, spending_txid]
+        );
+        assert_eq!(history[0][0].height, 1);
+        assert_eq!(history[0][1].height, 0);
+        assert_eq!(history[1].len(), 1);
+        assert!(history[2].is_empty());
+
+        let raw = source.transactions_raw(&[spending_txid]).unwrap();
+        assert_eq!(raw[0], spending.serialize());
+        assert!(source.transactions_raw(&[bitcoin::Txid::default()]).is_err());
+    }
+
+    #[test]
+    fn test_memory_merkle_proof() {
+        let source = MemoryChainSource::new(NETWORK);
+        let txs: Vec<BETransaction> =
+            (0..5u8).map(|i| tx(OutPoint::null(), Script::from(vec![i]))).collect();
+        for tx in txs.iter() {
+            source.insert_tx(tx.clone(), Some(7));
+        }
+        let txids: Vec<bitcoin::Txid> = txs.iter().map(|tx| tx.txid().into_bitcoin()).collect();
+
+        // 5 leaves: the last one is paired with itself on the first two levels
+        let leaves: Vec<[u8; 32]> = txids.iter().map(|t| t.into_inner()).collect();
+        let l1 = [
+            hash_pair(&leaves[0], &leaves[1]),
+            hash_pair(&leaves[2], &leaves[3]),
+            hash_pair(&leaves[4], &leaves[4]),
+        ];
+        let l2 = [hash_pair(&l1[0], &l1[1]), hash_pair(&l1[2], &l1[2])];
+        let expected_root = hash_pair(&l2[0], &l2[1]);
+
+        for txid in txids.iter() {
+            let proof = source.merkle_proof(txid, 7).unwrap();
+            let root: TxMerkleNode = compute_merkle_root(txid, proof).unwrap();
+            assert_eq!(root.into_inner(), expected_root);
+        }
+        assert!(source.merkle_proof(&txids[0], 8).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::error::Error;
+use crate::interface::ElectrumUrl;
+use crate::{determine_electrum_url_from_net, determine_esplora_url_from_net};
+use electrum_client::{GetHistoryRes, GetMerkleRes};
+use gdk_common::model::FeeEstimate;
+use gdk_common::network::Network;
+use serde::{Deserialize, Serialize};
+
+pub mod electrum;
+pub mod esplora;
+pub mod memory;
+
+pub use crate::chain::esplora::EsploraClient;
+pub use crate::chain::memory::MemoryChainSource;
+
+/// A backend the wallet fetches blockchain data from.
+///
+/// Txids and scripts are always the bitcoin types, also on liquid, mirroring what the electrum
+/// protocol does. Batch methods return their results in the same order of the given input.
+pub trait ChainSource {
+    /// history (confirmed and unconfirmed) of every script
+    fn scripts_history(
+        &self,
+        scripts: &[bitcoin::Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error>;
+
+    /// raw bytes of every transaction
+    fn transactions_raw(&self, txids: &[bitcoin::Txid]) -> Result<Vec<Vec<u8>>, Error>;
+
+    /// raw bytes of the block header at every height
+    fn headers_raw(&self, heights: &[u32]) -> Result<Vec<Vec<u8>>, Error>;
+
+    /// up to `count` consecutive bitcoin headers starting from `start_height`, less if the tip is
+    /// reached, used to build the local headers chain
+    fn headers(&self, start_height: u32, count: usize) -> Result<Vec<bitcoin::BlockHeader>, Error>;
+
+    /// merkle inclusion proof of `txid` in the block at `height`, in electrum format
+    fn merkle_proof(&self, txid: &bitcoin::Txid, height: u32) -> Result<GetMerkleRes, Error>;
+
+    fn broadcast(&self, raw_tx: &[u8]) -> Result<bitcoin::Txid, Error>;
+
+    /// height and raw header of the current tip
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error>;
+
+    /// 25 estimates in satoshi per 1000 bytes, the first is the minimum relay fee, the others
+    /// are for a confirmation within 1 to 24 blocks
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error>;
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum ChainSourceUrl {
+    Electrum(ElectrumUrl),
+    Esplora(String),
+}
+
+impl ChainSourceUrl {
+    /// The backend used when no `server_type` is given: esplora only if the network has no
+    /// electrum url
+    pub fn default_server_type(network: &Network) -> &'static str {
+        if network.electrum_url.is_none() && network.esplora_url.is_some() {
+            "esplora"
+        } else {
+            "electrum"
+        }
+    }
+
+    /// The url of the `server_type` backend, "electrum" or "esplora", configured in `network`
+    pub fn from_network(network: &Network, server_type: &str) -> Result<Self, Error> {
+        match server_type {
+            "electrum" => Ok(ChainSourceUrl::Electrum(determine_electrum_url_from_net(network)?)),
+            "esplora" => Ok(ChainSourceUrl::Esplora(determine_esplora_url_from_net(network)?)),
+            _ => Err(Error::Generic(format!("invalid server_type {}", server_type))),
+        }
+    }
+
+    pub fn build_source(&self, proxy: Option<&str>) -> Result<Box<dyn ChainSource>, Error> {
+        Ok(match self {
+            ChainSourceUrl::Electrum(url) => Box::new(url.build_client(proxy)?),
+            ChainSourceUrl::Esplora(url) => Box::new(EsploraClient::new(url, proxy)?),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            ChainSourceUrl::Electrum(url) => url.url(),
+            ChainSourceUrl::Esplora(url) => url,
+        }
+    }
+}
+
+impl From<ElectrumUrl> for ChainSourceUrl {
+    fn from(url: ElectrumUrl) -> Self {
+        ChainSourceUrl::Electrum(url)
+    }
+}
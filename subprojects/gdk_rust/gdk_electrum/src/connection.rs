@@ -0,0 +1,129 @@
+use gdk_common::model::{ConnectionState, NetworkNotification, PollSessionResult};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Connectivity to the chain source, updated by the syncer and tipper threads.
+///
+/// The syncer thread drives the state: a failed sync moves to `Disconnected` and schedules the
+/// next retry, the retry moves to `Reconnecting`, and a successful sync moves to `Connected`.
+#[derive(Debug)]
+pub struct ConnectionStatus {
+    state: ConnectionState,
+    last_sync: Option<SystemTime>,
+    tip_time: Option<u32>,
+    next_retry: Option<SystemTime>,
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        ConnectionStatus {
+            state: ConnectionState::Disconnected,
+            last_sync: None,
+            tip_time: None,
+            next_retry: None,
+        }
+    }
+}
+
+impl ConnectionStatus {
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// A sync is starting, returns the new state if it changed
+    pub fn attempt(&mut self) -> Option<ConnectionState> {
+        // the first attempt after login is not a reconnection
+        if self.state == ConnectionState::Disconnected && self.next_retry.is_some() {
+            self.next_retry = None;
+            self.transition(ConnectionState::Reconnecting)
+        } else {
+            None
+        }
+    }
+
+    /// A sync completed, returns the new state if it changed
+    pub fn succeeded(&mut self) -> Option<ConnectionState> {
+        self.last_sync = Some(SystemTime::now());
+        self.next_retry = None;
+        self.transition(ConnectionState::Connected)
+    }
+
+    /// A sync failed and the next one happens in `retry_in`, returns the new state if it changed
+    pub fn failed(&mut self, retry_in: Duration) -> Option<ConnectionState> {
+        self.next_retry = Some(SystemTime::now() + retry_in);
+        self.transition(ConnectionState::Disconnected)
+    }
+
+    /// Record the timestamp of the last tip header received
+    pub fn set_tip_time(&mut self, time: u32) {
+        self.tip_time = Some(time);
+    }
+
+    fn transition(&mut self, state: ConnectionState) -> Option<ConnectionState> {
+        if self.state == state {
+            None
+        } else {
+            self.state = state;
+            Some(state)
+        }
+    }
+
+    pub fn poll(&self, current_server: &str, tip_height: u32) -> PollSessionResult {
+        let now = unix_ts(SystemTime::now());
+        PollSessionResult {
+            state: self.state,
+            current_server: current_server.to_string(),
+            last_sync_ts: self.last_sync.map(unix_ts),
+            tip_height,
+            tip_age: self.tip_time.map(|time| now.saturating_sub(time as u64)),
+            next_retry_ts: self.next_retry.map(unix_ts),
+        }
+    }
+
+    /// The content of the `network` notification
+    pub fn notification(&self, current_server: &str) -> NetworkNotification {
+        let next_retry_ts = self.next_retry.map(unix_ts);
+        NetworkNotification {
+            connected: self.state == ConnectionState::Connected,
+            login_required: false,
+            heartbeat_timeout: false,
+            state: self.state,
+            current_server: current_server.to_string(),
+            next_retry_ts,
+            waiting: next_retry_ts.map(|ts| ts.saturating_sub(unix_ts(SystemTime::now()))),
+        }
+    }
+}
+
+fn unix_ts(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_transitions() {
+        let mut status = ConnectionStatus::default();
+        assert_eq!(status.attempt(), None);
+        assert_eq!(status.succeeded(), Some(ConnectionState::Connected));
+        assert_eq!(status.succeeded(), None);
+        assert!(status.poll("server", 10).last_sync_ts.is_some());
+
+        assert_eq!(status.failed(Duration::from_secs(7)), Some(ConnectionState::Disconnected));
+        let notification = status.notification("server");
+        assert!(!notification.connected);
+        assert_eq!(notification.state, ConnectionState::Disconnected);
+        assert!(notification.waiting.unwrap() <= 7);
+        assert!(status.poll("server", 10).next_retry_ts.is_some());
+
+        assert_eq!(status.attempt(), Some(ConnectionState::Reconnecting));
+        assert_eq!(status.failed(Duration::from_secs(7)), Some(ConnectionState::Disconnected));
+        assert_eq!(status.attempt(), Some(ConnectionState::Reconnecting));
+        assert_eq!(status.succeeded(), Some(ConnectionState::Connected));
+        assert_eq!(status.poll("server", 10).next_retry_ts, None);
+
+        status.set_tip_time(0);
+        assert!(status.poll("server", 10).tip_age.unwrap() > 0);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::store::StoreMeta;
 use aes_gcm_siv::aead;
+use gdk_common::mnemonic::MnemonicError;
 use serde::ser::Serialize;
 use std::convert::From;
 use std::fmt::Display;
@@ -9,17 +10,32 @@ use std::sync::{PoisonError, RwLockReadGuard, RwLockWriteGuard};
 pub enum Error {
     Generic(String),
     UnknownCall,
-    InvalidMnemonic,
+    InvalidMnemonic(MnemonicError),
     InsufficientFunds,
     InvalidAddress,
+    /// The address is not one of the cached addresses of the subaccount
+    AddressNotFound(String),
+    InvalidPrivateKey,
+    /// The payment URI is malformed or for another network
+    InvalidUri(String),
+    /// A required `req-` parameter of a payment URI is not supported
+    UnknownUriParameter(String),
+    /// The Liquid payment URI has an amount but no valid asset
+    InvalidUriAsset,
     NonConfidentialAddress,
     InvalidAmount,
+    /// An output amount is at or below the dust threshold
+    AmountBelowDust,
     EmptyAddressees,
     FeeRateBelowMinimum,
     AssetEmpty,
     InvalidHeaders,
     InvalidSubaccount(u32),
     AccountGapsDisallowed,
+    /// The threshold or the cosigners of a multisig subaccount are not valid
+    InvalidMultisig(String),
+    /// The descriptor of a miniscript subaccount is not supported
+    InvalidPolicy(String),
     InvalidReplacementRequest,
     SendAll,
     PinError,
@@ -28,7 +44,17 @@ pub enum Error {
     /// leave such counter unchanged.
     InvalidPin,
     PsetAndTxMismatch,
+    /// The asynchronous request running on the thread has been cancelled
+    Cancelled,
     TxNotFound(String),
+    /// The node refused the broadcasted transaction, with its reject reason
+    TxRejected(String),
+    /// The chain source could not be reached
+    ConnectionFailed(String),
+    /// The persisted data cannot be decrypted
+    StoreCorrupted(String),
+    /// The persisted data are protected by a store secret and the one given, if any, is wrong
+    InvalidStoreSecret,
     AddrParse(String),
     InvalidElectrumUrl(String),
     Bitcoin(bitcoin::util::Error),
@@ -55,12 +81,18 @@ impl Display for Error {
         match &self {
             Error::Generic(ref strerr) => write!(f, "{}", strerr),
             Error::AddrParse(ref addr) => write!(f, "could not parse SocketAddr `{}`", addr),
-            Error::InvalidMnemonic => write!(f, "invalid mnemonic"),
+            Error::InvalidMnemonic(err) => write!(f, "invalid mnemonic: {}", err),
             Error::InsufficientFunds => write!(f, "insufficient funds"),
             Error::SendAll => write!(f, "sendall error"),
             Error::InvalidAddress => write!(f, "invalid address"),
+            Error::AddressNotFound(address) => write!(f, "address not found ({})", address),
+            Error::InvalidPrivateKey => write!(f, "invalid private key"),
+            Error::InvalidUri(reason) => write!(f, "invalid uri: {}", reason),
+            Error::UnknownUriParameter(param) => write!(f, "unknown uri parameter {}", param),
+            Error::InvalidUriAsset => write!(f, "invalid or missing asset in the uri"),
             Error::NonConfidentialAddress => write!(f, "non confidential address"),
             Error::InvalidAmount => write!(f, "invalid amount"),
+            Error::AmountBelowDust => write!(f, "amount below the dust threshold"),
             Error::InvalidHeaders => write!(f, "invalid headers"),
             Error::EmptyAddressees => write!(f, "addressees cannot be empty"),
             Error::FeeRateBelowMinimum => write!(f, "fee rate below minimum"),
@@ -69,8 +101,11 @@ impl Display for Error {
             Error::AccountGapsDisallowed => {
                 write!(f, "cannot create a new subaccount while the last one is unused")
             }
+            Error::InvalidMultisig(reason) => write!(f, "invalid multisig: {}", reason),
+            Error::InvalidPolicy(reason) => write!(f, "invalid policy: {}", reason),
             Error::InvalidReplacementRequest => write!(f, "invalid replacement request fields"),
             Error::UnknownCall => write!(f, "unknown call"),
+            Error::Cancelled => write!(f, "request cancelled"),
             Error::Bitcoin(ref btcerr) => write!(f, "bitcoin: {}", btcerr),
             Error::BitcoinHashes(ref btcerr) => write!(f, "bitcoin_hashes: {}", btcerr),
             Error::BitcoinBIP32Error(ref bip32err) => write!(f, "bip32: {}", bip32err),
@@ -93,6 +128,10 @@ impl Display for Error {
             Error::InvalidElectrumUrl(url) => write!(f, "Invalid Electrum URL: {}", url),
             Error::PsetAndTxMismatch => write!(f, "PSET and Tx mismatch"),
             Error::TxNotFound(txid) => write!(f, "Transaction not found ({})", txid),
+            Error::TxRejected(reason) => write!(f, "transaction rejected: {}", reason),
+            Error::ConnectionFailed(reason) => write!(f, "connection failed: {}", reason),
+            Error::StoreCorrupted(reason) => write!(f, "store corrupted: {}", reason),
+            Error::InvalidStoreSecret => write!(f, "id_invalid_store_secret"),
         }
     }
 }
@@ -261,7 +300,8 @@ impl From<PoisonError<RwLockWriteGuard<'_, StoreMeta>>> for Error {
 
 impl From<aead::Error> for Error {
     fn from(err: aead::Error) -> Self {
-        Error::Generic(err.to_string())
+        // only raised decrypting the store
+        Error::StoreCorrupted(err.to_string())
     }
 }
 
@@ -1,12 +1,13 @@
-use crate::determine_electrum_url_from_net;
+use crate::chain::{ChainSource, ChainSourceUrl};
 use crate::error::Error;
 use crate::headers::bitcoin::HeadersChain;
 use crate::headers::liquid::Verifier;
+use crate::request;
 use ::bitcoin::hashes::hex::ToHex;
 use ::bitcoin::hashes::{sha256, sha256d, Hash};
 use aes_gcm_siv::aead::{Aead, NewAead};
 use aes_gcm_siv::{Aes256GcmSiv, Key, Nonce};
-use electrum_client::{Client, ElectrumApi, GetMerkleRes};
+use electrum_client::GetMerkleRes;
 use gdk_common::be::{BETxid, BETxidConvert};
 use gdk_common::model::{
     SPVCommonParams, SPVDownloadHeadersParams, SPVDownloadHeadersResult, SPVVerifyTxParams,
@@ -33,7 +34,7 @@ pub enum ChainOrVerifier {
 }
 
 /// compute the merkle root from the merkle path of a tx in electrum format (note the hash.reverse())
-fn compute_merkle_root<T, N>(txid: &T, merkle: GetMerkleRes) -> Result<N, Error>
+pub(crate) fn compute_merkle_root<T, N>(txid: &T, merkle: GetMerkleRes) -> Result<N, Error>
 where
     T: Hash<Inner = [u8; 32]>, // bitcoin::Txid or elements::Txid
     N: Hash<Inner = [u8; 32]>, // bitcoin::TxMerkleNode or elements::TxMerkleNode
@@ -58,20 +59,33 @@ where
     Ok(N::from_slice(&current)?)
 }
 
+/// Headers downloaded by every request of `download_headers`, between them the request can be
+/// cancelled
+const HEADERS_PER_BATCH: usize = 144;
+
 lazy_static! {
     static ref SPV_MUTEX: Mutex<()> = Mutex::new(());
 }
 
 trait ParamsMethods {
-    fn build_client(&self) -> Result<Client, Error>;
+    fn build_source(&self) -> Result<Box<dyn ChainSource>, Error>;
     fn headers_chain(&self) -> Result<HeadersChain, Error>;
     fn verified_cache(&self) -> Result<VerifiedCache, Error>;
 }
 
 impl ParamsMethods for SPVCommonParams {
-    fn build_client(&self) -> Result<Client, Error> {
-        let url = determine_electrum_url_from_net(&self.network)?;
-        url.build_client_with_proxy_and_timeout(&self.tor_proxy, self.timeout)
+    fn build_source(&self) -> Result<Box<dyn ChainSource>, Error> {
+        // the same backend of the session
+        let server_type = self
+            .server_type
+            .as_deref()
+            .unwrap_or_else(|| ChainSourceUrl::default_server_type(&self.network));
+        match ChainSourceUrl::from_network(&self.network, server_type)? {
+            ChainSourceUrl::Electrum(url) => Ok(Box::new(
+                url.build_client_with_proxy_and_timeout(&self.tor_proxy, self.timeout)?,
+            )),
+            url => url.build_source(self.tor_proxy.as_deref()),
+        }
     }
     fn headers_chain(&self) -> Result<HeadersChain, Error> {
         let network = self
@@ -95,21 +109,35 @@ pub fn download_headers(
     let _ = SPV_MUTEX.lock().unwrap();
 
     info!("download_headers {:?}", input);
-    let client = input.params.build_client()?;
+    let source = input.params.build_source()?;
     let mut chain = input.params.headers_chain()?;
     let headers_to_download = input.headers_to_download.unwrap_or(2016);
-    let headers = client.block_headers(chain.height() as usize + 1, headers_to_download)?.headers;
+    let mut downloaded = 0;
     let mut reorg_happened = false;
-    if let Err(Error::InvalidHeaders) = chain.push(headers) {
-        warn!(
-            "invalid headers, possible reorg, invalidating latest headers and latest verified tx"
-        );
-        // handle reorgs, using 144 as a super safe bet, should be parametrized per network
-        let mut cache = input.params.verified_cache()?;
-        chain.remove(144)?;
-        cache.remove(144)?;
-        reorg_happened = true;
+    while downloaded < headers_to_download {
+        request::check_cancelled()?;
+        request::progress("download_headers", downloaded as u64, Some(headers_to_download as u64));
+        let count = HEADERS_PER_BATCH.min(headers_to_download - downloaded);
+        let headers = source.headers(chain.height() + 1, count)?;
+        let received = headers.len();
+        if let Err(Error::InvalidHeaders) = chain.push(headers) {
+            warn!(
+                "invalid headers, possible reorg, invalidating latest headers and latest verified tx"
+            );
+            // handle reorgs, using 144 as a super safe bet, should be parametrized per network
+            let mut cache = input.params.verified_cache()?;
+            chain.remove(144)?;
+            cache.remove(144)?;
+            reorg_happened = true;
+            break;
+        }
+        downloaded += received;
+        if received < count {
+            // the tip is reached
+            break;
+        }
     }
+    request::progress("download_headers", downloaded as u64, Some(headers_to_download as u64));
     Ok(SPVDownloadHeadersResult {
         height: chain.height(),
         reorg: reorg_happened,
@@ -133,7 +161,7 @@ pub fn spv_verify_tx(input: &SPVVerifyTxParams) -> Result<SPVVerifyTxResult, Err
         return Ok(SPVVerifyTxResult::Verified);
     }
 
-    let client = input.params.build_client()?;
+    let source = input.params.build_source()?;
 
     match input.params.network.id() {
         NetworkId::Bitcoin(_bitcoin_network) => {
@@ -142,7 +170,7 @@ pub fn spv_verify_tx(input: &SPVVerifyTxParams) -> Result<SPVVerifyTxResult, Err
             if input.height <= chain.height() {
                 let btxid = txid.ref_bitcoin().unwrap();
                 info!("chain height ({}) enough to verify, downloading proof", chain.height());
-                let proof = match client.transaction_get_merkle(btxid, input.height as usize) {
+                let proof = match source.merkle_proof(btxid, input.height) {
                     Ok(proof) => proof,
                     Err(e) => {
                         warn!("failed fetching merkle inclusion proof for {}: {:?}", txid, e);
@@ -162,16 +190,18 @@ pub fn spv_verify_tx(input: &SPVVerifyTxParams) -> Result<SPVVerifyTxResult, Err
             }
         }
         NetworkId::Elements(elements_network) => {
-            let proof =
-                match client.transaction_get_merkle(&txid.into_bitcoin(), input.height as usize) {
-                    Ok(proof) => proof,
-                    Err(e) => {
-                        warn!("failed fetching merkle inclusion proof for {}: {:?}", txid, e);
-                        return Ok(SPVVerifyTxResult::NotVerified);
-                    }
-                };
+            let proof = match source.merkle_proof(&txid.into_bitcoin(), input.height) {
+                Ok(proof) => proof,
+                Err(e) => {
+                    warn!("failed fetching merkle inclusion proof for {}: {:?}", txid, e);
+                    return Ok(SPVVerifyTxResult::NotVerified);
+                }
+            };
             let verifier = Verifier::new(elements_network);
-            let header_bytes = client.block_header_raw(input.height as usize)?;
+            let header_bytes = source
+                .headers_raw(&[input.height])?
+                .pop()
+                .ok_or_else(|| Error::Generic("missing header".into()))?;
             let header: elements::BlockHeader = elements::encode::deserialize(&header_bytes)?;
             if verifier.verify_tx_proof(txid.ref_elements().unwrap(), proof, &header).is_ok() {
                 cache.write(&txid, input.height)?;
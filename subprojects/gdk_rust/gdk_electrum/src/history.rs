@@ -0,0 +1,103 @@
+//! Balance history of an account, sampled after each block or each day having transactions of
+//! the account. Only confirmed transactions are accounted for.
+
+use gdk_common::model::{BalanceInterval, BalancePoint, Balances};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// The balance changes of the transactions of the account confirmed in a block
+#[derive(Debug, Clone)]
+pub struct BlockChanges {
+    pub height: u32,
+    /// The block time in seconds, if the header is known
+    pub time: Option<u32>,
+    pub satoshi: Balances,
+}
+
+/// Accumulate `changes`, sorted by height, in the balance series sampled by `interval`
+pub fn balance_series(changes: &[BlockChanges], interval: BalanceInterval) -> Vec<BalancePoint> {
+    let mut points: Vec<BalancePoint> = vec![];
+    let mut balance = Balances::new();
+    let mut current_day = None;
+    for block in changes {
+        for (asset, value) in block.satoshi.iter() {
+            *balance.entry(asset.clone()).or_default() += value;
+        }
+        match interval {
+            BalanceInterval::Block => points.push(BalancePoint {
+                height: block.height,
+                timestamp: block.time.map(|t| t as u64 * 1_000_000),
+                satoshi: balance.clone(),
+            }),
+            BalanceInterval::Day => {
+                // blocks with an unknown time are counted in the day of the previous block
+                let day = block.time.map(|t| t as u64 / SECONDS_PER_DAY).or(current_day);
+                match points.last_mut() {
+                    Some(last) if day == current_day => {
+                        last.height = block.height;
+                        last.satoshi = balance.clone();
+                    }
+                    _ => points.push(BalancePoint {
+                        height: block.height,
+                        timestamp: day.map(|d| d * SECONDS_PER_DAY * 1_000_000),
+                        satoshi: balance.clone(),
+                    }),
+                }
+                current_day = day;
+            }
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(height: u32, time: Option<u32>, satoshi: i64) -> BlockChanges {
+        let mut balances = Balances::new();
+        balances.insert("btc".into(), satoshi);
+        BlockChanges {
+            height,
+            time,
+            satoshi: balances,
+        }
+    }
+
+    fn values(points: &[BalancePoint]) -> Vec<(u32, Option<u64>, i64)> {
+        points.iter().map(|p| (p.height, p.timestamp, p.satoshi["btc"])).collect()
+    }
+
+    #[test]
+    fn test_balance_series() {
+        let day = SECONDS_PER_DAY as u32;
+        let changes = vec![
+            block(100, Some(10 * day + 5), 1_000),
+            block(101, Some(10 * day + 600), -300),
+            block(102, None, 50),
+            block(150, Some(12 * day), 2_000),
+        ];
+
+        let by_block = balance_series(&changes, BalanceInterval::Block);
+        assert_eq!(
+            values(&by_block),
+            vec![
+                (100, Some((10 * day as u64 + 5) * 1_000_000), 1_000),
+                (101, Some((10 * day as u64 + 600) * 1_000_000), 700),
+                (102, None, 750),
+                (150, Some(12 * day as u64 * 1_000_000), 2_750),
+            ]
+        );
+
+        let by_day = balance_series(&changes, BalanceInterval::Day);
+        assert_eq!(
+            values(&by_day),
+            vec![
+                (102, Some(10 * day as u64 * 1_000_000), 750),
+                (150, Some(12 * day as u64 * 1_000_000), 2_750),
+            ]
+        );
+
+        assert!(balance_series(&[], BalanceInterval::Day).is_empty());
+    }
+}
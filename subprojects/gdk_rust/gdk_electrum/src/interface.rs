@@ -1,20 +1,31 @@
-use bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::PrivateKey;
 use serde::{Deserialize, Serialize};
 
-use gdk_common::mnemonic::Mnemonic;
+use gdk_common::mnemonic::{Mnemonic, SeedType};
 use gdk_common::model::{
-    AddressPointer, Balances, CreateAccountOpt, CreateTransaction, GetBalanceOpt,
-    GetTransactionsOpt, GetUnspentOpt, Settings, TransactionMeta, UpdateAccountOpt,
+    AddressPointer, BalanceHistory, Balances, CreateAccountOpt, CreateTransaction,
+    GetBalanceHistoryOpt, GetBalanceOpt, GetExpiringUtxosOpt, GetExpiringUtxosResult,
+    GetMultisigXpubOpt, GetMultisigXpubResult, GetTransactionsOpt, GetUnspentOpt, PayjoinOpt,
+    ProofOfReservesOpt, ProofOfReservesResult, Settings, SignMessageOpt, SignMessageResult,
+    TransactionMeta, UpdateAccountOpt,
 };
 use gdk_common::network::Network;
 use gdk_common::scripts::ScriptType;
 use gdk_common::wally::*;
 
 use crate::account::{
-    discover_accounts, get_account_script_purpose, get_last_next_account_nums, Account,
+    discover_accounts, get_account_script_purpose, get_last_next_account_nums, get_multisig_xpub,
+    Account, ELECTRUM_SEGWIT_ACCOUNT, ELECTRUM_STANDARD_ACCOUNT,
 };
+use crate::chain::ChainSource;
 use crate::error::*;
+use crate::multisig::Multisig;
+use crate::payjoin;
+use crate::policy::Policy;
 use crate::store::*;
+use crate::sweep::{self, SweepUtxo};
 
 use electrum_client::{Client, ConfigBuilder};
 use gdk_common::be::*;
@@ -25,6 +36,7 @@ use std::str::FromStr;
 pub struct WalletCtx {
     pub network: Network,
     pub mnemonic: Mnemonic,
+    pub seed_type: SeedType,
     pub store: Store,
     pub master_xprv: ExtendedPrivKey,
     pub master_xpub: ExtendedPubKey,
@@ -108,6 +120,7 @@ impl WalletCtx {
     pub fn new(
         store: Store,
         mnemonic: Mnemonic,
+        seed_type: SeedType,
         network: Network,
         master_xprv: ExtendedPrivKey,
         master_xpub: ExtendedPubKey,
@@ -115,6 +128,7 @@ impl WalletCtx {
     ) -> Result<Self, Error> {
         let mut wallet = WalletCtx {
             mnemonic,
+            seed_type,
             store: store.clone(),
             network, // TODO: from db
             master_xprv,
@@ -128,6 +142,16 @@ impl WalletCtx {
             wallet._ensure_account(account_num, false)?;
         }
         wallet._ensure_account(0, false)?;
+        // the funds of an Electrum wallet are all in its single account
+        match seed_type {
+            SeedType::Bip39 => (),
+            SeedType::ElectrumSegwit => {
+                wallet._ensure_account(ELECTRUM_SEGWIT_ACCOUNT, false)?;
+            }
+            SeedType::ElectrumStandard => {
+                wallet._ensure_account(ELECTRUM_STANDARD_ACCOUNT, false)?;
+            }
+        }
 
         Ok(wallet)
     }
@@ -172,26 +196,77 @@ impl WalletCtx {
                 bail!(Error::AccountGapsDisallowed);
             }
         }
+        match (script_type, &opt.multisig) {
+            (ScriptType::P2wsh, Some(multisig)) => {
+                if self.network.liquid {
+                    bail!(Error::InvalidMultisig("not supported on Liquid".into()));
+                }
+                // validate before persisting the cosigners
+                let (xpub, path) =
+                    get_multisig_xpub(&self.master_xprv, opt.subaccount, self.network.id())?;
+                Multisig::new(multisig, &xpub, self.master_xprv.fingerprint(&crate::EC), &path)?;
+                self.store.write()?.set_account_multisig(opt.subaccount, multisig.clone());
+            }
+            (ScriptType::P2wsh, None) => {
+                bail!(Error::InvalidMultisig("missing cosigners".into()));
+            }
+            (_, Some(_)) => {
+                bail!(Error::InvalidMultisig("not a multisig subaccount".into()));
+            }
+            (_, None) => (),
+        }
+        match (script_type, &opt.policy) {
+            (ScriptType::P2wshMiniscript, Some(policy)) => {
+                if self.network.liquid {
+                    bail!(Error::InvalidPolicy("not supported on Liquid".into()));
+                }
+                // validate before persisting the descriptor
+                let (xpub, _) =
+                    get_multisig_xpub(&self.master_xprv, opt.subaccount, self.network.id())?;
+                Policy::new(policy, &xpub)?;
+                self.store.write()?.set_account_policy(opt.subaccount, policy.clone());
+            }
+            (ScriptType::P2wshMiniscript, None) => {
+                bail!(Error::InvalidPolicy("missing descriptor".into()));
+            }
+            (_, Some(_)) => {
+                bail!(Error::InvalidPolicy("not a miniscript subaccount".into()));
+            }
+            (_, None) => (),
+        }
 
         let account = self._ensure_account(opt.subaccount, false)?;
         account.set_name(&opt.name)?;
         Ok(account)
     }
 
+    pub fn get_multisig_xpub(
+        &self,
+        opt: &GetMultisigXpubOpt,
+    ) -> Result<GetMultisigXpubResult, Error> {
+        if self.network.liquid {
+            bail!(Error::InvalidMultisig("not supported on Liquid".into()));
+        }
+        let (xpub, path) = get_multisig_xpub(&self.master_xprv, opt.subaccount, self.network.id())?;
+        Ok(GetMultisigXpubResult {
+            xpub: xpub.to_string(),
+            path: path.to_string(),
+        })
+    }
+
     pub fn update_account(&mut self, opt: UpdateAccountOpt) -> Result<(), Error> {
         self.get_account(opt.subaccount)?.set_settings(opt)
     }
 
-    pub fn recover_accounts(
-        &mut self,
-        electrum_url: &ElectrumUrl,
-        proxy: Option<&str>,
-    ) -> Result<Vec<u32>, Error> {
+    pub fn recover_accounts(&mut self, source: &dyn ChainSource) -> Result<Vec<u32>, Error> {
+        if self.seed_type != SeedType::Bip39 {
+            // Electrum does not use BIP44 accounts
+            return Ok(vec![]);
+        }
         let account_nums = discover_accounts(
             &self.master_xprv,
             self.network.id(),
-            electrum_url,
-            proxy,
+            source,
             self.master_blinding.as_ref(),
             &self.accounts.keys().cloned().collect::<Vec<u32>>(),
         )?;
@@ -214,6 +289,7 @@ impl WalletCtx {
                 self.master_blinding.clone(),
                 self.store.clone(),
                 account_num,
+                self.seed_type,
                 discovered,
             )?),
         })
@@ -251,6 +327,46 @@ impl WalletCtx {
         self.get_account(request.subaccount)?.create_tx(request)
     }
 
+    /// Create the transaction sweeping `utxos` of `private_key` to a new address of the
+    /// subaccount, the fee rate defaults to the estimate for the blocks of the settings
+    pub fn create_sweep_tx(
+        &self,
+        request: &mut CreateTransaction,
+        private_key: &PrivateKey,
+        utxos: &[SweepUtxo],
+    ) -> Result<TransactionMeta, Error> {
+        let account = self.get_account(request.subaccount)?;
+        if request.fee_rate.is_none() {
+            let store = self.store.read()?;
+            let estimates = store.fee_estimates();
+            let blocks = store.get_settings().unwrap_or_default().required_num_blocks as usize;
+            request.fee_rate = Some(estimates[blocks.min(estimates.len() - 1)].0);
+        }
+        // the address is reserved only when the sweep is sent, failed attempts and fee estimates
+        // would otherwise burn addresses
+        let address = account.peek_next_address()?.address;
+        sweep::create_sweep_tx(&self.network, &address, request, private_key, utxos)
+    }
+
+    pub fn payjoin_original(
+        &self,
+        request: &TransactionMeta,
+    ) -> Result<(payjoin::Original, Vec<DerivationPath>), Error> {
+        self.get_account(payjoin_account(request)?)?.payjoin_original(request)
+    }
+
+    pub fn sign_payjoin(
+        &self,
+        request: &TransactionMeta,
+        original: &payjoin::Original,
+        paths: &[DerivationPath],
+        proposal: &PartiallySignedTransaction,
+        opt: &PayjoinOpt,
+    ) -> Result<TransactionMeta, Error> {
+        self.get_account(payjoin_account(request)?)?
+            .sign_payjoin(request, original, paths, proposal, opt)
+    }
+
     pub fn sign(&self, request: &TransactionMeta) -> Result<TransactionMeta, Error> {
         let account_num = request
             .create_transaction
@@ -264,6 +380,28 @@ impl WalletCtx {
         self.get_account(account_num)?.get_next_address()
     }
 
+    pub fn sign_message(&self, opt: &SignMessageOpt) -> Result<SignMessageResult, Error> {
+        self.get_account(opt.subaccount)?.sign_message(opt)
+    }
+
+    pub fn balance_history(&self, opt: &GetBalanceHistoryOpt) -> Result<BalanceHistory, Error> {
+        self.get_account(opt.subaccount)?.balance_history(opt)
+    }
+
+    pub fn expiring_utxos(
+        &self,
+        opt: &GetExpiringUtxosOpt,
+    ) -> Result<GetExpiringUtxosResult, Error> {
+        self.get_account(opt.subaccount)?.expiring_utxos(opt)
+    }
+
+    pub fn proof_of_reserves(
+        &self,
+        opt: &ProofOfReservesOpt,
+    ) -> Result<ProofOfReservesResult, Error> {
+        self.get_account(opt.subaccount)?.proof_of_reserves(opt)
+    }
+
     pub fn get_asset_icons(&self) -> Result<Option<serde_json::Value>, Error> {
         self.store.read()?.read_asset_icons()
     }
@@ -272,6 +410,15 @@ impl WalletCtx {
     }
 }
 
+/// The subaccount of the signed payjoin `request`
+fn payjoin_account(request: &TransactionMeta) -> Result<u32, Error> {
+    request
+        .create_transaction
+        .as_ref()
+        .map(|c| c.subaccount)
+        .ok_or_else(|| Error::Generic("Cannot payjoin without tx data".into()))
+}
+
 #[cfg(test)]
 mod test {
     use bitcoin::consensus::deserialize;
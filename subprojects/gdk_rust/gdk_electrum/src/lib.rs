@@ -13,13 +13,28 @@ use log::{debug, info, trace, warn};
 use serde_json::Value;
 
 pub mod account;
+pub mod chain;
+pub mod connection;
 pub mod error;
 pub mod headers;
+pub mod history;
 pub mod interface;
+pub mod message;
+pub mod multisig;
+pub mod payjoin;
 pub mod pin;
+pub mod policy;
 pub mod pset;
+pub mod registry;
+pub mod request;
+pub mod reserves;
 pub mod spv;
+pub mod sweep;
+pub mod uri;
 
+use crate::account::Account;
+use crate::chain::{ChainSource, ChainSourceUrl};
+use crate::connection::ConnectionStatus;
 use crate::error::Error;
 use crate::interface::{ElectrumUrl, WalletCtx};
 use crate::store::*;
@@ -30,7 +45,7 @@ use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
 
 use electrum_client::GetHistoryRes;
 use gdk_common::be::*;
-use gdk_common::mnemonic::Mnemonic;
+use gdk_common::mnemonic::{Mnemonic, SeedType};
 use gdk_common::model::*;
 use gdk_common::network::{aqua_unique_id_and_xpub, Network};
 use gdk_common::password::Password;
@@ -50,21 +65,27 @@ use std::{iter, sync, thread};
 use crate::headers::bitcoin::HeadersChain;
 use crate::headers::liquid::Verifier;
 use crate::headers::ChainOrVerifier;
+use crate::pin::local::LocalPin;
 use crate::pin::PinManager;
+use crate::registry;
+use crate::request;
+use crate::reserves;
 use crate::spv::SpvCrossValidator;
+use crate::sweep;
+use crate::uri;
 use aes::Aes256;
 use bitcoin::blockdata::constants::DIFFCHANGE_INTERVAL;
 use block_modes::block_padding::Pkcs7;
 use block_modes::BlockMode;
 use block_modes::Cbc;
-use electrum_client::{Client, ElectrumApi};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use rand::Rng;
+use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::Hasher;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{mpsc, Arc, RwLock};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread::JoinHandle;
 
 const CROSS_VALIDATION_RATE: u8 = 4; // Once every 4 thread loop runs, or roughly 28 seconds
@@ -80,16 +101,33 @@ lazy_static! {
 
 type Aes256Cbc = Cbc<Aes256, Pkcs7>;
 
+thread_local! {
+    /// The notifications held back on this thread, see [`Notifier::hold`]
+    static HELD: RefCell<Option<Vec<(Notifier, Notification)>>> = RefCell::new(None);
+}
+
 struct Syncer {
     wallet: Arc<RwLock<WalletCtx>>,
     store: Store,
     master_blinding: Option<MasterBlindingKey>,
     network: Network,
+    /// The agent and base url used to fetch the registry entries of the wallet assets
+    registry: Option<(ureq::Agent, String)>,
+}
+
+/// The changes found by a sync, transactions in more than one account are notified once
+#[derive(Default, Debug)]
+pub struct SyncUpdates {
+    pub accounts: HashSet<u32>,
+    pub new_txs: Vec<TransactionNotification>,
+    pub confirmed_txs: Vec<TransactionNotification>,
+    pub replaced_txs: Vec<ReplacementNotification>,
 }
 
 pub struct Tipper {
     pub store: Store,
     pub network: Network,
+    pub connection: Arc<Mutex<ConnectionStatus>>,
 }
 
 pub struct Headers {
@@ -98,12 +136,83 @@ pub struct Headers {
     pub cross_validator: Option<SpvCrossValidator>,
 }
 
+/// A C callback receiving the notifications as json strings, with its context
 #[derive(Clone)]
 pub struct NativeNotif(
-    pub Option<(extern "C" fn(*const libc::c_void, *const libc::c_char), *const libc::c_void)>,
+    pub extern "C" fn(*const libc::c_void, *const libc::c_char),
+    pub *const libc::c_void,
 );
 unsafe impl Send for NativeNotif {}
 
+/// Where the session delivers its notifications
+#[derive(Clone)]
+pub enum NotificationHandler {
+    None,
+    Native(NativeNotif),
+    Channel(mpsc::Sender<Notification>),
+}
+
+/// The notification handler shared with the background threads, so that swapping it applies to
+/// the threads already running
+#[derive(Clone)]
+pub struct Notifier(Arc<Mutex<NotificationHandler>>);
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Notifier(Arc::new(Mutex::new(NotificationHandler::None)))
+    }
+}
+
+impl Notifier {
+    pub fn set(&self, handler: NotificationHandler) {
+        *self.0.lock().unwrap() = handler;
+    }
+
+    /// Run `f` holding back the notifications sent on this thread, which are delivered once it
+    /// returns, so that `f` may hold locks that the handler needs when calling back
+    pub fn hold<T, F: FnOnce() -> T>(f: F) -> T {
+        if HELD.with(|held| held.borrow().is_some()) {
+            return f();
+        }
+        HELD.with(|held| *held.borrow_mut() = Some(vec![]));
+        let result = f();
+        for (notifier, data) in HELD.with(|held| held.borrow_mut().take()).unwrap_or_default() {
+            notifier.deliver(data);
+        }
+        result
+    }
+
+    /// Deliver `data` to the current handler, or later if held back by [`Notifier::hold`]
+    pub fn send(&self, data: Notification) {
+        let data = HELD.with(|held| match *held.borrow_mut() {
+            Some(ref mut notifications) => {
+                notifications.push((self.clone(), data));
+                None
+            }
+            None => Some(data),
+        });
+        if let Some(data) = data {
+            self.deliver(data);
+        }
+    }
+
+    fn deliver(&self, data: Notification) {
+        // cloned so that the lock is not held while the handler runs
+        let handler = self.0.lock().unwrap().clone();
+        match handler {
+            NotificationHandler::Native(NativeNotif(handler, self_context)) => {
+                handler(self_context, make_str(data.to_value().to_string()))
+            }
+            NotificationHandler::Channel(sender) => {
+                if sender.send(data).is_err() {
+                    warn!("notification receiver dropped");
+                }
+            }
+            NotificationHandler::None => warn!("no registered handler to receive notification"),
+        }
+    }
+}
+
 pub struct Closer {
     pub terminates: Option<Arc<AtomicBool>>,
     pub handles: Vec<JoinHandle<()>>,
@@ -132,11 +241,12 @@ pub struct ElectrumSession {
     pub proxy: Option<String>,
     pub timeout: Option<u8>,
     pub network: Network,
-    pub url: ElectrumUrl,
+    pub url: ChainSourceUrl,
     pub wallet: Option<Arc<RwLock<WalletCtx>>>,
-    pub notify: NativeNotif,
+    pub notify: Notifier,
     pub closer: Closer,
     pub state: State,
+    pub connection: Arc<Mutex<ConnectionStatus>>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -146,34 +256,37 @@ pub enum State {
     Logged,
 }
 
-fn notify(notif: NativeNotif, data: Value, terminated: Arc<AtomicBool>) {
+fn notify(notif: Notifier, data: Notification, terminated: Arc<AtomicBool>) {
     info!("push notification: {:?}", data);
     if terminated.load(Ordering::Relaxed) {
         warn!("terminated signal already received, skipping notification");
         return;
     }
-    if let Some((handler, self_context)) = notif.0 {
-        handler(self_context, make_str(data.to_string()));
-    } else {
-        warn!("no registered handler to receive notification");
-    }
-}
-
-fn notify_block(notif: NativeNotif, height: u32, terminated: Arc<AtomicBool>) {
-    let data = json!({"block":{"block_height":height},"event":"block"});
-    notify(notif, data, terminated);
-}
-
-fn notify_settings(notif: NativeNotif, settings: &Settings, terminated: Arc<AtomicBool>) {
-    let data = json!({"settings":settings,"event":"settings"});
-    notify(notif, data, terminated);
+    notif.send(data);
 }
 
-fn notify_updated_txs(notif: NativeNotif, account_num: u32, terminated: Arc<AtomicBool>) {
-    // This is used as a signal to trigger syncing via get_transactions, the transaction
-    // list contained here is ignored and can be just a mock.
-    let mockup_json = json!({"event":"transaction","transaction":{"subaccounts":[account_num]}});
-    notify(notif, mockup_json, terminated);
+fn notify_sync_updates(notif: Notifier, updates: SyncUpdates, terminated: Arc<AtomicBool>) {
+    let mut notified_accounts = HashSet::new();
+    let mut notifications = vec![];
+    for tx in updates.new_txs {
+        notified_accounts.extend(tx.subaccounts.iter().cloned());
+        notifications.push(Notification::Transaction(tx));
+    }
+    for tx in updates.confirmed_txs {
+        notified_accounts.extend(tx.subaccounts.iter().cloned());
+        notifications.push(Notification::Confirmation(tx));
+    }
+    for replacement in updates.replaced_txs {
+        notified_accounts.extend(replacement.subaccounts.iter().cloned());
+        notifications.push(Notification::Replacement(replacement));
+    }
+    // accounts changed without events, for instance for a dropped tx, still need to reload
+    for account_num in updates.accounts.difference(&notified_accounts) {
+        notifications.push(Notification::SubaccountUpdated(*account_num));
+    }
+    for notification in notifications {
+        notify(notif.clone(), notification, terminated.clone());
+    }
 }
 
 fn determine_electrum_url(
@@ -197,6 +310,17 @@ pub fn determine_electrum_url_from_net(network: &Network) -> Result<ElectrumUrl,
     determine_electrum_url(&network.electrum_url, network.electrum_tls, network.validate_domain)
 }
 
+pub fn determine_esplora_url_from_net(network: &Network) -> Result<String, Error> {
+    let url = network
+        .esplora_url
+        .as_ref()
+        .ok_or_else(|| Error::Generic("network esplora url is missing".into()))?;
+    if url == "" {
+        return Err(Error::Generic("network esplora url is empty".into()));
+    }
+    Ok(url.into())
+}
+
 fn socksify(proxy: Option<&str>) -> Option<String> {
     const SOCKS5: &str = "socks5://";
     if let Some(proxy) = proxy {
@@ -218,7 +342,7 @@ impl ElectrumSession {
         network: Network,
         db_root: &str,
         proxy: Option<&str>,
-        url: ElectrumUrl,
+        url: ChainSourceUrl,
     ) -> Self {
         Self {
             data_root: db_root.to_string(),
@@ -226,16 +350,26 @@ impl ElectrumSession {
             network,
             url,
             wallet: None,
-            notify: NativeNotif(None),
+            notify: Notifier::default(),
             closer: Closer {
                 terminates: None,
                 handles: vec![],
             },
             state: State::Disconnected,
             timeout: None,
+            connection: Default::default(),
         }
     }
 
+    /// Deliver the notifications to the returned channel, replacing the current handler.
+    ///
+    /// The background threads already running deliver to the returned channel as well.
+    pub fn notifications(&self) -> mpsc::Receiver<Notification> {
+        let (sender, receiver) = mpsc::channel();
+        self.notify.set(NotificationHandler::Channel(sender));
+        receiver
+    }
+
     pub fn get_wallet(&self) -> Result<sync::RwLockReadGuard<WalletCtx>, Error> {
         let wallet =
             self.wallet.as_ref().ok_or_else(|| Error::Generic("wallet not initialized".into()))?;
@@ -259,20 +393,141 @@ impl ElectrumSession {
             None => Ok(ureq::agent()),
         }
     }
+
+    /// Validate the assets defined by the user, the registry stays authoritative so the assets
+    /// it knows, and the policy asset, are rejected
+    fn validate_user_assets(&self, assets: &HashMap<String, UserAsset>) -> Result<(), Error> {
+        let store = self.get_wallet()?.store.clone();
+        let store_read = store.read()?;
+        let registry = store_read.read_asset_registry()?;
+        for (asset_id, asset) in assets {
+            registry::validate_user_asset(asset_id, asset)?;
+            if Some(asset_id) == self.network.policy_asset.as_ref()
+                || registry::is_registered(
+                    asset_id,
+                    registry.as_ref(),
+                    &store_read.cache.asset_cache,
+                )
+            {
+                return Err(Error::Generic(format!(
+                    "invalid asset {}: it is in the registry",
+                    asset_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The precision of `asset_id` in the cached registry entries or in the assets defined by the
+    /// user, `None` if it is not known or the session is not logged in
+    fn asset_precision(&self, asset_id: &str) -> Option<u8> {
+        let store = self.get_wallet().ok()?.store.clone();
+        let store_read = store.read().ok()?;
+        let cached = store_read.cache.asset_cache.get(asset_id).and_then(|c| c.entry.as_ref());
+        match cached.and_then(|entry| entry["precision"].as_u64()) {
+            Some(precision) => Some(precision as u8),
+            None => store_read.get_user_assets().get(asset_id).map(|a| a.precision),
+        }
+    }
+
+    /// The signed payjoin proposal for `tx`, if its request carries a payjoin endpoint and the
+    /// receiver answers with a valid proposal
+    fn payjoin_proposal(&self, tx: &TransactionMeta) -> Option<TransactionMeta> {
+        let opt = tx.create_transaction.as_ref()?.payjoin.as_ref()?;
+        if self.network.liquid {
+            return None;
+        }
+        // the wallet is not locked while waiting for the receiver
+        let result =
+            self.get_wallet().and_then(|w| w.payjoin_original(tx)).and_then(|(original, paths)| {
+                let agent = self.build_request_agent()?;
+                let url = original.request_url(opt)?;
+                let proposal = original.send(&agent, &url)?;
+                self.get_wallet()?.sign_payjoin(tx, &original, &paths, &proposal, opt)
+            });
+        match result {
+            Ok(proposal) => Some(proposal),
+            Err(e) => {
+                warn!("payjoin with {} failed, sending the original: {}", opt.url, e);
+                None
+            }
+        }
+    }
+
+    /// Sweep all the coins of the WIF `private_key` to the subaccount of `tx_req`
+    fn create_sweep_transaction(
+        &self,
+        tx_req: &mut CreateTransaction,
+        private_key: &str,
+    ) -> Result<TransactionMeta, Error> {
+        if self.network.liquid {
+            return Err(Error::Generic("sweeping is not supported on Liquid".into()));
+        }
+        let private_key =
+            bitcoin::PrivateKey::from_wif(private_key).map_err(|_| Error::InvalidPrivateKey)?;
+        if (private_key.network == bitcoin::Network::Bitcoin) != self.network.mainnet {
+            return Err(Error::InvalidPrivateKey);
+        }
+        let source = self.url.build_source(self.proxy.as_deref())?;
+        let utxos = sweep::discover_utxos(&*source, &private_key)?;
+        if utxos.is_empty() {
+            return Err(Error::InsufficientFunds);
+        }
+        self.get_wallet()?.create_sweep_tx(tx_req, &private_key, &utxos)
+    }
 }
 
-fn try_get_fee_estimates(client: &Client) -> Result<Vec<FeeEstimate>, Error> {
-    let relay_fee = (client.relay_fee()? * 100_000_000.0) as u64;
-    let blocks: Vec<usize> = (1..25).collect();
-    // max is covering a rounding errors in production electrs which sometimes cause a fee
-    // estimates lower than relay fee
-    let mut estimates: Vec<FeeEstimate> = client
-        .batch_estimate_fee(blocks)?
-        .iter()
-        .map(|e| FeeEstimate(relay_fee.max((*e * 100_000_000.0) as u64)))
-        .collect();
-    estimates.insert(0, FeeEstimate(relay_fee));
-    Ok(estimates)
+/// Builds an [`ElectrumSession`] from a [`Network`], the entry point of the Rust API.
+///
+/// The session methods are the ones of the [`Session`] trait, taking and returning the types of
+/// `gdk_common::model`, the notifications are received with [`ElectrumSession::notifications`].
+pub struct ElectrumSessionBuilder {
+    network: Network,
+    data_root: String,
+    proxy: Option<String>,
+    server_type: Option<String>,
+}
+
+impl ElectrumSessionBuilder {
+    pub fn new(network: Network) -> Self {
+        ElectrumSessionBuilder {
+            network,
+            data_root: String::new(),
+            proxy: None,
+            server_type: None,
+        }
+    }
+
+    /// Directory where the wallet data is persisted
+    pub fn data_root(mut self, data_root: &str) -> Self {
+        self.data_root = data_root.to_string();
+        self
+    }
+
+    pub fn proxy(mut self, proxy: &str) -> Self {
+        self.proxy = Some(proxy.to_string());
+        self
+    }
+
+    /// "electrum" or "esplora", if not set esplora is used only if the network has no electrum url
+    pub fn server_type(mut self, server_type: &str) -> Self {
+        self.server_type = Some(server_type.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<ElectrumSession, Error> {
+        let server_type = self
+            .server_type
+            .as_deref()
+            .unwrap_or_else(|| ChainSourceUrl::default_server_type(&self.network));
+        let url = ChainSourceUrl::from_network(&self.network, server_type)?;
+        Ok(ElectrumSession::create_session(
+            self.network,
+            &self.data_root,
+            self.proxy.as_deref(),
+            url,
+        ))
+    }
 }
 
 pub fn make_txlist_item(
@@ -394,26 +649,35 @@ pub fn make_txlist_item(
         transaction_size: tx.size,
         transaction_vsize: tx.vsize,
         transaction_weight: tx.weight,
+        cursor: TxCursor {
+            height: tx.height,
+            txid: tx.txid.clone(),
+        }
+        .to_string(),
     }
 }
 
 impl Session<Error> for ElectrumSession {
     // type Value = ElectrumSession;
 
-    fn poll_session(&self) -> Result<(), Error> {
-        Err(Error::Generic("implementme: ElectrumSession poll_session".into()))
+    fn poll_session(&self) -> Result<PollSessionResult, Error> {
+        let tip_height = match self.get_wallet() {
+            Ok(wallet) => wallet.store.read()?.cache.tip.0,
+            Err(_) => 0,
+        };
+        Ok(self.connection.lock().unwrap().poll(self.url.url(), tip_height))
     }
 
     fn connect(&mut self, _net_params: &Value) -> Result<(), Error> {
         info!("connect network:{:?} state:{:?}", self.network, self.state);
 
         if self.state == State::Disconnected {
-            let mnemonic = match self.get_mnemonic() {
-                Ok(mnemonic) => Some(mnemonic.clone()),
-                Err(_) => None,
-            };
-            match mnemonic {
-                Some(mnemonic) => self.login(&mnemonic, None).map(|_| ())?,
+            let wallet =
+                self.get_wallet().ok().map(|wallet| (wallet.mnemonic.clone(), wallet.seed_type));
+            match wallet {
+                Some((mnemonic, seed_type)) => {
+                    self.login(&mnemonic, None, None, Some(seed_type)).map(|_| ())?
+                }
                 None => self.state = State::Connected,
             }
         }
@@ -437,8 +701,12 @@ impl Session<Error> for ElectrumSession {
         pin: String,
         details: PinGetDetails,
     ) -> Result<String, Error> {
+        if LocalPin::is_local(&details) {
+            return LocalPin::new(&self.data_root).get_mnemonic(pin.as_bytes(), &details);
+        }
         let agent = self.build_request_agent()?;
-        let manager = PinManager::new(agent)?;
+        let url = self.network.pin_server_url();
+        let manager = PinManager::new(agent, url, self.network.pin_server_public_key())?;
         let client_key = SecretKey::from_slice(&Vec::<u8>::from_hex(&details.pin_identifier)?)?;
         let server_key = manager.get_pin(pin.as_bytes(), &client_key)?;
         let iv = Vec::<u8>::from_hex(&details.salt)?;
@@ -448,7 +716,7 @@ impl Session<Error> for ElectrumSession {
         let mnemonic = decipher
             .decrypt_vec(&Vec::<u8>::from_hex(&details.encrypted_data)?)
             .map_err(|_| Error::InvalidPin)?;
-        let mnemonic = std::str::from_utf8(&mnemonic).unwrap().to_string();
+        let mnemonic = String::from_utf8(mnemonic).map_err(|_| Error::InvalidPin)?;
         Ok(mnemonic)
     }
 
@@ -456,6 +724,8 @@ impl Session<Error> for ElectrumSession {
         &mut self,
         mnemonic: &Mnemonic,
         password: Option<Password>,
+        store_secret: Option<Password>,
+        seed_type: Option<SeedType>,
     ) -> Result<LoginData, Error> {
         info!("login {:?} {:?}", self.network, self.state);
 
@@ -465,17 +735,36 @@ impl Session<Error> for ElectrumSession {
         if self.state == State::Logged {
             return Ok(LoginData {
                 wallet_hash_id: self.network.wallet_hash_id(&self.get_wallet()?.master_xpub),
+                ambiguous_seed_type: None,
             });
         }
 
+        *self.connection.lock().unwrap() = ConnectionStatus::default();
+
         // TODO: passphrase?
 
-        let mnem_str = mnemonic.clone().get_mnemonic_str();
-        let seed = wally::bip39_mnemonic_to_seed(
-            &mnem_str,
-            &password.map(|p| p.get_password_str()).unwrap_or_default(),
-        )
-        .ok_or(Error::InvalidMnemonic)?;
+        let ambiguous_seed_type = match seed_type {
+            Some(_) => None,
+            None => mnemonic.ambiguous_seed_type(),
+        };
+        let seed_type = mnemonic.seed_type(seed_type).map_err(Error::InvalidMnemonic)?;
+        info!("mnemonic seed type {:?}", seed_type);
+        if let Some(ambiguous_seed_type) = ambiguous_seed_type {
+            warn!("the mnemonic is also an Electrum {:?} seed", ambiguous_seed_type);
+        }
+        let passphrase = password.map(|p| p.get_password_str()).unwrap_or_default();
+        let seed = match seed_type {
+            SeedType::Bip39 => {
+                let mnem_str = mnemonic.clone().get_mnemonic_str();
+                wally::bip39_mnemonic_to_seed(&mnem_str, &passphrase).ok_or_else(|| {
+                    Error::Generic("cannot derive the seed from the mnemonic".into())
+                })?
+            }
+            _ if self.network.liquid => {
+                return Err(Error::Generic("Electrum seeds are not supported on Liquid".into()))
+            }
+            _ => mnemonic.electrum_seed(&passphrase),
+        };
 
         let master_xprv = ExtendedPrivKey::new_master(self.network.bip32_network(), &seed)?;
         let master_xpub = ExtendedPubKey::from_private(&EC, &master_xprv);
@@ -512,6 +801,7 @@ impl Session<Error> for ElectrumSession {
             Err(_) => Arc::new(RwLock::new(StoreMeta::new(
                 &path,
                 master_xpub,
+                store_secret.map(|s| s.get_password_str()).as_deref(),
                 fallback_path,
                 fallback_xpub,
                 self.network.id(),
@@ -519,25 +809,22 @@ impl Session<Error> for ElectrumSession {
         };
 
         let mut tip_height = store.read()?.cache.tip.0;
-        notify_block(self.notify.clone(), tip_height, self.closer.terminates()?);
+        notify(self.notify.clone(), Notification::Block(tip_height), self.closer.terminates()?);
 
         info!(
             "building client, url {}, proxy {}",
             self.url.url(),
             self.proxy.as_ref().unwrap_or(&"".to_string())
         );
-        if let Ok(fee_client) = self.url.build_client(self.proxy.as_deref()) {
-            info!("building built end");
-            let fee_store = store.clone();
-            thread::spawn(move || {
-                match try_get_fee_estimates(&fee_client) {
-                    Ok(fee_estimates) => {
-                        fee_store.write().unwrap().cache.fee_estimates = fee_estimates
-                    }
-                    Err(e) => warn!("can't update fee estimates {:?}", e),
-                };
-            });
-        }
+        let fee_store = store.clone();
+        let fee_url = self.url.clone();
+        let proxy = self.proxy.clone();
+        thread::spawn(move || {
+            match fee_url.build_source(proxy.as_deref()).and_then(|s| s.fee_estimates()) {
+                Ok(fee_estimates) => fee_store.write().unwrap().cache.fee_estimates = fee_estimates,
+                Err(e) => warn!("can't update fee estimates {:?}", e),
+            };
+        });
 
         let sync_interval = self.network.sync_interval.unwrap_or(7);
 
@@ -576,13 +863,14 @@ impl Session<Error> for ElectrumSession {
                         break;
                     }
 
-                    if let Ok(client) = headers_url.build_client(proxy.as_deref()) {
+                    if let Ok(source) = headers_url.build_source(proxy.as_deref()) {
+                        let spv_before = headers.spv_statuses();
                         loop {
                             if terminates.load(Ordering::Relaxed) {
                                 info!("closing headers thread");
                                 break 'outer;
                             }
-                            match headers.ask(chunk_size, &client) {
+                            match headers.ask(chunk_size, &*source) {
                                 Ok(headers_found) => {
                                     if headers_found < chunk_size {
                                         break;
@@ -607,7 +895,7 @@ impl Session<Error> for ElectrumSession {
                             }
                         }
 
-                        match headers.get_proofs(&client) {
+                        match headers.get_proofs(&*source) {
                             Ok(found) => {
                                 if found > 0 {
                                     info!("found proof {}", found)
@@ -617,17 +905,29 @@ impl Session<Error> for ElectrumSession {
                         }
 
                         if round % CROSS_VALIDATION_RATE == 0 {
-                            let status_changed = headers.cross_validate();
-                            if status_changed {
-                                // TODO account number
-                                notify_updated_txs(
-                                    notify_headers.clone(),
-                                    0u32.into(),
-                                    terminates.clone(),
-                                );
+                            if headers.cross_validate() {
+                                info!("cross validation status changed");
                             }
                         }
 
+                        match (spv_before, headers.spv_statuses()) {
+                            (Ok(before), Ok(after)) => {
+                                for (txid, spv) in after.iter() {
+                                    let changed = before
+                                        .get(txid)
+                                        .map_or(false, |b| b.spv_verified != spv.spv_verified);
+                                    if changed {
+                                        notify(
+                                            notify_headers.clone(),
+                                            Notification::Spv(spv.clone()),
+                                            terminates.clone(),
+                                        );
+                                    }
+                                }
+                            }
+                            (Err(e), _) | (_, Err(e)) => warn!("can't read spv statuses {:?}", e),
+                        }
+
                         round = round.wrapping_add(1);
                     }
                 }
@@ -641,6 +941,7 @@ impl Session<Error> for ElectrumSession {
                 let wallet = Arc::new(RwLock::new(WalletCtx::new(
                     store.clone(),
                     mnemonic.clone(),
+                    seed_type,
                     self.network.clone(),
                     master_xprv,
                     master_xpub,
@@ -651,16 +952,22 @@ impl Session<Error> for ElectrumSession {
             }
         };
 
+        let registry = match self.network.registry_base_url() {
+            Ok(base_url) if self.network.liquid => Some((self.build_request_agent()?, base_url)),
+            _ => None,
+        };
         let syncer = Syncer {
             wallet: wallet.clone(),
             store: store.clone(),
             master_blinding,
             network: self.network.clone(),
+            registry,
         };
 
         let tipper = Tipper {
             store: store.clone(),
             network: self.network.clone(),
+            connection: self.connection.clone(),
         };
 
         info!("login STATUS block:{:?} tx:{}", self.block_status()?, self.tx_status()?);
@@ -673,13 +980,14 @@ impl Session<Error> for ElectrumSession {
         let tipper_handle = thread::spawn(move || {
             info!("starting tipper thread");
             loop {
-                if let Ok(client) = tipper_url.build_client(proxy.as_deref()) {
-                    match tipper.tip(&client) {
+                if let Ok(source) = tipper_url.build_source(proxy.as_deref()) {
+                    match tipper.tip(&*source) {
                         Ok(current_tip) => {
                             if tip_height != current_tip {
                                 tip_height = current_tip;
                                 info!("tip is {:?}", tip_height);
-                                notify_block(notify_blocks.clone(), tip_height, terminates.clone());
+                                let block = Notification::Block(tip_height);
+                                notify(notify_blocks.clone(), block, terminates.clone());
                             }
                         }
                         Err(e) => {
@@ -699,24 +1007,44 @@ impl Session<Error> for ElectrumSession {
         let notify_txs = self.notify.clone();
         let syncer_url = self.url.clone();
         let proxy = self.proxy.clone();
+        let connection = self.connection.clone();
         let syncer_handle = thread::spawn(move || {
             info!("starting syncer thread");
+            // the lock is released before notifying, the handler may poll the session
+            let update_connection =
+                |update: &dyn Fn(&mut ConnectionStatus) -> Option<ConnectionState>| {
+                    let mut connection = connection.lock().unwrap();
+                    let state = update(&mut connection)?;
+                    info!("connection state is {:?}", state);
+                    Some(connection.notification(syncer_url.url()))
+                };
             loop {
-                match syncer_url.build_client(proxy.as_deref()) {
-                    Ok(client) => match syncer.sync(&client) {
-                        Ok(updated_accounts) => {
-                            for account_num in updated_accounts {
-                                info!("there are new transactions");
-                                notify_updated_txs(
-                                    notify_txs.clone(),
-                                    account_num,
-                                    terminates.clone(),
-                                );
-                            }
+                if let Some(network) = update_connection(&|c| c.attempt()) {
+                    notify(notify_txs.clone(), Notification::Network(network), terminates.clone());
+                }
+                let result = syncer_url
+                    .build_source(proxy.as_deref())
+                    .and_then(|source| syncer.sync(&*source));
+                let network = match result {
+                    Ok(updates) => {
+                        let network = update_connection(&|c| c.succeeded());
+                        if !updates.accounts.is_empty() {
+                            info!("there are new transactions");
+                            notify_sync_updates(notify_txs.clone(), updates, terminates.clone());
                         }
-                        Err(e) => warn!("Error during sync, {:?}", e),
-                    },
-                    Err(e) => warn!("Can't build client {:?}", e),
+                        if let Err(e) = syncer.update_assets() {
+                            warn!("Error fetching the wallet assets, {:?}", e);
+                        }
+                        network
+                    }
+                    Err(e) => {
+                        warn!("Error during sync, {:?}", e);
+                        let retry_in = Duration::from_secs(sync_interval as u64);
+                        update_connection(&|c| c.failed(retry_in))
+                    }
+                };
+                if let Some(network) = network {
+                    notify(notify_txs.clone(), Notification::Network(network), terminates.clone());
                 }
                 if wait_or_close(&terminates, sync_interval) {
                     info!("closing syncer thread");
@@ -726,11 +1054,13 @@ impl Session<Error> for ElectrumSession {
         });
         self.closer.handles.push(syncer_handle);
 
-        notify_settings(self.notify.clone(), &self.get_settings()?, self.closer.terminates()?);
+        let settings = Notification::Settings(self.get_settings()?);
+        notify(self.notify.clone(), settings, self.closer.terminates()?);
 
         self.state = State::Logged;
         Ok(LoginData {
             wallet_hash_id: self.network.wallet_hash_id(&master_xpub),
+            ambiguous_seed_type,
         })
     }
 
@@ -741,9 +1071,45 @@ impl Session<Error> for ElectrumSession {
         Ok(address)
     }
 
+    fn sign_message(&self, opt: &SignMessageOpt) -> Result<SignMessageResult, Error> {
+        info!("sign_message subaccount {} address {}", opt.subaccount, opt.address);
+        self.get_wallet()?.sign_message(opt)
+    }
+
+    fn create_proof_of_reserves(
+        &self,
+        opt: &ProofOfReservesOpt,
+    ) -> Result<ProofOfReservesResult, Error> {
+        self.get_wallet()?.proof_of_reserves(opt)
+    }
+
+    fn verify_proof_of_reserves(
+        &self,
+        opt: &VerifyProofOfReservesOpt,
+    ) -> Result<VerifyProofOfReservesResult, Error> {
+        if self.network.liquid {
+            return Err(Error::Generic("proof of reserves is not supported on Liquid".into()));
+        }
+        let source = self.url.build_source(self.proxy.as_deref())?;
+        reserves::verify_proof(&*source, opt)
+    }
+
+    fn parse_uri(&self, opt: &ParseUriOpt) -> Result<ParseUriResult, Error> {
+        uri::parse(&self.network, opt, |asset_id| self.asset_precision(asset_id))
+    }
+
+    fn create_uri(&self, opt: &CreateUriOpt) -> Result<CreateUriResult, Error> {
+        uri::create(&self.network, opt, |asset_id| self.asset_precision(asset_id))
+    }
+
     fn set_pin(&self, details: &PinSetDetails) -> Result<PinGetDetails, Error> {
+        if self.network.offline_pin.unwrap_or(false) {
+            let local = LocalPin::new(&self.data_root);
+            return local.set_pin(details.pin.as_bytes(), &details.mnemonic);
+        }
         let agent = self.build_request_agent()?;
-        let manager = PinManager::new(agent)?;
+        let url = self.network.pin_server_url();
+        let manager = PinManager::new(agent, url, self.network.pin_server_public_key())?;
         let client_key = SecretKey::new(&mut thread_rng());
         let server_key = manager.set_pin(details.pin.as_bytes(), &client_key)?;
         let iv = thread_rng().gen::<[u8; 16]>();
@@ -760,11 +1126,10 @@ impl Session<Error> for ElectrumSession {
 
     fn get_subaccounts(&mut self, refresh: bool) -> Result<Vec<AccountInfo>, Error> {
         if refresh {
-            let url = self.url.clone();
-            let proxy = self.proxy.clone();
+            let source = self.url.build_source(self.proxy.as_deref())?;
             let mut wallet = self.get_wallet_mut()?;
 
-            wallet.recover_accounts(&url, proxy.as_deref())?;
+            wallet.recover_accounts(&*source)?;
         }
 
         self.get_wallet()?.iter_accounts_sorted().map(|a| a.info()).collect()
@@ -784,6 +1149,10 @@ impl Session<Error> for ElectrumSession {
         Ok(self.get_wallet()?.get_next_subaccount(opt.script_type))
     }
 
+    fn get_multisig_xpub(&self, opt: &GetMultisigXpubOpt) -> Result<GetMultisigXpubResult, Error> {
+        self.get_wallet()?.get_multisig_xpub(opt)
+    }
+
     fn rename_subaccount(&mut self, opt: RenameAccountOpt) -> Result<(), Error> {
         self.get_wallet_mut()?.update_account(UpdateAccountOpt {
             subaccount: opt.subaccount,
@@ -843,6 +1212,17 @@ impl Session<Error> for ElectrumSession {
         self.get_wallet()?.balance(opt)
     }
 
+    fn get_balance_history(&self, opt: &GetBalanceHistoryOpt) -> Result<BalanceHistory, Error> {
+        self.get_wallet()?.balance_history(opt)
+    }
+
+    fn get_expiring_utxos(
+        &self,
+        opt: &GetExpiringUtxosOpt,
+    ) -> Result<GetExpiringUtxosResult, Error> {
+        self.get_wallet()?.expiring_utxos(opt)
+    }
+
     fn set_transaction_memo(&self, txid: &str, memo: &str) -> Result<(), Error> {
         let txid = BETxid::from_hex(txid, self.network.id())?;
         if memo.len() > 1024 {
@@ -853,12 +1233,12 @@ impl Session<Error> for ElectrumSession {
         Ok(())
     }
 
-    fn create_transaction(
-        &mut self,
-        tx_req: &mut CreateTransaction,
-    ) -> Result<TransactionMeta, Error> {
+    fn create_transaction(&self, tx_req: &mut CreateTransaction) -> Result<TransactionMeta, Error> {
         info!("electrum create_transaction {:?}", tx_req);
 
+        if let Some(private_key) = tx_req.private_key.clone() {
+            return self.create_sweep_transaction(tx_req, &private_key.0);
+        }
         self.get_wallet()?.create_tx(tx_req)
     }
 
@@ -867,24 +1247,46 @@ impl Session<Error> for ElectrumSession {
         self.get_wallet()?.sign(create_tx)
     }
 
-    fn send_transaction(&mut self, tx: &TransactionMeta) -> Result<TransactionMeta, Error> {
+    fn send_transaction(&self, tx: &TransactionMeta) -> Result<TransactionMeta, Error> {
         info!("electrum send_transaction {:#?}", tx);
-        let client = self.url.build_client(self.proxy.as_deref())?;
-        let tx_bytes = Vec::<u8>::from_hex(&tx.hex)?;
-        let txid = client.transaction_broadcast_raw(&tx_bytes)?;
-        if let Some(memo) = tx.create_transaction.as_ref().and_then(|o| o.memo.as_ref()) {
-            self.get_wallet()?.store.write()?.insert_memo(txid.into(), memo)?;
+        let source = self.url.build_source(self.proxy.as_deref())?;
+        // the original transaction is broadcast if the payjoin fails at any step
+        let sent = match self.payjoin_proposal(tx) {
+            Some(proposal) => match source.broadcast(&Vec::<u8>::from_hex(&proposal.hex)?) {
+                Ok(_) => proposal,
+                Err(e) => {
+                    warn!("payjoin proposal broadcast failed, sending the original: {:?}", e);
+                    source.broadcast(&Vec::<u8>::from_hex(&tx.hex)?)?;
+                    tx.clone()
+                }
+            },
+            None => {
+                source.broadcast(&Vec::<u8>::from_hex(&tx.hex)?)?;
+                tx.clone()
+            }
+        };
+        let txid = BETxid::from_hex(&sent.txid, self.network.id())?;
+        if let Some(memo) = sent.create_transaction.as_ref().and_then(|o| o.memo.as_ref()) {
+            self.get_wallet()?.store.write()?.insert_memo(txid, memo)?;
         }
-        Ok(tx.clone())
+        if let (true, Some(create_transaction)) = (sent.is_sweep, sent.create_transaction.as_ref())
+        {
+            let wallet = self.get_wallet()?;
+            let account = wallet.get_account(create_transaction.subaccount)?;
+            for addressee in create_transaction.addressees.iter() {
+                account.reserve_address(&addressee.address)?;
+            }
+        }
+        Ok(sent)
     }
 
     fn broadcast_transaction(&mut self, tx_hex: &str) -> Result<String, Error> {
         let transaction = BETransaction::from_hex(&tx_hex, self.network.id())?;
 
         info!("broadcast_transaction {:#?}", transaction.txid());
-        let client = self.url.build_client(self.proxy.as_deref())?;
+        let source = self.url.build_source(self.proxy.as_deref())?;
         let hex = Vec::<u8>::from_hex(tx_hex)?;
-        let txid = client.transaction_broadcast_raw(&hex)?;
+        let txid = source.broadcast(&hex)?;
         Ok(format!("{}", txid))
     }
 
@@ -898,7 +1300,10 @@ impl Session<Error> for ElectrumSession {
             NetworkId::Bitcoin(_) => 1000,
             NetworkId::Elements(_) => 100,
         };
-        let fee_estimates = try_get_fee_estimates(&self.url.build_client(self.proxy.as_deref())?)
+        let fee_estimates = self
+            .url
+            .build_source(self.proxy.as_deref())?
+            .fee_estimates()
             .unwrap_or_else(|_| vec![FeeEstimate(min_fee); 25]);
         self.get_wallet()?.store.write()?.cache.fee_estimates = fee_estimates.clone();
         Ok(fee_estimates)
@@ -918,7 +1323,7 @@ impl Session<Error> for ElectrumSession {
         let mut settings = wallet.get_settings()?;
         settings.update(value);
         self.get_wallet()?.change_settings(&settings)?;
-        notify_settings(self.notify.clone(), &settings, self.closer.terminates()?);
+        notify(self.notify.clone(), Notification::Settings(settings), self.closer.terminates()?);
         Ok(())
     }
 
@@ -937,6 +1342,7 @@ impl Session<Error> for ElectrumSession {
         }
 
         let mut assets = Value::Null;
+        let mut assets_rejected = Value::Null;
         let mut icons = Value::Null;
         let mut assets_last_modified = String::new();
         let mut icons_last_modified = String::new();
@@ -973,17 +1379,24 @@ impl Session<Error> for ElectrumSession {
                 });
             }
 
+            let total = details.assets as u64 + details.icons as u64;
+            request::progress("refresh_assets", 0, Some(total));
             if details.assets {
-                if let Ok(Some(assets_recv)) = rx_assets.recv() {
+                if let Some(Some(assets_recv)) = request::recv(&rx_assets)? {
                     assets = assets_recv.0;
                     assets_last_modified = assets_recv.1;
+                    assets_rejected = assets_recv.2;
                 }
+                request::check_cancelled()?;
+                request::progress("refresh_assets", 1, Some(total));
             }
             if details.icons {
-                if let Ok(Some(icons_recv)) = rx_icons.recv() {
+                if let Some(Some(icons_recv)) = request::recv(&rx_icons)? {
                     icons = icons_recv.0;
                     icons_last_modified = icons_recv.1;
                 }
+                request::check_cancelled()?;
+                request::progress("refresh_assets", total, Some(total));
             }
 
             let wallet = self.get_wallet()?;
@@ -998,9 +1411,10 @@ impl Session<Error> for ElectrumSession {
             }
         }
 
+        let user_assets = self.get_wallet()?.store.read()?.get_user_assets();
         let mut map = serde_json::Map::new();
         if details.assets {
-            let assets_not_null = match assets {
+            let mut assets_not_null = match assets {
                 Value::Object(_) => assets,
                 _ => self
                     .get_wallet()?
@@ -1009,11 +1423,20 @@ impl Session<Error> for ElectrumSession {
                     .read_asset_registry()?
                     .unwrap_or_else(|| get_registry_sentinel()),
             };
+            if let Value::Object(assets) = &mut assets_not_null {
+                // an empty map is the sentinel of missing data, replaced by the inbuilt one
+                if !assets.is_empty() {
+                    registry::merge_user_assets(&user_assets, Some(assets), None);
+                }
+            }
             map.insert("assets".to_string(), assets_not_null);
+            if let Value::Object(_) = assets_rejected {
+                map.insert("assets_rejected".to_string(), assets_rejected);
+            }
         }
 
         if details.icons {
-            let icons_not_null = match icons {
+            let mut icons_not_null = match icons {
                 Value::Object(_) => icons,
                 _ => self
                     .get_wallet()?
@@ -1022,12 +1445,93 @@ impl Session<Error> for ElectrumSession {
                     .read_asset_icons()?
                     .unwrap_or_else(|| get_registry_sentinel()),
             };
+            if let Value::Object(icons) = &mut icons_not_null {
+                if !icons.is_empty() {
+                    registry::merge_user_assets(&user_assets, None, Some(icons));
+                }
+            }
             map.insert("icons".to_string(), icons_not_null);
         }
 
         Ok(Value::Object(map))
     }
 
+    fn get_assets(&self, opt: &GetAssetsOpt) -> Result<Value, Error> {
+        let policy_asset = self
+            .network
+            .policy_asset
+            .clone()
+            .ok_or_else(|| Error::Generic("policy assets not available".into()))?;
+        let store = self.get_wallet()?.store.clone();
+        let assets_id: Vec<String> = match &opt.assets_id {
+            Some(assets_id) => assets_id.clone(),
+            None => store.read()?.wallet_assets().into_iter().collect(),
+        };
+        for asset_id in assets_id.iter() {
+            elements::issuance::AssetId::from_hex(asset_id)
+                .map_err(|_| Error::Generic(format!("invalid asset id {}", asset_id)))?;
+        }
+
+        // the assets defined by the user are not looked up, they may be private
+        let user_assets = store.read()?.get_user_assets();
+        let lookup: Vec<String> = assets_id
+            .iter()
+            .filter(|id| **id != policy_asset && !user_assets.contains_key(*id))
+            .cloned()
+            .collect();
+        let agent = self.build_request_agent()?;
+        let base_url = self.network.registry_base_url()?;
+        registry::update_asset_cache(&store, &agent, &base_url, &lookup, opt.refresh)?;
+
+        let store_read = store.read()?;
+        let mut assets = serde_json::Map::new();
+        for asset_id in assets_id {
+            let entry = if asset_id == policy_asset {
+                Some(registry::policy_asset_entry(&policy_asset))
+            } else {
+                // the registry entries prevail over the user ones
+                let cached = store_read.cache.asset_cache.get(&asset_id);
+                cached.and_then(|c| c.entry.clone()).or_else(|| {
+                    user_assets.get(&asset_id).map(|a| registry::user_asset_entry(&asset_id, a))
+                })
+            };
+            if let Some(entry) = entry {
+                assets.insert(asset_id, entry);
+            }
+        }
+        Ok(json!({ "assets": assets }))
+    }
+
+    fn get_user_assets(&self) -> Result<UserAssets, Error> {
+        Ok(UserAssets {
+            assets: self.get_wallet()?.store.read()?.get_user_assets(),
+        })
+    }
+
+    fn set_user_asset(&self, opt: SetUserAssetOpt) -> Result<(), Error> {
+        let wallet = self.get_wallet()?;
+        match opt.asset {
+            Some(asset) => {
+                let assets = iter::once((opt.asset_id, asset)).collect();
+                self.validate_user_assets(&assets)?;
+                wallet.store.write()?.insert_user_assets(assets)
+            }
+            None => wallet.store.write()?.remove_user_asset(&opt.asset_id),
+        }
+    }
+
+    fn import_user_assets(&self, assets: UserAssets) -> Result<(), Error> {
+        self.validate_user_assets(&assets.assets)?;
+        self.get_wallet()?.store.write()?.insert_user_assets(assets.assets)
+    }
+
+    fn change_store_secret(&self, opt: ChangeStoreSecretOpt) -> Result<(), Error> {
+        self.get_wallet()?
+            .store
+            .write()?
+            .change_secret(opt.current_secret.as_deref(), opt.new_secret.as_deref())
+    }
+
     fn block_status(&self) -> Result<(u32, BEBlockHash), Error> {
         let tip = self.get_wallet()?.get_tip()?;
         info!("tip={:?}", tip);
@@ -1072,7 +1576,6 @@ fn call_icons(
     base_url: String,
     last_modified: String,
 ) -> Result<(Value, String), Error> {
-    // TODO gzip encoding
     let url = format!("{}/{}", base_url, "icons.json");
     info!("START call_icons {}", &url);
     let icons_response = agent
@@ -1080,11 +1583,12 @@ fn call_icons(
         .timeout_connect(15_000)
         .timeout_read(15_000)
         .set("If-Modified-Since", &last_modified)
+        .set("Accept-Encoding", "gzip")
         .call();
     let status = icons_response.status();
     info!("call_icons {} returns {}", &url, status);
     let last_modified = icons_response.header("Last-Modified").unwrap_or_default().to_string();
-    let value = icons_response.into_json()?;
+    let value = registry::read_json(icons_response)?;
     info!("END call_icons {} {}", &url, status);
     Ok((value, last_modified))
 }
@@ -1094,8 +1598,7 @@ fn call_assets(
     base_url: String,
     registry_policy: String,
     last_modified: String,
-) -> Result<(Value, String), Error> {
-    // TODO add gzip encoding
+) -> Result<(Value, String, Value), Error> {
     let url = format!("{}/{}", base_url, "index.json");
     info!("START call_assets {}", &url);
     let assets_response = agent
@@ -1103,24 +1606,28 @@ fn call_assets(
         .timeout_connect(15_000)
         .timeout_read(15_000)
         .set("If-Modified-Since", &last_modified)
+        .set("Accept-Encoding", "gzip")
         .call();
     let status = assets_response.status();
     info!("call_assets {} returns {}", url, status);
     let last_modified = assets_response.header("Last-Modified").unwrap_or_default().to_string();
-    let mut assets = assets_response.into_json()?;
-    assets[registry_policy] =
-        json!({"asset_id": &registry_policy, "name": "Liquid Bitcoin", "ticker": "L-BTC"});
+    let (mut assets, rejected) = registry::verify_registry(registry::read_json(assets_response)?);
+    for (asset_id, reason) in rejected.iter() {
+        warn!("call_assets dropping registry entry {}: {}", asset_id, reason);
+    }
+    assets.insert(registry_policy.clone(), registry::policy_asset_entry(&registry_policy));
     info!("END call_assets {} {}", &url, status);
-    Ok((assets, last_modified))
+    Ok((Value::Object(assets), last_modified, Value::Object(rejected)))
 }
 
 impl Tipper {
-    pub fn tip(&self, client: &Client) -> Result<u32, Error> {
-        let header = client.block_headers_subscribe_raw()?;
-        let height = header.height as u32;
+    pub fn tip(&self, source: &dyn ChainSource) -> Result<u32, Error> {
+        let (height, header) = source.tip()?;
+        let header = BEBlockHeader::deserialize(&header, self.network.id())?;
+        self.connection.lock().unwrap().set_tip_time(header.time());
         let tip_height = self.store.read()?.cache.tip.0;
         if height != tip_height {
-            let hash = BEBlockHeader::deserialize(&header.header, self.network.id())?.block_hash();
+            let hash = header.block_hash();
             info!("saving in store new tip {:?}", (height, hash));
             self.store.write()?.cache.tip = (height, hash);
         }
@@ -1129,10 +1636,10 @@ impl Tipper {
 }
 
 impl Headers {
-    pub fn ask(&mut self, chunk_size: usize, client: &Client) -> Result<usize, Error> {
+    pub fn ask(&mut self, chunk_size: usize, source: &dyn ChainSource) -> Result<usize, Error> {
         if let ChainOrVerifier::Chain(chain) = &mut self.checker {
             info!("asking headers, current height:{} chunk_size:{} ", chain.height(), chunk_size);
-            let headers = client.block_headers(chain.height() as usize + 1, chunk_size)?.headers;
+            let headers = source.headers(chain.height() + 1, chunk_size)?;
             let len = headers.len();
             chain.push(headers)?;
             Ok(len)
@@ -1142,7 +1649,7 @@ impl Headers {
         }
     }
 
-    pub fn get_proofs(&mut self, client: &Client) -> Result<usize, Error> {
+    pub fn get_proofs(&mut self, source: &dyn ChainSource) -> Result<usize, Error> {
         let mut proofs_done = 0;
         let account_nums = self.store.read()?.account_nums();
 
@@ -1172,9 +1679,7 @@ impl Headers {
 
             let mut txs_verified = HashMap::new();
             for (txid, height) in needs_proof {
-                let verified = match client
-                    .transaction_get_merkle(&txid.into_bitcoin(), height as usize)
-                {
+                let verified = match source.merkle_proof(&txid.into_bitcoin(), height) {
                     Ok(proof) => match &self.checker {
                         ChainOrVerifier::Chain(chain) => chain
                             .verify_tx_proof(txid.ref_bitcoin().unwrap(), height, proof)
@@ -1218,6 +1723,32 @@ impl Headers {
         Ok(proofs_done)
     }
 
+    /// The SPV status of the confirmed transactions of every account
+    pub fn spv_statuses(&self) -> Result<HashMap<BETxid, SpvNotification>, Error> {
+        let store = self.store.read()?;
+        let mut result: HashMap<BETxid, SpvNotification> = HashMap::new();
+        for account_num in store.account_nums() {
+            let acc_store = store.account_cache(account_num)?;
+            for (txid, height) in acc_store.heights.iter() {
+                let height = match height {
+                    Some(height) => *height,
+                    None => continue,
+                };
+                result
+                    .entry(txid.clone())
+                    .or_insert_with(|| SpvNotification {
+                        txhash: txid.to_hex(),
+                        subaccounts: vec![],
+                        block_height: height,
+                        spv_verified: store.spv_verification_status(account_num, txid).to_string(),
+                    })
+                    .subaccounts
+                    .push(account_num);
+            }
+        }
+        Ok(result)
+    }
+
     pub fn remove(&mut self, headers: u32) -> Result<(), Error> {
         if let ChainOrVerifier::Chain(chain) = &mut self.checker {
             chain.remove(headers)?;
@@ -1255,14 +1786,117 @@ struct DownloadTxResult {
     unblinds: Vec<(elements::OutPoint, elements::TxOutSecrets)>,
 }
 
+impl SyncUpdates {
+    /// Compare the account history with the one before the sync to find the transaction events
+    fn add_account_changes(
+        &mut self,
+        account: &Account,
+        old_heights: &HashMap<BETxid, Option<u32>>,
+        store: &Store,
+    ) -> Result<(), Error> {
+        self.accounts.insert(account.num());
+
+        let (added, height_changed, replaced) = {
+            let store_read = store.read()?;
+            let acc_store = store_read.account_cache(account.num())?;
+            let added: Vec<BETxid> = acc_store
+                .heights
+                .keys()
+                .filter(|txid| !old_heights.contains_key(txid))
+                .cloned()
+                .collect();
+            let height_changed: Vec<BETxid> = acc_store
+                .heights
+                .iter()
+                .filter(|(txid, height)| old_heights.get(txid).map_or(false, |old| old != *height))
+                .map(|(txid, _)| txid.clone())
+                .collect();
+
+            // a dropped tx is replaced by a new one spending some of its inputs
+            let mut replaced = vec![];
+            for txid in old_heights.keys().filter(|txid| !acc_store.heights.contains_key(txid)) {
+                let spent = match acc_store.all_txs.get(txid) {
+                    Some(txe) => txe.tx.previous_outputs(),
+                    None => continue,
+                };
+                let replacement = added.iter().find(|new_txid| {
+                    acc_store.all_txs.get(new_txid).map_or(false, |txe| {
+                        txe.tx.previous_outputs().iter().any(|outpoint| spent.contains(outpoint))
+                    })
+                });
+                if let Some(replacement) = replacement {
+                    replaced.push(ReplacementNotification {
+                        txhash: txid.to_hex(),
+                        replaced_by: replacement.to_hex(),
+                        subaccounts: vec![account.num()],
+                    });
+                }
+            }
+            (added, height_changed, replaced)
+        };
+
+        for txid in added {
+            if let Some(tx) = account.tx_notification(&txid)? {
+                merge_tx_notification(&mut self.new_txs, tx);
+            }
+        }
+        for txid in height_changed {
+            if let Some(tx) = account.tx_notification(&txid)? {
+                merge_tx_notification(&mut self.confirmed_txs, tx);
+            }
+        }
+        for replacement in replaced {
+            match self.replaced_txs.iter_mut().find(|r| r.txhash == replacement.txhash) {
+                Some(existing) => existing.subaccounts.extend(replacement.subaccounts),
+                None => self.replaced_txs.push(replacement),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add `tx` to `txs`, merging it with the notification of the same tx from another account
+fn merge_tx_notification(txs: &mut Vec<TransactionNotification>, tx: TransactionNotification) {
+    match txs.iter_mut().find(|t| t.txhash == tx.txhash) {
+        Some(existing) => {
+            existing.subaccounts.extend(tx.subaccounts);
+            for (asset, satoshi) in tx.satoshi {
+                *existing.satoshi.entry(asset).or_default() += satoshi;
+            }
+            // funds moved between subaccounts of the wallet
+            if existing.type_ != tx.type_ {
+                existing.type_ = "redeposit".to_string();
+            }
+        }
+        None => txs.push(tx),
+    }
+}
+
 impl Syncer {
-    /// Sync the wallet, return the set of updated accounts
-    pub fn sync(&self, client: &Client) -> Result<HashSet<u32>, Error> {
+    /// Fetch the registry entries of the assets received by the wallet, if missing or expired
+    pub fn update_assets(&self) -> Result<(), Error> {
+        if let Some((agent, base_url)) = &self.registry {
+            let policy_asset = self.network.policy_asset.as_deref();
+            let store_read = self.store.read()?;
+            let user_assets = store_read.get_user_assets();
+            let assets_id: Vec<String> = store_read
+                .wallet_assets()
+                .into_iter()
+                .filter(|id| Some(id.as_str()) != policy_asset && !user_assets.contains_key(id))
+                .collect();
+            drop(store_read);
+            registry::update_asset_cache(&self.store, agent, base_url, &assets_id, false)?;
+        }
+        Ok(())
+    }
+
+    /// Sync the wallet, return the updated accounts and the transaction events
+    pub fn sync(&self, source: &dyn ChainSource) -> Result<SyncUpdates, Error> {
         debug!("start sync");
         let start = Instant::now();
 
         let wallet = self.wallet.read().unwrap();
-        let mut updated_accounts = HashSet::new();
+        let mut updates = SyncUpdates::default();
 
         for account in wallet.iter_accounts() {
             let mut history_txs_id = HashSet::<BETxid>::new();
@@ -1277,11 +1911,10 @@ impl Syncer {
                 let mut batch_count = 0;
                 loop {
                     let batch = account.get_script_batch(i == 1, batch_count)?;
-                    // convert the BEScript into bitcoin::Script for electrum-client
+                    // convert the BEScript into bitcoin::Script for the chain source
                     let b_scripts =
                         batch.value.iter().map(|e| e.0.clone().into_bitcoin()).collect::<Vec<_>>();
-                    let result: Vec<Vec<GetHistoryRes>> =
-                        client.batch_script_get_history(b_scripts.iter())?;
+                    let result: Vec<Vec<GetHistoryRes>> = source.scripts_history(&b_scripts)?;
                     if !batch.cached {
                         scripts.extend(batch.value);
                     }
@@ -1327,8 +1960,8 @@ impl Syncer {
                 }
             }
 
-            let new_txs = self.download_txs(account.num(), &history_txs_id, &scripts, &client)?;
-            let headers = self.download_headers(account.num(), &heights_set, &client)?;
+            let new_txs = self.download_txs(account.num(), &history_txs_id, &scripts, source)?;
+            let headers = self.download_headers(account.num(), &heights_set, source)?;
 
             let store_read = self.store.read()?;
             let acc_store = store_read.account_cache(account.num())?;
@@ -1364,7 +1997,7 @@ impl Syncer {
 
                 // height map is used for the live list of transactions, since due to reorg or rbf tx
                 // could disappear from the list, we clear the list and keep only the last values returned by the server
-                acc_store.heights.clear();
+                let old_heights = std::mem::take(&mut acc_store.heights);
                 acc_store.heights.extend(txid_height.into_iter());
                 acc_store.scripts.extend(scripts.clone().into_iter().map(|(a, b)| (b, a)));
                 acc_store.paths.extend(scripts.into_iter());
@@ -1372,11 +2005,11 @@ impl Syncer {
                 store_write.flush()?;
                 drop(store_write);
 
-                updated_accounts.insert(account.num());
-
                 // the transactions are first indexed into the db and then verified so that all the prevouts
                 // and scripts are available for querying. invalid transactions will be removed by verify_own_txs.
                 account.verify_own_txs(&new_txs.txs)?;
+
+                updates.add_account_changes(account, &old_heights, &self.store)?;
                 true
             } else {
                 false
@@ -1389,14 +2022,14 @@ impl Syncer {
             );
         }
 
-        Ok(updated_accounts)
+        Ok(updates)
     }
 
     fn download_headers(
         &self,
         account_num: u32,
         heights_set: &HashSet<u32>,
-        client: &Client,
+        source: &dyn ChainSource,
     ) -> Result<Vec<(u32, BEBlockHeader)>, Error> {
         let heights_in_db: HashSet<u32> = {
             let store_read = self.store.read()?;
@@ -1408,8 +2041,7 @@ impl Syncer {
         let heights_to_download: Vec<u32> =
             heights_set.difference(&heights_in_db).cloned().collect();
         if !heights_to_download.is_empty() {
-            let headers_bytes_downloaded =
-                client.batch_block_header_raw(heights_to_download.clone())?;
+            let headers_bytes_downloaded = source.headers_raw(&heights_to_download)?;
             let mut headers_downloaded: Vec<BEBlockHeader> = vec![];
             for vec in headers_bytes_downloaded {
                 headers_downloaded.push(BEBlockHeader::deserialize(&vec, self.network.id())?);
@@ -1430,18 +2062,18 @@ impl Syncer {
         account_num: u32,
         history_txs_id: &HashSet<BETxid>,
         scripts: &HashMap<BEScript, DerivationPath>,
-        client: &Client,
+        source: &dyn ChainSource,
     ) -> Result<DownloadTxResult, Error> {
         let mut txs = vec![];
         let mut unblinds = vec![];
 
         let mut txs_in_db =
             self.store.read()?.account_cache(account_num)?.all_txs.keys().cloned().collect();
-        // BETxid has to be converted into bitcoin::Txid for rust-electrum-client
+        // BETxid has to be converted into bitcoin::Txid for the chain source
         let txs_to_download: Vec<bitcoin::Txid> =
             history_txs_id.difference(&txs_in_db).map(BETxidConvert::into_bitcoin).collect();
         if !txs_to_download.is_empty() {
-            let txs_bytes_downloaded = client.batch_transaction_get_raw(txs_to_download.iter())?;
+            let txs_bytes_downloaded = source.transactions_raw(&txs_to_download)?;
             let mut txs_downloaded: Vec<BETransaction> = vec![];
             for vec in txs_bytes_downloaded {
                 let tx = BETransaction::deserialize(&vec, self.network.id())?;
@@ -1490,8 +2122,7 @@ impl Syncer {
                 .collect();
 
             if !txs_to_download.is_empty() {
-                let txs_bytes_downloaded =
-                    client.batch_transaction_get_raw(txs_to_download.iter())?;
+                let txs_bytes_downloaded = source.transactions_raw(&txs_to_download)?;
                 for vec in txs_bytes_downloaded {
                     let mut tx = BETransaction::deserialize(&vec, self.network.id())?;
                     tx.strip_witness();
@@ -1559,3 +2190,326 @@ fn wait_or_close(terminate: &Arc<AtomicBool>, interval: u32) -> bool {
 fn get_registry_sentinel() -> Value {
     json!({})
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chain::MemoryChainSource;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHeader, OutPoint, Script, Transaction, TxIn, TxOut};
+    use std::str::FromStr;
+    use tempfile::TempDir;
+
+    fn header(prev_blockhash: bitcoin::BlockHash) -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_blockhash,
+            merkle_root: Default::default(),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+        }
+    }
+
+    fn tx(previous_output: OutPoint, script_pubkey: Script, value: u64) -> BETransaction {
+        BETransaction::Bitcoin(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: Script::default(),
+                sequence: 0xffff_fffd,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey,
+            }],
+        })
+    }
+
+    /// A chain with 2 blocks, the second contains a coinbase-like tx to spend from
+    fn source(network: &Network) -> (MemoryChainSource, OutPoint) {
+        let source = MemoryChainSource::new(network.id());
+        let genesis = header(Default::default());
+        source.push_header(bitcoin::consensus::serialize(&genesis));
+        source.push_header(bitcoin::consensus::serialize(&header(genesis.block_hash())));
+        let parent = tx(OutPoint::null(), Script::from(vec![0x51]), 20_000);
+        let outpoint = OutPoint::new(parent.txid().into_bitcoin(), 0);
+        source.insert_tx(parent, Some(1));
+        (source, outpoint)
+    }
+
+    fn syncer(network: Network) -> Syncer {
+        let mut dir = TempDir::new().unwrap().into_path();
+        dir.push("store");
+        let master_xprv = ExtendedPrivKey::new_master(network.bip32_network(), &[7u8; 32]).unwrap();
+        let master_xpub = ExtendedPubKey::from_private(&EC, &master_xprv);
+        let store = Arc::new(RwLock::new(
+            StoreMeta::new(&dir, master_xpub, None, None, None, network.id()).unwrap(),
+        ));
+        let wallet = WalletCtx::new(
+            store.clone(),
+            Mnemonic::from(String::new()),
+            SeedType::Bip39,
+            network.clone(),
+            master_xprv,
+            master_xpub,
+            None,
+        )
+        .unwrap();
+        Syncer {
+            wallet: Arc::new(RwLock::new(wallet)),
+            store,
+            master_blinding: None,
+            network,
+            registry: None,
+        }
+    }
+
+    fn wallet_script(syncer: &Syncer) -> Script {
+        let address = syncer.wallet.read().unwrap().get_next_address(0).unwrap().address;
+        bitcoin::Address::from_str(&address).unwrap().script_pubkey()
+    }
+
+    fn btc_balance(syncer: &Syncer) -> i64 {
+        let opt = GetBalanceOpt {
+            subaccount: 0,
+            num_confs: 0,
+            confidential_utxos_only: None,
+        };
+        syncer.wallet.read().unwrap().balance(&opt).unwrap()["btc"]
+    }
+
+    #[test]
+    fn test_sync_memory_source() {
+        let network = Network::default();
+        let syncer = syncer(network.clone());
+        let (source, outpoint) = source(&network);
+
+        let tx = tx(outpoint, wallet_script(&syncer), 10_000);
+        let txid = tx.txid().into_bitcoin();
+        source.insert_tx(tx, Some(1));
+
+        let tipper = Tipper {
+            store: syncer.store.clone(),
+            network: network.clone(),
+            connection: Default::default(),
+        };
+        assert_eq!(tipper.tip(&source).unwrap(), 1);
+        assert_eq!(syncer.store.read().unwrap().cache.tip.0, 1);
+        assert!(tipper.connection.lock().unwrap().poll("", 1).tip_age.is_some());
+
+        let updates = syncer.sync(&source).unwrap();
+        assert!(updates.accounts.contains(&0));
+        assert_eq!(updates.new_txs.len(), 1);
+        let notification = &updates.new_txs[0];
+        assert_eq!(notification.txhash, txid.to_hex());
+        assert_eq!(notification.subaccounts, vec![0]);
+        assert_eq!(notification.type_, "incoming");
+        assert_eq!(notification.satoshi.get("btc"), Some(&10_000));
+        assert_eq!(notification.block_height, 1);
+        assert_eq!(btc_balance(&syncer), 10_000);
+        // nothing changed on the chain
+        assert!(syncer.sync(&source).unwrap().accounts.is_empty());
+
+        // the tx disappears, as it happens after a reorg
+        source.remove_tx(&txid);
+        let updates = syncer.sync(&source).unwrap();
+        assert!(updates.accounts.contains(&0));
+        assert!(updates.new_txs.is_empty());
+        assert!(updates.replaced_txs.is_empty());
+        assert_eq!(btc_balance(&syncer), 0);
+    }
+
+    #[test]
+    fn test_reserve_address() {
+        let syncer = syncer(Network::default());
+        let wallet = syncer.wallet.read().unwrap();
+        let account = wallet.get_account(0).unwrap();
+
+        let peeked = account.peek_next_address().unwrap();
+        assert_eq!(account.peek_next_address().unwrap().address, peeked.address);
+        account.reserve_address(&peeked.address).unwrap();
+        assert_ne!(account.peek_next_address().unwrap().address, peeked.address);
+
+        // an address handed out in between is not reserved twice
+        let peeked = account.peek_next_address().unwrap();
+        let next = account.get_next_address().unwrap();
+        assert_eq!(next.address, peeked.address);
+        account.reserve_address(&peeked.address).unwrap();
+        assert_eq!(account.get_next_address().unwrap().pointer, next.pointer + 1);
+    }
+
+    #[test]
+    fn test_sign_payjoin() {
+        let network = Network::default();
+        let syncer = syncer(network.clone());
+        let (source, outpoint) = source(&network);
+        let script = wallet_script(&syncer);
+        let funding = tx(outpoint, script.clone(), 10_000);
+        let funding_outpoint = OutPoint::new(funding.txid().into_bitcoin(), 0);
+        source.insert_tx(funding, Some(1));
+        syncer.sync(&source).unwrap();
+
+        let wallet = syncer.wallet.read().unwrap();
+        let opt = GetUnspentOpt {
+            subaccount: 0,
+            num_confs: None,
+            confidential_utxos_only: None,
+            all_coins: None,
+        };
+        let mut utxos = HashMap::new();
+        for (outpoint, info) in wallet.utxos(&opt).unwrap().iter() {
+            utxos
+                .entry(info.asset.clone())
+                .or_insert(vec![])
+                .push(UnspentOutput::new(outpoint, info));
+        }
+        let payment = bitcoin::Address::p2wsh(&Script::from(vec![0x51]), bitcoin::Network::Testnet);
+        let payjoin_opt = PayjoinOpt {
+            url: "https://example.com/pj".into(),
+            disable_output_substitution: false,
+        };
+        let mut request = CreateTransaction {
+            addressees: vec![AddressAmount {
+                address: payment.to_string(),
+                satoshi: 5_000,
+                asset_id: None,
+            }],
+            utxos: GetUnspentOutputs(utxos),
+            payjoin: Some(payjoin_opt.clone()),
+            ..Default::default()
+        };
+        let signed = wallet.sign(&wallet.create_tx(&mut request).unwrap()).unwrap();
+        let (original, paths) = wallet.payjoin_original(&signed).unwrap();
+
+        // the receiver adds a P2SH-P2WPKH input of 20_000 to the payment and takes 91, the fee
+        // of its input, from our change
+        let mut proposal = original.psbt.clone();
+        for input in proposal.inputs.iter_mut() {
+            input.final_script_sig = None;
+            input.final_script_witness = None;
+        }
+        let sequence = proposal.global.unsigned_tx.input[0].sequence;
+        proposal.global.unsigned_tx.input.insert(
+            0,
+            TxIn {
+                previous_output: OutPoint::new(bitcoin::Txid::from_slice(&[9; 32]).unwrap(), 0),
+                script_sig: Script::default(),
+                sequence,
+                witness: vec![],
+            },
+        );
+        let receiver_prevout = TxOut {
+            value: 20_000,
+            script_pubkey: bitcoin::Address::p2sh(
+                &Script::from(vec![0x52]),
+                bitcoin::Network::Testnet,
+            )
+            .script_pubkey(),
+        };
+        proposal.inputs.insert(
+            0,
+            bitcoin::util::psbt::Input {
+                witness_utxo: Some(receiver_prevout),
+                final_script_sig: Some(Script::from([&[22u8][..], &[0; 22][..]].concat())),
+                final_script_witness: Some(vec![vec![1; 72], vec![2; 33]]),
+                ..Default::default()
+            },
+        );
+        let outputs = &mut proposal.global.unsigned_tx.output;
+        let payment_index =
+            outputs.iter().position(|o| o.script_pubkey == payment.script_pubkey()).unwrap();
+        outputs[payment_index].value += 20_000;
+        outputs[original.fee_output_index.unwrap()].value -= 91;
+
+        let result =
+            wallet.sign_payjoin(&signed, &original, &paths, &proposal, &payjoin_opt).unwrap();
+        assert_eq!(result.fee, signed.fee + 91);
+        assert_eq!(result.satoshi["btc"], signed.satoshi["btc"] + 91);
+        let tx: Transaction =
+            bitcoin::consensus::deserialize(&Vec::<u8>::from_hex(&result.hex).unwrap()).unwrap();
+        assert_eq!(tx.txid().to_hex(), result.txid);
+        assert_eq!(tx.input[0].witness, vec![vec![1; 72], vec![2; 33]]);
+        assert_eq!(tx.input[1].previous_output, funding_outpoint);
+        let prevout = TxOut {
+            value: 10_000,
+            script_pubkey: script,
+        };
+        assert!(crate::reserves::verify_input(&tx, 1, &prevout));
+
+        // a proposal where the receiver did not sign its input is rejected
+        proposal.inputs[0].final_script_witness = None;
+        proposal.inputs[0].final_script_sig = None;
+        assert!(wallet.sign_payjoin(&signed, &original, &paths, &proposal, &payjoin_opt).is_err());
+    }
+
+    #[test]
+    fn test_builder_notifications() {
+        let mut network = Network::default();
+        assert!(ElectrumSessionBuilder::new(network.clone()).build().is_err());
+
+        network.esplora_url = Some("http://127.0.0.1:3000".into());
+        let session = ElectrumSessionBuilder::new(network).build().unwrap();
+        assert!(matches!(session.url, ChainSourceUrl::Esplora(_)));
+
+        // a clone held by a running thread sees the handler swap
+        let thread_notify = session.notify.clone();
+        let receiver = session.notifications();
+        let terminated = Arc::new(AtomicBool::new(false));
+        notify(thread_notify, Notification::Block(3), terminated.clone());
+        assert_eq!(receiver.try_recv().unwrap(), Notification::Block(3));
+
+        // held back until the closure returns
+        Notifier::hold(|| {
+            notify(session.notify.clone(), Notification::Block(5), terminated.clone());
+            assert!(receiver.try_recv().is_err());
+        });
+        assert_eq!(receiver.try_recv().unwrap(), Notification::Block(5));
+
+        terminated.store(true, Ordering::Relaxed);
+        notify(session.notify.clone(), Notification::Block(4), terminated);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_sync_replacement_and_confirmation() {
+        let network = Network::default();
+        let syncer = syncer(network.clone());
+        let (source, outpoint) = source(&network);
+        let script = wallet_script(&syncer);
+
+        let original = tx(outpoint, script.clone(), 10_000);
+        let original_txid = original.txid().into_bitcoin();
+        source.insert_tx(original, None);
+        let updates = syncer.sync(&source).unwrap();
+        assert_eq!(updates.new_txs.len(), 1);
+        assert_eq!(updates.new_txs[0].block_height, 0);
+
+        // same input, less value: the sender bumped the fee
+        let replacement = tx(outpoint, script, 9_000);
+        let replacement_txid = replacement.txid().into_bitcoin();
+        source.remove_tx(&original_txid);
+        source.insert_tx(replacement.clone(), None);
+        let updates = syncer.sync(&source).unwrap();
+        assert_eq!(
+            updates.replaced_txs,
+            vec![ReplacementNotification {
+                txhash: original_txid.to_hex(),
+                replaced_by: replacement_txid.to_hex(),
+                subaccounts: vec![0],
+            }]
+        );
+        assert_eq!(updates.new_txs[0].txhash, replacement_txid.to_hex());
+        assert!(updates.confirmed_txs.is_empty());
+        assert_eq!(btc_balance(&syncer), 9_000);
+
+        source.insert_tx(replacement, Some(1));
+        let updates = syncer.sync(&source).unwrap();
+        assert!(updates.new_txs.is_empty());
+        assert_eq!(updates.confirmed_txs.len(), 1);
+        assert_eq!(updates.confirmed_txs[0].txhash, replacement_txid.to_hex());
+        assert_eq!(updates.confirmed_txs[0].block_height, 1);
+    }
+}
@@ -0,0 +1,328 @@
+//! Sign and verify messages with the keys of wallet addresses.
+//!
+//! Two formats are supported: the BIP137 recoverable signatures, understood by most wallets for
+//! P2PKH, P2SH-P2WPKH and P2WPKH addresses, and the BIP322 "simple" signatures of P2WPKH
+//! addresses.
+
+use std::str::FromStr;
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::recovery::{RecoverableSignature, RecoveryId};
+use bitcoin::secp256k1::{Message, Signature};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::util::misc::signed_msg_hash;
+use bitcoin::{
+    Address, OutPoint, PrivateKey, PublicKey, Script, SigHashType, Transaction, TxIn, TxOut, Txid,
+};
+
+use gdk_common::model::{MessageSignatureFormat, VerifyMessageOpt, VerifyMessageResult};
+use gdk_common::scripts::{p2pkh_script, ScriptType};
+
+use crate::error::Error;
+
+/// The BIP137 header byte is this plus the recovery id, for compressed P2PKH keys it is
+/// `BIP137_HEADER_P2PKH + 4`
+const BIP137_HEADER_P2PKH: u8 = 27;
+const BIP137_HEADER_P2SH_P2WPKH: u8 = 35;
+const BIP137_HEADER_P2WPKH: u8 = 39;
+
+const BIP137_SIGNATURE_LEN: usize = 65;
+
+const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// Sign `message` with `private_key`, the key of an address of type `script_type`
+pub fn sign(
+    private_key: &PrivateKey,
+    script_type: ScriptType,
+    message: &str,
+    format: MessageSignatureFormat,
+) -> Result<String, Error> {
+    match format {
+        MessageSignatureFormat::Bip137 => sign_bip137(private_key, script_type, message),
+        MessageSignatureFormat::Bip322 => sign_bip322(private_key, script_type, message),
+    }
+}
+
+/// Verify the signature of a message, a malformed signature is reported as invalid
+pub fn verify(opt: &VerifyMessageOpt) -> Result<VerifyMessageResult, Error> {
+    let address = Address::from_str(&opt.address).map_err(|_| Error::InvalidAddress)?;
+    let signature = base64::decode(&opt.signature).unwrap_or_default();
+    let format = opt.format.unwrap_or(if signature.len() == BIP137_SIGNATURE_LEN {
+        MessageSignatureFormat::Bip137
+    } else {
+        MessageSignatureFormat::Bip322
+    });
+    let valid = match format {
+        MessageSignatureFormat::Bip137 => verify_bip137(&address, &opt.message, &signature),
+        MessageSignatureFormat::Bip322 => verify_bip322(&address, &opt.message, &signature),
+    };
+    Ok(VerifyMessageResult {
+        valid,
+        format,
+    })
+}
+
+fn sign_bip137(
+    private_key: &PrivateKey,
+    script_type: ScriptType,
+    message: &str,
+) -> Result<String, Error> {
+    let hash = signed_msg_hash(message);
+    let message = Message::from_slice(&hash[..]).unwrap();
+    let signature = crate::EC.sign_recoverable(&message, &private_key.key);
+    let (recovery_id, compact) = signature.serialize_compact();
+
+    let header = match script_type {
+        ScriptType::P2pkh if private_key.compressed => BIP137_HEADER_P2PKH + 4,
+        ScriptType::P2pkh => BIP137_HEADER_P2PKH,
+        ScriptType::P2shP2wpkh => BIP137_HEADER_P2SH_P2WPKH,
+        ScriptType::P2wpkh => BIP137_HEADER_P2WPKH,
+        ScriptType::P2wsh | ScriptType::P2wshMiniscript => {
+            return Err(Error::Generic(
+                "multisig and miniscript accounts cannot sign messages".into(),
+            ))
+        }
+    };
+    let mut bytes = Vec::with_capacity(BIP137_SIGNATURE_LEN);
+    bytes.push(header + recovery_id.to_i32() as u8);
+    bytes.extend_from_slice(&compact);
+    Ok(base64::encode(&bytes))
+}
+
+fn verify_bip137(address: &Address, message: &str, signature: &[u8]) -> bool {
+    if signature.len() != BIP137_SIGNATURE_LEN
+        || !(BIP137_HEADER_P2PKH..BIP137_HEADER_P2WPKH + 4).contains(&signature[0])
+    {
+        return false;
+    }
+    let header = signature[0] - BIP137_HEADER_P2PKH;
+    let signature = match RecoveryId::from_i32((header % 4) as i32)
+        .and_then(|id| RecoverableSignature::from_compact(&signature[1..], id))
+    {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let hash = signed_msg_hash(message);
+    let message = Message::from_slice(&hash[..]).unwrap();
+    let public_key = match crate::EC.recover(&message, &signature) {
+        Ok(key) => PublicKey {
+            compressed: header >= 4,
+            key,
+        },
+        Err(_) => return false,
+    };
+
+    // The address type in the header is not enforced, some wallets always use the P2PKH one
+    let network = address.network;
+    let candidates = vec![
+        Some(Address::p2pkh(&public_key, network)),
+        Address::p2shwpkh(&public_key, network).ok(),
+        Address::p2wpkh(&public_key, network).ok(),
+    ];
+    let script_pubkey = address.script_pubkey();
+    candidates.into_iter().flatten().any(|candidate| candidate.script_pubkey() == script_pubkey)
+}
+
+/// The transaction spending the virtual output locked by `script_pubkey` and committing to
+/// `message`, its witness is the BIP322 signature
+fn bip322_to_sign(script_pubkey: &Script, message: &str) -> Transaction {
+    let tag = sha256::Hash::hash(BIP322_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message.as_bytes());
+    let message_hash = sha256::Hash::from_engine(engine);
+
+    let to_spend = Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::default(), 0xFFFF_FFFF),
+            script_sig: Builder::new().push_int(0).push_slice(&message_hash[..]).into_script(),
+            sequence: 0,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    };
+    Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: 0,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script(),
+        }],
+    }
+}
+
+fn bip322_sighash(public_key: &PublicKey, message: &str) -> Message {
+    // only P2WPKH is supported, the script of the virtual output is derived from the key
+    let script_pubkey = Address::p2wpkh(public_key, bitcoin::Network::Regtest)
+        .expect("compressed key")
+        .script_pubkey();
+    let to_sign = bip322_to_sign(&script_pubkey, message);
+    let hash = SigHashCache::new(&to_sign).signature_hash(
+        0,
+        &p2pkh_script(public_key),
+        0,
+        SigHashType::All,
+    );
+    Message::from_slice(&hash[..]).unwrap()
+}
+
+fn sign_bip322(
+    private_key: &PrivateKey,
+    script_type: ScriptType,
+    message: &str,
+) -> Result<String, Error> {
+    if script_type != ScriptType::P2wpkh {
+        return Err(Error::Generic("BIP322 simple signatures require a P2WPKH address".into()));
+    }
+    let public_key = PublicKey::from_private_key(&crate::EC, private_key);
+    let sighash = bip322_sighash(&public_key, message);
+    let mut signature = crate::EC.sign(&sighash, &private_key.key).serialize_der().to_vec();
+    signature.push(SigHashType::All as u8);
+
+    let witness = vec![signature, public_key.to_bytes()];
+    Ok(base64::encode(&serialize(&witness)))
+}
+
+fn verify_bip322(address: &Address, message: &str, signature: &[u8]) -> bool {
+    let witness: Vec<Vec<u8>> = match deserialize(signature) {
+        Ok(witness) => witness,
+        Err(_) => return false,
+    };
+    if witness.len() != 2 {
+        return false;
+    }
+    let public_key = match PublicKey::from_slice(&witness[1]) {
+        Ok(public_key) if public_key.compressed => public_key,
+        _ => return false,
+    };
+    let expected = Address::p2wpkh(&public_key, address.network).map(|a| a.script_pubkey());
+    if expected.ok() != Some(address.script_pubkey()) {
+        return false;
+    }
+    let signature = match witness[0].split_last() {
+        Some((&sighash_type, der)) if sighash_type == SigHashType::All as u8 => {
+            match Signature::from_der(der) {
+                Ok(signature) => signature,
+                Err(_) => return false,
+            }
+        }
+        _ => return false,
+    };
+    let sighash = bip322_sighash(&public_key, message);
+    crate::EC.verify(&sighash, &signature, &public_key.key).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(address: &Address, message: &str, signature: String) -> VerifyMessageOpt {
+        VerifyMessageOpt {
+            address: address.to_string(),
+            message: message.into(),
+            signature,
+            format: None,
+        }
+    }
+
+    #[test]
+    fn test_sign_verify_message() {
+        let network = bitcoin::Network::Testnet;
+        let key = |byte| PrivateKey {
+            compressed: true,
+            network,
+            key: bitcoin::secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap(),
+        };
+        let private_key = key(1);
+        let public_key = PublicKey::from_private_key(&crate::EC, &private_key);
+        let addresses = vec![
+            (ScriptType::P2pkh, Address::p2pkh(&public_key, network)),
+            (ScriptType::P2shP2wpkh, Address::p2shwpkh(&public_key, network).unwrap()),
+            (ScriptType::P2wpkh, Address::p2wpkh(&public_key, network).unwrap()),
+        ];
+        let other_key = PublicKey::from_private_key(&crate::EC, &key(2));
+        let other = Address::p2wpkh(&other_key, network).unwrap();
+
+        for (script_type, address) in addresses.iter() {
+            let signature =
+                sign(&private_key, *script_type, "hello", MessageSignatureFormat::Bip137).unwrap();
+            let result = verify(&opt(address, "hello", signature.clone())).unwrap();
+            assert_eq!(result.format, MessageSignatureFormat::Bip137);
+            assert!(result.valid);
+            assert!(!verify(&opt(address, "hell0", signature.clone())).unwrap().valid);
+            assert!(!verify(&opt(&other, "hello", signature)).unwrap().valid);
+        }
+
+        let (_, p2wpkh) = &addresses[2];
+        let signature =
+            sign(&private_key, ScriptType::P2wpkh, "hello", MessageSignatureFormat::Bip322)
+                .unwrap();
+        let result = verify(&opt(p2wpkh, "hello", signature.clone())).unwrap();
+        assert_eq!(result.format, MessageSignatureFormat::Bip322);
+        assert!(result.valid);
+        assert!(!verify(&opt(p2wpkh, "hell0", signature.clone())).unwrap().valid);
+        assert!(!verify(&opt(&other, "hello", signature)).unwrap().valid);
+        assert!(
+            sign(&private_key, ScriptType::P2pkh, "hello", MessageSignatureFormat::Bip322).is_err()
+        );
+
+        assert!(!verify(&opt(p2wpkh, "hello", "not base64".into())).unwrap().valid);
+        assert!(verify(&opt(p2wpkh, "hello", "".into())).is_ok());
+        assert!(matches!(
+            verify(&VerifyMessageOpt {
+                address: "invalid".into(),
+                ..opt(p2wpkh, "hello", "".into())
+            }),
+            Err(Error::InvalidAddress)
+        ));
+    }
+
+    #[test]
+    fn test_bip322_message_hash() {
+        // test vectors from BIP322
+        let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap();
+        let to_sign = bip322_to_sign(&address.script_pubkey(), "");
+        assert_eq!(
+            to_sign.input[0].previous_output.txid.to_string(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        let to_sign = bip322_to_sign(&address.script_pubkey(), "Hello World");
+        assert_eq!(
+            to_sign.input[0].previous_output.txid.to_string(),
+            "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b"
+        );
+
+        // both signatures are in BIP322, the second one is the RFC6979 one without grinding
+        let private_key =
+            PrivateKey::from_wif("L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k").unwrap();
+        let signatures = [
+            "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=",
+            "AkgwRQIhAOzyynlqt93lOKJr+wmmxIens//zPzl9tqIOua93wO6MAiBi5n5EyAcPScOjf1lAqIUIQtr3zKNeavYabHyR8eGhowEhAsfxIAMZZEKUPYWI4BruhAQjzFT8FSFSajuFwrDL1Yhy",
+        ];
+        for signature in signatures.iter() {
+            let result = verify(&opt(&address, "Hello World", signature.to_string())).unwrap();
+            assert_eq!(result.format, MessageSignatureFormat::Bip322);
+            assert!(result.valid);
+            assert!(!verify(&opt(&address, "", signature.to_string())).unwrap().valid);
+        }
+        let signature =
+            sign(&private_key, ScriptType::P2wpkh, "Hello World", MessageSignatureFormat::Bip322)
+                .unwrap();
+        assert_eq!(signature, signatures[1]);
+    }
+}
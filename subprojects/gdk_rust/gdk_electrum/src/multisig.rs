@@ -0,0 +1,533 @@
+//! Multisig subaccounts, a `threshold`-of-n P2WSH sortedmulti of our key and the keys of the
+//! cosigners, all derived at `<account xpub>/<change>/<index>`.
+//!
+//! Transactions are signed through psbts: every signer adds its partial signatures and the
+//! input witnesses are completed once `threshold` of them are collected.
+
+use std::str::FromStr;
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::secp256k1::{Message, Signature};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::util::bip32::{
+    ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint,
+};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, PublicKey, Script, SigHashType, Transaction, TxOut};
+use serde::{Deserialize, Serialize};
+
+use gdk_common::model::MultisigOpt;
+use gdk_common::scripts::{sortedmulti_script, InputShape};
+
+use crate::error::Error;
+
+/// The most keys of a standard P2WSH CHECKMULTISIG are 20, but other wallets stop at 15
+pub const MAX_MULTISIG_KEYS: usize = 15;
+
+pub struct Multisig {
+    opt: MultisigOpt,
+    threshold: usize,
+    /// the account xpubs of all the signers, ours first
+    xpubs: Vec<ExtendedPubKey>,
+    /// the master fingerprint and account path of `xpubs`
+    origins: Vec<(Fingerprint, DerivationPath)>,
+}
+
+impl Multisig {
+    /// Validate `opt` against our account `xpub`, derived from the `master_fingerprint` at `path`
+    pub fn new(
+        opt: &MultisigOpt,
+        xpub: &ExtendedPubKey,
+        master_fingerprint: Fingerprint,
+        path: &DerivationPath,
+    ) -> Result<Self, Error> {
+        let is_mainnet = |xpub: &ExtendedPubKey| xpub.network == bitcoin::Network::Bitcoin;
+        let mut xpubs = vec![*xpub];
+        let mut origins = vec![(master_fingerprint, path.clone())];
+        for cosigner in opt.cosigners.iter() {
+            let (cosigner_xpub, origin) = parse_cosigner(cosigner)
+                .ok_or_else(|| Error::InvalidMultisig(format!("invalid xpub {}", cosigner)))?;
+            if is_mainnet(&cosigner_xpub) != is_mainnet(xpub) {
+                return Err(Error::InvalidMultisig(format!("{} is for another network", cosigner)));
+            }
+            if xpubs.iter().any(|x| x.public_key == cosigner_xpub.public_key) {
+                return Err(Error::InvalidMultisig(format!("{} is repeated", cosigner)));
+            }
+            xpubs.push(cosigner_xpub);
+            origins.push(origin);
+        }
+        if xpubs.len() < 2 || xpubs.len() > MAX_MULTISIG_KEYS {
+            return Err(Error::InvalidMultisig(format!(
+                "between 2 and {} signers are supported",
+                MAX_MULTISIG_KEYS
+            )));
+        }
+        let threshold = opt.threshold as usize;
+        if threshold == 0 || threshold > xpubs.len() {
+            return Err(Error::InvalidMultisig(format!("invalid threshold {}", threshold)));
+        }
+        Ok(Multisig {
+            opt: opt.clone(),
+            threshold,
+            xpubs,
+            origins,
+        })
+    }
+
+    pub fn opt(&self) -> &MultisigOpt {
+        &self.opt
+    }
+
+    pub fn shape(&self) -> InputShape {
+        InputShape::Multisig {
+            threshold: self.threshold as u8,
+            keys: self.xpubs.len() as u8,
+        }
+    }
+
+    /// The keys of every signer at `path`, `<change>/<index>`
+    fn public_keys(&self, path: &DerivationPath) -> Result<Vec<PublicKey>, Error> {
+        self.xpubs.iter().map(|xpub| Ok(xpub.derive_pub(&crate::EC, path)?.public_key)).collect()
+    }
+
+    pub fn witness_script(&self, path: &DerivationPath) -> Result<Script, Error> {
+        Ok(sortedmulti_script(self.threshold, &self.public_keys(path)?))
+    }
+
+    pub fn address(
+        &self,
+        is_change: bool,
+        index: u32,
+        network: bitcoin::Network,
+    ) -> Result<Address, Error> {
+        let path = DerivationPath::from(&[(is_change as u32).into(), index.into()][..]);
+        Ok(Address::p2wsh(&self.witness_script(&path)?, network))
+    }
+
+    /// Add to the input `input_index` of `psbt` what the signers need to sign it, it spends
+    /// `txout` derived at `path`
+    pub fn fill_input(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        input_index: usize,
+        txout: TxOut,
+        path: &DerivationPath,
+    ) -> Result<(), Error> {
+        let public_keys = self.public_keys(path)?;
+        let input = &mut psbt.inputs[input_index];
+        input.witness_utxo = Some(txout);
+        input.witness_script = Some(sortedmulti_script(self.threshold, &public_keys));
+        for (public_key, (fingerprint, origin)) in public_keys.into_iter().zip(self.origins.iter())
+        {
+            let full_path: Vec<ChildNumber> =
+                origin.as_ref().iter().chain(path.as_ref()).cloned().collect();
+            input.bip32_derivation.insert(public_key, (*fingerprint, full_path.into()));
+        }
+        Ok(())
+    }
+
+    /// Add our signature for the input `input_index` of `psbt`, derived at `path`
+    pub fn sign_input(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        input_index: usize,
+        xprv: &ExtendedPrivKey,
+        path: &DerivationPath,
+    ) -> Result<(), Error> {
+        let private_key = xprv.derive_priv(&crate::EC, path)?.private_key;
+        let public_key = PublicKey::from_private_key(&crate::EC, &private_key);
+        let input = &psbt.inputs[input_index];
+        let (witness_script, value) = match (&input.witness_script, &input.witness_utxo) {
+            (Some(witness_script), Some(txout)) => (witness_script.clone(), txout.value),
+            _ => return Err(Error::Generic(format!("input {} is not filled", input_index))),
+        };
+        let hash = SigHashCache::new(&psbt.global.unsigned_tx).signature_hash(
+            input_index,
+            &witness_script,
+            value,
+            SigHashType::All,
+        );
+        let message = Message::from_slice(&hash[..]).unwrap();
+        let mut signature = crate::EC.sign(&message, &private_key.key).serialize_der().to_vec();
+        signature.push(SigHashType::All as u8);
+        psbt.inputs[input_index].partial_sigs.insert(public_key, signature);
+        Ok(())
+    }
+
+    /// Check the witness of the input `input_index` of `tx`, spending `value` from our script
+    /// at `path`, has our witness script and `threshold` valid signatures
+    pub fn verify_input(
+        &self,
+        tx: &Transaction,
+        input_index: usize,
+        path: &DerivationPath,
+        value: u64,
+    ) -> Result<(), Error> {
+        let invalid = || Error::Common(gdk_common::error::Error::InputValidationFailed);
+        let witness_script = self.witness_script(path)?;
+        let witness = &tx.input[input_index].witness;
+        let signatures = match witness.split_last() {
+            Some((script, signatures)) if *script == witness_script.to_bytes() => signatures,
+            _ => return Err(invalid()),
+        };
+        // the dummy element consumed by CHECKMULTISIG, then the signatures
+        match signatures.split_first() {
+            Some((dummy, signatures)) if dummy.is_empty() && signatures.len() == self.threshold => {
+                let hash = SigHashCache::new(tx).signature_hash(
+                    input_index,
+                    &witness_script,
+                    value,
+                    SigHashType::All,
+                );
+                let message = Message::from_slice(&hash[..]).unwrap();
+                let (_, keys) = parse_multisig(&witness_script).ok_or_else(invalid)?;
+                // as CHECKMULTISIG, the signatures are in the order of the keys
+                let mut keys = keys.iter();
+                for signature in signatures {
+                    // we only ever create SIGHASH_ALL transactions
+                    let signature = match signature.split_last() {
+                        Some((&sighash, der)) if sighash == SigHashType::All as u8 => {
+                            Signature::from_der(der).map_err(|_| invalid())?
+                        }
+                        _ => return Err(invalid()),
+                    };
+                    if !keys.any(|key| crate::EC.verify(&message, &signature, &key.key).is_ok()) {
+                        return Err(invalid());
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Parse the account xpub of a cosigner, optionally prefixed by its key origin as in the
+/// descriptors, `[d34db33f/48'/1'/0'/2']tpub...`, without it the xpub is its own master
+fn parse_cosigner(cosigner: &str) -> Option<(ExtendedPubKey, (Fingerprint, DerivationPath))> {
+    let (origin, xpub) = match cosigner.strip_prefix('[') {
+        Some(rest) => {
+            let mut parts = rest.splitn(2, ']');
+            (parts.next(), parts.next()?)
+        }
+        None => (None, cosigner),
+    };
+    let xpub = ExtendedPubKey::from_str(xpub).ok()?;
+    let origin = match origin {
+        Some(origin) => {
+            let mut parts = origin.splitn(2, '/');
+            let fingerprint = Fingerprint::from_hex(parts.next()?).ok()?;
+            let path = match parts.next() {
+                Some(path) => DerivationPath::from_str(&format!("m/{}", path)).ok()?,
+                None => DerivationPath::master(),
+            };
+            // the origin path leads to the xpub
+            if path.as_ref().len() != xpub.depth as usize {
+                return None;
+            }
+            (fingerprint, path)
+        }
+        None => (xpub.fingerprint(), DerivationPath::master()),
+    };
+    Some((xpub, origin))
+}
+
+/// The threshold and the keys of a CHECKMULTISIG `script`
+fn parse_multisig(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let small_int = |instruction: &Instruction| match instruction {
+        Instruction::Op(op) => {
+            let (op, first) = (op.into_u8(), opcodes::all::OP_PUSHNUM_1.into_u8());
+            let last = opcodes::all::OP_PUSHNUM_16.into_u8();
+            if (first..=last).contains(&op) {
+                Some((op - first + 1) as usize)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (last, instructions) = instructions.split_last()?;
+    if *last != Instruction::Op(opcodes::all::OP_CHECKMULTISIG) || instructions.len() < 3 {
+        return None;
+    }
+    let threshold = small_int(&instructions[0])?;
+    let num_keys = small_int(&instructions[instructions.len() - 1])?;
+    let keys = instructions[1..instructions.len() - 1]
+        .iter()
+        .map(|i| match i {
+            Instruction::PushBytes(bytes) => PublicKey::from_slice(bytes).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if keys.len() != num_keys || threshold > num_keys {
+        return None;
+    }
+    Some((threshold, keys))
+}
+
+/// Complete the witness of the multisig inputs of `psbt` having enough signatures, returns
+/// whether every input is complete
+pub fn finalize(psbt: &mut PartiallySignedTransaction) -> bool {
+    let mut complete = true;
+    for input in psbt.inputs.iter_mut() {
+        if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+            continue;
+        }
+        let witness_script = match &input.witness_script {
+            Some(witness_script) => witness_script.clone(),
+            None => {
+                complete = false;
+                continue;
+            }
+        };
+        let (threshold, keys) = match parse_multisig(&witness_script) {
+            Some(multisig) => multisig,
+            None => {
+                complete = false;
+                continue;
+            }
+        };
+        // CHECKMULTISIG wants the signatures in the order of the keys
+        let signatures: Vec<Vec<u8>> = keys
+            .iter()
+            .filter_map(|key| input.partial_sigs.get(key).cloned())
+            .take(threshold)
+            .collect();
+        if signatures.len() < threshold {
+            complete = false;
+            continue;
+        }
+        // the dummy element consumed by CHECKMULTISIG
+        let mut witness = vec![vec![]];
+        witness.extend(signatures);
+        witness.push(witness_script.to_bytes());
+        input.final_script_witness = Some(witness);
+        input.partial_sigs.clear();
+        input.bip32_derivation.clear();
+        input.witness_script = None;
+    }
+    complete
+}
+
+pub fn psbt_from_base64(psbt: &str) -> Result<PartiallySignedTransaction, Error> {
+    let bytes = base64::decode(psbt).map_err(|_| Error::Generic("invalid base64 psbt".into()))?;
+    Ok(deserialize(&bytes)?)
+}
+
+pub fn psbt_to_base64(psbt: &PartiallySignedTransaction) -> String {
+    base64::encode(&serialize(psbt))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CombinePsbtParam {
+    psbts: Vec<String>,
+}
+#[derive(Debug, Serialize)]
+pub struct CombinePsbtResult {
+    psbt: String,
+    /// The raw tx hex, once enough signatures are collected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction: Option<String>,
+    complete: bool,
+}
+/// Merge the signatures of the given base64 psbts of the same transaction
+pub fn combine(param: &CombinePsbtParam) -> Result<CombinePsbtResult, Error> {
+    let mut psbts = param.psbts.iter().map(|p| psbt_from_base64(p));
+    let mut psbt = psbts.next().ok_or_else(|| Error::Generic("no psbt to combine".into()))??;
+    for other in psbts {
+        psbt.merge(other?).map_err(|e| Error::Generic(e.to_string()))?;
+    }
+    let complete = finalize(&mut psbt);
+    let transaction = if complete {
+        Some(serialize(&psbt.clone().extract_tx()).to_hex())
+    } else {
+        None
+    };
+    Ok(CombinePsbtResult {
+        psbt: psbt_to_base64(&psbt),
+        transaction,
+        complete,
+    })
+}
+
+/// The index of `path` on its chain, `<change>/<index>`
+pub fn chain_index(path: &DerivationPath) -> Option<(bool, u32)> {
+    match path.as_ref() {
+        [ChildNumber::Normal {
+            index: change,
+        }, ChildNumber::Normal {
+            index,
+        }] => Some((*change == 1, *index)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{OutPoint, TxIn};
+
+    fn xprv(seed: u8) -> ExtendedPrivKey {
+        ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[seed; 32]).unwrap()
+    }
+
+    fn multisig(ours: u8, others: &[u8], threshold: u32) -> Result<Multisig, Error> {
+        let xpub = |seed| ExtendedPubKey::from_private(&crate::EC, &xprv(seed));
+        let opt = MultisigOpt {
+            threshold,
+            cosigners: others.iter().map(|s| xpub(*s).to_string()).collect(),
+        };
+        Multisig::new(&opt, &xpub(ours), Fingerprint::default(), &DerivationPath::master())
+    }
+
+    #[test]
+    fn test_multisig_opt() {
+        assert!(multisig(1, &[2, 3], 2).is_ok());
+        assert!(multisig(1, &[2, 3], 0).is_err());
+        assert!(multisig(1, &[2, 3], 4).is_err());
+        assert!(multisig(1, &[], 1).is_err());
+        assert!(multisig(1, &[2, 2], 2).is_err());
+        assert!(multisig(1, &[1, 2], 2).is_err());
+
+        // every signer derives the same addresses, whatever the order of the xpubs
+        let network = bitcoin::Network::Regtest;
+        let a = multisig(1, &[2, 3], 2).unwrap();
+        let b = multisig(3, &[1, 2], 2).unwrap();
+        assert_eq!(a.address(false, 7, network).unwrap(), b.address(false, 7, network).unwrap());
+        assert_ne!(a.address(false, 7, network).unwrap(), a.address(true, 7, network).unwrap());
+
+        let path = DerivationPath::from_str("m/1/7").unwrap();
+        assert_eq!(chain_index(&path), Some((true, 7)));
+
+        // the key origins of the cosigners go in the psbt
+        let origin = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let account = xprv(2).derive_priv(&crate::EC, &origin).unwrap();
+        let account = ExtendedPubKey::from_private(&crate::EC, &account);
+        let fingerprint = xprv(2).fingerprint(&crate::EC);
+        let cosigner = format!("[{}/48'/1'/0'/2']{}", fingerprint, account);
+        assert_eq!(parse_cosigner(&cosigner), Some((account, (fingerprint, origin.clone()))));
+        assert!(parse_cosigner(&format!("[{}/48'/1'/0']{}", fingerprint, account)).is_none());
+        assert!(parse_cosigner(&format!("[{}]{}", fingerprint, account)).is_none());
+        assert!(parse_cosigner(&format!("[xyz/48'/1'/0'/2']{}", account)).is_none());
+        let our_xpub = ExtendedPubKey::from_private(&crate::EC, &xprv(1));
+        let opt = MultisigOpt {
+            threshold: 2,
+            cosigners: vec![cosigner],
+        };
+        let with_origin =
+            Multisig::new(&opt, &our_xpub, Fingerprint::default(), &DerivationPath::master())
+                .unwrap();
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0xffff_fffd,
+                witness: vec![],
+            }],
+            output: vec![],
+        })
+        .unwrap();
+        let txout = TxOut {
+            value: 100_000,
+            script_pubkey: with_origin.address(false, 7, network).unwrap().script_pubkey(),
+        };
+        with_origin.fill_input(&mut psbt, 0, txout, &path).unwrap();
+        let key = account.derive_pub(&crate::EC, &path).unwrap().public_key;
+        assert_eq!(
+            psbt.inputs[0].bip32_derivation[&key],
+            (fingerprint, DerivationPath::from_str("m/48'/1'/0'/2'/1/7").unwrap())
+        );
+        let (threshold, keys) = parse_multisig(&a.witness_script(&path).unwrap()).unwrap();
+        assert_eq!(threshold, 2);
+        assert_eq!(keys.len(), 3);
+    }
+
+    #[test]
+    fn test_sign_combine() {
+        let signers = [(1, [2, 3]), (2, [1, 3]), (3, [1, 2])];
+        let multisigs: Vec<Multisig> =
+            signers.iter().map(|(s, o)| multisig(*s, o, 2).unwrap()).collect();
+
+        let path = DerivationPath::from_str("m/0/3").unwrap();
+        let txout = TxOut {
+            value: 100_000,
+            script_pubkey: multisigs[0]
+                .address(false, 3, bitcoin::Network::Regtest)
+                .unwrap()
+                .script_pubkey(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0xffff_fffd,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 99_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let sign = |signer: usize| {
+            let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx.clone()).unwrap();
+            multisigs[signer].fill_input(&mut psbt, 0, txout.clone(), &path).unwrap();
+            multisigs[signer].sign_input(&mut psbt, 0, &xprv(signers[signer].0), &path).unwrap();
+            psbt
+        };
+
+        // a single signature is not enough
+        let mut first = sign(0);
+        assert!(!finalize(&mut first));
+        let result = combine(&CombinePsbtParam {
+            psbts: vec![psbt_to_base64(&first)],
+        })
+        .unwrap();
+        assert!(!result.complete);
+        assert!(result.transaction.is_none());
+
+        let result = combine(&CombinePsbtParam {
+            psbts: vec![psbt_to_base64(&sign(2)), result.psbt],
+        })
+        .unwrap();
+        assert!(result.complete);
+        let bytes = bitcoin::hashes::hex::FromHex::from_hex(&result.transaction.unwrap()).unwrap();
+        let signed: Transaction = deserialize(&bytes).unwrap();
+        let witness = &signed.input[0].witness;
+        assert_eq!(witness.len(), 4);
+        let witness_script = Script::from(witness[3].clone());
+        assert_eq!(
+            Address::p2wsh(&witness_script, bitcoin::Network::Regtest).script_pubkey(),
+            txout.script_pubkey
+        );
+
+        // the signatures are of the first and third signers, in the order of the keys
+        let hash = SigHashCache::new(&signed).signature_hash(
+            0,
+            &witness_script,
+            txout.value,
+            SigHashType::All,
+        );
+        let message = Message::from_slice(&hash[..]).unwrap();
+        let (_, keys) = parse_multisig(&witness_script).unwrap();
+        let mut keys = keys.iter();
+        for signature in witness[1..3].iter() {
+            let signature = Signature::from_der(&signature[..signature.len() - 1]).unwrap();
+            assert!(keys.any(|key| crate::EC.verify(&message, &signature, &key.key).is_ok()));
+        }
+        assert!(multisigs[1].verify_input(&signed, 0, &path, txout.value).is_ok());
+        assert!(multisigs[1].verify_input(&signed, 0, &path, txout.value + 1).is_err());
+        let other_path = DerivationPath::from_str("m/0/4").unwrap();
+        assert!(multisigs[1].verify_input(&signed, 0, &other_path, txout.value).is_err());
+        let mut swapped = signed.clone();
+        swapped.input[0].witness.swap(1, 2);
+        assert!(multisigs[1].verify_input(&swapped, 0, &path, txout.value).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+//! Payjoin sender, following BIP78.
+//!
+//! The signed transaction paying a receiver that supports payjoin is sent to its endpoint as the
+//! original PSBT. The receiver answers with a proposal adding its own inputs, which is signed
+//! and broadcasted only if it passes the checks below, otherwise the original transaction is.
+
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Script, Transaction, TxOut};
+use log::info;
+
+use gdk_common::model::PayjoinOpt;
+use gdk_common::scripts::ScriptType;
+
+use crate::error::Error;
+
+const TIMEOUT_MS: u64 = 30_000;
+
+/// The original transaction sent to the receiver, with what is needed to check the proposal
+pub struct Original {
+    /// The finalized PSBT of the signed transaction, with the outputs spent by every input
+    pub psbt: PartiallySignedTransaction,
+    /// Our change output, that the receiver can decrease to pay the fee of its inputs
+    pub fee_output_index: Option<usize>,
+    pub payment_script: Script,
+    /// The type of all our inputs, which the receiver inputs must match
+    pub script_type: ScriptType,
+}
+
+/// What the proposal costs us once checked
+#[derive(Debug, PartialEq)]
+pub struct Contribution {
+    /// The fee of the proposal
+    pub fee: u64,
+    /// The amount taken from our change to pay the fee of the receiver inputs
+    pub additional_fee: u64,
+}
+
+/// The vsize of a signed input of `script_type`, multisig and miniscript accounts cannot payjoin
+fn input_vsize(script_type: ScriptType) -> Option<u64> {
+    match script_type {
+        ScriptType::P2pkh => Some(148),
+        ScriptType::P2shP2wpkh => Some(91),
+        ScriptType::P2wpkh => Some(68),
+        ScriptType::P2wsh | ScriptType::P2wshMiniscript => None,
+    }
+}
+
+fn script_type_of(script: &Script) -> Option<ScriptType> {
+    if script.is_p2pkh() {
+        Some(ScriptType::P2pkh)
+    } else if script.is_p2sh() {
+        Some(ScriptType::P2shP2wpkh)
+    } else if script.is_v0_p2wpkh() {
+        Some(ScriptType::P2wpkh)
+    } else {
+        None
+    }
+}
+
+/// The output spent by the input `index` of `psbt`
+fn spent_txout(psbt: &PartiallySignedTransaction, index: usize) -> Option<TxOut> {
+    let input = &psbt.inputs[index];
+    let vout = psbt.global.unsigned_tx.input[index].previous_output.vout as usize;
+    input
+        .witness_utxo
+        .clone()
+        .or_else(|| input.non_witness_utxo.as_ref().and_then(|tx| tx.output.get(vout).cloned()))
+}
+
+fn fee(psbt: &PartiallySignedTransaction) -> Option<u64> {
+    let inputs = (0..psbt.inputs.len()).map(|i| spent_txout(psbt, i).map(|o| o.value));
+    let inputs: u64 = inputs.collect::<Option<Vec<u64>>>()?.iter().sum();
+    let outputs: u64 = psbt.global.unsigned_tx.output.iter().map(|o| o.value).sum();
+    inputs.checked_sub(outputs)
+}
+
+/// The transaction of `psbt` with the finalized inputs signed
+pub fn extract(psbt: &PartiallySignedTransaction) -> Transaction {
+    let mut tx = psbt.global.unsigned_tx.clone();
+    for (txin, input) in tx.input.iter_mut().zip(psbt.inputs.iter()) {
+        txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+        txin.witness = input.final_script_witness.clone().unwrap_or_default();
+    }
+    tx
+}
+
+impl Original {
+    fn fee(&self) -> u64 {
+        fee(&self.psbt).unwrap_or(0)
+    }
+
+    /// In satoshi per vbyte
+    fn fee_rate(&self) -> f64 {
+        self.fee() as f64 / extract(&self.psbt).get_weight() as f64 * 4.0
+    }
+
+    fn max_additional_fee(&self) -> Option<u64> {
+        input_vsize(self.script_type).map(|vsize| (self.fee_rate() * vsize as f64).ceil() as u64)
+    }
+
+    fn is_ours(&self, outpoint: &OutPoint) -> bool {
+        self.psbt.global.unsigned_tx.input.iter().any(|i| i.previous_output == *outpoint)
+    }
+
+    /// The url of the request to the receiver, with the parameters of the sender
+    pub fn request_url(&self, opt: &PayjoinOpt) -> Result<String, Error> {
+        let url = &opt.url;
+        let onion = url
+            .strip_prefix("http://")
+            .and_then(|rest| rest.split(|c| c == '/' || c == ':' || c == '?').next())
+            .map_or(false, |host| host.ends_with(".onion"));
+        if !url.starts_with("https://") && !onion {
+            return Err(Error::Generic(format!("payjoin endpoint {} is not https", url)));
+        }
+        let max_additional_fee = self
+            .max_additional_fee()
+            .ok_or_else(|| Error::Generic("payjoin requires a single signature account".into()))?;
+        let mut params = vec!["v=1".to_string()];
+        if let Some(index) = self.fee_output_index {
+            params.push(format!("additionalfeeoutputindex={}", index));
+            params.push(format!("maxadditionalfeecontribution={}", max_additional_fee));
+        }
+        if opt.disable_output_substitution {
+            params.push("disableoutputsubstitution=true".into());
+        }
+        params.push(format!("minfeerate={}", self.fee_rate().floor()));
+        let separator = if url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        Ok(format!("{}{}{}", url, separator, params.join("&")))
+    }
+
+    /// Post the original psbt to the receiver and return its proposal
+    pub fn send(
+        &self,
+        agent: &ureq::Agent,
+        url: &str,
+    ) -> Result<PartiallySignedTransaction, Error> {
+        info!("sending payjoin original to {}", url);
+        let response = agent
+            .post(url)
+            .set("Content-Type", "text/plain")
+            .timeout_connect(TIMEOUT_MS)
+            .timeout_read(TIMEOUT_MS)
+            .send_string(&base64::encode(&serialize(&self.psbt)));
+        if let Some(err) = response.synthetic_error() {
+            return Err(Error::ConnectionFailed(format!("payjoin {} failed: {}", url, err)));
+        }
+        let status = response.status();
+        let body = response.into_string()?;
+        if !(200..300).contains(&status) {
+            // the body is a json with the receiver errorCode and message
+            return Err(Error::Generic(format!("payjoin receiver returned {}: {}", status, body)));
+        }
+        base64::decode(body.trim())
+            .ok()
+            .and_then(|bytes| deserialize(&bytes).ok())
+            .ok_or_else(|| Error::Generic("payjoin receiver returned an invalid psbt".into()))
+    }
+
+    /// Check the proposal of the receiver as BIP78 requires from the sender
+    pub fn check_proposal(
+        &self,
+        proposal: &PartiallySignedTransaction,
+        opt: &PayjoinOpt,
+    ) -> Result<Contribution, Error> {
+        self.check(proposal, opt)
+            .map_err(|reason| Error::Generic(format!("payjoin proposal rejected: {}", reason)))
+    }
+
+    fn check(
+        &self,
+        proposal: &PartiallySignedTransaction,
+        opt: &PayjoinOpt,
+    ) -> Result<Contribution, String> {
+        let original_tx = &self.psbt.global.unsigned_tx;
+        let tx = &proposal.global.unsigned_tx;
+        if tx.version != original_tx.version || tx.lock_time != original_tx.lock_time {
+            return Err("version or locktime changed".into());
+        }
+        if tx.input.len() != proposal.inputs.len() || tx.output.len() != proposal.outputs.len() {
+            return Err("malformed psbt".into());
+        }
+        let sequence = original_tx.input[0].sequence;
+
+        let mut ours = 0;
+        for (i, (txin, input)) in tx.input.iter().zip(proposal.inputs.iter()).enumerate() {
+            if txin.sequence != sequence {
+                return Err(format!("sequence of input {} changed", i));
+            }
+            let finalized =
+                input.final_script_sig.is_some() || input.final_script_witness.is_some();
+            if self.is_ours(&txin.previous_output) {
+                if finalized || !input.partial_sigs.is_empty() {
+                    return Err(format!("our input {} is signed", i));
+                }
+                ours += 1;
+            } else {
+                if !finalized {
+                    return Err(format!("receiver input {} is not finalized", i));
+                }
+                let spent =
+                    spent_txout(proposal, i).ok_or(format!("input {} misses its utxo", i))?;
+                if script_type_of(&spent.script_pubkey) != Some(self.script_type) {
+                    return Err(format!("receiver input {} is of another type", i));
+                }
+            }
+        }
+        if ours != original_tx.input.len() {
+            return Err("our inputs are missing".into());
+        }
+
+        let mut additional_fee = 0;
+        for (i, original_output) in original_tx.output.iter().enumerate() {
+            let output =
+                tx.output.iter().find(|o| o.script_pubkey == original_output.script_pubkey);
+            if original_output.script_pubkey == self.payment_script {
+                match output {
+                    Some(output) if output.value >= original_output.value => (),
+                    _ if opt.disable_output_substitution => {
+                        return Err("payment output changed".into())
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+            let output = output.ok_or(format!("output {} was removed", i))?;
+            if Some(i) == self.fee_output_index && output.value <= original_output.value {
+                additional_fee = original_output.value - output.value;
+            } else if output.value != original_output.value {
+                return Err(format!("output {} changed", i));
+            }
+        }
+
+        let (new_fee, original_fee) = (fee(proposal).ok_or("invalid fee")?, self.fee());
+        let new_inputs = (tx.input.len() - ours) as u64;
+        let vsize = input_vsize(self.script_type).ok_or("unsupported script type")?;
+        if additional_fee > (self.fee_rate() * vsize as f64).ceil() as u64
+            || additional_fee > new_fee.saturating_sub(original_fee)
+            || additional_fee > (self.fee_rate() * (vsize * new_inputs) as f64).ceil() as u64
+        {
+            return Err(format!("fee contribution {} too high", additional_fee));
+        }
+        Ok(Contribution {
+            fee: new_fee,
+            additional_fee,
+        })
+    }
+
+    /// Check the fee rate of the signed proposal is not lower than the original one
+    pub fn check_fee_rate(&self, tx: &Transaction, fee: u64) -> Result<(), Error> {
+        let vsize = (tx.get_weight() as f64 / 4.0).ceil();
+        // signatures may be a byte shorter or longer
+        let tolerance = tx.input.len() as f64;
+        if (fee as f64) < self.fee_rate() * (vsize - tolerance) {
+            return Err(Error::Generic("payjoin proposal fee rate too low".into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{TxIn, Txid};
+
+    fn p2wpkh(byte: u8) -> Script {
+        Builder::new().push_int(0).push_slice(&[byte; 20]).into_script()
+    }
+
+    fn txin(byte: u8) -> TxIn {
+        TxIn {
+            previous_output: OutPoint::new(Txid::from_slice(&[byte; 32]).unwrap(), 0),
+            script_sig: Script::new(),
+            sequence: 0xffff_fffd,
+            witness: vec![],
+        }
+    }
+
+    fn txout(value: u64, script_pubkey: Script) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey,
+        }
+    }
+
+    fn psbt(inputs: Vec<(TxIn, TxOut, bool)>, outputs: Vec<TxOut>) -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: inputs.iter().map(|(txin, _, _)| txin.clone()).collect(),
+            output: outputs,
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        for (input, (_, spent, finalized)) in psbt.inputs.iter_mut().zip(inputs.into_iter()) {
+            input.witness_utxo = Some(spent);
+            if finalized {
+                input.final_script_witness = Some(vec![vec![1; 72], vec![2; 33]]);
+            }
+        }
+        psbt
+    }
+
+    #[test]
+    fn test_check_proposal() {
+        let (payment, change) = (p2wpkh(1), p2wpkh(2));
+        let original = Original {
+            psbt: psbt(
+                vec![(txin(1), txout(100_000, p2wpkh(3)), true)],
+                vec![txout(50_000, payment.clone()), txout(48_500, change.clone())],
+            ),
+            fee_output_index: Some(1),
+            payment_script: payment.clone(),
+            script_type: ScriptType::P2wpkh,
+        };
+        let opt = PayjoinOpt {
+            url: "https://example.com/pj".into(),
+            disable_output_substitution: false,
+        };
+        let fee_rate = original.fee_rate();
+        assert!(fee_rate > 9.0 && fee_rate < 12.0, "{}", fee_rate);
+        assert_eq!(
+            original.request_url(&opt).unwrap(),
+            format!(
+                "https://example.com/pj?v=1&additionalfeeoutputindex=1&maxadditionalfeecontribution={}&minfeerate={}",
+                original.max_additional_fee().unwrap(),
+                fee_rate.floor()
+            )
+        );
+        let onion = PayjoinOpt {
+            url: "http://pj.onion/pj?id=1".into(),
+            ..opt.clone()
+        };
+        assert!(original.request_url(&onion).unwrap().starts_with("http://pj.onion/pj?id=1&v=1"));
+        let http = PayjoinOpt {
+            url: "http://example.com/pj".into(),
+            ..opt.clone()
+        };
+        assert!(original.request_url(&http).is_err());
+
+        // the receiver adds an input of 20_000 to the payment and takes 400 of fee from the change
+        let proposal = |ours_finalized: bool, receiver_script: Script, change_value: u64| {
+            psbt(
+                vec![
+                    (txin(2), txout(20_000, receiver_script), true),
+                    (txin(1), txout(100_000, p2wpkh(3)), ours_finalized),
+                ],
+                vec![txout(change_value, change.clone()), txout(70_000, payment.clone())],
+            )
+        };
+        let valid = proposal(false, p2wpkh(4), 48_100);
+        assert_eq!(
+            original.check(&valid, &opt),
+            Ok(Contribution {
+                fee: 1_900,
+                additional_fee: 400,
+            })
+        );
+
+        assert!(original.check(&proposal(true, p2wpkh(4), 48_100), &opt).is_err());
+        let p2pkh = Script::new_p2pkh(&bitcoin::PubkeyHash::from_slice(&[0; 20]).unwrap());
+        assert!(original.check(&proposal(false, p2pkh, 48_100), &opt).is_err());
+        // more than the fee of one input
+        assert!(original.check(&proposal(false, p2wpkh(4), 47_000), &opt).is_err());
+
+        let mut removed = valid.clone();
+        removed.global.unsigned_tx.output.remove(0);
+        removed.outputs.remove(0);
+        assert!(original.check(&removed, &opt).is_err());
+
+        let mut locktime = valid.clone();
+        locktime.global.unsigned_tx.lock_time = 1;
+        assert!(original.check(&locktime, &opt).is_err());
+
+        let mut substituted = valid.clone();
+        substituted.global.unsigned_tx.output[1].script_pubkey = p2wpkh(5);
+        assert!(original.check(&substituted, &opt).is_ok());
+        let disabled = PayjoinOpt {
+            disable_output_substitution: true,
+            ..opt
+        };
+        assert!(original.check(&substituted, &disabled).is_err());
+    }
+}
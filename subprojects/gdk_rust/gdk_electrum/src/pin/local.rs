@@ -0,0 +1,184 @@
+//! PIN protection of the mnemonic without a PIN server, for offline deployments.
+//!
+//! The mnemonic is encrypted with a key derived from the PIN through scrypt, salted with random
+//! bytes drawn at every `set_pin`. The attempts are counted on disk, each one a file created
+//! atomically so that concurrent attempts, also from other processes, can't share one, and after
+//! `MAX_ATTEMPTS` wrong ones the PIN data are refused even with the right PIN, as the PIN server
+//! does.
+//! Since the encrypted mnemonic is kept by the app, the lockout only stops guesses made through
+//! the library, offline guesses are slowed by the memory-hard KDF alone.
+
+use crate::Error;
+use aes_gcm_siv::aead::{AeadInPlace, NewAead};
+use aes_gcm_siv::{Aes256GcmSiv, Key, Nonce};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use gdk_common::model::PinGetDetails;
+use gdk_common::wally::{self, SCRYPT_BLOCK_SIZE, SCRYPT_COST, SCRYPT_PARALLELISM};
+use rand::{thread_rng, Rng};
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the `pin_identifier` of the PIN data created without the PIN server
+pub const PIN_IDENTIFIER_PREFIX: &str = "local:";
+
+/// The wrong attempts after which the PIN data are locked
+pub const MAX_ATTEMPTS: u32 = 3;
+
+pub struct LocalPin {
+    /// where the attempts of each PIN data are counted, in a directory named by its id
+    dir: PathBuf,
+}
+
+impl LocalPin {
+    pub fn new<P: AsRef<Path>>(data_root: P) -> Self {
+        LocalPin {
+            dir: data_root.as_ref().join("pin"),
+        }
+    }
+
+    /// Whether `details` were created by [`LocalPin::set_pin`]
+    pub fn is_local(details: &PinGetDetails) -> bool {
+        details.pin_identifier.starts_with(PIN_IDENTIFIER_PREFIX)
+    }
+
+    pub fn set_pin(&self, pin: &[u8], mnemonic: &str) -> Result<PinGetDetails, Error> {
+        let id = thread_rng().gen::<[u8; 16]>().to_hex();
+        let salt = thread_rng().gen::<[u8; 32]>();
+        let nonce = thread_rng().gen::<[u8; 12]>();
+        let mut encrypted = mnemonic.as_bytes().to_vec();
+        cipher(pin, &salt).encrypt_in_place(Nonce::from_slice(&nonce), b"", &mut encrypted)?;
+        fs::create_dir_all(self.dir.join(&id))?;
+
+        Ok(PinGetDetails {
+            salt: salt.to_hex(),
+            encrypted_data: [&nonce[..], &encrypted[..]].concat().to_hex(),
+            pin_identifier: format!("{}{}", PIN_IDENTIFIER_PREFIX, id),
+        })
+    }
+
+    pub fn get_mnemonic(&self, pin: &[u8], details: &PinGetDetails) -> Result<String, Error> {
+        // the id names a file, accept only what `set_pin` creates
+        let id = details
+            .pin_identifier
+            .strip_prefix(PIN_IDENTIFIER_PREFIX)
+            .filter(|id| Vec::<u8>::from_hex(id).map_or(false, |id| id.len() == 16))
+            .ok_or_else(|| Error::Generic("invalid pin_identifier".into()))?;
+        // claimed before running the KDF, so that interrupting it doesn't spare an attempt
+        self.claim_attempt(id)?;
+
+        let salt = Vec::<u8>::from_hex(&details.salt)?;
+        let encrypted = Vec::<u8>::from_hex(&details.encrypted_data)?;
+        if encrypted.len() < 12 {
+            return Err(Error::Generic("invalid encrypted_data".into()));
+        }
+        let (nonce, encrypted) = encrypted.split_at(12);
+        let mut mnemonic = encrypted.to_vec();
+        match cipher(pin, &salt).decrypt_in_place(Nonce::from_slice(nonce), b"", &mut mnemonic) {
+            Ok(()) => {
+                self.reset_attempts(id)?;
+                String::from_utf8(mnemonic).map_err(|_| Error::InvalidPin)
+            }
+            Err(_) => Err(Error::InvalidPin),
+        }
+    }
+
+    /// Claim the first free attempt of the PIN data `id`, failing if none is left. `set_pin`
+    /// creates the directory of the attempts, a missing one means it was removed or the PIN data
+    /// come from another device, and counts as locked.
+    fn claim_attempt(&self, id: &str) -> Result<(), Error> {
+        let dir = self.dir.join(id);
+        if !dir.is_dir() {
+            return Err(Error::InvalidPin);
+        }
+        for attempt in 1..=MAX_ATTEMPTS {
+            let path = dir.join(attempt.to_string());
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(Error::InvalidPin)
+    }
+
+    fn reset_attempts(&self, id: &str) -> Result<(), Error> {
+        for entry in fs::read_dir(self.dir.join(id))? {
+            fs::remove_file(entry?.path())?;
+        }
+        Ok(())
+    }
+}
+
+fn cipher(pin: &[u8], salt: &[u8]) -> Aes256GcmSiv {
+    let key = wally::scrypt(pin, salt, SCRYPT_COST, SCRYPT_BLOCK_SIZE, SCRYPT_PARALLELISM);
+    Aes256GcmSiv::new(Key::from_slice(&key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_local_pin() {
+        let dir = TempDir::new().unwrap();
+        let local = LocalPin::new(dir.path());
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+        let details = local.set_pin(b"123456", mnemonic).unwrap();
+        assert!(LocalPin::is_local(&details));
+        assert_eq!(local.get_mnemonic(b"123456", &details).unwrap(), mnemonic);
+
+        // a right PIN resets the wrong attempts
+        assert!(matches!(local.get_mnemonic(b"000000", &details), Err(Error::InvalidPin)));
+        assert!(matches!(local.get_mnemonic(b"000000", &details), Err(Error::InvalidPin)));
+        assert_eq!(local.get_mnemonic(b"123456", &details).unwrap(), mnemonic);
+
+        // the lockout survives a restart
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(matches!(local.get_mnemonic(b"000000", &details), Err(Error::InvalidPin)));
+        }
+        let local = LocalPin::new(dir.path());
+        assert!(matches!(local.get_mnemonic(b"123456", &details), Err(Error::InvalidPin)));
+
+        // other PIN data are not affected
+        let other = local.set_pin(b"123456", mnemonic).unwrap();
+        assert_ne!(other.salt, details.salt);
+        assert_eq!(local.get_mnemonic(b"123456", &other).unwrap(), mnemonic);
+
+        // removing the counter doesn't reset the wrong attempts
+        fs::remove_dir_all(
+            dir.path().join("pin").join(&other.pin_identifier[PIN_IDENTIFIER_PREFIX.len()..]),
+        )
+        .unwrap();
+        assert!(matches!(local.get_mnemonic(b"123456", &other), Err(Error::InvalidPin)));
+
+        let mut traversal = other.clone();
+        traversal.pin_identifier = "local:../../etc/passwd".into();
+        assert!(local.get_mnemonic(b"123456", &traversal).is_err());
+    }
+
+    #[test]
+    fn test_local_pin_concurrent_attempts() {
+        let dir = TempDir::new().unwrap();
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let details = LocalPin::new(dir.path()).set_pin(b"123456", mnemonic).unwrap();
+
+        // more parallel wrong attempts than allowed, as if from several sessions
+        let handles: Vec<_> = (0..2 * MAX_ATTEMPTS)
+            .map(|_| {
+                let (path, details) = (dir.path().to_path_buf(), details.clone());
+                std::thread::spawn(move || LocalPin::new(path).get_mnemonic(b"000000", &details))
+            })
+            .collect();
+        for handle in handles {
+            assert!(matches!(handle.join().unwrap(), Err(Error::InvalidPin)));
+        }
+        let id = &details.pin_identifier[PIN_IDENTIFIER_PREFIX.len()..];
+        let claimed = fs::read_dir(dir.path().join("pin").join(id)).unwrap().count();
+        assert_eq!(claimed, MAX_ATTEMPTS as usize);
+        let local = LocalPin::new(dir.path());
+        assert!(matches!(local.get_mnemonic(b"123456", &details), Err(Error::InvalidPin)));
+    }
+}
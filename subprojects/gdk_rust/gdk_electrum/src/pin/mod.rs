@@ -13,9 +13,7 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::str::FromStr;
 
-const PINSERVER_URL: &'static str = "https://jadepin.blockstream.com";
-const PINSERVER_PUBKEY: &'static str =
-    "0332b7b1348bde8ca4b46b9dcc30320e140ca26428160a27bdbfc30b34ec87c547";
+pub mod local;
 
 type Aes256Cbc = Cbc<Aes256, Pkcs7>;
 type ShaHmac = Hmac<sha256::Hash>;
@@ -49,6 +47,7 @@ pub struct PinManager {
     response_hmac_key: ShaHmac,
     rng: ThreadRng,
     agent: ureq::Agent,
+    url: String,
 }
 
 enum PinOp {
@@ -57,10 +56,13 @@ enum PinOp {
 }
 
 impl PinManager {
-    pub fn new(agent: ureq::Agent) -> Result<Self, Error> {
-        info!("PinManager new()");
-        let data = Self::handshake_request(&agent)?;
-        Self::with_handshake(data, agent)
+    /// Start a handshake with the PIN server at `url`, authenticated by `public_key`
+    pub fn new(agent: ureq::Agent, url: &str, public_key: &str) -> Result<Self, Error> {
+        info!("PinManager new() {}", url);
+        let public_key = PublicKey::from_str(public_key)
+            .map_err(|_| Error::Generic("invalid pin server public key".into()))?;
+        let data = Self::handshake_request(&agent, url)?;
+        Self::with_handshake(data, agent, url, &public_key)
     }
 
     /// `set_pin` consume self, because handshake must be done for every request
@@ -73,11 +75,9 @@ impl PinManager {
         self.server_call(pin_secret, private_key, PinOp::Get).map_err(|_| Error::PinError)
     }
 
-    fn handshake_request(agent: &ureq::Agent) -> Result<Handshake, Error> {
-        let response = agent
-            .post(&format!("{}/start_handshake", PINSERVER_URL))
-            .set("content-length", "0")
-            .call();
+    fn handshake_request(agent: &ureq::Agent, url: &str) -> Result<Handshake, Error> {
+        let response =
+            agent.post(&format!("{}/start_handshake", url)).set("content-length", "0").call();
         if !response.ok() {
             return Err(Error::PinError);
         }
@@ -85,9 +85,13 @@ impl PinManager {
         Ok(data)
     }
 
-    fn with_handshake(data: Handshake, agent: ureq::Agent) -> Result<Self, Error> {
+    fn with_handshake(
+        data: Handshake,
+        agent: ureq::Agent,
+        url: &str,
+        pinserver_pubkey: &PublicKey,
+    ) -> Result<Self, Error> {
         let mut rng = rand::thread_rng();
-        let pinserver_pubkey = PublicKey::from_str(PINSERVER_PUBKEY).unwrap();
 
         let sig = data.sig()?;
         let (ske, msg) = data.ske()?;
@@ -107,6 +111,7 @@ impl PinManager {
             response_encryption_key: Self::derive(2, &shared_secret),
             response_hmac_key: Self::derive(3, &shared_secret),
             agent,
+            url: url.to_string(),
         })
     }
 
@@ -165,7 +170,7 @@ impl PinManager {
 
         let response = self
             .agent
-            .post(&format!("{}/{}", PINSERVER_URL, op))
+            .post(&format!("{}/{}", self.url, op))
             .send_json(serde_json::to_value(&req).unwrap());
 
         if !response.ok() {
@@ -227,27 +232,17 @@ mod test {
     use crate::pin::{Handshake, PinManager, ResponseData};
     use bitcoin::hashes::hex::{FromHex, ToHex};
     use bitcoin::hashes::Hmac;
-    use bitcoin::secp256k1::SecretKey;
-
-    #[test]
-    fn test_with_pin_server() {
-        // requires internet connection and pin server working
-        let mut rng = rand::thread_rng();
-        let secret_key = SecretKey::new(&mut rng);
-
-        let manager = PinManager::new(ureq::Agent::new()).unwrap();
-        let pin_key_set = manager.set_pin(&[0u8; 4], &secret_key).unwrap();
-
-        let manager = PinManager::new(ureq::Agent::new()).unwrap();
-        let pin_key_get = manager.get_pin(&[0u8; 4], &secret_key).unwrap();
-        assert_eq!(pin_key_get, pin_key_set);
-    }
+    use bitcoin::PublicKey;
+    use gdk_common::network::{PIN_SERVER_PUBLIC_KEY, PIN_SERVER_URL};
+    use std::str::FromStr;
 
     #[test]
     fn test_handshake() {
         // test vector taken from a random response from the production pin server
         let data = Handshake { sig: "004a58b09b6b4b6585536c5fbd662fb729a277426875a644fa56f5d05d6724281576f9d7844fc131102cd9d4fd56ca0b7f3cf9872379510407b3075f5c862c70".to_string(), ske: "032541c31f808a28750daf386e52ad70f16db153fa9e8375a6178021a0c7a74c09".to_string() };
-        assert!(PinManager::with_handshake(data, ureq::Agent::new()).is_ok());
+        let pubkey = PublicKey::from_str(PIN_SERVER_PUBLIC_KEY).unwrap();
+        let agent = ureq::Agent::new();
+        assert!(PinManager::with_handshake(data, agent, PIN_SERVER_URL, &pubkey).is_ok());
     }
 
     #[test]
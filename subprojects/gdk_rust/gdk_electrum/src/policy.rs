@@ -0,0 +1,461 @@
+//! Miniscript subaccounts, defined by a P2WSH descriptor whose keys are account xpubs, such as
+//! `wsh(or_d(pk(A),and_v(v:pk(B),older(52560))))`: "A, or B after about a year without moving
+//! the coins". The keys are derived at `<account xpub>/<change>/<index>` and one of them is ours.
+//!
+//! The wallet signs with the only key it holds, so the spending path is fixed per account: the
+//! cheapest satisfaction needing just our signature and the least restrictive timelocks.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use bitcoin::secp256k1::{Message, Signature};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::{Address, PublicKey, Script, SigHashType};
+use miniscript::descriptor::{DescriptorPublicKey, Wildcard, WshInner};
+use miniscript::{
+    BitcoinSig, Descriptor, Interpreter, Miniscript, Satisfier, Segwitv0, Terminal, TranslatePk2,
+};
+
+use gdk_common::be::Timelocks;
+use gdk_common::model::PolicyOpt;
+use gdk_common::scripts::InputShape;
+
+use crate::error::Error;
+
+/// Block based relative timelocks are encoded in the low 16 bits of nSequence
+const MAX_OLDER: u32 = 0xffff;
+
+/// nLockTime values from this one on are timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// BIP68 disable flag, when set the nSequence has no relative timelock
+const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;
+
+/// BIP68 type flag, when set the relative timelock is in units of 512 seconds
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+
+pub struct Policy {
+    opt: PolicyOpt,
+    ms: Miniscript<DescriptorPublicKey, Segwitv0>,
+    /// our account xpub, as it appears in the descriptor
+    xpub: ExtendedPubKey,
+    /// the relative and absolute timelocks appearing in the descriptor
+    olders: BTreeSet<u32>,
+    afters: BTreeSet<u32>,
+    /// the timelocks of the spending path we can satisfy
+    timelocks: Timelocks,
+    shape: InputShape,
+}
+
+/// Provides the signatures of our key and tells which timelocks the spending transaction meets
+struct PolicySatisfier {
+    public_key: PublicKey,
+    signature: Signature,
+    timelocks: Timelocks,
+}
+
+impl Satisfier<PublicKey> for PolicySatisfier {
+    fn lookup_sig(&self, public_key: &PublicKey) -> Option<BitcoinSig> {
+        if *public_key == self.public_key {
+            Some((self.signature, SigHashType::All))
+        } else {
+            None
+        }
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.timelocks.older.map_or(false, |older| n <= older)
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.timelocks.after.map_or(false, |after| n <= after)
+    }
+}
+
+impl Policy {
+    /// Validate `opt` against our account `xpub`
+    pub fn new(opt: &PolicyOpt, xpub: &ExtendedPubKey) -> Result<Self, Error> {
+        let invalid = |reason: String| Error::InvalidPolicy(reason);
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(&opt.descriptor)
+            .map_err(|e| invalid(e.to_string()))?;
+        let ms = match descriptor {
+            Descriptor::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::Ms(ms) => ms.clone(),
+                WshInner::SortedMulti(_) => {
+                    return Err(invalid("use a multisig subaccount for sortedmulti".into()))
+                }
+            },
+            _ => return Err(invalid("only wsh() descriptors are supported".into())),
+        };
+        ms.sanity_check().map_err(|e| invalid(e.to_string()))?;
+
+        let is_mainnet = |xpub: &ExtendedPubKey| xpub.network == bitcoin::Network::Bitcoin;
+        let mut ours = false;
+        for key in ms.iter_pk() {
+            let xkey = match &key {
+                DescriptorPublicKey::XPub(xkey)
+                    if xkey.derivation_path.as_ref().is_empty()
+                        && xkey.wildcard == Wildcard::None =>
+                {
+                    xkey.xkey
+                }
+                _ => return Err(invalid(format!("{} is not an account xpub", key))),
+            };
+            if is_mainnet(&xkey) != is_mainnet(xpub) {
+                return Err(invalid(format!("{} is for another network", xkey)));
+            }
+            ours |= xkey.public_key == xpub.public_key && xkey.chain_code == xpub.chain_code;
+        }
+        if !ours {
+            return Err(invalid("our xpub is not in the descriptor".into()));
+        }
+
+        let mut olders = BTreeSet::new();
+        let mut afters = BTreeSet::new();
+        for node in ms.iter() {
+            match node.node {
+                Terminal::Older(n) if n & SEQUENCE_TYPE_FLAG != 0 || n > MAX_OLDER => {
+                    return Err(invalid(format!("older({}) is not in blocks", n)))
+                }
+                Terminal::After(n) if n >= LOCKTIME_THRESHOLD => {
+                    return Err(invalid(format!("after({}) is not a block height", n)))
+                }
+                Terminal::Older(n) => {
+                    olders.insert(n);
+                }
+                Terminal::After(n) => {
+                    afters.insert(n);
+                }
+                _ => (),
+            }
+        }
+
+        let mut policy = Policy {
+            opt: opt.clone(),
+            ms,
+            xpub: *xpub,
+            olders,
+            afters,
+            timelocks: Timelocks::default(),
+            shape: InputShape::Satisfaction {
+                elements: 0,
+                size: 0,
+            },
+        };
+        let (timelocks, witness) = policy
+            .plan()
+            .ok_or_else(|| invalid("our key alone cannot satisfy the descriptor".into()))?;
+        policy.timelocks = timelocks;
+        policy.shape = InputShape::Satisfaction {
+            elements: witness.len() as u8,
+            size: witness.iter().map(Vec::len).sum::<usize>() as u16,
+        };
+        Ok(policy)
+    }
+
+    pub fn opt(&self) -> &PolicyOpt {
+        &self.opt
+    }
+
+    pub fn shape(&self) -> InputShape {
+        self.shape
+    }
+
+    /// The timelocks the inputs must set to be spent with our key
+    pub fn timelocks(&self) -> Timelocks {
+        self.timelocks
+    }
+
+    /// The first height at which a timelocked spending path of a coin confirmed at `height` can
+    /// be used, whoever holds its key
+    pub fn expiry_height(&self, height: u32) -> Option<u32> {
+        let olders = self.olders.iter().map(|older| height + older);
+        // a transaction with nLockTime `after` can be mined from the following block
+        olders.chain(self.afters.iter().map(|after| after + 1)).min()
+    }
+
+    /// Whether a coin confirmed at `height`, if any, can be spent with our key in the next block
+    pub fn is_spendable(&self, height: Option<u32>, tip_height: u32) -> bool {
+        let older_ok = match (self.timelocks.older, height) {
+            (None, _) => true,
+            (Some(older), Some(height)) => tip_height + 1 >= height + older,
+            (Some(_), None) => false,
+        };
+        older_ok && self.timelocks.after.map_or(true, |after| tip_height >= after)
+    }
+
+    /// The miniscript with every key derived at `path`
+    fn derive(&self, path: &DerivationPath) -> Result<Miniscript<PublicKey, Segwitv0>, Error> {
+        self.ms.translate_pk2(|key: &DescriptorPublicKey| match key {
+            DescriptorPublicKey::XPub(xkey) => {
+                Ok(xkey.xkey.derive_pub(&crate::EC, path)?.public_key)
+            }
+            _ => Err(Error::InvalidPolicy("only xpubs are supported".into())),
+        })
+    }
+
+    pub fn witness_script(&self, path: &DerivationPath) -> Result<Script, Error> {
+        Ok(self.derive(path)?.encode())
+    }
+
+    pub fn address(
+        &self,
+        is_change: bool,
+        index: u32,
+        network: bitcoin::Network,
+    ) -> Result<Address, Error> {
+        let path = DerivationPath::from(&[(is_change as u32).into(), index.into()][..]);
+        Ok(Address::p2wsh(&self.witness_script(&path)?, network))
+    }
+
+    /// The least restrictive timelocks with which our key can satisfy the descriptor, with a
+    /// witness of the expected size
+    fn plan(&self) -> Option<(Timelocks, Vec<Vec<u8>>)> {
+        let path = DerivationPath::from(&[0.into(), 0.into()][..]);
+        let ms = self.derive(&path).ok()?;
+        let public_key = self.xpub.derive_pub(&crate::EC, &path).ok()?.public_key;
+        // a 72 bytes DER signature once the sighash byte is added, the biggest one we produce
+        let mut compact = [1u8; 64];
+        compact[0] = 0x80;
+        let signature = Signature::from_compact(&compact).ok()?;
+
+        let olders = std::iter::once(None).chain(self.olders.iter().map(|n| Some(*n)));
+        let mut candidates: Vec<Timelocks> = olders
+            .flat_map(|older| {
+                let afters = std::iter::once(None).chain(self.afters.iter().map(|n| Some(*n)));
+                afters.map(move |after| Timelocks {
+                    older,
+                    after,
+                })
+            })
+            .collect();
+        candidates.sort_by_key(|t| (t.older.unwrap_or(0), t.after.unwrap_or(0)));
+
+        candidates.into_iter().find_map(|timelocks| {
+            let satisfier = PolicySatisfier {
+                public_key,
+                signature,
+                timelocks,
+            };
+            let mut witness = ms.satisfy(satisfier).ok()?;
+            witness.push(ms.encode().into_bytes());
+            Some((timelocks, witness))
+        })
+    }
+
+    /// The witness spending the input `input_index` of `tx`, derived at `path` with `value`,
+    /// signed with our key and satisfied with the timelocks `tx` sets
+    pub fn sign_input(
+        &self,
+        tx: &bitcoin::Transaction,
+        input_index: usize,
+        xprv: &ExtendedPrivKey,
+        path: &DerivationPath,
+        value: u64,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let ms = self.derive(path)?;
+        let witness_script = ms.encode();
+        let private_key = xprv.derive_priv(&crate::EC, path)?.private_key;
+        let hash = SigHashCache::new(tx).signature_hash(
+            input_index,
+            &witness_script,
+            value,
+            SigHashType::All,
+        );
+        let message = Message::from_slice(&hash[..]).unwrap();
+        let satisfier = PolicySatisfier {
+            public_key: PublicKey::from_private_key(&crate::EC, &private_key),
+            signature: crate::EC.sign(&message, &private_key.key),
+            timelocks: tx_timelocks(tx, input_index),
+        };
+        let mut witness = ms.satisfy(satisfier).map_err(|e| Error::Generic(e.to_string()))?;
+        witness.push(witness_script.into_bytes());
+        Ok(witness)
+    }
+
+    /// Check the witness of the input `input_index` of `tx`, spending `value` from our script
+    /// at `path`, has our witness script and satisfies it with valid signatures
+    pub fn verify_input(
+        &self,
+        tx: &bitcoin::Transaction,
+        input_index: usize,
+        path: &DerivationPath,
+        value: u64,
+    ) -> Result<(), Error> {
+        let invalid = || Error::Common(gdk_common::error::Error::InputValidationFailed);
+        let witness_script = self.witness_script(path)?;
+        let txin = &tx.input[input_index];
+        if txin.witness.last() != Some(&witness_script.to_bytes()) {
+            return Err(invalid());
+        }
+        let timelocks = tx_timelocks(tx, input_index);
+        let interpreter = Interpreter::from_txdata(
+            &witness_script.to_v0_p2wsh(),
+            &txin.script_sig,
+            &txin.witness,
+            timelocks.older.unwrap_or(0),
+            timelocks.after.unwrap_or(0),
+        )
+        .map_err(|_| invalid())?;
+        let verify_sig = interpreter.sighash_verify(&crate::EC, tx, input_index, value);
+        for constraint in interpreter.iter(verify_sig) {
+            constraint.map_err(|_| invalid())?;
+        }
+        Ok(())
+    }
+}
+
+/// The block based timelocks met by the input `input_index` of `tx`
+fn tx_timelocks(tx: &bitcoin::Transaction, input_index: usize) -> Timelocks {
+    let sequence = tx.input[input_index].sequence;
+    let older = if tx.version >= 2
+        && sequence & SEQUENCE_DISABLE_FLAG == 0
+        && sequence & SEQUENCE_TYPE_FLAG == 0
+    {
+        Some(sequence & MAX_OLDER)
+    } else {
+        None
+    };
+    let after = if tx.lock_time > 0 && tx.lock_time < LOCKTIME_THRESHOLD && sequence != 0xffff_ffff
+    {
+        Some(tx.lock_time)
+    } else {
+        None
+    };
+    Timelocks {
+        older,
+        after,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::util::bip32::ChildNumber;
+    use bitcoin::{OutPoint, Transaction, TxIn, TxOut};
+
+    fn xprv(seed: u8) -> ExtendedPrivKey {
+        ExtendedPrivKey::new_master(bitcoin::Network::Testnet, &[seed; 32]).unwrap()
+    }
+
+    fn xpub(seed: u8) -> ExtendedPubKey {
+        ExtendedPubKey::from_private(&crate::EC, &xprv(seed))
+    }
+
+    fn inheritance(older: u32) -> PolicyOpt {
+        PolicyOpt {
+            descriptor: format!(
+                "wsh(or_d(pk({}),and_v(v:pk({}),older({}))))",
+                xpub(1),
+                xpub(2),
+                older
+            ),
+        }
+    }
+
+    #[test]
+    fn test_policy_opt() {
+        let opt = inheritance(52560);
+        let owner = Policy::new(&opt, &xpub(1)).unwrap();
+        assert_eq!(owner.timelocks(), Timelocks::default());
+        let heir = Policy::new(&opt, &xpub(2)).unwrap();
+        assert_eq!(
+            heir.timelocks(),
+            Timelocks {
+                older: Some(52560),
+                after: None
+            }
+        );
+        assert_eq!(
+            owner.address(false, 3, bitcoin::Network::Testnet).unwrap(),
+            heir.address(false, 3, bitcoin::Network::Testnet).unwrap()
+        );
+        // the heir also pushes an empty dissatisfaction of pk(A)
+        let size = |shape| match shape {
+            InputShape::Satisfaction {
+                elements,
+                size,
+            } => (elements, size),
+            _ => panic!("unexpected shape"),
+        };
+        assert_eq!(size(owner.shape()).0, 2);
+        assert_eq!(size(heir.shape()).0, 3);
+        assert!(size(heir.shape()).1 > size(owner.shape()).1);
+
+        assert!(!heir.is_spendable(Some(100), 100 + 52558));
+        assert!(heir.is_spendable(Some(100), 100 + 52559));
+        assert!(!heir.is_spendable(None, 100));
+        assert!(owner.is_spendable(None, 100));
+        assert_eq!(owner.expiry_height(100), Some(100 + 52560));
+
+        let err = |opt: &PolicyOpt, xpub: &ExtendedPubKey| match Policy::new(opt, xpub) {
+            Err(Error::InvalidPolicy(reason)) => reason,
+            _ => panic!("expected an invalid policy"),
+        };
+        err(&opt, &xpub(3));
+        err(&inheritance(SEQUENCE_TYPE_FLAG | 10), &xpub(1));
+        err(&inheritance(0x10000), &xpub(1));
+        let timestamp = PolicyOpt {
+            descriptor: format!("wsh(and_v(v:pk({}),after(1600000000)))", xpub(1)),
+        };
+        err(&timestamp, &xpub(1));
+        let derived = PolicyOpt {
+            descriptor: format!("wsh(pk({}/0/*))", xpub(1)),
+        };
+        err(&derived, &xpub(1));
+        let sortedmulti = PolicyOpt {
+            descriptor: format!("wsh(sortedmulti(1,{},{}))", xpub(1), xpub(2)),
+        };
+        err(&sortedmulti, &xpub(1));
+    }
+
+    #[test]
+    fn test_sign_timelocked() {
+        let opt = inheritance(144);
+        let heir = Policy::new(&opt, &xpub(2)).unwrap();
+        let path = DerivationPath::from(vec![ChildNumber::from(0), ChildNumber::from(7)]);
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0xffff_fffd,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        // without the relative timelock set the heir cannot satisfy the descriptor
+        assert!(heir.sign_input(&tx, 0, &xprv(2), &path, 10_000).is_err());
+
+        tx.input[0].sequence = heir.timelocks().older.unwrap();
+        let witness = heir.sign_input(&tx, 0, &xprv(2), &path, 10_000).unwrap();
+        assert_eq!(witness.len(), 3);
+        assert!(witness[1].is_empty());
+        assert_eq!(witness[2], heir.witness_script(&path).unwrap().into_bytes());
+        let (elements, size) = match heir.shape() {
+            InputShape::Satisfaction {
+                elements,
+                size,
+            } => (elements as usize, size as usize),
+            _ => panic!("unexpected shape"),
+        };
+        assert_eq!(witness.len(), elements);
+        assert!(witness.iter().map(Vec::len).sum::<usize>() <= size);
+
+        // the owner wallet verifies the spend of the heir
+        let owner = Policy::new(&opt, &xpub(1)).unwrap();
+        tx.input[0].witness = witness;
+        assert!(owner.verify_input(&tx, 0, &path, 10_000).is_ok());
+        assert!(owner.verify_input(&tx, 0, &path, 10_001).is_err());
+        let other_path = DerivationPath::from(vec![ChildNumber::from(0), ChildNumber::from(8)]);
+        assert!(owner.verify_input(&tx, 0, &other_path, 10_000).is_err());
+        // the timelock is part of the signed transaction
+        tx.input[0].sequence -= 1;
+        assert!(owner.verify_input(&tx, 0, &path, 10_000).is_err());
+    }
+}
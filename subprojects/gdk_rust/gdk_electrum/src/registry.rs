@@ -0,0 +1,556 @@
+//! Verification and caching of the Liquid asset registry entries.
+//!
+//! An entry is accepted only if its asset id commits to the issuance prevout and to the contract
+//! it carries, and if the fields shown to the user (domain, ticker, precision, name) are the ones
+//! in the contract.
+//!
+//! Besides the full index, single entries are looked up by asset id and kept in the encrypted
+//! wallet cache, refreshed once they expire.
+
+use crate::error::Error;
+use crate::request;
+use crate::store::Store;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::{sha256, Hash};
+use elements::issuance::{AssetId, ContractHash};
+use elements::OutPoint;
+use flate2::read::GzDecoder;
+use gdk_common::model::UserAsset;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How long a registry entry is cached before being fetched again
+pub const ENTRY_EXPIRY_SECS: u64 = 7 * 86_400;
+
+/// How long an asset missing from the registry is remembered as unknown
+pub const UNKNOWN_EXPIRY_SECS: u64 = 86_400;
+
+/// The delay before fetching again an entry that could not be fetched, doubled at every
+/// consecutive failure up to `UNKNOWN_EXPIRY_SECS`
+pub const FAILURE_BACKOFF_SECS: u64 = 60;
+
+/// The cached registry entries, by asset id
+pub type AssetCache = HashMap<String, CachedAsset>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CachedAsset {
+    /// The verified registry entry, `None` if the registry doesn't have a valid one
+    pub entry: Option<Value>,
+
+    /// When the entry was fetched, in seconds since the epoch
+    pub fetched_at: u64,
+
+    /// The consecutive failed fetches since `fetched_at`
+    #[serde(default)]
+    pub failures: u32,
+
+    /// When the last fetch failed, in seconds since the epoch
+    #[serde(default)]
+    pub failed_at: u64,
+}
+
+impl CachedAsset {
+    pub fn is_expired(&self, now: u64) -> bool {
+        if self.failures > 0 {
+            let backoff = FAILURE_BACKOFF_SECS << (self.failures - 1).min(16);
+            return now.saturating_sub(self.failed_at) >= backoff.min(UNKNOWN_EXPIRY_SECS);
+        }
+        let expiry = match self.entry {
+            Some(_) => ENTRY_EXPIRY_SECS,
+            None => UNKNOWN_EXPIRY_SECS,
+        };
+        now.saturating_sub(self.fetched_at) >= expiry
+    }
+
+    /// The cached entry after a failed fetch at `now`, the previous entry is kept
+    fn failed(mut self, now: u64) -> Self {
+        self.failures += 1;
+        self.failed_at = now;
+        self
+    }
+}
+
+/// Fetch the entries of `assets_id` missing from the cache or expired, or all of them if `force`,
+/// and persist the updated cache. Assets that cannot be fetched are retried with a backoff, once
+/// a fetch fails the registry is assumed unreachable and the remaining assets are not tried.
+/// If the request is cancelled the entries fetched so far are persisted.
+pub fn update_asset_cache(
+    store: &Store,
+    agent: &ureq::Agent,
+    base_url: &str,
+    assets_id: &[String],
+    force: bool,
+) -> Result<(), Error> {
+    let now = now_secs();
+    let stale: Vec<(&String, CachedAsset)> = {
+        let store_read = store.read()?;
+        assets_id
+            .iter()
+            .filter_map(|id| {
+                let cached = store_read.cache.asset_cache.get(id);
+                if force || cached.map_or(true, |c| c.is_expired(now)) {
+                    Some((id, cached.cloned().unwrap_or_default()))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    };
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    let mut updated = vec![];
+    let mut unreachable = false;
+    let mut cancelled = Ok(());
+    let total = stale.len() as u64;
+    for (done, (asset_id, cached)) in stale.into_iter().enumerate() {
+        if let Err(e) = request::check_cancelled() {
+            cancelled = Err(e);
+            break;
+        }
+        request::progress("update_asset_cache", done as u64, Some(total));
+        if unreachable {
+            updated.push((asset_id.clone(), cached.failed(now)));
+            continue;
+        }
+        match call_asset(agent, base_url, asset_id) {
+            Ok(entry) => updated.push((
+                asset_id.clone(),
+                CachedAsset {
+                    entry,
+                    fetched_at: now,
+                    ..Default::default()
+                },
+            )),
+            Err(e) => {
+                warn!("cannot fetch registry entry {}: {:?}", asset_id, e);
+                updated.push((asset_id.clone(), cached.failed(now)));
+                unreachable = true;
+            }
+        }
+    }
+
+    let mut store_write = store.write()?;
+    store_write.cache.asset_cache.extend(updated);
+    store_write.flush()?;
+    cancelled
+}
+
+/// Fetch the registry entry of `asset_id`, `None` if the registry doesn't know the asset or its
+/// entry doesn't verify
+pub fn call_asset(
+    agent: &ureq::Agent,
+    base_url: &str,
+    asset_id: &str,
+) -> Result<Option<Value>, Error> {
+    let url = format!("{}/{}", base_url, asset_id);
+    info!("START call_asset {}", &url);
+    let response = agent
+        .get(&url)
+        .timeout_connect(15_000)
+        .timeout_read(15_000)
+        .set("Accept-Encoding", "gzip")
+        .call();
+    let status = response.status();
+    info!("call_asset {} returns {}", &url, status);
+    match status {
+        200 => {
+            let entry = read_json(response)?;
+            match verify_entry(asset_id, &entry) {
+                Ok(()) => Ok(Some(entry)),
+                Err(reason) => {
+                    warn!("call_asset dropping registry entry {}: {}", asset_id, reason);
+                    Ok(None)
+                }
+            }
+        }
+        404 => Ok(None),
+        _ => Err(Error::Generic(format!("asset registry returned {} for {}", status, asset_id))),
+    }
+}
+
+/// Parse the json body of `response`, decompressing it if the server sent it gzipped
+pub fn read_json(response: ureq::Response) -> Result<Value, Error> {
+    let gzipped = response.header("Content-Encoding") == Some("gzip");
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(GzDecoder::new(response.into_reader()))
+    } else {
+        Box::new(response.into_reader())
+    };
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Check the metadata defined by the user for `asset_id`
+pub fn validate_user_asset(asset_id: &str, asset: &UserAsset) -> Result<(), Error> {
+    let invalid =
+        |reason: &str| Err(Error::Generic(format!("invalid asset {}: {}", asset_id, reason)));
+    if AssetId::from_hex(asset_id).is_err() {
+        return invalid("not an asset id");
+    }
+    if asset.name.is_empty() || asset.name.len() > 255 {
+        return invalid("name must have 1 to 255 characters");
+    }
+    if asset.ticker.as_ref().map_or(false, |t| t.is_empty() || t.len() > 24) {
+        return invalid("ticker must have 1 to 24 characters");
+    }
+    if asset.precision > 8 {
+        return invalid("precision must be at most 8");
+    }
+    if asset.icon.as_ref().map_or(false, |i| base64::decode(i).is_err()) {
+        return invalid("icon is not base64");
+    }
+    Ok(())
+}
+
+/// Whether the registry has an entry for `asset_id`, in the full `registry` index or in the
+/// `asset_cache`, such assets cannot be defined by the user
+pub fn is_registered(asset_id: &str, registry: Option<&Value>, asset_cache: &AssetCache) -> bool {
+    registry.and_then(|r| r.get(asset_id)).is_some()
+        || asset_cache.get(asset_id).map_or(false, |c| c.entry.is_some())
+}
+
+/// Add the assets defined by the user to the registry `assets` and `icons`, the registry
+/// entries and icons are kept so that an imported asset cannot relabel a registered one
+pub fn merge_user_assets(
+    user_assets: &HashMap<String, UserAsset>,
+    assets: Option<&mut Map<String, Value>>,
+    icons: Option<&mut Map<String, Value>>,
+) {
+    if let Some(assets) = assets {
+        for (asset_id, asset) in user_assets {
+            assets.entry(asset_id).or_insert_with(|| user_asset_entry(asset_id, asset));
+        }
+    }
+    if let Some(icons) = icons {
+        for (asset_id, asset) in user_assets {
+            if let Some(icon) = &asset.icon {
+                icons.entry(asset_id).or_insert_with(|| Value::String(icon.clone()));
+            }
+        }
+    }
+}
+
+/// The registry-like entry of an asset defined by the user
+pub fn user_asset_entry(asset_id: &str, asset: &UserAsset) -> Value {
+    let mut entry = json!({
+        "asset_id": asset_id,
+        "name": asset.name,
+        "precision": asset.precision,
+        "user_defined": true,
+    });
+    if let Some(ticker) = &asset.ticker {
+        entry["ticker"] = json!(ticker);
+    }
+    entry
+}
+
+/// The entry of the policy asset, which is not in the registry
+pub fn policy_asset_entry(policy_asset: &str) -> Value {
+    json!({"asset_id": policy_asset, "name": "Liquid Bitcoin", "ticker": "L-BTC"})
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Split the registry `index` in the verified entries and the rejected ones, the latter mapped to
+/// the reason of the rejection.
+pub fn verify_registry(index: Value) -> (Map<String, Value>, Map<String, Value>) {
+    let mut verified = Map::new();
+    let mut rejected = Map::new();
+    if let Value::Object(entries) = index {
+        for (asset_id, entry) in entries {
+            match verify_entry(&asset_id, &entry) {
+                Ok(()) => {
+                    verified.insert(asset_id, entry);
+                }
+                Err(reason) => {
+                    rejected.insert(asset_id, Value::String(reason));
+                }
+            }
+        }
+    }
+    (verified, rejected)
+}
+
+/// Check that `entry` is a valid registry entry for `asset_id`
+pub fn verify_entry(asset_id: &str, entry: &Value) -> Result<(), String> {
+    if entry["asset_id"].as_str() != Some(asset_id) {
+        return Err("asset_id mismatch".into());
+    }
+    let contract = entry.get("contract").filter(|c| c.is_object()).ok_or("missing contract")?;
+    let prevout = &entry["issuance_prevout"];
+    let txid = prevout["txid"]
+        .as_str()
+        .and_then(|t| elements::Txid::from_hex(t).ok())
+        .ok_or("invalid issuance_prevout txid")?;
+    let vout = prevout["vout"].as_u64().ok_or("invalid issuance_prevout vout")? as u32;
+
+    let computed = asset_id_from_contract(
+        OutPoint {
+            txid,
+            vout,
+        },
+        contract,
+    );
+    if computed.to_string() != asset_id {
+        return Err("asset_id does not commit to the contract and issuance prevout".into());
+    }
+
+    for field in &["ticker", "precision", "name"] {
+        if let Some(value) = entry.get(*field) {
+            if value != &contract[*field] {
+                return Err(format!("{} does not match the contract", field));
+            }
+        }
+    }
+    if let Some(domain) = entry.get("entity").and_then(|e| e.get("domain")) {
+        if domain != &contract["entity"]["domain"] {
+            return Err("domain does not match the contract".into());
+        }
+    }
+    Ok(())
+}
+
+/// The asset id issued by spending `prevout` with the contract `contract`
+pub fn asset_id_from_contract(prevout: OutPoint, contract: &Value) -> AssetId {
+    let hash = sha256::Hash::hash(canonical_json(contract).as_bytes());
+    let contract_hash = ContractHash::from_inner(hash.into_inner());
+    AssetId::from_entropy(AssetId::generate_asset_entropy(prevout, contract_hash))
+}
+
+/// Serialize `value` as the registry does when committing to a contract: sorted keys, no
+/// whitespace and non-ASCII characters escaped.
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let fields: Vec<String> = keys
+                .into_iter()
+                .map(|k| format!("{}:{}", escape(k), canonical_json(&map[k])))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::String(s) => escape(s),
+        other => other.to_string(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in Value::String(s.to_string()).to_string().chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            let mut buf = [0u16; 2];
+            for unit in c.encode_utf16(&mut buf) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ticker: &str) -> (String, Value) {
+        let contract = json!({
+            "entity": { "domain": "example.com" },
+            "issuer_pubkey": "02a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f90",
+            "name": "Test Ünit",
+            "precision": 2,
+            "ticker": ticker,
+            "version": 0,
+        });
+        let prevout = OutPoint {
+            txid: elements::Txid::from_hex(
+                "0a9388d39cc8b6d4d30e4c3eb0ac2a8f6a2c1fe6bd08c1d4fe8e3f58c9f8c3a1",
+            )
+            .unwrap(),
+            vout: 1,
+        };
+        let asset_id = asset_id_from_contract(prevout, &contract).to_string();
+        let entry = json!({
+            "asset_id": asset_id,
+            "contract": contract,
+            "issuance_prevout": { "txid": prevout.txid.to_string(), "vout": 1 },
+            "entity": { "domain": "example.com" },
+            "name": "Test Ünit",
+            "precision": 2,
+            "ticker": ticker,
+        });
+        (asset_id, entry)
+    }
+
+    #[test]
+    fn test_cached_asset_expiry() {
+        let (_, good) = entry("TST");
+        let known = CachedAsset {
+            entry: Some(good),
+            fetched_at: 1_000,
+            ..Default::default()
+        };
+        let unknown = CachedAsset {
+            entry: None,
+            fetched_at: 1_000,
+            ..Default::default()
+        };
+        assert!(!known.is_expired(1_000 + UNKNOWN_EXPIRY_SECS));
+        assert!(unknown.is_expired(1_000 + UNKNOWN_EXPIRY_SECS));
+        assert!(known.is_expired(1_000 + ENTRY_EXPIRY_SECS));
+        // a clock going backwards doesn't expire anything
+        assert!(!unknown.is_expired(0));
+
+        // failed fetches are retried later and later, keeping the previous entry
+        let now = 1_000 + ENTRY_EXPIRY_SECS;
+        let failed = known.clone().failed(now);
+        assert_eq!(failed.entry, known.entry);
+        assert!(!failed.is_expired(now + FAILURE_BACKOFF_SECS - 1));
+        assert!(failed.is_expired(now + FAILURE_BACKOFF_SECS));
+        let failed = failed.failed(now);
+        assert!(!failed.is_expired(now + FAILURE_BACKOFF_SECS));
+        assert!(failed.is_expired(now + 2 * FAILURE_BACKOFF_SECS));
+        let failed = (0..40).fold(CachedAsset::default(), |c, _| c.failed(now));
+        assert!(failed.is_expired(now + UNKNOWN_EXPIRY_SECS));
+    }
+
+    #[test]
+    fn test_user_assets() {
+        let (asset_id, registry_entry) = entry("TST");
+        let asset = UserAsset {
+            name: "Company Shares".into(),
+            ticker: Some("CSH".into()),
+            precision: 0,
+            icon: Some(base64::encode(b"png")),
+        };
+        assert!(validate_user_asset(&asset_id, &asset).is_ok());
+        assert!(validate_user_asset("00", &asset).is_err());
+        let mut bad = asset.clone();
+        bad.precision = 9;
+        assert!(validate_user_asset(&asset_id, &bad).is_err());
+        bad = asset.clone();
+        bad.icon = Some("not base64!".into());
+        assert!(validate_user_asset(&asset_id, &bad).is_err());
+
+        let user_id = "11".repeat(32);
+        let mut user_assets = HashMap::new();
+        user_assets.insert(asset_id.clone(), asset.clone());
+        user_assets.insert(user_id.clone(), asset);
+        let mut assets = Map::new();
+        assets.insert(asset_id.clone(), registry_entry.clone());
+        let mut icons = Map::new();
+        icons.insert(asset_id.clone(), json!("registry icon"));
+        merge_user_assets(&user_assets, Some(&mut assets), Some(&mut icons));
+        assert_eq!(assets[&user_id]["name"], "Company Shares");
+        assert_eq!(assets[&user_id]["ticker"], "CSH");
+        assert_eq!(assets[&user_id]["user_defined"], true);
+        assert_eq!(icons[&user_id], base64::encode(b"png"));
+        // the registry entries cannot be relabeled
+        assert_eq!(assets[&asset_id], registry_entry);
+        assert_eq!(icons[&asset_id], "registry icon");
+
+        let index = json!({ asset_id.clone(): registry_entry.clone() });
+        assert!(is_registered(&asset_id, Some(&index), &AssetCache::new()));
+        assert!(!is_registered(&user_id, Some(&index), &AssetCache::new()));
+        let mut asset_cache = AssetCache::new();
+        asset_cache.insert(
+            user_id.clone(),
+            CachedAsset {
+                entry: None,
+                ..Default::default()
+            },
+        );
+        assert!(!is_registered(&user_id, None, &asset_cache));
+        asset_cache.insert(
+            user_id.clone(),
+            CachedAsset {
+                entry: Some(registry_entry),
+                ..Default::default()
+            },
+        );
+        assert!(is_registered(&user_id, None, &asset_cache));
+    }
+
+    #[test]
+    fn test_canonical_json() {
+        let value = json!({"b": [1, {"d": true, "c": null}], "a": "é\"x"});
+        assert_eq!(canonical_json(&value), r#"{"a":"\u00e9\"x","b":[1,{"c":null,"d":true}]}"#);
+    }
+
+    #[test]
+    fn test_asset_id_from_contract() {
+        // the registry entry of Tether USD on Liquid
+        let contract = json!({
+            "entity": { "domain": "tether.to" },
+            "issuer_pubkey": "0337cceec0beea0232ebe14cba0197a9fbd45fcf2ec946749de920e71434c2b904",
+            "name": "Tether USD",
+            "precision": 8,
+            "ticker": "USDt",
+            "version": 0,
+        });
+        let prevout = OutPoint {
+            txid: elements::Txid::from_hex(
+                "9596d259270ef5bac0020435e6d859aea633409483ba64e232b8ba04ce288668",
+            )
+            .unwrap(),
+            vout: 0,
+        };
+        let asset_id = "ce091c998b83c78bb71a632313ba3760f1763d9cfcffae02258ffa9865a37bd2";
+        assert_eq!(asset_id_from_contract(prevout, &contract).to_string(), asset_id);
+
+        let entry = json!({
+            "asset_id": asset_id,
+            "contract": contract,
+            "issuance_prevout": { "txid": prevout.txid.to_string(), "vout": 0 },
+            "entity": { "domain": "tether.to" },
+            "name": "Tether USD",
+            "precision": 8,
+            "ticker": "USDt",
+        });
+        assert_eq!(verify_entry(asset_id, &entry), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_registry() {
+        let (good_id, good) = entry("TST");
+        assert_eq!(verify_entry(&good_id, &good), Ok(()));
+
+        // the displayed ticker differs from the committed one
+        let (wrong_ticker_id, mut wrong_ticker) = entry("TSU");
+        wrong_ticker["ticker"] = json!("USDT");
+
+        // the contract was swapped, the asset id no longer commits to it
+        let (swapped_id, mut swapped) = entry("TSV");
+        swapped["contract"]["entity"]["domain"] = json!("evil.com");
+        swapped["entity"]["domain"] = json!("evil.com");
+
+        // the entry is listed under another asset id
+        let mut moved = good.clone();
+        moved["asset_id"] = json!(swapped_id);
+
+        let index = json!({
+            good_id.clone(): good,
+            wrong_ticker_id.clone(): wrong_ticker,
+            swapped_id.clone(): swapped,
+            "00".repeat(32): moved,
+        });
+        let (verified, rejected) = verify_registry(index);
+        assert_eq!(verified.keys().collect::<Vec<_>>(), vec![&good_id]);
+        assert_eq!(rejected.len(), 3);
+        assert_eq!(rejected[&wrong_ticker_id], json!("ticker does not match the contract"));
+        assert!(rejected[&swapped_id].as_str().unwrap().contains("does not commit"));
+        assert_eq!(rejected[&"00".repeat(32)], json!("asset_id mismatch"));
+    }
+}
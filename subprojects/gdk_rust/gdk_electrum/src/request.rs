@@ -0,0 +1,191 @@
+//! Asynchronous requests, each one runs on its own thread.
+//!
+//! Long operations report their progress with [`progress`] and stop at the next
+//! [`check_cancelled`] once the request running on the thread is cancelled, outside of a request
+//! both calls do nothing.
+
+use crate::error::Error;
+use crate::Notifier;
+use gdk_common::model::{Notification, RequestNotification, RequestProgress};
+use log::info;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+lazy_static! {
+    static ref REQUESTS: Mutex<HashMap<u32, Arc<AtomicBool>>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+thread_local! {
+    static CURRENT: RefCell<Option<Request>> = RefCell::new(None);
+}
+
+#[derive(Clone)]
+pub struct Request {
+    id: u32,
+    cancelled: Arc<AtomicBool>,
+    notify: Notifier,
+}
+
+impl Request {
+    /// Register a new request, its notifications are delivered to `notify`
+    pub fn register(notify: Notifier) -> Request {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        REQUESTS.lock().unwrap().insert(id, cancelled.clone());
+        Request {
+            id,
+            cancelled,
+            notify,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Run `f` as the request of the current thread, then notify its outcome and unregister it
+    pub fn run<F: FnOnce() -> Result<Value, Value>>(self, f: F) {
+        CURRENT.with(|current| *current.borrow_mut() = Some(self.clone()));
+        let outcome = f();
+        CURRENT.with(|current| *current.borrow_mut() = None);
+
+        REQUESTS.lock().unwrap().remove(&self.id);
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        self.notify.send(Notification::Request(RequestNotification {
+            id: self.id,
+            progress: None,
+            result,
+            error,
+        }));
+    }
+}
+
+/// Cancel the request `id`, returns false if it is not running
+pub fn cancel(id: u32) -> bool {
+    match REQUESTS.lock().unwrap().get(&id) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Fails with `Error::Cancelled` if the request of the current thread has been cancelled
+pub fn check_cancelled() -> Result<(), Error> {
+    CURRENT.with(|current| match *current.borrow() {
+        Some(ref request) if request.cancelled.load(Ordering::Relaxed) => {
+            info!("request {} cancelled", request.id);
+            Err(Error::Cancelled)
+        }
+        _ => Ok(()),
+    })
+}
+
+/// Wait for the value sent by a download running on another thread, `None` if the sender is
+/// dropped. The wait stops with `Error::Cancelled` once the request of the current thread is
+/// cancelled, leaving the download to complete in the background.
+pub fn recv<T>(receiver: &Receiver<T>) -> Result<Option<T>, Error> {
+    loop {
+        check_cancelled()?;
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(value) => return Ok(Some(value)),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return Ok(None),
+        }
+    }
+}
+
+/// Notify the progress of the request of the current thread
+pub fn progress(step: &str, done: u64, total: Option<u64>) {
+    CURRENT.with(|current| {
+        if let Some(ref request) = *current.borrow() {
+            request.notify.send(Notification::Request(RequestNotification {
+                id: request.id,
+                progress: Some(RequestProgress {
+                    step: step.to_string(),
+                    done,
+                    total,
+                }),
+                result: None,
+                error: None,
+            }));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NotificationHandler;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_request_cancel() {
+        assert!(check_cancelled().is_ok());
+
+        let (sender, receiver) = mpsc::channel();
+        let notify = Notifier::default();
+        notify.set(NotificationHandler::Channel(sender));
+
+        let request = Request::register(notify);
+        let id = request.id();
+        request.run(|| {
+            progress("step", 1, Some(2));
+            assert!(check_cancelled().is_ok());
+            assert!(cancel(id));
+            check_cancelled().map(|_| json!(true)).map_err(|e| json!(e.to_string()))
+        });
+        assert!(!cancel(id));
+        assert!(check_cancelled().is_ok());
+
+        let progress = match receiver.try_recv().unwrap() {
+            Notification::Request(request) => request.progress.unwrap(),
+            n => panic!("unexpected notification {:?}", n),
+        };
+        assert_eq!((progress.done, progress.total), (1, Some(2)));
+        match receiver.try_recv().unwrap() {
+            Notification::Request(request) => {
+                assert_eq!(request.id, id);
+                assert_eq!(request.result, None);
+                assert_eq!(request.error, Some(json!("request cancelled")));
+            }
+            n => panic!("unexpected notification {:?}", n),
+        }
+    }
+
+    #[test]
+    fn test_request_recv_cancel() {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(1).unwrap();
+        assert_eq!(recv(&receiver).unwrap(), Some(1));
+
+        // the download never completes, the wait stops once the request is cancelled
+        let request = Request::register(Notifier::default());
+        let id = request.id();
+        let (outcome_sender, outcome_receiver) = mpsc::channel();
+        let waiting = std::thread::spawn(move || {
+            request.run(|| {
+                outcome_sender
+                    .send(recv(&receiver).map(|_| ()).map_err(|e| e.to_string()))
+                    .unwrap();
+                Ok(json!(null))
+            })
+        });
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(cancel(id));
+        waiting.join().unwrap();
+        assert_eq!(outcome_receiver.recv().unwrap(), Err("request cancelled".to_string()));
+        drop(sender);
+    }
+}
@@ -0,0 +1,333 @@
+//! Proof of reserves, following BIP127.
+//!
+//! The proof is a transaction spending the wallet utxos plus a first "challenge" input, which
+//! commits to the auditor message and spends an output that cannot exist, so that the proof can
+//! be signed by the wallet but never broadcast.
+
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::{Message, Signature};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, OutPoint, PublicKey, Script, SigHashType, Transaction, TxIn, TxOut, Txid};
+use log::info;
+
+use gdk_common::model::{VerifyProofOfReservesOpt, VerifyProofOfReservesResult};
+use gdk_common::scripts::{p2pkh_script, p2shwpkh_script, p2shwpkh_script_sig};
+
+use crate::chain::ChainSource;
+use crate::error::Error;
+
+const COMMITMENT_PREFIX: &str = "Proof-of-Reserves: ";
+
+/// The first input of the proof, committing to `message`
+pub fn challenge_txin(message: &str) -> TxIn {
+    let hash = sha256d::Hash::hash(format!("{}{}", COMMITMENT_PREFIX, message).as_bytes());
+    TxIn {
+        previous_output: OutPoint::new(Txid::from_hash(hash), 0),
+        script_sig: Script::new(),
+        sequence: 0xFFFF_FFFF,
+        witness: vec![],
+    }
+}
+
+/// The unsigned proof transaction, spending the challenge and then `utxos` to a single
+/// `OP_TRUE` output
+pub fn proof_tx(message: &str, utxos: &[(OutPoint, TxOut)]) -> Transaction {
+    let mut input = vec![challenge_txin(message)];
+    input.extend(utxos.iter().map(|(outpoint, _)| TxIn {
+        previous_output: *outpoint,
+        script_sig: Script::new(),
+        sequence: 0xFFFF_FFFF,
+        witness: vec![],
+    }));
+    Transaction {
+        version: 1,
+        lock_time: 0,
+        input,
+        output: vec![TxOut {
+            value: utxos.iter().map(|(_, txout)| txout.value).sum(),
+            script_pubkey: op_true(),
+        }],
+    }
+}
+
+/// The output spent by the challenge input, it has the script of the first utxo so that the
+/// signature of the challenge proves control of the same key
+pub fn challenge_txout(first_utxo: &TxOut) -> TxOut {
+    TxOut {
+        value: 0,
+        script_pubkey: first_utxo.script_pubkey.clone(),
+    }
+}
+
+fn op_true() -> Script {
+    Builder::new().push_opcode(opcodes::OP_TRUE).into_script()
+}
+
+/// Verify the proof in `opt` against the utxo set of `source`
+pub fn verify_proof(
+    source: &dyn ChainSource,
+    opt: &VerifyProofOfReservesOpt,
+) -> Result<VerifyProofOfReservesResult, Error> {
+    let psbt = base64::decode(&opt.psbt)
+        .ok()
+        .and_then(|bytes| deserialize::<PartiallySignedTransaction>(&bytes).ok());
+    let checked = match psbt {
+        Some(psbt) => check_proof(source, &psbt, &opt.message)?,
+        None => Err("malformed psbt".to_string()),
+    };
+    info!("proof of reserves check {:?}", checked);
+    Ok(match checked {
+        Ok(satoshi) => VerifyProofOfReservesResult {
+            valid: true,
+            satoshi,
+            reason: None,
+        },
+        Err(reason) => VerifyProofOfReservesResult {
+            valid: false,
+            satoshi: 0,
+            reason: Some(reason),
+        },
+    })
+}
+
+/// The errors of the outer result are failures in reaching `source`, the inner ones are the
+/// reasons the proof is not valid
+fn check_proof(
+    source: &dyn ChainSource,
+    psbt: &PartiallySignedTransaction,
+    message: &str,
+) -> Result<Result<u64, String>, Error> {
+    let mut tx = psbt.global.unsigned_tx.clone();
+    if tx.input.len() < 2 || tx.input.len() != psbt.inputs.len() {
+        return Ok(Err("the proof has no utxos".into()));
+    }
+    if tx.input[0].previous_output != challenge_txin(message).previous_output {
+        return Ok(Err("the proof does not commit to the message".into()));
+    }
+    if tx.output.len() != 1 || tx.output[0].script_pubkey != op_true() {
+        return Ok(Err("the proof must have a single OP_TRUE output".into()));
+    }
+    for (txin, input) in tx.input.iter_mut().zip(psbt.inputs.iter()) {
+        txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+        txin.witness = input.final_script_witness.clone().unwrap_or_default();
+    }
+
+    // the outputs spent, as found on the chain
+    let outpoints: Vec<OutPoint> = tx.input[1..].iter().map(|i| i.previous_output).collect();
+    if outpoints.iter().collect::<HashSet<_>>().len() != outpoints.len() {
+        return Ok(Err("the proof spends an output more than once".into()));
+    }
+    let txids: Vec<Txid> =
+        outpoints.iter().map(|o| o.txid).collect::<HashSet<_>>().into_iter().collect();
+    let prev_txs: HashMap<Txid, Transaction> = match source.transactions_raw(&txids) {
+        Ok(raws) => txids
+            .into_iter()
+            .zip(raws.iter())
+            .map(|(txid, raw)| Ok((txid, deserialize(raw)?)))
+            .collect::<Result<_, Error>>()?,
+        Err(Error::TxNotFound(txid)) => return Ok(Err(format!("unknown transaction {}", txid))),
+        Err(e) => return Err(e),
+    };
+    let mut spent = vec![];
+    for outpoint in outpoints.iter() {
+        match prev_txs[&outpoint.txid].output.get(outpoint.vout as usize) {
+            Some(txout) => spent.push(txout.clone()),
+            None => return Ok(Err(format!("unknown output {}", outpoint))),
+        }
+    }
+    spent.insert(0, challenge_txout(&spent[0]));
+
+    for (index, txout) in spent.iter().enumerate() {
+        if !verify_input(&tx, index, txout) {
+            return Ok(Err(format!("invalid signature for input {}", index)));
+        }
+    }
+    let satoshi: u64 = spent.iter().map(|txout| txout.value).sum();
+    if tx.output[0].value != satoshi {
+        return Ok(Err("the output value does not match the inputs".into()));
+    }
+
+    // an utxo is spent if a transaction in the history of its script spends it
+    let scripts: Vec<Script> = spent[1..]
+        .iter()
+        .map(|txout| txout.script_pubkey.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let history_txids: Vec<Txid> = source
+        .scripts_history(&scripts)?
+        .into_iter()
+        .flatten()
+        .map(|h| h.tx_hash)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    for raw in source.transactions_raw(&history_txids)? {
+        let history_tx: Transaction = deserialize(&raw)?;
+        if let Some(input) =
+            history_tx.input.iter().find(|i| outpoints.contains(&i.previous_output))
+        {
+            return Ok(Err(format!("output {} is already spent", input.previous_output)));
+        }
+    }
+
+    Ok(Ok(satoshi))
+}
+
+/// Check the signature of a P2PKH, P2SH-P2WPKH or P2WPKH input spending `prevout`
+pub(crate) fn verify_input(tx: &Transaction, index: usize, prevout: &TxOut) -> bool {
+    let input = &tx.input[index];
+    let (signature, public_key, segwit) = if input.witness.len() == 2 {
+        (&input.witness[0][..], &input.witness[1][..], true)
+    } else {
+        let pushes: Vec<&[u8]> = input
+            .script_sig
+            .instructions()
+            .filter_map(|i| match i {
+                Ok(Instruction::PushBytes(bytes)) => Some(bytes),
+                _ => None,
+            })
+            .collect();
+        match pushes.as_slice() {
+            [signature, public_key] => (*signature, *public_key, false),
+            _ => return false,
+        }
+    };
+    let public_key = match PublicKey::from_slice(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+
+    let script_code = p2pkh_script(&public_key);
+    let expected_script = if !segwit {
+        script_code.clone()
+    } else if input.script_sig.is_empty() && public_key.compressed {
+        Address::p2wpkh(&public_key, bitcoin::Network::Regtest).unwrap().script_pubkey()
+    } else if input.script_sig == p2shwpkh_script_sig(&public_key) {
+        p2shwpkh_script(&public_key)
+    } else {
+        return false;
+    };
+    if expected_script != prevout.script_pubkey {
+        return false;
+    }
+
+    let hash = if segwit {
+        SigHashCache::new(tx).signature_hash(index, &script_code, prevout.value, SigHashType::All)
+    } else {
+        tx.signature_hash(index, &script_code, SigHashType::All as u32)
+    };
+    let signature = match signature.split_last() {
+        Some((&sighash_type, der)) if sighash_type == SigHashType::All as u8 => {
+            match Signature::from_der(der) {
+                Ok(signature) => signature,
+                Err(_) => return false,
+            }
+        }
+        _ => return false,
+    };
+    let message = Message::from_slice(&hash[..]).unwrap();
+    crate::EC.verify(&message, &signature, &public_key.key).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::internal_sign_bitcoin;
+    use crate::chain::MemoryChainSource;
+    use bitcoin::consensus::encode::serialize;
+    use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+    use gdk_common::be::BETransaction;
+    use gdk_common::scripts::ScriptType;
+    use gdk_common::NetworkId;
+
+    fn funding_tx(script_pubkey: Script, value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey,
+            }],
+        }
+    }
+
+    fn prove(message: &str, xprv: &ExtendedPrivKey, utxos: &[(OutPoint, TxOut)]) -> String {
+        let path = DerivationPath::from(vec![]);
+        let tx = proof_tx(message, utxos);
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx.clone()).unwrap();
+        let mut spent: Vec<TxOut> = utxos.iter().map(|(_, txout)| txout.clone()).collect();
+        spent.insert(0, challenge_txout(&spent[0]));
+        for (index, txout) in spent.iter().enumerate() {
+            let (_, witness) =
+                internal_sign_bitcoin(&tx, index, xprv, &path, txout.value, ScriptType::P2wpkh)
+                    .unwrap();
+            psbt.inputs[index].witness_utxo = Some(txout.clone());
+            psbt.inputs[index].final_script_witness = Some(witness);
+        }
+        base64::encode(&serialize(&psbt))
+    }
+
+    #[test]
+    fn test_proof_of_reserves() {
+        let network = bitcoin::Network::Regtest;
+        let xprv = ExtendedPrivKey::new_master(network, &[3u8; 32]).unwrap();
+        let public_key = ExtendedPubKey::from_private(&crate::EC, &xprv).public_key;
+        let script_pubkey = Address::p2wpkh(&public_key, network).unwrap().script_pubkey();
+
+        let source = MemoryChainSource::new(NetworkId::Bitcoin(network));
+        let funding = funding_tx(script_pubkey.clone(), 10_000);
+        source.insert_tx(BETransaction::Bitcoin(funding.clone()), Some(1));
+        let utxos = vec![(OutPoint::new(funding.txid(), 0), funding.output[0].clone())];
+
+        let opt = |psbt: String, message: &str| VerifyProofOfReservesOpt {
+            psbt,
+            message: message.into(),
+        };
+        let psbt = prove("audit", &xprv, &utxos);
+        let result = verify_proof(&source, &opt(psbt.clone(), "audit")).unwrap();
+        assert_eq!(
+            result,
+            VerifyProofOfReservesResult {
+                valid: true,
+                satoshi: 10_000,
+                reason: None,
+            }
+        );
+
+        let result = verify_proof(&source, &opt(psbt.clone(), "other audit")).unwrap();
+        assert!(!result.valid);
+        assert!(!verify_proof(&source, &opt("invalid".into(), "audit")).unwrap().valid);
+
+        // signed by another key
+        let other = ExtendedPrivKey::new_master(network, &[4u8; 32]).unwrap();
+        let result = verify_proof(&source, &opt(prove("audit", &other, &utxos), "audit")).unwrap();
+        assert_eq!(result.reason, Some("invalid signature for input 0".into()));
+
+        // the same utxo listed twice doesn't count twice
+        let twice = vec![utxos[0].clone(), utxos[0].clone()];
+        let result = verify_proof(&source, &opt(prove("audit", &xprv, &twice), "audit")).unwrap();
+        assert!(!result.valid);
+        assert_eq!(result.satoshi, 0);
+        assert_eq!(result.reason, Some("the proof spends an output more than once".into()));
+
+        let mut spending = funding_tx(script_pubkey, 9_000);
+        spending.input[0].previous_output = utxos[0].0;
+        source.insert_tx(BETransaction::Bitcoin(spending), None);
+        let result = verify_proof(&source, &opt(psbt, "audit")).unwrap();
+        assert!(!result.valid);
+        assert_eq!(result.satoshi, 0);
+    }
+}
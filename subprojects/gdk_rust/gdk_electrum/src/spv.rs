@@ -6,10 +6,10 @@ use std::str::FromStr;
 use bitcoin::blockdata::constants::{max_target, DIFFCHANGE_INTERVAL, DIFFCHANGE_TIMESPAN};
 use bitcoin::BlockHash;
 use bitcoin::{util::uint::Uint256, util::BitArray, BlockHeader};
-use electrum_client::{Client as ElectrumClient, ElectrumApi};
 
 use gdk_common::network::Network;
 
+use crate::chain::ChainSource;
 use crate::error::Error;
 use crate::headers::bitcoin::HeadersChain;
 use crate::interface::ElectrumUrl;
@@ -66,11 +66,9 @@ pub enum CrossValidationError {
     ForkDepthExceeded,
     KnownAncestorMismatch,
     GdkError(crate::error::Error),
-    ElectrumError(electrum_client::Error),
 }
 
 impl_error_variant!(crate::error::Error, CrossValidationError, GdkError);
-impl_error_variant!(electrum_client::Error, CrossValidationError, ElectrumError);
 
 impl SpvCrossValidator {
     pub fn validate(&mut self, chain: &HeadersChain) -> CrossValidationResult {
@@ -155,9 +153,21 @@ pub fn spv_cross_validate(
     proxy: &Option<String>,
 ) -> Result<CrossValidationResult, CrossValidationError> {
     let client = server_url.build_client_with_proxy_and_timeout(proxy, timeout)?;
-    let remote_tip = client.block_headers_subscribe()?;
-    let remote_tip_hash = remote_tip.header.block_hash();
-    let remote_tip_height = remote_tip.height as u32;
+    spv_cross_validate_source(chain, local_tip_hash, &client, server_url)
+}
+
+/// Like `spv_cross_validate`, fetching the remote chain from `source`, which is identified by
+/// `server_url` in the result.
+pub fn spv_cross_validate_source(
+    chain: &HeadersChain,
+    local_tip_hash: &BlockHash,
+    source: &dyn ChainSource,
+    server_url: &ElectrumUrl,
+) -> Result<CrossValidationResult, CrossValidationError> {
+    let (remote_tip_height, remote_tip_header) = source.tip()?;
+    let remote_tip: BlockHeader =
+        bitcoin::consensus::deserialize(&remote_tip_header).map_err(Error::from)?;
+    let remote_tip_hash = remote_tip.block_hash();
 
     // Both point to the same tip
     if remote_tip_height == chain.height() && remote_tip_hash == *local_tip_hash {
@@ -174,9 +184,12 @@ pub fn spv_cross_validate(
 
     // The local tip is lagging behind the remote tip and can be fast-forwarded to it
     if chain.height() < remote_tip_height {
-        let remote_header = client.block_header(chain.height() as usize)?;
+        let remote_header = source
+            .headers(chain.height(), 1)?
+            .pop()
+            .ok_or(CrossValidationError::IncompleteHeaders)?;
         if remote_header.block_hash() == *local_tip_hash {
-            let fork = get_fork_branch(chain, &client, remote_tip_height, Some(chain.height()))?;
+            let fork = get_fork_branch(chain, source, remote_tip_height, Some(chain.height()))?;
 
             return Ok(CrossValidationResult::Invalid(CrossValidationInvalid {
                 our_height: chain.height(),
@@ -189,7 +202,7 @@ pub fn spv_cross_validate(
         }
     }
 
-    let fork = get_fork_branch(chain, &client, remote_tip_height, None)?;
+    let fork = get_fork_branch(chain, source, remote_tip_height, None)?;
 
     let our_work: Uint256 = (fork.common_ancestor + 1..=chain.height())
         .fold(Uint256::zero(), |total, height| total + chain.get(height).unwrap().work());
@@ -220,7 +233,7 @@ struct ForkBranch {
 /// Analyse the forked branch and return the common ancestor, the fork work and the fork tip height.
 fn get_fork_branch(
     chain: &HeadersChain,
-    client: &ElectrumClient,
+    source: &dyn ChainSource,
     remote_tip_height: u32,
     known_ancestor: Option<u32>,
 ) -> Result<ForkBranch, CrossValidationError> {
@@ -244,7 +257,7 @@ fn get_fork_branch(
     'chunk_fetch: loop {
         let c_start = curr_height.saturating_sub(chunk_size).max(height_limit);
         let c_size = curr_height - c_start;
-        let chunk = client.block_headers(c_start as usize, c_size as usize)?.headers;
+        let chunk = source.headers(c_start, c_size as usize)?;
 
         ensure!(chunk.len() == c_size as usize, CrossValidationError::IncompleteHeaders);
 
@@ -414,8 +427,7 @@ pub fn get_cross_servers(network: &Network) -> Result<Vec<ElectrumUrl>, Error> {
         }),
     }?;
 
-    // Don't cross validation against the primary server
-    let primary_server = super::determine_electrum_url_from_net(network)?;
-    let primary_url = primary_server.url();
+    // Don't cross validation against the primary server, if any (esplora sessions have none)
+    let primary_url = network.electrum_url.clone().unwrap_or_default();
     Ok(servers.into_iter().filter(|s| s.url() != primary_url).collect())
 }
@@ -1,7 +1,9 @@
+use crate::registry::AssetCache;
 use crate::spv::CrossValidationResult;
 use crate::Error;
 use aes_gcm_siv::aead::{AeadInPlace, NewAead};
 use aes_gcm_siv::{Aes256GcmSiv, Key, Nonce};
+use bitcoin::hashes::hex::{FromHex, ToHex};
 use bitcoin::hashes::{sha256, Hash};
 use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey};
 use bitcoin::Transaction;
@@ -10,7 +12,10 @@ use gdk_common::be::BETxidConvert;
 use gdk_common::be::{
     BEBlockHash, BEBlockHeader, BEScript, BETransaction, BETransactionEntry, BETransactions, BETxid,
 };
-use gdk_common::model::{AccountSettings, FeeEstimate, SPVVerifyTxResult, Settings};
+use gdk_common::model::{
+    AccountSettings, FeeEstimate, MultisigOpt, PolicyOpt, SPVVerifyTxResult, Settings, UserAsset,
+};
+use gdk_common::wally::{self, SCRYPT_BLOCK_SIZE, SCRYPT_COST, SCRYPT_PARALLELISM};
 use gdk_common::NetworkId;
 use log::{info, warn};
 use rand::{thread_rng, Rng};
@@ -28,6 +33,9 @@ pub const BATCH_SIZE: u32 = 20;
 
 pub type Store = Arc<RwLock<StoreMeta>>;
 
+/// The plain text file with the salts of the store key, present only if a secret protects the store
+const STORE_KEY: &str = "store_key";
+
 /// RawCache is a persisted and encrypted cache of wallet data, contains stuff like wallet transactions
 /// It is fully reconstructable from xpub and data from electrum server (plus master blinding for elements)
 #[derive(Default, Serialize, Deserialize)]
@@ -58,6 +66,10 @@ pub struct RawCache {
 
     /// whether BIP 44 account recovery was already run for this wallet
     pub accounts_recovered: bool, // TODO deprecated, remove when cache breaking change should happen
+
+    /// registry entries of the assets received by the wallet, fetched one by one
+    #[serde(default)]
+    pub asset_cache: AssetCache,
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -94,12 +106,29 @@ pub struct RawStore {
     // additional fields should always be appended at the end as an `Option` to retain db backwards compatibility.
     /// account settings
     accounts_settings: Option<HashMap<u32, AccountSettings>>,
+
+    /// the cosigners and threshold of the multisig accounts
+    accounts_multisig: Option<HashMap<u32, MultisigOpt>>,
+
+    /// the descriptors of the miniscript accounts
+    accounts_policy: Option<HashMap<u32, PolicyOpt>>,
+
+    /// the asset metadata defined by the user (asset_id -> metadata)
+    assets_user: Option<HashMap<String, UserAsset>>,
+}
+
+/// The scrypt salts of the store key, the first is the current one, the others are left by an
+/// interrupted change of the secret and are dropped once the files are encrypted again
+#[derive(Default, Serialize, Deserialize)]
+struct StoreKey {
+    salts: Vec<String>,
 }
 
 pub struct StoreMeta {
     pub cache: RawCache,
     pub store: RawStore,
     id: NetworkId,
+    xpub: ExtendedPubKey,
     path: PathBuf,
     cipher: Aes256GcmSiv,
     last: HashMap<Kind, sha256::Hash>,
@@ -211,26 +240,97 @@ fn load_decrypt<P: AsRef<Path>>(
     Ok(plaintext)
 }
 
-fn get_cipher(xpub: &ExtendedPubKey) -> Aes256GcmSiv {
+/// The key of the `cache` and `store` files. Without a secret and its salt it depends on the xpub
+/// only, so anyone knowing the xpub can decrypt them.
+fn get_cipher(xpub: &ExtendedPubKey, secret: Option<(&str, &[u8])>) -> Aes256GcmSiv {
     let mut enc_key_data = vec![];
     enc_key_data.extend(&xpub.public_key.to_bytes());
     enc_key_data.extend(&xpub.chain_code.to_bytes());
     enc_key_data.extend(&xpub.network.magic().to_be_bytes());
-    let key_bytes = sha256::Hash::hash(&enc_key_data).into_inner();
+    let mut key_bytes = sha256::Hash::hash(&enc_key_data).into_inner();
+    if let Some((secret, salt)) = secret {
+        let stretched = wally::scrypt(
+            secret.as_bytes(),
+            salt,
+            SCRYPT_COST,
+            SCRYPT_BLOCK_SIZE,
+            SCRYPT_PARALLELISM,
+        );
+        key_bytes = sha256::Hash::hash(&[&key_bytes[..], &stretched[..]].concat()).into_inner();
+    }
     let key = Key::from_slice(&key_bytes);
     Aes256GcmSiv::new(&key)
 }
 
+/// Write `chunks` in `path` through a temporary file, so that an interruption leaves either the
+/// previous content or the new one, never a truncated file
+fn write_atomically(path: &Path, chunks: &[&[u8]]) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    for chunk in chunks {
+        file.write_all(chunk)?;
+    }
+    file.sync_all()?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+fn read_store_key(path: &Path) -> Result<StoreKey, Error> {
+    let path = path.join(STORE_KEY);
+    if path.exists() {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    } else {
+        Ok(StoreKey::default())
+    }
+}
+
 impl StoreMeta {
+    /// Load the store in `path`, encrypting it again if `secret` is not the one protecting it yet
     pub fn new<P: AsRef<Path>>(
         path: P,
         xpub: ExtendedPubKey,
+        secret: Option<&str>,
         fallback_path: Option<&Path>,
         fallback_xpub: Option<ExtendedPubKey>,
         id: NetworkId,
     ) -> Result<StoreMeta, Error> {
-        let cipher = get_cipher(&xpub);
-        let fallback_cipher = &fallback_xpub.and_then(|xpub| Some(get_cipher(&xpub)));
+        let store_key = read_store_key(path.as_ref())?;
+        // the xpub alone decrypts the stores never protected and the ones left by an interrupted
+        // change of the secret
+        let mut ciphers = vec![];
+        if let Some(secret) = secret {
+            for salt in store_key.salts.iter() {
+                ciphers.push(get_cipher(&xpub, Some((secret, &Vec::<u8>::from_hex(salt)?[..]))));
+            }
+        }
+        ciphers.push(get_cipher(&xpub, None));
+        let position =
+            ciphers.iter().position(|cipher| load_decrypt("store", path.as_ref(), cipher).is_ok());
+        let store_path = path.as_ref().join("store");
+        if !store_key.salts.is_empty() && store_path.exists() {
+            // starting over would overwrite the store at the next flush. The secret is right if it
+            // opens the cache, then a store it can't open or decode is damaged, as a truncated one.
+            let salted = &ciphers[..ciphers.len() - 1]; // all but the xpub one
+            let corrupted = match position {
+                Some(i) => RawStore::try_new(path.as_ref(), &ciphers[i]).is_err(),
+                None => {
+                    std::fs::metadata(&store_path)?.len() < 12 + 16
+                        || salted.iter().any(|c| load_decrypt("cache", path.as_ref(), c).is_ok())
+                }
+            };
+            if corrupted {
+                return Err(Error::StoreCorrupted(format!("{:?} can't be decoded", store_path)));
+            }
+            if position.is_none() {
+                return Err(Error::InvalidStoreSecret);
+            }
+        }
+        let rekey = match secret {
+            Some(_) => position != Some(0) || store_key.salts.len() != 1,
+            None => !store_key.salts.is_empty(),
+        };
+        let cipher = ciphers.swap_remove(position.unwrap_or(ciphers.len() - 1));
+        let fallback_cipher = &fallback_xpub.and_then(|xpub| Some(get_cipher(&xpub, None)));
         let mut cache =
             RawCache::new(path.as_ref(), &cipher, fallback_path, fallback_cipher.as_ref());
         let mut store =
@@ -243,14 +343,66 @@ impl StoreMeta {
         cache.accounts.entry(0).or_default();
         store.accounts_settings.get_or_insert_with(|| Default::default());
 
-        Ok(StoreMeta {
+        let mut store_meta = StoreMeta {
             cache,
             store,
             id,
+            xpub,
             cipher,
             path,
             last: HashMap::new(),
-        })
+        };
+        // the plain text asset cache of previous versions leaked the assets of the wallet
+        let legacy_asset_cache = store_meta.path.join("asset_cache");
+        if legacy_asset_cache.exists() {
+            std::fs::remove_file(legacy_asset_cache)?;
+        }
+        if rekey {
+            store_meta.rekey(secret)?;
+        }
+        Ok(store_meta)
+    }
+
+    /// Change the secret protecting the store, `current` is the one given when loading it
+    pub fn change_secret(&mut self, current: Option<&str>, new: Option<&str>) -> Result<(), Error> {
+        let store_key = read_store_key(&self.path)?;
+        let salt = store_key.salts.first().map(|salt| Vec::<u8>::from_hex(salt)).transpose()?;
+        let cipher = match (current, salt) {
+            (Some(secret), Some(salt)) => get_cipher(&self.xpub, Some((secret, &salt[..]))),
+            (None, None) => get_cipher(&self.xpub, None),
+            _ => return Err(Error::InvalidStoreSecret),
+        };
+        self.flush()?;
+        load_decrypt("store", &self.path, &cipher).map_err(|_| Error::InvalidStoreSecret)?;
+        self.rekey(new)
+    }
+
+    /// Encrypt the files again with a key derived from `secret` and a new salt, or from the xpub
+    /// alone if `None`
+    fn rekey(&mut self, secret: Option<&str>) -> Result<(), Error> {
+        let mut store_key = read_store_key(&self.path)?;
+        self.last.clear();
+        match secret {
+            Some(secret) => {
+                let salt = thread_rng().gen::<[u8; 32]>();
+                // the previous salts must survive an interruption before both files are written
+                store_key.salts.insert(0, salt.to_hex());
+                self.write(STORE_KEY, &serde_json::to_value(&store_key)?)?;
+                self.cipher = get_cipher(&self.xpub, Some((secret, &salt[..])));
+                self.flush()?;
+                store_key.salts.truncate(1);
+                self.write(STORE_KEY, &serde_json::to_value(&store_key)?)?;
+            }
+            None => {
+                self.cipher = get_cipher(&self.xpub, None);
+                self.flush()?;
+                if !store_key.salts.is_empty() {
+                    std::fs::remove_file(self.path.join(STORE_KEY))?;
+                }
+            }
+        }
+        info!("store encrypted again, protected by a secret: {}", secret.is_some());
+        Ok(())
     }
 
     fn flush_serializable(&mut self, kind: Kind) -> Result<(), Error> {
@@ -279,9 +431,7 @@ impl StoreMeta {
         store_path.push(kind.to_string());
         //TODO should avoid rewriting if not changed? it involves saving plaintext (or struct hash)
         // in the front of the file
-        let mut file = File::create(&store_path)?;
-        file.write(&nonce_bytes)?;
-        file.write(&ciphertext)?;
+        write_atomically(&store_path, &[&nonce_bytes, &ciphertext])?;
         info!(
             "flushing {} bytes on {:?} took {}ms",
             ciphertext.len() + 16,
@@ -327,10 +477,9 @@ impl StoreMeta {
     fn write(&self, name: &str, value: &Value) -> Result<(), Error> {
         let mut path = self.path.clone();
         path.push(name);
-        let mut file = File::create(path)?;
         let vec = serde_json::to_vec(value)?;
         info!("start write {} bytes to {}", vec.len(), name);
-        file.write(&vec)?;
+        write_atomically(&path, &[&vec])?;
         info!("end write {} bytes to {}", vec.len(), name);
         Ok(())
     }
@@ -384,6 +533,16 @@ impl StoreMeta {
         self.write("asset_registry", asset_registry)
     }
 
+    /// the ids of the assets received by the wallet
+    pub fn wallet_assets(&self) -> HashSet<String> {
+        self.cache
+            .accounts
+            .values()
+            .flat_map(|acc_store| acc_store.unblinded.values())
+            .map(|unblinded| unblinded.asset.to_hex())
+            .collect()
+    }
+
     pub fn fee_estimates(&self) -> Vec<FeeEstimate> {
         if self.cache.fee_estimates.is_empty() {
             let min_fee = match self.id {
@@ -436,6 +595,44 @@ impl StoreMeta {
         self.store.accounts_settings.as_mut().unwrap().insert(account_num, settings);
     }
 
+    pub fn get_account_multisig(&self, account_num: u32) -> Option<&MultisigOpt> {
+        self.store.accounts_multisig.as_ref().and_then(|m| m.get(&account_num))
+    }
+
+    pub fn set_account_multisig(&mut self, account_num: u32, multisig: MultisigOpt) {
+        self.store
+            .accounts_multisig
+            .get_or_insert_with(Default::default)
+            .insert(account_num, multisig);
+    }
+
+    pub fn get_account_policy(&self, account_num: u32) -> Option<&PolicyOpt> {
+        self.store.accounts_policy.as_ref().and_then(|m| m.get(&account_num))
+    }
+
+    pub fn set_account_policy(&mut self, account_num: u32, policy: PolicyOpt) {
+        self.store.accounts_policy.get_or_insert_with(Default::default).insert(account_num, policy);
+    }
+
+    pub fn get_user_assets(&self) -> HashMap<String, UserAsset> {
+        self.store.assets_user.clone().unwrap_or_default()
+    }
+
+    /// insert `assets` in the user assets, replacing the ones with the same asset id
+    pub fn insert_user_assets(&mut self, assets: HashMap<String, UserAsset>) -> Result<(), Error> {
+        self.store.assets_user.get_or_insert_with(Default::default).extend(assets);
+        self.flush_store()?;
+        Ok(())
+    }
+
+    pub fn remove_user_asset(&mut self, asset_id: &str) -> Result<(), Error> {
+        if let Some(assets) = self.store.assets_user.as_mut() {
+            assets.remove(asset_id);
+        }
+        self.flush_store()?;
+        Ok(())
+    }
+
     pub fn spv_verification_status(&self, account_num: u32, txid: &BETxid) -> SPVVerifyTxResult {
         let acc_store = match self.account_cache(account_num) {
             Ok(store) => store,
@@ -491,6 +688,7 @@ impl RawAccountCache {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::registry::CachedAsset;
     use bitcoin::util::bip32::ExtendedPubKey;
     use bitcoin::Network;
     use gdk_common::{be::BETxid, NetworkId};
@@ -512,14 +710,22 @@ mod tests {
         let txid_btc = txid.ref_bitcoin().unwrap();
 
         {
-            let mut store = StoreMeta::new(&dir, xpub, None, None, id).unwrap();
+            let mut store = StoreMeta::new(&dir, xpub, None, None, None, id).unwrap();
             store.account_cache_mut(0).unwrap().heights.insert(txid, Some(1));
             store.store.memos.insert(*txid_btc, "memo".to_string());
+            let cached = CachedAsset {
+                entry: None,
+                fetched_at: 1,
+                ..Default::default()
+            };
+            store.cache.asset_cache.insert("asset".to_string(), cached);
+            store.flush().unwrap();
         }
 
-        let store = StoreMeta::new(&dir, xpub, None, None, id).unwrap();
+        let store = StoreMeta::new(&dir, xpub, None, None, None, id).unwrap();
         assert_eq!(store.account_cache(0).unwrap().heights.get(&txid), Some(&Some(1)));
         assert_eq!(store.store.memos.get(txid_btc), Some(&"memo".to_string()));
+        assert_eq!(store.cache.asset_cache["asset"].fetched_at, 1);
 
         let mut dir2 = TempDir::new().unwrap().into_path();
         dir2.push("store");
@@ -528,17 +734,99 @@ mod tests {
 
         // Before creating a new empty store, attempt recovery from fallback path
         {
-            let mut store = StoreMeta::new(&dir2, xpub2, Some(&dir), Some(xpub), id).unwrap();
+            let mut store = StoreMeta::new(&dir2, xpub2, None, Some(&dir), Some(xpub), id).unwrap();
             assert_eq!(store.account_cache_mut(0).unwrap().heights.get(&txid), Some(&Some(1)));
             assert_eq!(store.store.memos.get(txid_btc), Some(&"memo".to_string()));
             // Persist data in new path
         }
 
-        let store = StoreMeta::new(&dir2, xpub2, None, None, id).unwrap();
+        let store = StoreMeta::new(&dir2, xpub2, None, None, None, id).unwrap();
         assert_eq!(store.account_cache(0).unwrap().heights.get(&txid), Some(&Some(1)));
         assert_eq!(store.store.memos.get(txid_btc), Some(&"memo".to_string()));
     }
 
+    #[test]
+    fn test_db_secret() {
+        let id = NetworkId::Bitcoin(Network::Testnet);
+        let dir = TempDir::new().unwrap();
+        let xpub = ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let txid = bitcoin::Txid::default();
+        let memo = |store: &StoreMeta| store.store.memos.get(&txid).cloned();
+
+        {
+            let mut store = StoreMeta::new(&dir, xpub, None, None, None, id).unwrap();
+            store.store.memos.insert(txid, "memo".to_string());
+        }
+
+        // the existing store is encrypted again, the xpub alone no longer opens it
+        {
+            let store = StoreMeta::new(&dir, xpub, Some("secret"), None, None, id).unwrap();
+            assert_eq!(memo(&store), Some("memo".to_string()));
+        }
+        assert!(load_decrypt("store", &dir, &get_cipher(&xpub, None)).is_err());
+        assert!(matches!(
+            StoreMeta::new(&dir, xpub, None, None, None, id),
+            Err(Error::InvalidStoreSecret)
+        ));
+        assert!(matches!(
+            StoreMeta::new(&dir, xpub, Some("wrong"), None, None, id),
+            Err(Error::InvalidStoreSecret)
+        ));
+
+        // a damaged store is not mistaken for a wrong secret
+        let store_path = dir.path().join("store");
+        let saved = std::fs::read(&store_path).unwrap();
+        std::fs::write(&store_path, &saved[..20]).unwrap();
+        assert!(matches!(
+            StoreMeta::new(&dir, xpub, Some("secret"), None, None, id),
+            Err(Error::StoreCorrupted(_))
+        ));
+        let mut flipped = saved.clone();
+        flipped[20] ^= 1;
+        std::fs::write(&store_path, &flipped).unwrap();
+        assert!(matches!(
+            StoreMeta::new(&dir, xpub, Some("secret"), None, None, id),
+            Err(Error::StoreCorrupted(_))
+        ));
+        assert!(matches!(
+            StoreMeta::new(&dir, xpub, Some("wrong"), None, None, id),
+            Err(Error::InvalidStoreSecret)
+        ));
+        std::fs::write(&store_path, &saved).unwrap();
+        assert!(!dir.path().join("store.tmp").exists());
+
+        // rotation
+        {
+            let mut store = StoreMeta::new(&dir, xpub, Some("secret"), None, None, id).unwrap();
+            assert_eq!(memo(&store), Some("memo".to_string()));
+            assert!(matches!(
+                store.change_secret(Some("wrong"), Some("new")),
+                Err(Error::InvalidStoreSecret)
+            ));
+            store.change_secret(Some("secret"), Some("new")).unwrap();
+        }
+        assert!(StoreMeta::new(&dir, xpub, Some("secret"), None, None, id).is_err());
+        let store = StoreMeta::new(&dir, xpub, Some("new"), None, None, id).unwrap();
+        assert_eq!(memo(&store), Some("memo".to_string()));
+        assert_eq!(read_store_key(dir.path()).unwrap().salts.len(), 1);
+
+        // a rotation interrupted before rewriting the files keeps the previous secret working
+        let mut store_key = read_store_key(dir.path()).unwrap();
+        store_key.salts.insert(0, [0u8; 32].to_hex());
+        store.write(STORE_KEY, &serde_json::to_value(&store_key).unwrap()).unwrap();
+        drop(store);
+        let mut store = StoreMeta::new(&dir, xpub, Some("new"), None, None, id).unwrap();
+        assert_eq!(memo(&store), Some("memo".to_string()));
+        assert_eq!(read_store_key(dir.path()).unwrap().salts.len(), 1);
+
+        // removing the secret goes back to the xpub alone
+        store.change_secret(Some("new"), None).unwrap();
+        drop(store);
+        assert!(!dir.path().join(STORE_KEY).exists());
+        let store = StoreMeta::new(&dir, xpub, None, None, None, id).unwrap();
+        assert_eq!(memo(&store), Some("memo".to_string()));
+    }
+
     #[test]
     fn test_db_upgrade() {
         #[derive(Serialize, Deserialize)]
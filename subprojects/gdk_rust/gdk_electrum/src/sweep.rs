@@ -0,0 +1,266 @@
+//! Sweep the funds of a private key, usually coming from a paper wallet, to the wallet.
+//!
+//! The coins of the key are looked up on every script type the key can lock, a single
+//! transaction spends them all to a wallet address and is signed with the swept key.
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::{Address, OutPoint, PrivateKey, PublicKey, Script, Transaction, TxIn, TxOut, Txid};
+use log::info;
+
+use gdk_common::be::{BETransaction, DUST_VALUE};
+use gdk_common::model::{
+    AddressAmount, Balances, CreateTransaction, SPVVerifyTxResult, TransactionMeta,
+};
+use gdk_common::scripts::{p2pkh_script, p2shwpkh_script, ScriptType};
+use gdk_common::Network;
+
+use crate::account::sign_bitcoin_input;
+use crate::chain::ChainSource;
+use crate::error::Error;
+
+/// An unspent output of the swept key
+#[derive(Debug, Clone)]
+pub struct SweepUtxo {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+    pub script_type: ScriptType,
+}
+
+/// The scripts locked by `private_key`, segwit ones require a compressed key
+fn key_scripts(private_key: &PrivateKey) -> Vec<(ScriptType, Script)> {
+    let public_key = PublicKey::from_private_key(&crate::EC, private_key);
+    let mut scripts = vec![(ScriptType::P2pkh, p2pkh_script(&public_key))];
+    if public_key.compressed {
+        scripts.push((ScriptType::P2shP2wpkh, p2shwpkh_script(&public_key)));
+        let p2wpkh = Address::p2wpkh(&public_key, bitcoin::Network::Regtest).unwrap();
+        scripts.push((ScriptType::P2wpkh, p2wpkh.script_pubkey()));
+    }
+    scripts
+}
+
+/// Find the unspent outputs of `private_key` in `source`
+pub fn discover_utxos(
+    source: &dyn ChainSource,
+    private_key: &PrivateKey,
+) -> Result<Vec<SweepUtxo>, Error> {
+    let scripts: HashMap<Script, ScriptType> =
+        key_scripts(private_key).into_iter().map(|(t, s)| (s, t)).collect();
+    let script_list: Vec<Script> = scripts.keys().cloned().collect();
+    let txids: Vec<Txid> = source
+        .scripts_history(&script_list)?
+        .into_iter()
+        .flatten()
+        .map(|h| h.tx_hash)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut txs = vec![];
+    for raw in source.transactions_raw(&txids)? {
+        txs.push(deserialize::<Transaction>(&raw)?);
+    }
+    // every spend of the key outputs is in the history of their scripts
+    let spent: HashSet<OutPoint> =
+        txs.iter().flat_map(|tx| tx.input.iter().map(|i| i.previous_output)).collect();
+
+    let mut utxos = vec![];
+    for tx in txs.iter() {
+        let txid = tx.txid();
+        for (vout, txout) in tx.output.iter().enumerate() {
+            let outpoint = OutPoint::new(txid, vout as u32);
+            if let Some(script_type) = scripts.get(&txout.script_pubkey) {
+                if !spent.contains(&outpoint) {
+                    utxos.push(SweepUtxo {
+                        outpoint,
+                        txout: txout.clone(),
+                        script_type: *script_type,
+                    });
+                }
+            }
+        }
+    }
+    info!("found {} utxos to sweep", utxos.len());
+    Ok(utxos)
+}
+
+fn sign_all(
+    tx: &mut Transaction,
+    private_key: &PrivateKey,
+    utxos: &[SweepUtxo],
+) -> Result<(), Error> {
+    let unsigned = tx.clone();
+    for (i, utxo) in utxos.iter().enumerate() {
+        let (script_sig, witness) =
+            sign_bitcoin_input(&unsigned, i, private_key, utxo.txout.value, utxo.script_type)?;
+        tx.input[i].script_sig = script_sig;
+        tx.input[i].witness = witness;
+    }
+    Ok(())
+}
+
+/// Create and sign the transaction sending all `utxos` to `address`, paying the fee rate of
+/// `request` which must be set
+pub fn create_sweep_tx(
+    network: &Network,
+    address: &str,
+    request: &CreateTransaction,
+    private_key: &PrivateKey,
+    utxos: &[SweepUtxo],
+) -> Result<TransactionMeta, Error> {
+    let fee_rate_sat_kb = request.fee_rate.ok_or(Error::FeeRateBelowMinimum)?;
+    if fee_rate_sat_kb < 1000 {
+        return Err(Error::FeeRateBelowMinimum);
+    }
+    if utxos.is_empty() {
+        return Err(Error::InsufficientFunds);
+    }
+    let script_pubkey =
+        Address::from_str(address).map_err(|_| Error::InvalidAddress)?.script_pubkey();
+    let total: u64 = utxos.iter().map(|u| u.txout.value).sum();
+
+    let mut tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: utxos
+            .iter()
+            .map(|u| TxIn {
+                previous_output: u.outpoint,
+                script_sig: Script::new(),
+                sequence: 0xffff_fffd,
+                witness: vec![],
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: total,
+            script_pubkey,
+        }],
+    };
+
+    // the size is measured on the signed transaction, signatures may be a byte shorter than
+    // the final ones so one vbyte per input is added
+    sign_all(&mut tx, private_key, utxos)?;
+    let vbytes = (tx.get_weight() + 3) / 4 + tx.input.len();
+    let fee = (vbytes as u64 * fee_rate_sat_kb + 999) / 1000;
+    if total <= fee + DUST_VALUE {
+        return Err(Error::InsufficientFunds);
+    }
+    tx.output[0].value = total - fee;
+    sign_all(&mut tx, private_key, utxos)?;
+    info!("sweep tx {} fee {} vbytes {}", tx.txid(), fee, vbytes);
+
+    let mut create_transaction = request.clone();
+    create_transaction.private_key = None;
+    create_transaction.send_all = true;
+    create_transaction.addressees = vec![AddressAmount {
+        address: address.to_string(),
+        satoshi: total - fee,
+        asset_id: None,
+    }];
+    let mut satoshi = Balances::new();
+    satoshi.insert("btc".to_string(), (total - fee) as i64);
+
+    let mut created_tx = TransactionMeta::new(
+        BETransaction::Bitcoin(tx),
+        None,
+        None,
+        satoshi,
+        fee,
+        network.id().get_bitcoin_network().unwrap_or(bitcoin::Network::Bitcoin),
+        "incoming".to_string(),
+        create_transaction,
+        true,
+        SPVVerifyTxResult::InProgress,
+    );
+    created_tx.is_sweep = true;
+    created_tx.addressees_read_only = true;
+    info!("returning: {:?}", created_tx);
+    Ok(created_tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryChainSource;
+    use crate::reserves::verify_input;
+    use bitcoin::hashes::hex::FromHex;
+    use gdk_common::NetworkId;
+
+    fn funding_tx(outputs: Vec<TxOut>, previous_output: OutPoint) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![],
+            }],
+            output: outputs,
+        }
+    }
+
+    #[test]
+    fn test_sweep() {
+        let bitcoin_network = bitcoin::Network::Testnet;
+        let key = |byte| PrivateKey {
+            compressed: true,
+            network: bitcoin_network,
+            key: bitcoin::secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap(),
+        };
+        let private_key = key(1);
+        let scripts = key_scripts(&private_key);
+        assert_eq!(scripts.len(), 3);
+
+        let source = MemoryChainSource::new(NetworkId::Bitcoin(bitcoin_network));
+        let outputs = scripts
+            .iter()
+            .map(|(_, script_pubkey)| TxOut {
+                value: 10_000,
+                script_pubkey: script_pubkey.clone(),
+            })
+            .collect();
+        let funding = funding_tx(outputs, OutPoint::null());
+        source.insert_tx(BETransaction::Bitcoin(funding.clone()), Some(1));
+        // the P2PKH output is already spent
+        let spending = funding_tx(vec![], OutPoint::new(funding.txid(), 0));
+        source.insert_tx(BETransaction::Bitcoin(spending), None);
+
+        let mut utxos = discover_utxos(&source, &private_key).unwrap();
+        utxos.sort_by_key(|u| u.outpoint.vout);
+        let types: Vec<ScriptType> = utxos.iter().map(|u| u.script_type).collect();
+        assert_eq!(types, vec![ScriptType::P2shP2wpkh, ScriptType::P2wpkh]);
+
+        let mut network = Network::default();
+        network.development = true;
+        let wallet_key = PublicKey::from_private_key(&crate::EC, &key(2));
+        let address = Address::p2wpkh(&wallet_key, bitcoin::Network::Regtest).unwrap();
+        let address = &address.to_string();
+        let mut request = CreateTransaction::default();
+        request.fee_rate = Some(999);
+        assert!(matches!(
+            create_sweep_tx(&network, address, &request, &private_key, &utxos),
+            Err(Error::FeeRateBelowMinimum)
+        ));
+        request.fee_rate = Some(100_000);
+        assert!(matches!(
+            create_sweep_tx(&network, address, &request, &private_key, &utxos[..1]),
+            Err(Error::InsufficientFunds)
+        ));
+
+        request.fee_rate = Some(2_000);
+        let created = create_sweep_tx(&network, address, &request, &private_key, &utxos).unwrap();
+        assert!(created.is_sweep);
+        assert_eq!(created.satoshi["btc"] as u64 + created.fee, 20_000);
+        assert!(created.fee >= created.vsize as u64 * 2);
+
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(&created.hex).unwrap()).unwrap();
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].script_pubkey, Address::from_str(address).unwrap().script_pubkey());
+        for (i, utxo) in utxos.iter().enumerate() {
+            assert!(verify_input(&tx, i, &utxo.txout));
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! BIP21 payment URIs, `bitcoin:` ones and the `liquidnetwork:` ones of Liquid, which also
+//! carry the `assetid` of the payment.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use gdk_common::model::{CreateUriOpt, CreateUriResult, ParseUriOpt, ParseUriResult, PayjoinOpt};
+use gdk_common::{Network, NetworkId};
+
+use crate::error::Error;
+
+const PARAM_AMOUNT: &str = "amount";
+const PARAM_ASSET_ID: &str = "assetid";
+const PARAM_LABEL: &str = "label";
+const PARAM_MESSAGE: &str = "message";
+const PARAM_PAYJOIN: &str = "pj";
+const PARAM_PAYJOIN_OUTPUT_SUBSTITUTION: &str = "pjos";
+
+/// Parse `opt.uri`, a payment URI or a plain address of `network`. The `amount` of an asset other
+/// than the policy one is converted to satoshi only if `asset_precision` knows its precision.
+pub fn parse<F>(
+    network: &Network,
+    opt: &ParseUriOpt,
+    asset_precision: F,
+) -> Result<ParseUriResult, Error>
+where
+    F: Fn(&str) -> Option<u8>,
+{
+    let uri = opt.uri.trim();
+    let (scheme, rest) = match uri.find(':') {
+        Some(pos) => (Some(&uri[..pos]), &uri[pos + 1..]),
+        None => (None, uri),
+    };
+    if let Some(scheme) = scheme {
+        if !scheme.eq_ignore_ascii_case(network.bip21_prefix()) {
+            return Err(Error::InvalidUri(format!("unexpected scheme {}", scheme)));
+        }
+    }
+    let (address, query) = match rest.find('?') {
+        Some(pos) if scheme.is_some() => (&rest[..pos], &rest[pos + 1..]),
+        _ => (rest, ""),
+    };
+    if address.is_empty() {
+        return Err(Error::InvalidUri("missing address".into()));
+    }
+
+    let mut params = HashMap::new();
+    for param in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = match param.find('=') {
+            Some(pos) => (&param[..pos], &param[pos + 1..]),
+            None => (param, ""),
+        };
+        if key.starts_with("req-") {
+            return Err(Error::UnknownUriParameter(key.to_string()));
+        }
+        if params.insert(key, percent_decode(value)?).is_some() {
+            return Err(Error::InvalidUri(format!("duplicated parameter {}", key)));
+        }
+    }
+
+    let amount = params.remove(PARAM_AMOUNT);
+    let asset_id = match (network.liquid, params.get(PARAM_ASSET_ID)) {
+        (true, Some(asset_id)) => Some(parse_asset_id(asset_id)?),
+        (true, None) if amount.is_some() => return Err(Error::InvalidUriAsset),
+        _ => None,
+    };
+    let satoshi = match &amount {
+        Some(amount) => {
+            // no asset has more than 8 decimals, the amount is invalid even if the precision
+            // of the asset is not known
+            parse_amount(amount, 8).ok_or(Error::InvalidAmount)?;
+            match precision(network, asset_id.as_deref(), &asset_precision) {
+                Some(precision) => {
+                    Some(parse_amount(amount, precision).ok_or(Error::InvalidAmount)?)
+                }
+                None => None,
+            }
+        }
+        None => None,
+    };
+    let payjoin = params.remove(PARAM_PAYJOIN).map(|url| PayjoinOpt {
+        url,
+        disable_output_substitution: params.get(PARAM_PAYJOIN_OUTPUT_SUBSTITUTION).map(|s| &s[..])
+            == Some("0"),
+    });
+    Ok(ParseUriResult {
+        address: normalize_address(network, address)?,
+        amount,
+        satoshi,
+        asset_id,
+        label: params.remove(PARAM_LABEL),
+        message: params.remove(PARAM_MESSAGE),
+        payjoin,
+    })
+}
+
+/// Build the payment URI requesting `opt.satoshi` to `opt.address`, the precision of an asset
+/// other than the policy one must be known by `asset_precision`
+pub fn create<F>(
+    network: &Network,
+    opt: &CreateUriOpt,
+    asset_precision: F,
+) -> Result<CreateUriResult, Error>
+where
+    F: Fn(&str) -> Option<u8>,
+{
+    let mut uri =
+        format!("{}:{}", network.bip21_prefix(), normalize_address(network, &opt.address)?);
+    let asset_id = match (network.liquid, &opt.asset_id) {
+        (true, Some(asset_id)) => Some(parse_asset_id(asset_id)?),
+        (true, None) if opt.satoshi.is_some() => {
+            let policy_asset = network.policy_asset_id().map_err(|_| Error::InvalidUriAsset)?;
+            Some(policy_asset.to_string())
+        }
+        (false, Some(_)) => return Err(Error::InvalidUriAsset),
+        _ => None,
+    };
+    let mut params = vec![];
+    if let Some(satoshi) = opt.satoshi {
+        let precision = precision(network, asset_id.as_deref(), &asset_precision)
+            .ok_or(Error::InvalidUriAsset)?;
+        params.push((PARAM_AMOUNT, format_amount(satoshi, precision)));
+    }
+    if let Some(asset_id) = asset_id {
+        params.push((PARAM_ASSET_ID, asset_id));
+    }
+    if let Some(label) = &opt.label {
+        params.push((PARAM_LABEL, percent_encode(label)));
+    }
+    if let Some(message) = &opt.message {
+        params.push((PARAM_MESSAGE, percent_encode(message)));
+    }
+    for (i, (key, value)) in params.iter().enumerate() {
+        uri.push(if i == 0 {
+            '?'
+        } else {
+            '&'
+        });
+        uri.push_str(&format!("{}={}", key, value));
+    }
+    Ok(CreateUriResult {
+        uri,
+    })
+}
+
+/// Validate `address` for `network`, returning its canonical form, lowercase for bech32
+fn normalize_address(network: &Network, address: &str) -> Result<String, Error> {
+    match network.id() {
+        NetworkId::Bitcoin(network) => {
+            let address = bitcoin::Address::from_str(address).map_err(|_| Error::InvalidAddress)?;
+            // legacy regtest addresses are the testnet ones
+            if address.network != network
+                && !(address.network == bitcoin::Network::Testnet
+                    && network == bitcoin::Network::Regtest)
+            {
+                return Err(Error::InvalidAddress);
+            }
+            Ok(address.to_string())
+        }
+        NetworkId::Elements(network) => {
+            let address = elements::Address::parse_with_params(address, network.address_params())
+                .map_err(|_| Error::InvalidAddress)?;
+            if !address.is_blinded() {
+                return Err(Error::NonConfidentialAddress);
+            }
+            Ok(address.to_string())
+        }
+    }
+}
+
+fn parse_asset_id(asset_id: &str) -> Result<String, Error> {
+    let asset_id = asset_id.to_lowercase();
+    elements::issuance::AssetId::from_str(&asset_id).map_err(|_| Error::InvalidUriAsset)?;
+    Ok(asset_id)
+}
+
+/// The decimals of the amounts of `asset_id`, 8 for bitcoin and the policy asset
+fn precision<F>(network: &Network, asset_id: Option<&str>, asset_precision: F) -> Option<usize>
+where
+    F: Fn(&str) -> Option<u8>,
+{
+    match asset_id {
+        Some(asset_id) if Some(asset_id) != network.policy_asset.as_deref() => {
+            asset_precision(asset_id).filter(|p| *p <= 8).map(usize::from)
+        }
+        _ => Some(8),
+    }
+}
+
+/// Parse an amount with `decimals` decimals, such as `0.001` BTC, into satoshi
+fn parse_amount(amount: &str, decimals: usize) -> Option<u64> {
+    let (int, frac) = match amount.find('.') {
+        Some(pos) => (&amount[..pos], &amount[pos + 1..]),
+        None => (amount, ""),
+    };
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (int.is_empty() && frac.is_empty())
+        || frac.len() > decimals
+        || !is_digits(int)
+        || !is_digits(frac)
+    {
+        return None;
+    }
+    let int: u64 = if int.is_empty() {
+        0
+    } else {
+        int.parse().ok()?
+    };
+    let frac: u64 = if decimals == 0 {
+        0
+    } else {
+        format!("{:0<width$}", frac, width = decimals).parse().ok()?
+    };
+    int.checked_mul(10u64.pow(decimals as u32))?.checked_add(frac)
+}
+
+fn format_amount(satoshi: u64, decimals: usize) -> String {
+    let unit = 10u64.pow(decimals as u32);
+    let amount = format!("{}.{:0width$}", satoshi / unit, satoshi % unit, width = decimals);
+    amount.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Result<String, Error> {
+    let invalid = || Error::InvalidUri(format!("invalid encoding of {}", value));
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value.get(i + 1..i + 3).ok_or_else(invalid)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            decoded.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::{PrivateKey, PublicKey};
+    use gdk_common::wally::ec_public_key_from_private_key;
+    use gdk_common::ElementsNetwork;
+
+    const POLICY_ASSET: &str = "5ac9f65c0efcc4775e0baec4ec03abdde22473cd3cf33c0419ca290e0751b225";
+
+    fn network(liquid: bool) -> Network {
+        let mut network = Network::default();
+        network.liquid = liquid;
+        network.development = true;
+        if liquid {
+            network.policy_asset = Some(POLICY_ASSET.into());
+        }
+        network
+    }
+
+    fn public_key(byte: u8) -> PublicKey {
+        let private_key = PrivateKey {
+            compressed: true,
+            network: bitcoin::Network::Regtest,
+            key: SecretKey::from_slice(&[byte; 32]).unwrap(),
+        };
+        PublicKey::from_private_key(&crate::EC, &private_key)
+    }
+
+    const OTHER_ASSET: &str = "38fca2d939696061a8f76d4e6b5eecd54e3b4221c846f24a6b279e79952850a5";
+
+    fn asset_precision(asset_id: &str) -> Option<u8> {
+        if asset_id == OTHER_ASSET {
+            Some(2)
+        } else {
+            None
+        }
+    }
+
+    fn parse_uri(network: &Network, uri: &str) -> Result<ParseUriResult, Error> {
+        parse(
+            network,
+            &ParseUriOpt {
+                uri: uri.into(),
+                network: None,
+            },
+            asset_precision,
+        )
+    }
+
+    #[test]
+    fn test_amount() {
+        assert_eq!(parse_amount("1", 8), Some(100_000_000));
+        assert_eq!(parse_amount("0.001", 8), Some(100_000));
+        assert_eq!(parse_amount(".00000001", 8), Some(1));
+        assert_eq!(parse_amount("21000000.00000000", 8), Some(2_100_000_000_000_000));
+        for invalid in &["", ".", "-1", "1e3", "0.000000001", "1,5", "99999999999999999999"] {
+            assert_eq!(parse_amount(invalid, 8), None, "{}", invalid);
+        }
+        for decimals in 0..=8 {
+            for satoshi in &[1, 100_000, 100_000_000, 123_456_789] {
+                let amount = format_amount(*satoshi, decimals);
+                assert_eq!(parse_amount(&amount, decimals), Some(*satoshi), "{}", amount);
+            }
+        }
+        assert_eq!(format_amount(100_000, 8), "0.001");
+        assert_eq!(format_amount(100_000_000, 8), "1");
+        assert_eq!(format_amount(150, 2), "1.5");
+        assert_eq!(format_amount(150, 0), "150");
+        assert_eq!(parse_amount("1.5", 2), Some(150));
+        assert_eq!(parse_amount("1.", 0), Some(1));
+        assert_eq!(parse_amount("0.001", 2), None);
+        assert_eq!(parse_amount("1.5", 0), None);
+    }
+
+    #[test]
+    fn test_parse_create_bitcoin_uri() {
+        let network = network(false);
+        let address = &bitcoin::Address::p2wpkh(&public_key(1), bitcoin::Network::Regtest)
+            .unwrap()
+            .to_string();
+        let mainnet_address =
+            bitcoin::Address::p2wpkh(&public_key(1), bitcoin::Network::Bitcoin).unwrap();
+
+        let parsed = parse_uri(&network, address).unwrap();
+        assert_eq!(parsed.address, address);
+        assert_eq!(parsed.satoshi, None);
+
+        let uri = format!(
+            "BITCOIN:{}?amount=0.001&label=Luke%20Jr&message=Donation&foo=bar",
+            address.to_uppercase()
+        );
+        let parsed = parse_uri(&network, &uri).unwrap();
+        assert_eq!(
+            parsed,
+            ParseUriResult {
+                address: address.into(),
+                amount: Some("0.001".into()),
+                satoshi: Some(100_000),
+                asset_id: None,
+                label: Some("Luke Jr".into()),
+                message: Some("Donation".into()),
+                payjoin: None,
+            }
+        );
+
+        let uri = format!("bitcoin:{}?amount=1&pj=https://example.com/pj%3Fid%3D1&pjos=0", address);
+        let parsed = parse_uri(&network, &uri).unwrap();
+        assert_eq!(
+            parsed.payjoin,
+            Some(PayjoinOpt {
+                url: "https://example.com/pj?id=1".into(),
+                disable_output_substitution: true,
+            })
+        );
+
+        let created = create(
+            &network,
+            &CreateUriOpt {
+                address: address.into(),
+                satoshi: Some(100_000),
+                label: Some("Luke Jr".into()),
+                ..Default::default()
+            },
+            asset_precision,
+        )
+        .unwrap();
+        assert_eq!(created.uri, format!("bitcoin:{}?amount=0.001&label=Luke%20Jr", address));
+        assert_eq!(parse_uri(&network, &created.uri).unwrap().label, Some("Luke Jr".into()));
+
+        let err = |uri: &str| parse_uri(&network, uri).unwrap_err();
+        assert!(
+            matches!(err(&format!("{}&req-foo=1", uri)), Error::UnknownUriParameter(p) if p == "req-foo")
+        );
+        assert!(matches!(err(&format!("liquidnetwork:{}", address)), Error::InvalidUri(_)));
+        assert!(matches!(
+            err(&format!("bitcoin:{}?amount=1&amount=2", address)),
+            Error::InvalidUri(_)
+        ));
+        assert!(matches!(err(&format!("bitcoin:{}?amount=-1", address)), Error::InvalidAmount));
+        assert!(matches!(err(&format!("bitcoin:{}?label=%zz", address)), Error::InvalidUri(_)));
+        assert!(matches!(err("bitcoin:"), Error::InvalidUri(_)));
+        assert!(matches!(err(&format!("bitcoin:{}", mainnet_address)), Error::InvalidAddress));
+    }
+
+    #[test]
+    fn test_parse_create_liquid_uri() {
+        let network = network(true);
+        let params = ElementsNetwork::ElementsRegtest.address_params();
+        let unblinded = elements::Address::p2wpkh(&public_key(1), None, params);
+        let blinder = ec_public_key_from_private_key(SecretKey::from_slice(&[2; 32]).unwrap());
+        let address = &unblinded.to_confidential(blinder).to_string();
+        let unknown_asset = "144c654344aa716d6f3abcc1ca90e5641e4e2a7f633bc09fe3baf64585819a49";
+
+        let uri = format!("liquidnetwork:{}?amount=0.001&assetid={}", address, POLICY_ASSET);
+        let parsed = parse_uri(&network, &uri).unwrap();
+        assert_eq!(parsed.satoshi, Some(100_000));
+        assert_eq!(parsed.asset_id, Some(POLICY_ASSET.into()));
+
+        // the amounts of the other assets are in units of their precision
+        let uri =
+            format!("liquidnetwork:{}?amount=1.5&assetid={}", address, OTHER_ASSET.to_uppercase());
+        let parsed = parse_uri(&network, &uri).unwrap();
+        assert_eq!(parsed.amount, Some("1.5".into()));
+        assert_eq!(parsed.satoshi, Some(150));
+        assert_eq!(parsed.asset_id, Some(OTHER_ASSET.into()));
+
+        let uri = format!("liquidnetwork:{}?amount=1.5&assetid={}", address, unknown_asset);
+        let parsed = parse_uri(&network, &uri).unwrap();
+        assert_eq!(parsed.amount, Some("1.5".into()));
+        assert_eq!(parsed.satoshi, None);
+
+        let err = |uri: &str| parse_uri(&network, uri).unwrap_err();
+        assert!(matches!(
+            err(&format!("liquidnetwork:{}?amount=1", address)),
+            Error::InvalidUriAsset
+        ));
+        assert!(matches!(
+            err(&format!("liquidnetwork:{}?amount=1&assetid=00", address)),
+            Error::InvalidUriAsset
+        ));
+        assert!(matches!(
+            err(&format!("liquidnetwork:{}?amount=0.001&assetid={}", address, OTHER_ASSET)),
+            Error::InvalidAmount
+        ));
+        assert!(matches!(
+            err(&format!("liquidnetwork:{}?amount=0.000000001&assetid={}", address, unknown_asset)),
+            Error::InvalidAmount
+        ));
+        assert!(matches!(err(&format!("bitcoin:{}", address)), Error::InvalidUri(_)));
+        assert!(matches!(err(&unblinded.to_string()), Error::NonConfidentialAddress));
+
+        let create_uri = |satoshi, asset_id: Option<&str>| {
+            create(
+                &network,
+                &CreateUriOpt {
+                    address: address.into(),
+                    satoshi: Some(satoshi),
+                    asset_id: asset_id.map(Into::into),
+                    ..Default::default()
+                },
+                asset_precision,
+            )
+        };
+        let created = create_uri(100_000, None).unwrap();
+        assert_eq!(
+            created.uri,
+            format!("liquidnetwork:{}?amount=0.001&assetid={}", address, POLICY_ASSET)
+        );
+        assert_eq!(parse_uri(&network, &created.uri).unwrap().asset_id, Some(POLICY_ASSET.into()));
+        let created = create_uri(150, Some(OTHER_ASSET)).unwrap();
+        assert_eq!(
+            created.uri,
+            format!("liquidnetwork:{}?amount=1.5&assetid={}", address, OTHER_ASSET)
+        );
+        assert_eq!(parse_uri(&network, &created.uri).unwrap().satoshi, Some(150));
+        assert!(matches!(create_uri(150, Some(unknown_asset)), Err(Error::InvalidUriAsset)));
+    }
+}
@@ -47,6 +47,29 @@ GDK_API int GDKRUST_call_session(GDKRUST_session session, const char *method, co
  */
 GDK_API int GDKRUST_call(const char *method, const char *input, char** output);
 
+#ifndef SWIG
+/**
+ * Start a session call on a background thread.
+ *
+ * :param request_id: Destination for the id of the request. Its progress and its result or
+ *|     error are delivered to the session notification handler as ``request`` notifications.
+ */
+GDK_API int GDKRUST_call_session_async(GDKRUST_session session, const char *method, const char *input, uint32_t* request_id);
+
+/**
+ * Start a stateless function on a background thread.
+ *
+ * :param handler: The handler receiving the ``request`` notifications of the call.
+ * :param request_id: Destination for the id of the request.
+ */
+GDK_API int GDKRUST_call_async(const char *method, const char *input, GDKRUST_notification_handler handler, void *self_context, uint32_t* request_id);
+
+/**
+ * Cancel an asynchronous call, which then completes with an ``id_action_canceled`` error.
+ */
+GDK_API int GDKRUST_cancel_request(uint32_t request_id);
+#endif /* SWIG */
+
 #ifndef SWIG
 /**
  * Set a handler to be called when notifications arrive.
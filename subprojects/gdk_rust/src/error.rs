@@ -1,5 +1,153 @@
+use gdk_common::be::DUST_VALUE;
 use gdk_common::model::ExchangeRateError;
 use gdk_electrum as electrum;
+use serde_json::Value;
+
+/// Stable error codes, returned in the `error` field of the json errors.
+///
+/// A code is never renamed nor reused, a new failure mode gets a new code.
+pub mod codes {
+    /// Unclassified failure, described only by the `message` field
+    pub const UNKNOWN: &str = "id_unknown";
+    /// The method called does not exist, `details.method` is its name
+    pub const METHOD_NOT_FOUND: &str = "id_method_not_found";
+    /// The json input is malformed or misses required fields
+    pub const INVALID_INPUT: &str = "id_invalid_input";
+    /// Malformed transaction, pset, hash or other encoded data
+    pub const INVALID_DATA: &str = "id_invalid_data";
+    /// A key, derivation or signature operation failed
+    pub const CRYPTO_ERROR: &str = "id_crypto_error";
+    /// Blinding or unblinding a liquid output failed
+    pub const BLINDING_FAILED: &str = "id_blinding_failed";
+    /// The request was cancelled before completing
+    pub const CANCELLED: &str = "id_action_canceled";
+    /// A failure inside the library, such as a thread that stopped
+    pub const INTERNAL_ERROR: &str = "id_internal_error";
+    /// Reading or writing local files failed
+    pub const IO_ERROR: &str = "id_io_error";
+    /// The local store cannot be decrypted or decoded
+    pub const STORE_CORRUPTED: &str = "id_store_corrupted";
+    /// The local store is protected by a secret, which is missing or wrong
+    pub const INVALID_STORE_SECRET: &str = "id_invalid_store_secret";
+
+    /// The server or the PIN server cannot be reached
+    pub const CONNECTION_FAILED: &str = "id_connection_failed";
+    /// The server url is invalid, `details.url` is the url if known
+    pub const INVALID_URL: &str = "id_invalid_url";
+    /// The server answered with an error, `details.server_error` is the error if known
+    pub const SERVER_ERROR: &str = "id_server_error";
+    /// The server answered with data that cannot be decoded
+    pub const INVALID_SERVER_RESPONSE: &str = "id_invalid_server_response";
+    /// The headers received from the server do not form a valid chain
+    pub const INVALID_HEADERS: &str = "id_invalid_headers";
+    /// No exchange rate source is available
+    pub const EXCHANGE_RATES_UNAVAILABLE: &str = "id_exchange_rates_unavailable";
+
+    /// The mnemonic, or the word count or language of `generate_mnemonic`, is not valid.
+    /// `details.reason` is `invalid_word_count`, `unknown_words`, with the zero based
+    /// `details.positions` of the words, `invalid_checksum` or `unsupported_language`
+    pub const INVALID_MNEMONIC: &str = "id_invalid_mnemonic";
+    pub const INVALID_PIN: &str = "id_invalid_pin";
+    /// `details.subaccount` is the subaccount requested
+    pub const INVALID_SUBACCOUNT: &str = "id_invalid_subaccount";
+    /// A new subaccount of a type can be created only once the previous one is used
+    pub const SUBACCOUNT_GAP: &str = "id_subaccount_gap";
+    /// `details.reason` tells which of the threshold or the cosigners is not valid
+    pub const INVALID_MULTISIG: &str = "id_invalid_multisig";
+    /// `details.reason` tells why the miniscript descriptor is not supported
+    pub const INVALID_POLICY: &str = "id_invalid_policy";
+    /// `details.txid` is the transaction requested
+    pub const TX_NOT_FOUND: &str = "id_tx_not_found";
+
+    pub const INSUFFICIENT_FUNDS: &str = "id_insufficient_funds";
+    pub const INVALID_ADDRESS: &str = "id_invalid_address";
+    /// `details.address` does not belong to the subaccount
+    pub const ADDRESS_NOT_FOUND: &str = "id_address_not_found";
+    /// The private key to sweep is not a valid WIF key of the network
+    pub const INVALID_PRIVATE_KEY: &str = "id_invalid_private_key";
+    /// The payment URI is malformed or for another network
+    pub const INVALID_URI: &str = "id_invalid_uri";
+    /// `details.parameter` is the unsupported required parameter
+    pub const UNKNOWN_BIP21_PARAMETER: &str = "id_unknown_bip21_parameter";
+    /// A Liquid payment URI with an amount has no valid `assetid`
+    pub const INVALID_PAYMENT_REQUEST_ASSET_ID: &str = "id_invalid_payment_request_assetid";
+    pub const NONCONFIDENTIAL_ADDRESS: &str = "id_nonconfidential_addresses_not";
+    pub const INVALID_AMOUNT: &str = "id_invalid_amount";
+    /// `details.dust_threshold` is the threshold in satoshi
+    pub const AMOUNT_BELOW_DUST: &str = "id_amount_below_the_dust_threshold";
+    pub const NO_RECIPIENTS: &str = "id_no_recipients";
+    pub const FEE_RATE_BELOW_MINIMUM: &str = "id_fee_rate_is_below_minimum";
+    pub const INVALID_ASSET_ID: &str = "id_invalid_asset_id";
+    pub const INVALID_REPLACEMENT: &str = "id_invalid_replacement_request";
+    pub const SEND_ALL_REQUIRES_SINGLE_OUTPUT: &str = "id_send_all_requires_a_single_output";
+    pub const PSET_TX_MISMATCH: &str = "id_pset_tx_mismatch";
+
+    /// The node rejected the transaction for another reason, all the `TX_REJECTED*` codes come
+    /// with `details.reject_reason` and `details.reject_code` if the node returned one
+    pub const TX_REJECTED: &str = "id_tx_rejected";
+    pub const TX_REJECTED_DUST: &str = "id_tx_rejected_dust";
+    pub const TX_REJECTED_FEE_TOO_LOW: &str = "id_tx_rejected_fee_too_low";
+    pub const TX_REJECTED_FEE_TOO_HIGH: &str = "id_tx_rejected_fee_too_high";
+    /// It conflicts with a mempool transaction that it cannot replace
+    pub const TX_REJECTED_CONFLICT: &str = "id_tx_rejected_conflict";
+    /// An input is missing or already spent
+    pub const TX_REJECTED_INPUTS_MISSING: &str = "id_tx_rejected_inputs_missing";
+    pub const TX_REJECTED_ALREADY_KNOWN: &str = "id_tx_rejected_already_known";
+    /// It is timelocked
+    pub const TX_REJECTED_NON_FINAL: &str = "id_tx_rejected_non_final";
+
+    /// Every code, in the order above
+    pub const ALL: &[&str] = &[
+        UNKNOWN,
+        METHOD_NOT_FOUND,
+        INVALID_INPUT,
+        INVALID_DATA,
+        CRYPTO_ERROR,
+        BLINDING_FAILED,
+        CANCELLED,
+        INTERNAL_ERROR,
+        IO_ERROR,
+        STORE_CORRUPTED,
+        INVALID_STORE_SECRET,
+        CONNECTION_FAILED,
+        INVALID_URL,
+        SERVER_ERROR,
+        INVALID_SERVER_RESPONSE,
+        INVALID_HEADERS,
+        EXCHANGE_RATES_UNAVAILABLE,
+        INVALID_MNEMONIC,
+        INVALID_PIN,
+        INVALID_SUBACCOUNT,
+        SUBACCOUNT_GAP,
+        INVALID_MULTISIG,
+        INVALID_POLICY,
+        TX_NOT_FOUND,
+        INSUFFICIENT_FUNDS,
+        INVALID_ADDRESS,
+        ADDRESS_NOT_FOUND,
+        INVALID_PRIVATE_KEY,
+        INVALID_URI,
+        UNKNOWN_BIP21_PARAMETER,
+        INVALID_PAYMENT_REQUEST_ASSET_ID,
+        NONCONFIDENTIAL_ADDRESS,
+        INVALID_AMOUNT,
+        AMOUNT_BELOW_DUST,
+        NO_RECIPIENTS,
+        FEE_RATE_BELOW_MINIMUM,
+        INVALID_ASSET_ID,
+        INVALID_REPLACEMENT,
+        SEND_ALL_REQUIRES_SINGLE_OUTPUT,
+        PSET_TX_MISMATCH,
+        TX_REJECTED,
+        TX_REJECTED_DUST,
+        TX_REJECTED_FEE_TOO_LOW,
+        TX_REJECTED_FEE_TOO_HIGH,
+        TX_REJECTED_CONFLICT,
+        TX_REJECTED_INPUTS_MISSING,
+        TX_REJECTED_ALREADY_KNOWN,
+        TX_REJECTED_NON_FINAL,
+    ];
+}
 
 #[derive(Debug)]
 pub enum Error {
@@ -15,32 +163,47 @@ pub enum Error {
 }
 
 impl Error {
-    /// Convert the error to a GDK-compatible code.
+    /// Convert the error to a GDK-compatible code, one of [`codes`].
     pub fn to_gdk_code(&self) -> String {
-        // Unhandles error codes:
-        // id_no_amount_specified
-        // id_invalid_replacement_fee_rate
-        // id_send_all_requires_a_single_output
-
-        match *self {
-            Error::Electrum(electrum::error::Error::InsufficientFunds) => {
-                "id_insufficient_funds".to_string()
-            }
-            Error::Electrum(electrum::error::Error::InvalidAddress) => {
-                "id_invalid_address".to_string()
-            }
-            Error::Electrum(electrum::error::Error::NonConfidentialAddress) => {
-                "id_nonconfidential_addresses_not".to_string()
+        let code = match self {
+            Error::Other(_) => codes::UNKNOWN,
+            Error::JsonFrom(_) => codes::INVALID_INPUT,
+            Error::Electrum(err) => electrum_code(err),
+            Error::Rates(_) => codes::EXCHANGE_RATES_UNAVAILABLE,
+            Error::Common(err) => common_code(err),
+            Error::MethodNotFound {
+                ..
+            } => codes::METHOD_NOT_FOUND,
+        };
+        code.to_string()
+    }
+
+    /// Structured information about the error, returned in the `details` field of the json errors
+    pub fn details(&self) -> Option<Value> {
+        use electrum::error::Error as E;
+        match self {
+            Error::Electrum(E::TxRejected(reason)) => Some(reject_details(reason)),
+            Error::Electrum(E::ClientError(electrum_client::Error::Protocol(value))) => {
+                Some(json!({ "server_error": value }))
             }
-            Error::Electrum(electrum::error::Error::InvalidAmount) => {
-                "id_invalid_amount".to_string()
+            Error::Electrum(E::InvalidSubaccount(subaccount)) => {
+                Some(json!({ "subaccount": subaccount }))
             }
-            Error::Electrum(electrum::error::Error::FeeRateBelowMinimum) => {
-                "id_fee_rate_is_below_minimum".to_string()
+            Error::Electrum(E::InvalidMultisig(reason)) => Some(json!({ "reason": reason })),
+            Error::Electrum(E::InvalidPolicy(reason)) => Some(json!({ "reason": reason })),
+            Error::Electrum(E::TxNotFound(txid)) => Some(json!({ "txid": txid })),
+            Error::Electrum(E::AddressNotFound(address)) => Some(json!({ "address": address })),
+            Error::Electrum(E::UnknownUriParameter(parameter)) => {
+                Some(json!({ "parameter": parameter }))
             }
-            Error::Electrum(electrum::error::Error::PinError) => "id_connection_failed".to_string(),
-            Error::Electrum(electrum::error::Error::InvalidPin) => "id_invalid_pin".to_string(),
-            _ => "id_unknown".to_string(),
+            Error::Electrum(E::InvalidMnemonic(err)) => Some(json!(err)),
+            Error::Electrum(E::InvalidElectrumUrl(url)) => Some(json!({ "url": url })),
+            Error::Electrum(E::AmountBelowDust) => Some(json!({ "dust_threshold": DUST_VALUE })),
+            Error::MethodNotFound {
+                method,
+                in_session,
+            } => Some(json!({ "method": method, "in_session": in_session })),
+            _ => None,
         }
     }
 
@@ -89,3 +252,281 @@ impl From<serde_json::Error> for Error {
         Error::JsonFrom(e)
     }
 }
+
+fn electrum_code(err: &electrum::error::Error) -> &'static str {
+    use electrum::error::Error as E;
+    match err {
+        E::Generic(_) => codes::UNKNOWN,
+        E::UnknownCall => codes::METHOD_NOT_FOUND,
+        E::InvalidMnemonic(_) => codes::INVALID_MNEMONIC,
+        E::InsufficientFunds => codes::INSUFFICIENT_FUNDS,
+        E::InvalidAddress => codes::INVALID_ADDRESS,
+        E::AddressNotFound(_) => codes::ADDRESS_NOT_FOUND,
+        E::InvalidPrivateKey => codes::INVALID_PRIVATE_KEY,
+        E::InvalidUri(_) => codes::INVALID_URI,
+        E::UnknownUriParameter(_) => codes::UNKNOWN_BIP21_PARAMETER,
+        E::InvalidUriAsset => codes::INVALID_PAYMENT_REQUEST_ASSET_ID,
+        E::NonConfidentialAddress => codes::NONCONFIDENTIAL_ADDRESS,
+        E::InvalidAmount => codes::INVALID_AMOUNT,
+        E::AmountBelowDust => codes::AMOUNT_BELOW_DUST,
+        E::EmptyAddressees => codes::NO_RECIPIENTS,
+        E::FeeRateBelowMinimum => codes::FEE_RATE_BELOW_MINIMUM,
+        E::AssetEmpty => codes::INVALID_ASSET_ID,
+        E::InvalidHeaders => codes::INVALID_HEADERS,
+        E::InvalidSubaccount(_) => codes::INVALID_SUBACCOUNT,
+        E::AccountGapsDisallowed => codes::SUBACCOUNT_GAP,
+        E::InvalidMultisig(_) => codes::INVALID_MULTISIG,
+        E::InvalidPolicy(_) => codes::INVALID_POLICY,
+        E::InvalidReplacementRequest => codes::INVALID_REPLACEMENT,
+        E::SendAll => codes::SEND_ALL_REQUIRES_SINGLE_OUTPUT,
+        E::PinError => codes::CONNECTION_FAILED,
+        E::InvalidPin => codes::INVALID_PIN,
+        E::PsetAndTxMismatch => codes::PSET_TX_MISMATCH,
+        E::Cancelled => codes::CANCELLED,
+        E::TxNotFound(_) => codes::TX_NOT_FOUND,
+        E::TxRejected(reason) => reject_code(reason),
+        E::ConnectionFailed(_) => codes::CONNECTION_FAILED,
+        E::StoreCorrupted(_) => codes::STORE_CORRUPTED,
+        E::InvalidStoreSecret => codes::INVALID_STORE_SECRET,
+        E::AddrParse(_) | E::InvalidElectrumUrl(_) => codes::INVALID_URL,
+        E::Bitcoin(_)
+        | E::BitcoinHashes(_)
+        | E::BitcoinConsensus(_)
+        | E::SliceConversionError(_)
+        | E::ElementsEncode(_)
+        | E::ElementsPset(_) => codes::INVALID_DATA,
+        E::BitcoinBIP32Error(_) | E::Encryption(_) | E::Secp256k1(_) | E::Secp256k1Zkp(_) => {
+            codes::CRYPTO_ERROR
+        }
+        E::PsetBlindError(_) | E::UnblindError(_) => codes::BLINDING_FAILED,
+        E::JSON(_) => codes::INVALID_INPUT,
+        E::StdIOError(_) => codes::IO_ERROR,
+        E::ClientError(err) => client_code(err),
+        E::Common(err) => common_code(err),
+        E::Send(_) => codes::INTERNAL_ERROR,
+    }
+}
+
+fn client_code(err: &electrum_client::Error) -> &'static str {
+    use electrum_client::Error as C;
+    match err {
+        C::IOError(_)
+        | C::SharedIOError(_)
+        | C::AllAttemptsErrored(_)
+        | C::InvalidDNSNameError(_)
+        | C::MissingDomain => codes::CONNECTION_FAILED,
+        C::Protocol(_) | C::Message(_) => codes::SERVER_ERROR,
+        C::JSON(_) | C::Hex(_) | C::Bitcoin(_) | C::InvalidResponse(_) => {
+            codes::INVALID_SERVER_RESPONSE
+        }
+        C::AlreadySubscribed(_) | C::NotSubscribed(_) | C::CouldntLockReader | C::Mpsc => {
+            codes::INTERNAL_ERROR
+        }
+        // the tls errors, which depend on the enabled features
+        _ => codes::CONNECTION_FAILED,
+    }
+}
+
+fn common_code(err: &gdk_common::error::Error) -> &'static str {
+    use gdk_common::error::Error as C;
+    match err {
+        C::Generic(_) => codes::UNKNOWN,
+        C::InvalidAddress => codes::INVALID_ADDRESS,
+        C::InputValidationFailed => codes::INVALID_INPUT,
+    }
+}
+
+/// Classify the reject reason of the node, such as `min relay fee not met` or, as forwarded by
+/// electrs, `sendrawtransaction RPC error: {"code":-26,"message":"dust"}`
+fn reject_code(reason: &str) -> &'static str {
+    let reason = reason.to_lowercase();
+    let contains = |patterns: &[&str]| patterns.iter().any(|p| reason.contains(p));
+    if contains(&["absurdly-high-fee", "max-fee-exceeded", "fee exceeds maximum"]) {
+        codes::TX_REJECTED_FEE_TOO_HIGH
+    } else if contains(&["dust"]) {
+        codes::TX_REJECTED_DUST
+    } else if contains(&["conflict", "replacement"]) {
+        // before the fee check, a replacement paying too little is a conflict
+        codes::TX_REJECTED_CONFLICT
+    } else if contains(&["already"]) {
+        codes::TX_REJECTED_ALREADY_KNOWN
+    } else if contains(&["missingorspent", "missing-inputs", "missing inputs", "inputs-spent"]) {
+        codes::TX_REJECTED_INPUTS_MISSING
+    } else if contains(&["non-final", "non-bip68-final"]) {
+        codes::TX_REJECTED_NON_FINAL
+    } else if contains(&["fee not met", "insufficient fee", "min-fee", "min relay fee"]) {
+        codes::TX_REJECTED_FEE_TOO_LOW
+    } else {
+        codes::TX_REJECTED
+    }
+}
+
+fn reject_details(reason: &str) -> Value {
+    let rpc_error = reason.find('{').and_then(|i| serde_json::from_str::<Value>(&reason[i..]).ok());
+    match rpc_error {
+        Some(rpc_error) => json!({
+            "reject_reason": rpc_error["message"],
+            "reject_code": rpc_error["code"],
+        }),
+        None => json!({ "reject_reason": reason }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use electrum::error::Error as E;
+    use gdk_common::mnemonic::MnemonicError;
+    use std::collections::HashSet;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_codes_unique() {
+        let unique: HashSet<_> = codes::ALL.iter().collect();
+        assert_eq!(unique.len(), codes::ALL.len());
+        assert!(codes::ALL.iter().all(|code| code.starts_with("id_")));
+    }
+
+    #[test]
+    fn test_codes_documented() {
+        let doc = concat!(env!("CARGO_MANIFEST_DIR"), "/../../docs/source/gdk-json.rst");
+        let doc = std::fs::read_to_string(doc).unwrap();
+        for code in codes::ALL {
+            assert!(doc.contains(&format!(":{}:", code)), "{} is not documented", code);
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let io_error = || std::io::Error::new(std::io::ErrorKind::Other, "io");
+        let cases = vec![
+            (Error::Other("other".into()), codes::UNKNOWN),
+            (Error::JsonFrom(serde_json::from_str::<u8>("").unwrap_err()), codes::INVALID_INPUT),
+            (
+                Error::MethodNotFound {
+                    method: "method".into(),
+                    in_session: true,
+                },
+                codes::METHOD_NOT_FOUND,
+            ),
+            (Error::Common(gdk_common::error::Error::Generic("".into())), codes::UNKNOWN),
+            (Error::Common(gdk_common::error::Error::InvalidAddress), codes::INVALID_ADDRESS),
+            (Error::Common(gdk_common::error::Error::InputValidationFailed), codes::INVALID_INPUT),
+            (E::Generic("generic".into()).into(), codes::UNKNOWN),
+            (E::UnknownCall.into(), codes::METHOD_NOT_FOUND),
+            (E::InvalidMnemonic(MnemonicError::InvalidChecksum).into(), codes::INVALID_MNEMONIC),
+            (E::InsufficientFunds.into(), codes::INSUFFICIENT_FUNDS),
+            (E::InvalidAddress.into(), codes::INVALID_ADDRESS),
+            (E::AddressNotFound("address".into()).into(), codes::ADDRESS_NOT_FOUND),
+            (E::InvalidPrivateKey.into(), codes::INVALID_PRIVATE_KEY),
+            (E::InvalidUri("uri".into()).into(), codes::INVALID_URI),
+            (E::UnknownUriParameter("req-foo".into()).into(), codes::UNKNOWN_BIP21_PARAMETER),
+            (E::InvalidUriAsset.into(), codes::INVALID_PAYMENT_REQUEST_ASSET_ID),
+            (E::NonConfidentialAddress.into(), codes::NONCONFIDENTIAL_ADDRESS),
+            (E::InvalidAmount.into(), codes::INVALID_AMOUNT),
+            (E::AmountBelowDust.into(), codes::AMOUNT_BELOW_DUST),
+            (E::EmptyAddressees.into(), codes::NO_RECIPIENTS),
+            (E::FeeRateBelowMinimum.into(), codes::FEE_RATE_BELOW_MINIMUM),
+            (E::AssetEmpty.into(), codes::INVALID_ASSET_ID),
+            (E::InvalidHeaders.into(), codes::INVALID_HEADERS),
+            (E::InvalidSubaccount(1).into(), codes::INVALID_SUBACCOUNT),
+            (E::AccountGapsDisallowed.into(), codes::SUBACCOUNT_GAP),
+            (E::InvalidMultisig("threshold".into()).into(), codes::INVALID_MULTISIG),
+            (E::InvalidPolicy("timelock".into()).into(), codes::INVALID_POLICY),
+            (E::InvalidReplacementRequest.into(), codes::INVALID_REPLACEMENT),
+            (E::SendAll.into(), codes::SEND_ALL_REQUIRES_SINGLE_OUTPUT),
+            (E::PinError.into(), codes::CONNECTION_FAILED),
+            (E::InvalidPin.into(), codes::INVALID_PIN),
+            (E::PsetAndTxMismatch.into(), codes::PSET_TX_MISMATCH),
+            (E::Cancelled.into(), codes::CANCELLED),
+            (E::TxNotFound("txid".into()).into(), codes::TX_NOT_FOUND),
+            (E::TxRejected("bad-txns-in-belowout".into()).into(), codes::TX_REJECTED),
+            (E::ConnectionFailed("refused".into()).into(), codes::CONNECTION_FAILED),
+            (E::StoreCorrupted("aead".into()).into(), codes::STORE_CORRUPTED),
+            (E::InvalidStoreSecret.into(), codes::INVALID_STORE_SECRET),
+            (E::AddrParse("addr".into()).into(), codes::INVALID_URL),
+            (E::InvalidElectrumUrl("url".into()).into(), codes::INVALID_URL),
+            (E::Bitcoin(bitcoin::util::Error::BlockBadTarget).into(), codes::INVALID_DATA),
+            (
+                E::BitcoinHashes(bitcoin::hashes::error::Error::InvalidLength(32, 1)).into(),
+                codes::INVALID_DATA,
+            ),
+            (
+                E::BitcoinBIP32Error(bitcoin::util::bip32::Error::CannotDeriveFromHardenedKey)
+                    .into(),
+                codes::CRYPTO_ERROR,
+            ),
+            (
+                E::BitcoinConsensus(bitcoin::consensus::encode::Error::ParseFailed("")).into(),
+                codes::INVALID_DATA,
+            ),
+            (
+                E::SliceConversionError(<[u8; 1]>::try_from(&[][..]).unwrap_err()).into(),
+                codes::INVALID_DATA,
+            ),
+            (
+                E::ElementsEncode(elements::encode::Error::ParseFailed("")).into(),
+                codes::INVALID_DATA,
+            ),
+            (E::Secp256k1(bitcoin::secp256k1::Error::InvalidPublicKey).into(), codes::CRYPTO_ERROR),
+            (E::JSON(serde_json::from_str::<u8>("").unwrap_err()).into(), codes::INVALID_INPUT),
+            (E::StdIOError(io_error()).into(), codes::IO_ERROR),
+            (E::Common(gdk_common::error::Error::InvalidAddress).into(), codes::INVALID_ADDRESS),
+            (E::Send(std::sync::mpsc::SendError(())).into(), codes::INTERNAL_ERROR),
+            (
+                E::ClientError(electrum_client::Error::IOError(io_error())).into(),
+                codes::CONNECTION_FAILED,
+            ),
+            (
+                E::ClientError(electrum_client::Error::AllAttemptsErrored(vec![])).into(),
+                codes::CONNECTION_FAILED,
+            ),
+            (
+                E::ClientError(electrum_client::Error::Protocol(json!({"code": 1}))).into(),
+                codes::SERVER_ERROR,
+            ),
+            (
+                E::ClientError(electrum_client::Error::Message("message".into())).into(),
+                codes::SERVER_ERROR,
+            ),
+            (
+                E::ClientError(electrum_client::Error::InvalidResponse(json!(null))).into(),
+                codes::INVALID_SERVER_RESPONSE,
+            ),
+            (E::ClientError(electrum_client::Error::Mpsc).into(), codes::INTERNAL_ERROR),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.to_gdk_code(), code, "{:?}", error);
+            assert!(codes::ALL.contains(&code));
+        }
+    }
+
+    #[test]
+    fn test_reject_reasons() {
+        let cases = vec![
+            ("dust", codes::TX_REJECTED_DUST),
+            ("min relay fee not met, 100 < 141", codes::TX_REJECTED_FEE_TOO_LOW),
+            ("mempool min fee not met", codes::TX_REJECTED_FEE_TOO_LOW),
+            ("insufficient fee, rejecting replacement", codes::TX_REJECTED_CONFLICT),
+            ("txn-mempool-conflict", codes::TX_REJECTED_CONFLICT),
+            ("bad-txns-inputs-missingorspent", codes::TX_REJECTED_INPUTS_MISSING),
+            ("txn-already-in-mempool", codes::TX_REJECTED_ALREADY_KNOWN),
+            ("Transaction already in block chain", codes::TX_REJECTED_ALREADY_KNOWN),
+            ("non-BIP68-final", codes::TX_REJECTED_NON_FINAL),
+            ("absurdly-high-fee", codes::TX_REJECTED_FEE_TOO_HIGH),
+            ("scriptpubkey", codes::TX_REJECTED),
+        ];
+        for (reason, code) in cases {
+            assert_eq!(reject_code(reason), code, "{}", reason);
+        }
+
+        let error: Error =
+            E::TxRejected(r#"sendrawtransaction RPC error: {"code":-26,"message":"dust"}"#.into())
+                .into();
+        assert_eq!(error.to_gdk_code(), codes::TX_REJECTED_DUST);
+        assert_eq!(error.details(), Some(json!({"reject_reason": "dust", "reject_code": -26})));
+
+        let error: Error = E::TxRejected("min relay fee not met".into()).into();
+        assert_eq!(error.details(), Some(json!({"reject_reason": "min relay fee not met"})));
+    }
+}
@@ -18,18 +18,26 @@ use log::Level;
 use std::ffi::CString;
 use std::fmt;
 use std::os::raw::c_char;
-use std::sync::Once;
+use std::sync::{Arc, Mutex, Once, RwLock};
+use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use gdk_common::mnemonic::Mnemonic;
 use gdk_common::model::{
-    CreateAccountOpt, GetNextAccountOpt, GetSubaccountsOpt, GetTransactionsOpt, RenameAccountOpt,
-    SPVDownloadHeadersParams, SPVVerifyTxParams, SetAccountHiddenOpt, UpdateAccountOpt,
+    CreateAccountOpt, GenerateMnemonicOpt, GenerateMnemonicResult, GetNextAccountOpt,
+    GetSubaccountsOpt, GetTransactionsOpt, ParseUriOpt, RenameAccountOpt, SPVDownloadHeadersParams,
+    SPVVerifyTxParams, SetAccountHiddenOpt, UpdateAccountOpt, ValidateMnemonicOpt,
+    ValidateMnemonicResult, VerifyMessageOpt,
 };
 use gdk_common::session::Session;
 
 use crate::error::Error;
+use gdk_electrum::multisig::CombinePsbtParam;
 use gdk_electrum::pset::{ExtractTxParam, FromTxParam, MergeTxParam};
-use gdk_electrum::{ElectrumSession, NativeNotif};
+use gdk_electrum::request::Request;
+use gdk_electrum::{
+    ElectrumSession, ElectrumSessionBuilder, NativeNotif, NotificationHandler, Notifier,
+};
 use log::{LevelFilter, Metadata, Record};
 use serde::Serialize;
 use std::str::FromStr;
@@ -38,8 +46,16 @@ pub const GA_OK: i32 = 0;
 pub const GA_ERROR: i32 = -1;
 pub const GA_NOT_AUTHORIZED: i32 = -5;
 
+/// The session behind the FFI pointer, callable from several threads.
+///
+/// Methods taking the backend by shared reference run concurrently, the others are serialized.
 pub struct GdkSession {
-    pub backend: GdkBackend,
+    pub backend: RwLock<GdkBackend>,
+    pub notify: Notifier,
+    pub exchange_rates: Mutex<ExchangeRates>,
+}
+
+pub struct ExchangeRates {
     pub last_xr_fetch: std::time::SystemTime,
     pub last_xr: Option<Vec<Ticker>>,
 }
@@ -85,7 +101,7 @@ pub extern "C" fn GDKRUST_create_session(
             GA_ERROR
         }
         Ok(session) => {
-            let session = Box::new(session);
+            let session = Box::new(Arc::new(session));
             unsafe {
                 *ret = Box::into_raw(session) as *mut libc::c_void;
             };
@@ -134,19 +150,26 @@ fn create_session(network: &Value) -> Result<GdkSession, Value> {
 
     match network["server_type"].as_str() {
         // Some("rpc") => GDKRUST_session::Rpc( GDKRPC_session::create_session(parsed_network.unwrap()).unwrap() ),
-        Some("electrum") => {
-            let url = gdk_electrum::determine_electrum_url_from_net(&parsed_network)
-                .map_err(|x| json!(x))?;
-
-            let session = ElectrumSession::create_session(parsed_network, db_root, proxy, url);
+        Some(server_type @ "electrum") | Some(server_type @ "esplora") => {
+            let mut builder = ElectrumSessionBuilder::new(parsed_network)
+                .data_root(db_root)
+                .server_type(server_type);
+            if let Some(proxy) = proxy {
+                builder = builder.proxy(proxy);
+            }
+            let session = builder.build().map_err(|x| json!(x))?;
+            let notify = session.notify.clone();
             let backend = GdkBackend::Electrum(session);
 
             // some time in the past
             let last_xr_fetch = SystemTime::now() - Duration::from_secs(1000);
             let gdk_session = GdkSession {
-                backend,
-                last_xr_fetch,
-                last_xr: None,
+                backend: RwLock::new(backend),
+                notify,
+                exchange_rates: Mutex::new(ExchangeRates {
+                    last_xr_fetch,
+                    last_xr: None,
+                }),
             };
             Ok(gdk_session)
         }
@@ -154,12 +177,13 @@ fn create_session(network: &Value) -> Result<GdkSession, Value> {
     }
 }
 
-fn fetch_cached_exchange_rates(sess: &mut GdkSession) -> Option<Vec<Ticker>> {
-    if SystemTime::now() < (sess.last_xr_fetch + Duration::from_secs(60)) {
+fn fetch_cached_exchange_rates(sess: &GdkSession) -> Option<Vec<Ticker>> {
+    let mut cache = sess.exchange_rates.lock().unwrap();
+    if SystemTime::now() < (cache.last_xr_fetch + Duration::from_secs(60)) {
         debug!("hit exchange rate cache");
     } else {
         info!("missed exchange rate cache");
-        let (agent, is_mainnet) = match sess.backend {
+        let (agent, is_mainnet) = match *sess.backend.read().unwrap() {
             GdkBackend::Electrum(ref s) => (s.build_request_agent(), s.network.mainnet),
         };
         if let Ok(agent) = agent {
@@ -172,15 +196,15 @@ fn fetch_cached_exchange_rates(sess: &mut GdkSession) -> Option<Vec<Ticker>> {
                 }]
             };
             // still record time even if we get no results
-            sess.last_xr_fetch = SystemTime::now();
+            cache.last_xr_fetch = SystemTime::now();
             if !rates.is_empty() {
                 // only set last_xr if we got new non-empty rates
-                sess.last_xr = Some(rates);
+                cache.last_xr = Some(rates);
             }
         }
     }
 
-    sess.last_xr.clone()
+    cache.last_xr.clone()
 }
 
 #[no_mangle]
@@ -202,22 +226,88 @@ pub extern "C" fn GDKRUST_call_session(
     if ptr.is_null() {
         return GA_ERROR;
     }
-    let sess: &mut GdkSession = unsafe { &mut *(ptr as *mut GdkSession) };
+    let sess: &Arc<GdkSession> = unsafe { &*(ptr as *const Arc<GdkSession>) };
 
+    let (s, ret) = match call_session(sess, &method, &input) {
+        Ok(ref val) => (val.to_string(), GA_OK),
+        Err(ref e) => {
+            let ret_val = match e {
+                Error::Electrum(gdk_electrum::error::Error::InvalidPin) => GA_NOT_AUTHORIZED,
+                _ => GA_ERROR,
+            };
+            let json_error = build_error(&method, e);
+            (json_error, ret_val)
+        }
+    };
+    let s = make_str(s);
+    unsafe {
+        *output = s;
+    }
+    ret
+}
+
+/// Like `GDKRUST_call_session` but returns immediately, the id of the request is written to
+/// `request_id` and its progress and result are delivered as `request` notifications.
+#[no_mangle]
+pub extern "C" fn GDKRUST_call_session_async(
+    ptr: *mut libc::c_void,
+    method: *const c_char,
+    input: *const c_char,
+    request_id: *mut u32,
+) -> i32 {
+    let method = read_str(method);
+    let input: Value = match serde_json::from_str(&read_str(input)) {
+        Ok(x) => x,
+        Err(err) => {
+            error!("error: {:?}", err);
+            return GA_ERROR;
+        }
+    };
+
+    if ptr.is_null() {
+        return GA_ERROR;
+    }
+    // the thread keeps the session alive even if it is destroyed in the meantime
+    let sess: Arc<GdkSession> = unsafe { &*(ptr as *const Arc<GdkSession>) }.clone();
+
+    let request = Request::register(sess.notify.clone());
+    unsafe {
+        *request_id = request.id();
+    }
+    thread::spawn(move || {
+        request.run(|| call_session(&sess, &method, &input).map_err(|e| json!(json_error(&e))))
+    });
+    GA_OK
+}
+
+/// Cancel a request started with `GDKRUST_call_session_async` or `GDKRUST_call_async`, which
+/// completes with an `id_action_canceled` error at the next cancellation point.
+#[no_mangle]
+pub extern "C" fn GDKRUST_cancel_request(request_id: u32) -> i32 {
+    if gdk_electrum::request::cancel(request_id) {
+        GA_OK
+    } else {
+        GA_ERROR
+    }
+}
+
+fn call_session(sess: &GdkSession, method: &str, input: &Value) -> Result<Value, Error> {
     if method == "exchange_rates" {
         let rates = fetch_cached_exchange_rates(sess).unwrap_or_default();
-        let s = make_str(tickers_to_json(rates).to_string());
-        unsafe {
-            *output = s;
-        }
-        return GA_OK;
+        return Ok(tickers_to_json(rates));
     }
 
     // Redact inputs containing private data
-    let methods_to_redact_in =
-        vec!["login", "register_user", "set_pin", "create_subaccount", "mnemonic_from_pin_data"];
+    let methods_to_redact_in = vec![
+        "login",
+        "register_user",
+        "set_pin",
+        "create_subaccount",
+        "mnemonic_from_pin_data",
+        "change_store_secret",
+    ];
     let input_str = format!("{:?}", &input);
-    let input_redacted = if methods_to_redact_in.contains(&method.as_str())
+    let input_redacted = if methods_to_redact_in.contains(&method)
         || input_str.contains("pin")
         || input_str.contains("mnemonic")
         || input_str.contains("xprv")
@@ -228,13 +318,25 @@ pub extern "C" fn GDKRUST_call_session(
     };
 
     info!("GDKRUST_call_session handle_call {} input {:?}", method, input_redacted);
-    let res = match sess.backend {
-        GdkBackend::Electrum(ref mut s) => handle_session_call(s, &method, &input),
+    let res = match *sess.backend.read().unwrap() {
+        GdkBackend::Electrum(ref s) => handle_session_read_call(s, method, input),
         // GdkSession::Rpc(ref s) => handle_call(s, method),
     };
+    let res = match res {
+        Err(Error::MethodNotFound {
+            ..
+        }) => {
+            // a handler calling back into the session would deadlock on the write lock, so the
+            // notifications wait for its release
+            Notifier::hold(|| match *sess.backend.write().unwrap() {
+                GdkBackend::Electrum(ref mut s) => handle_session_call(s, method, input),
+            })
+        }
+        res => res,
+    };
 
     let methods_to_redact_out = vec!["get_mnemonic", "mnemonic_from_pin_data"];
-    let mut output_redacted = if methods_to_redact_out.contains(&method.as_str()) {
+    let mut output_redacted = if methods_to_redact_out.contains(&method) {
         "redacted".to_string()
     } else {
         format!("{:?}", res)
@@ -242,22 +344,7 @@ pub extern "C" fn GDKRUST_call_session(
     output_redacted.truncate(200);
     info!("GDKRUST_call_session {} output {:?}", method, output_redacted);
 
-    let (s, ret) = match res {
-        Ok(ref val) => (val.to_string(), GA_OK),
-        Err(ref e) => {
-            let ret_val = match e {
-                Error::Electrum(gdk_electrum::error::Error::InvalidPin) => GA_NOT_AUTHORIZED,
-                _ => GA_ERROR,
-            };
-            let json_error = build_error(&method, e);
-            (json_error, ret_val)
-        }
-    };
-    let s = make_str(s);
-    unsafe {
-        *output = s;
-    }
-    ret
+    res
 }
 
 #[no_mangle]
@@ -269,12 +356,10 @@ pub extern "C" fn GDKRUST_set_notification_handler(
     if ptr.is_null() {
         return GA_ERROR;
     }
-    let sess: &mut GdkSession = unsafe { &mut *(ptr as *mut GdkSession) };
-    let backend = &mut sess.backend;
+    let sess: &Arc<GdkSession> = unsafe { &*(ptr as *const Arc<GdkSession>) };
 
-    match backend {
-        GdkBackend::Electrum(ref mut s) => s.notify = NativeNotif(Some((handler, self_context))),
-    };
+    // swapped without locking the backend, the session threads pick up the new handler
+    sess.notify.set(NotificationHandler::Native(NativeNotif(handler, self_context)));
 
     info!("set notification handler");
 
@@ -315,45 +400,28 @@ fn tickers_to_json(tickers: Vec<Ticker>) -> Value {
 }
 
 // dynamic dispatch shenanigans
+/// The methods that need exclusive access to the session, see [`handle_session_read_call`] for
+/// the others
 fn handle_session_call<S, E>(session: &mut S, method: &str, input: &Value) -> Result<Value, Error>
 where
     E: Into<Error>,
     S: Session<E>,
 {
     match method {
-        "poll_session" => session.poll_session().map(|v| json!(v)).map_err(Into::into),
-
         "connect" => session.connect(input).map(|v| json!(v)).map_err(Into::into),
-
         "disconnect" => session.disconnect().map(|v| json!(v)).map_err(Into::into),
-
         "login" => login(session, input).map(|v| json!(v)),
         "mnemonic_from_pin_data" => {
             mnemonic_from_pin_data(session, input).map(|v| json!(v)).map_err(Into::into)
         }
-        "set_pin" => session
-            .set_pin(&serde_json::from_value(input.clone())?)
-            .map(|v| json!(v))
-            .map_err(Into::into),
-
         "get_subaccounts" => {
             let opt: GetSubaccountsOpt = serde_json::from_value(input.clone())?;
             session.get_subaccounts(opt.refresh).map(|v| json!(v)).map_err(Into::into)
         }
-
-        "get_subaccount" => get_subaccount(session, input),
-
         "create_subaccount" => {
             let opt: CreateAccountOpt = serde_json::from_value(input.clone())?;
             session.create_subaccount(opt).map(|v| json!(v)).map_err(Into::into)
         }
-        "get_next_subaccount" => {
-            let opt: GetNextAccountOpt = serde_json::from_value(input.clone())?;
-            session
-                .get_next_subaccount(opt)
-                .map(|next_subaccount| json!(next_subaccount))
-                .map_err(Into::into)
-        }
         "rename_subaccount" => {
             let opt: RenameAccountOpt = serde_json::from_value(input.clone())?;
             session.rename_subaccount(opt).map(|_| json!(true)).map_err(Into::into)
@@ -366,12 +434,64 @@ where
             let opt: UpdateAccountOpt = serde_json::from_value(input.clone())?;
             session.update_subaccount(opt).map(|_| json!(true)).map_err(Into::into)
         }
+        "broadcast_transaction" => {
+            session
+                .broadcast_transaction(input.as_str().ok_or_else(|| {
+                    Error::Other("broadcast_transaction: input not a string".into())
+                })?)
+                .map(|v| json!(v))
+                .map_err(Into::into)
+        }
+        "get_fee_estimates" => {
+            session.get_fee_estimates().map_err(Into::into).and_then(|x| fee_estimate_values(&x))
+        }
+        "change_settings" => session
+            .change_settings(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+
+        _ => Err(Error::MethodNotFound {
+            method: method.to_string(),
+            in_session: true,
+        }),
+    }
+}
 
+/// The methods that can run concurrently on a shared session, unknown ones return
+/// `Error::MethodNotFound` and are dispatched to [`handle_session_call`]
+fn handle_session_read_call<S, E>(session: &S, method: &str, input: &Value) -> Result<Value, Error>
+where
+    E: Into<Error>,
+    S: Session<E>,
+{
+    match method {
+        "poll_session" => session.poll_session().map(|v| json!(v)).map_err(Into::into),
+        // under the read lock, the payjoin requests and the sweep discovery are slow
+        "create_transaction" => serialize::create_transaction(session, input),
+        "send_transaction" => session
+            .send_transaction(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "set_pin" => session
+            .set_pin(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "get_subaccount" => get_subaccount(session, input),
+        "get_next_subaccount" => {
+            let opt: GetNextAccountOpt = serde_json::from_value(input.clone())?;
+            session
+                .get_next_subaccount(opt)
+                .map(|next_subaccount| json!(next_subaccount))
+                .map_err(Into::into)
+        }
+        "get_multisig_xpub" => session
+            .get_multisig_xpub(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
         "get_transactions" => {
             let opt: GetTransactionsOpt = serde_json::from_value(input.clone())?;
             session.get_transactions(&opt).map(|x| txs_result_value(&x)).map_err(Into::into)
         }
-
         "get_transaction_hex" => {
             get_transaction_hex(session, input).map(|v| json!(v)).map_err(Into::into)
         }
@@ -385,25 +505,19 @@ where
             .get_balance(&serde_json::from_value(input.clone())?)
             .map(|v| json!(v))
             .map_err(Into::into),
+        "get_balance_history" => session
+            .get_balance_history(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "get_expiring_utxos" => session
+            .get_expiring_utxos(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
         "set_transaction_memo" => set_transaction_memo(session, input),
-        "create_transaction" => serialize::create_transaction(session, input),
         "sign_transaction" => session
             .sign_transaction(&serde_json::from_value(input.clone())?)
             .map_err(Into::into)
             .map(|v| json!(v)),
-        "send_transaction" => session
-            .send_transaction(&serde_json::from_value(input.clone())?)
-            .map(|v| json!(v))
-            .map_err(Into::into),
-        "broadcast_transaction" => {
-            session
-                .broadcast_transaction(input.as_str().ok_or_else(|| {
-                    Error::Other("broadcast_transaction: input not a string".into())
-                })?)
-                .map(|v| json!(v))
-                .map_err(Into::into)
-        }
-
         "get_receive_address" => {
             let a = session
                 .get_receive_address(&serde_json::from_value(input.clone())?)
@@ -412,26 +526,52 @@ where
             info!("gdk_rust get_receive_address returning {:?}", a);
             a
         }
-
+        "sign_message" => session
+            .sign_message(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "create_proof_of_reserves" => session
+            .create_proof_of_reserves(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "verify_proof_of_reserves" => session
+            .verify_proof_of_reserves(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "parse_uri" => session
+            .parse_uri(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "create_uri" => session
+            .create_uri(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
         "get_mnemonic" => {
             session.get_mnemonic().map(|m| Value::String(m.get_mnemonic_str())).map_err(Into::into)
         }
-
-        "get_fee_estimates" => {
-            session.get_fee_estimates().map_err(Into::into).and_then(|x| fee_estimate_values(&x))
-        }
-
         "get_settings" => session.get_settings().map_err(Into::into).map(|s| json!(s)),
         "get_available_currencies" => session.get_available_currencies().map_err(Into::into),
-        "change_settings" => session
-            .change_settings(&serde_json::from_value(input.clone())?)
-            .map(|v| json!(v))
-            .map_err(Into::into),
-
         "refresh_assets" => session
             .refresh_assets(&serde_json::from_value(input.clone())?)
             .map(|v| json!(v))
             .map_err(Into::into),
+        "get_assets" => session
+            .get_assets(&serde_json::from_value(input.clone())?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "get_user_assets" => session.get_user_assets().map(|v| json!(v)).map_err(Into::into),
+        "set_user_asset" => session
+            .set_user_asset(serde_json::from_value(input.clone())?)
+            .map(|_| json!(true))
+            .map_err(Into::into),
+        "import_user_assets" => session
+            .import_user_assets(serde_json::from_value(input.clone())?)
+            .map(|_| json!(true))
+            .map_err(Into::into),
+        "change_store_secret" => session
+            .change_store_secret(serde_json::from_value(input.clone())?)
+            .map(|_| json!(true))
+            .map_err(Into::into),
         "get_unspent_outputs" => session
             .get_unspent_outputs(&serde_json::from_value(input.clone())?)
             .map(|v| json!(v))
@@ -457,7 +597,7 @@ pub extern "C" fn GDKRUST_destroy_string(ptr: *mut c_char) {
 pub extern "C" fn GDKRUST_destroy_session(ptr: *mut libc::c_void) {
     unsafe {
         // retake pointer and drop
-        let _ = Box::from_raw(ptr as *mut GdkSession);
+        let _ = Box::from_raw(ptr as *mut Arc<GdkSession>);
     }
 }
 
@@ -465,16 +605,20 @@ pub extern "C" fn GDKRUST_destroy_session(ptr: *mut libc::c_void) {
 struct JsonError {
     message: String,
     error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+}
+
+fn json_error(error: &Error) -> JsonError {
+    JsonError {
+        message: error.gdk_display(),
+        error: error.to_gdk_code(),
+        details: error.details(),
+    }
 }
 
 fn build_error(_method: &str, error: &Error) -> String {
-    let message = format!("{}", error.gdk_display());
-    let error = error.to_gdk_code();
-    let json_error = JsonError {
-        message,
-        error,
-    };
-    to_string(&json_error)
+    to_string(&json_error(error))
 }
 
 fn to_string<T: Serialize>(value: &T) -> String {
@@ -505,6 +649,37 @@ pub extern "C" fn GDKRUST_call(
     error_value
 }
 
+/// Like `GDKRUST_call` but returns immediately, the id of the request is written to `request_id`
+/// and its progress and result are delivered to `handler` as `request` notifications.
+#[no_mangle]
+pub extern "C" fn GDKRUST_call_async(
+    method: *const c_char,
+    input: *const c_char,
+    handler: extern "C" fn(*const libc::c_void, *const c_char),
+    self_context: *const libc::c_void,
+    request_id: *mut u32,
+) -> i32 {
+    init_logging(LevelFilter::Info);
+    let method = read_str(method);
+    let input = read_str(input);
+    debug!("GDKRUST_call_async {}", &method);
+
+    let notify = Notifier::default();
+    notify.set(NotificationHandler::Native(NativeNotif(handler, self_context)));
+    let request = Request::register(notify);
+    unsafe {
+        *request_id = request.id();
+    }
+    thread::spawn(move || {
+        request.run(|| {
+            handle_call(&method, &input)
+                .and_then(|result| Ok(serde_json::from_str(&result)?))
+                .map_err(|e| json!(json_error(&e)))
+        })
+    });
+    GA_OK
+}
+
 fn handle_call(method: &str, input: &str) -> Result<String, Error> {
     match method {
         "psbt_extract_tx" => {
@@ -519,6 +694,10 @@ fn handle_call(method: &str, input: &str) -> Result<String, Error> {
             let param: MergeTxParam = serde_json::from_str(input)?;
             Ok(to_string(&gdk_electrum::pset::merge_tx(&param)?))
         }
+        "psbt_combine" => {
+            let param: CombinePsbtParam = serde_json::from_str(input)?;
+            Ok(to_string(&gdk_electrum::multisig::combine(&param)?))
+        }
         "spv_verify_tx" => {
             let param: SPVVerifyTxParams = serde_json::from_str(input)?;
             Ok(to_string(&gdk_electrum::headers::spv_verify_tx(&param)?.as_i32()))
@@ -527,6 +706,47 @@ fn handle_call(method: &str, input: &str) -> Result<String, Error> {
             let param: SPVDownloadHeadersParams = serde_json::from_str(input)?;
             Ok(to_string(&gdk_electrum::headers::download_headers(&param)?))
         }
+        "verify_message" => {
+            let opt: VerifyMessageOpt = serde_json::from_str(input)?;
+            Ok(to_string(&gdk_electrum::message::verify(&opt)?))
+        }
+        "parse_uri" => {
+            let opt: ParseUriOpt = serde_json::from_str(input)?;
+            let network = opt
+                .network
+                .as_ref()
+                .ok_or(Error::Common(gdk_common::error::Error::InputValidationFailed))?;
+            // without a session the precision of the assets other than the policy one is unknown
+            Ok(to_string(&gdk_electrum::uri::parse(network, &opt, |_| None)?))
+        }
+        "generate_mnemonic" => {
+            let opt: GenerateMnemonicOpt = serde_json::from_str(input)?;
+            let mnemonic = Mnemonic::generate(opt.words, &opt.language)
+                .map_err(gdk_electrum::error::Error::InvalidMnemonic)?;
+            Ok(to_string(&GenerateMnemonicResult {
+                mnemonic: mnemonic.get_mnemonic_str(),
+            }))
+        }
+        "validate_mnemonic" => {
+            let opt: ValidateMnemonicOpt = serde_json::from_str(input)?;
+            let mnemonic = Mnemonic::from(opt.mnemonic);
+            let validation = match opt.language {
+                Some(language) => mnemonic.validate(&language).map(|_| language),
+                None => mnemonic.validate_any().map(ToString::to_string),
+            };
+            Ok(to_string(&match validation {
+                Ok(language) => ValidateMnemonicResult {
+                    valid: true,
+                    language: Some(language),
+                    error: None,
+                },
+                Err(error) => ValidateMnemonicResult {
+                    valid: false,
+                    language: None,
+                    error: Some(error),
+                },
+            }))
+        }
         _ => Err(Error::MethodNotFound {
             method: method.to_string(),
             in_session: false,
@@ -641,3 +861,59 @@ pub struct Ticker {
     pub pair: Pair,
     pub rate: f64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::codes;
+    use gdk_common::Network;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+
+    static CALLBACKS: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn reentrant_handler(context: *const libc::c_void, _notification: *const c_char) {
+        let sess = unsafe { &*(context as *const GdkSession) };
+        assert!(call_session(sess, "get_settings", &Value::Null).is_ok());
+        CALLBACKS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_reentrant_notification_handler() {
+        let data_root = TempDir::new().unwrap();
+        let mut network = serde_json::to_value(Network::default()).unwrap();
+        network["server_type"] = json!("electrum");
+        network["electrum_url"] = json!("127.0.0.1:1");
+        network["state_dir"] = json!(data_root.path().to_str().unwrap());
+        let sess = create_session(&network).unwrap();
+        let context = &sess as *const GdkSession as *const libc::c_void;
+        sess.notify.set(NotificationHandler::Native(NativeNotif(reentrant_handler, context)));
+
+        // login and change_settings notify while holding the write lock
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        call_session(&sess, "login", &json!({ "mnemonic": mnemonic })).unwrap();
+        let before = CALLBACKS.load(Ordering::Relaxed);
+        assert!(before >= 2);
+        call_session(&sess, "change_settings", &json!({ "unit": "sats" })).unwrap();
+        assert!(CALLBACKS.load(Ordering::Relaxed) > before);
+
+        call_session(&sess, "disconnect", &Value::Null).unwrap();
+    }
+
+    #[test]
+    fn test_generate_mnemonic_errors() {
+        let generated = handle_call("generate_mnemonic", r#"{"words":12}"#).unwrap();
+        let generated: GenerateMnemonicResult = serde_json::from_str(&generated).unwrap();
+        assert_eq!(generated.mnemonic.split(' ').count(), 12);
+
+        let err = handle_call("generate_mnemonic", r#"{"words":13}"#).unwrap_err();
+        assert_eq!(err.to_gdk_code(), codes::INVALID_MNEMONIC);
+        assert_eq!(err.details(), Some(json!({ "reason": "invalid_word_count", "words": 13 })));
+        let err = handle_call("generate_mnemonic", r#"{"language":"xx"}"#).unwrap_err();
+        assert_eq!(err.to_gdk_code(), codes::INVALID_MNEMONIC);
+        assert_eq!(
+            err.details(),
+            Some(json!({ "reason": "unsupported_language", "language": "xx" }))
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::error::Error;
 
+use gdk_common::mnemonic::SeedType;
 use gdk_common::model::*;
 use gdk_common::session::Session;
 use serde_json::Value;
@@ -19,9 +20,16 @@ where
         .ok_or_else(|| Error::Other("login: missing mnemonic argument".into()))?;
 
     let pass_str = input["password"].as_str().map(|x| x.to_string());
+    let store_secret = input["store_secret"].as_str().map(|x| x.to_string());
+    let seed_type: Option<SeedType> = serde_json::from_value(input["seed_type"].clone())?;
 
     session
-        .login(&mnemonic_str.into(), pass_str.map(Into::into))
+        .login(
+            &mnemonic_str.into(),
+            pass_str.map(Into::into),
+            store_secret.map(Into::into),
+            seed_type,
+        )
         .map(|x| serde_json::to_value(&x).unwrap())
         .map_err(Into::into)
 }
@@ -42,7 +50,7 @@ where
         .map_err(Into::into)
 }
 
-pub fn get_subaccount<S, E>(session: &mut S, input: &Value) -> Result<Value, Error>
+pub fn get_subaccount<S, E>(session: &S, input: &Value) -> Result<Value, Error>
 where
     E: Into<Error>,
     S: Session<E>,
@@ -67,7 +75,7 @@ where
     session.get_transaction_hex(txid).map_err(Into::into)
 }
 
-pub fn create_transaction<S, E>(session: &mut S, input: &Value) -> Result<Value, Error>
+pub fn create_transaction<S, E>(session: &S, input: &Value) -> Result<Value, Error>
 where
     E: Into<Error>,
     S: Session<E>,
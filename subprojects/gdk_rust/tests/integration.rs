@@ -2,8 +2,9 @@ use electrum_client::ElectrumApi;
 use gdk_common::be::BETransaction;
 use gdk_common::model::{
     AddressAmount, CreateAccountOpt, CreateTransaction, GetBalanceOpt, GetNextAccountOpt,
-    GetTransactionsOpt, GetUnspentOutputs, RenameAccountOpt, SPVCommonParams,
-    SPVDownloadHeadersParams, SPVVerifyTxResult, UpdateAccountOpt, UtxoStrategy,
+    GetTransactionsOpt, GetUnspentOutputs, PinSetDetails, RenameAccountOpt, SPVCommonParams,
+    SPVDownloadHeadersParams, SPVVerifyTxResult, SetUserAssetOpt, UpdateAccountOpt, UserAsset,
+    UtxoStrategy,
 };
 use gdk_common::scripts::ScriptType;
 use gdk_common::session::Session;
@@ -11,7 +12,8 @@ use gdk_common::Network;
 use gdk_electrum::error::Error;
 use gdk_electrum::headers::bitcoin::HeadersChain;
 use gdk_electrum::interface::ElectrumUrl;
-use gdk_electrum::{determine_electrum_url_from_net, spv, ElectrumSession};
+use gdk_electrum::pin::PinManager;
+use gdk_electrum::{determine_electrum_url_from_net, spv, ElectrumSession, ElectrumSessionBuilder};
 
 use log::info;
 use std::collections::HashMap;
@@ -21,7 +23,9 @@ use std::time::{Duration, Instant};
 use std::{env, thread};
 use tempfile::TempDir;
 
+mod pin_server;
 mod test_session;
+use pin_server::PinServer;
 use test_session::TestSession;
 
 static MEMO1: &str = "hello memo";
@@ -62,6 +66,7 @@ fn roundtrip_bitcoin() {
     test_session.reconnect();
     test_session.spv_verify_tx(&txid, 102);
     test_session.test_set_get_memo(&txid, MEMO2, ""); // after reconnect memo has been reloaded from disk
+    test_session.tx_list_filters(&txid, MEMO2);
     let mut utxos = test_session.utxo("btc", vec![149739, 96697483]);
     test_session.check_decryption(103, &[&txid]);
 
@@ -186,7 +191,7 @@ fn create_tx_err(is_liquid: bool) {
     );
     assert!(matches!(
         test_session.session.create_transaction(&mut create_opt),
-        Err(Error::InvalidAmount)
+        Err(Error::AmountBelowDust)
     ));
 
     // No utxos passed
@@ -638,6 +643,7 @@ fn subaccounts(is_liquid: bool) {
             subaccount: 1,
             name: "Account 1".into(),
             // p2wpkh
+            ..Default::default()
         })
         .unwrap();
     let account2 = test_session
@@ -646,6 +652,7 @@ fn subaccounts(is_liquid: bool) {
             subaccount: 2,
             name: "Account 2".into(),
             // p2pkh
+            ..Default::default()
         })
         .unwrap();
     assert_eq!(account1.account_num, 1);
@@ -763,6 +770,7 @@ fn subaccounts(is_liquid: bool) {
         .create_subaccount(CreateAccountOpt {
             subaccount: 18,
             name: "Second PKPH".into(),
+            ..Default::default()
         })
         .unwrap();
     assert_eq!(account3.script_type, ScriptType::P2pkh);
@@ -774,6 +782,7 @@ fn subaccounts(is_liquid: bool) {
         .create_subaccount(CreateAccountOpt {
             subaccount: 34,
             name: "Won't work".into(),
+            ..Default::default()
         })
         .unwrap_err();
     assert!(matches!(err, Error::AccountGapsDisallowed));
@@ -795,6 +804,7 @@ fn subaccounts(is_liquid: bool) {
         .create_subaccount(CreateAccountOpt {
             subaccount: 34,
             name: "Third PKPH".into(),
+            ..Default::default()
         })
         .unwrap();
     assert_eq!(account4.script_type, ScriptType::P2pkh);
@@ -821,10 +831,10 @@ fn subaccounts(is_liquid: bool) {
         let db_root_dir = TempDir::new().unwrap();
         let db_root = format!("{}", db_root_dir.path().display());
         let proxy = Some("");
-        ElectrumSession::create_session(network, &db_root, proxy, url)
+        ElectrumSession::create_session(network, &db_root, proxy, url.into())
     };
     let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string().into();
-    new_session.login(&mnemonic, None).unwrap();
+    new_session.login(&mnemonic, None, None, None).unwrap();
 
     let subaccounts = new_session.get_subaccounts(false).unwrap();
     assert_eq!(subaccounts.len(), 1);
@@ -844,6 +854,7 @@ fn subaccounts(is_liquid: bool) {
     let account_opt = CreateAccountOpt {
         subaccount: new_account,
         name: "next_p2pkh".to_string(),
+        ..Default::default()
     };
     test_session.session.create_subaccount(account_opt).unwrap();
     let address = test_session.get_receive_address(new_account);
@@ -949,6 +960,9 @@ fn registry_liquid() {
     let value = test_session.refresh_assets(true, true, false).unwrap();
     assert!(value.get("assets").unwrap().get(&policy_asset).is_some());
     assert!(value.get("icons").is_none());
+    // entries failing verification are not returned
+    let rejected = value.get("assets_rejected").unwrap().as_object().unwrap();
+    assert!(rejected.keys().all(|id| value["assets"].get(id).is_none()));
 
     // refresh false, asset false, icons true (no cache)
     let value = test_session.refresh_assets(false, false, true).unwrap();
@@ -970,6 +984,40 @@ fn registry_liquid() {
     let value = test_session.refresh_assets(false, true, true).unwrap();
     assert!(value.get("assets").unwrap().get(&policy_asset).is_some());
     assert!(value.get("icons").is_some());
+
+    // single assets lookup, the policy asset is not fetched from the registry
+    let value = test_session.get_assets(Some(vec![policy_asset.clone()])).unwrap();
+    assert_eq!(value["assets"][&policy_asset]["ticker"], "L-BTC");
+    assert!(test_session.get_assets(Some(vec!["not an asset".into()])).is_err());
+
+    // user defined assets override the registry and roundtrip through export and import
+    let private_asset = "ff".repeat(32);
+    let asset = UserAsset {
+        name: "Company Shares".into(),
+        ticker: Some("CSH".into()),
+        precision: 0,
+        icon: None,
+    };
+    let opt = SetUserAssetOpt {
+        asset_id: private_asset.clone(),
+        asset: Some(asset.clone()),
+    };
+    test_session.session.set_user_asset(opt).unwrap();
+    let value = test_session.refresh_assets(false, true, false).unwrap();
+    assert_eq!(value["assets"][&private_asset]["ticker"], "CSH");
+    let value = test_session.get_assets(Some(vec![private_asset.clone()])).unwrap();
+    assert_eq!(value["assets"][&private_asset]["name"], "Company Shares");
+
+    let exported = test_session.session.get_user_assets().unwrap();
+    assert_eq!(exported.assets[&private_asset], asset);
+    let opt = SetUserAssetOpt {
+        asset_id: private_asset.clone(),
+        asset: None,
+    };
+    test_session.session.set_user_asset(opt).unwrap();
+    assert!(test_session.session.get_user_assets().unwrap().assets.is_empty());
+    test_session.session.import_user_assets(exported.clone()).unwrap();
+    assert_eq!(test_session.session.get_user_assets().unwrap(), exported);
 }
 
 #[test]
@@ -982,6 +1030,7 @@ fn labels() {
             name: "Account 1".into(),
             subaccount: 1,
             // p2wpkh
+            ..Default::default()
         })
         .unwrap();
     let account2 = test_session
@@ -990,6 +1039,7 @@ fn labels() {
             name: "Account 2".into(),
             subaccount: 2,
             // p2pkh
+            ..Default::default()
         })
         .unwrap();
 
@@ -1034,6 +1084,7 @@ fn rbf() {
         .create_subaccount(CreateAccountOpt {
             name: "Account 1".into(),
             subaccount: 1,
+            ..Default::default()
         })
         .unwrap();
     let txid = test_session.node_sendtoaddress(
@@ -1268,6 +1319,7 @@ fn test_spv_timeout() {
             tor_proxy: None,
             timeout: Some(1),
             encryption_key: None,
+            server_type: None,
         },
         headers_to_download: Some(1),
     };
@@ -1276,6 +1328,91 @@ fn test_spv_timeout() {
     assert!(now.elapsed().as_secs() <= 5, "more than timeout time passed");
 }
 
+#[test]
+fn pin_manager_calls() {
+    let server = PinServer::start();
+    let mut network = Network::default();
+    network.electrum_url = Some("127.0.0.1:1".into());
+    let session = ElectrumSessionBuilder::new(network).build().unwrap();
+    let manager = || {
+        let agent = session.build_request_agent().unwrap();
+        PinManager::new(agent, &server.url, &server.public_key).unwrap()
+    };
+
+    let secret_key = bitcoin::secp256k1::SecretKey::from_slice(&[1u8; 32]).unwrap();
+    let pin_key_set = manager().set_pin(&[0u8; 4], &secret_key).unwrap();
+    let pin_key_get = manager().get_pin(&[0u8; 4], &secret_key).unwrap();
+    assert_eq!(pin_key_get, pin_key_set);
+}
+
+#[test]
+fn pin_server_calls() {
+    let server = PinServer::start();
+    let mut network = Network::default();
+    // the PIN calls don't need a connection
+    network.electrum_url = Some("127.0.0.1:1".into());
+    network.pin_server_url = Some(server.url.clone());
+    network.pin_server_public_key = Some(server.public_key.clone());
+    let mut session = ElectrumSessionBuilder::new(network).build().unwrap();
+
+    let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    let details = PinSetDetails {
+        pin: "123456".into(),
+        mnemonic: mnemonic.into(),
+        device_id: "".into(),
+    };
+    let pin_data = session.set_pin(&details).unwrap();
+    let mut get = |pin: &str| session.mnemonic_from_pin_data(pin.into(), pin_data.clone());
+    assert_eq!(get("123456").unwrap(), mnemonic);
+
+    // a right PIN resets the wrong attempts
+    assert!(matches!(get("000000"), Err(Error::InvalidPin)));
+    assert!(matches!(get("000000"), Err(Error::InvalidPin)));
+    assert_eq!(get("123456").unwrap(), mnemonic);
+
+    // the server forgets the PIN after 3 wrong attempts
+    for _ in 0..3 {
+        assert!(matches!(get("000000"), Err(Error::InvalidPin)));
+    }
+    assert!(matches!(get("123456"), Err(Error::InvalidPin)));
+
+    // a server not proving the configured key is refused
+    let mut network = Network::default();
+    network.electrum_url = Some("127.0.0.1:1".into());
+    network.pin_server_url = Some(server.url.clone());
+    let session = ElectrumSessionBuilder::new(network).build().unwrap();
+    assert!(session.set_pin(&details).is_err());
+}
+
+#[test]
+fn offline_pin() {
+    let data_root = TempDir::new().unwrap();
+    let mut network = Network::default();
+    network.electrum_url = Some("127.0.0.1:1".into());
+    network.offline_pin = Some(true);
+    let builder = ElectrumSessionBuilder::new(network.clone());
+    let session = builder.data_root(data_root.path().to_str().unwrap()).build().unwrap();
+
+    let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    let details = PinSetDetails {
+        pin: "123456".into(),
+        mnemonic: mnemonic.into(),
+        device_id: "".into(),
+    };
+    let pin_data = session.set_pin(&details).unwrap();
+
+    // the PIN data are recognized even when the session is not in offline mode
+    network.offline_pin = None;
+    let builder = ElectrumSessionBuilder::new(network);
+    let mut session = builder.data_root(data_root.path().to_str().unwrap()).build().unwrap();
+    let mut get = |pin: &str| session.mnemonic_from_pin_data(pin.into(), pin_data.clone());
+    assert_eq!(get("123456").unwrap(), mnemonic);
+    for _ in 0..3 {
+        assert!(matches!(get("000000"), Err(Error::InvalidPin)));
+    }
+    assert!(matches!(get("123456"), Err(Error::InvalidPin)));
+}
+
 fn setup_forking_sessions(enable_session_cross: bool) -> (TestSession, TestSession) {
     let test_session2 = setup_session(false, |_| ());
 
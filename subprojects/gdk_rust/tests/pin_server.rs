@@ -0,0 +1,196 @@
+//! An in-memory blind PIN server speaking the protocol of `gdk_electrum::pin`, so that the tests
+//! don't depend on the production server.
+
+use aes::Aes256;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use bitcoin::secp256k1::recovery::{RecoverableSignature, RecoveryId};
+use bitcoin::secp256k1::{ecdh, Message, PublicKey, Secp256k1, SecretKey};
+use block_modes::block_padding::Pkcs7;
+use block_modes::{BlockMode, Cbc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Aes256Cbc = Cbc<Aes256, Pkcs7>;
+
+/// The wrong attempts after which a PIN is forgotten
+const MAX_ATTEMPTS: u8 = 3;
+
+pub struct PinServer {
+    pub url: String,
+    pub public_key: String,
+}
+
+struct PinEntry {
+    pin_secret: Vec<u8>,
+    key: Vec<u8>,
+    attempts: u8,
+}
+
+#[derive(Default)]
+struct State {
+    /// the ephemeral keys of the pending handshakes
+    handshakes: HashMap<PublicKey, SecretKey>,
+    /// the PINs by client public key
+    pins: HashMap<PublicKey, PinEntry>,
+}
+
+impl PinServer {
+    /// Serve on a free local port until the test process exits
+    pub fn start() -> Self {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap(); // 0 means the OS choose a free port
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server_key = random_key();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &server_key);
+        let state = Arc::new(Mutex::new(State::default()));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                handle(&mut stream.unwrap(), &server_key, &state);
+            }
+        });
+        PinServer {
+            url,
+            public_key: public_key.serialize().to_hex(),
+        }
+    }
+}
+
+fn handle(stream: &mut TcpStream, server_key: &SecretKey, state: &Mutex<State>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+            content_length = length.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).unwrap();
+
+    let mut state = state.lock().unwrap();
+    let response = match path.as_str() {
+        "/start_handshake" => Some(state.handshake(server_key)),
+        "/set_pin" => state.pin_call(&body, true),
+        "/get_pin" => state.pin_call(&body, false),
+        _ => None,
+    };
+    let (status, body) = match response {
+        Some(value) => ("200 OK", value.to_string()),
+        None => ("400 Bad Request", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+    .unwrap();
+}
+
+impl State {
+    fn handshake(&mut self, server_key: &SecretKey) -> Value {
+        let secp = Secp256k1::new();
+        let ske_key = random_key();
+        let ske = PublicKey::from_secret_key(&secp, &ske_key);
+        let hash = sha256::Hash::hash(&ske.serialize());
+        let sig = secp.sign(&Message::from_slice(&hash[..]).unwrap(), server_key);
+        self.handshakes.insert(ske, ske_key);
+        json!({
+            "ske": ske.serialize().to_hex(),
+            "sig": sig.serialize_compact()[..].to_hex(),
+        })
+    }
+
+    fn pin_call(&mut self, body: &[u8], set: bool) -> Option<Value> {
+        let request: Value = serde_json::from_slice(body).ok()?;
+        let field = |name: &str| Vec::<u8>::from_hex(request[name].as_str()?).ok();
+        let cke_bytes = field("cke")?;
+        let cke = PublicKey::from_slice(&cke_bytes).ok()?;
+        let ske = PublicKey::from_slice(&field("ske")?).ok()?;
+        let ske_key = self.handshakes.remove(&ske)?;
+        let shared_secret = ecdh::SharedSecret::new(&cke, &ske_key);
+
+        let encrypted = field("encrypted_data")?;
+        let mut auth_payload = cke_bytes.clone();
+        auth_payload.extend(&encrypted);
+        if hmac(&derive(1, &shared_secret), &auth_payload) != field("hmac_encrypted_data")? {
+            return None;
+        }
+        let decipher = Aes256Cbc::new_from_slices(&derive(0, &shared_secret), &encrypted[..16]);
+        let payload = decipher.ok()?.decrypt_vec(&encrypted[16..]).ok()?;
+        if payload.len() != 129 {
+            return None;
+        }
+
+        // pin secret, entropy, recovery id and signature of the client key
+        let mut signed = cke_bytes;
+        signed.extend(&payload[..64]);
+        let msg = Message::from_slice(&sha256::Hash::hash(&signed)[..]).ok()?;
+        let rec_id = RecoveryId::from_i32(payload[64] as i32 - 31).ok()?;
+        let sig = RecoverableSignature::from_compact(&payload[65..], rec_id).ok()?;
+        let client = Secp256k1::new().recover(&msg, &sig).ok()?;
+        let pin_secret = payload[..32].to_vec();
+
+        let key = if set {
+            let key = rand::random::<[u8; 32]>().to_vec();
+            let entry = PinEntry {
+                pin_secret,
+                key: key.clone(),
+                attempts: 0,
+            };
+            self.pins.insert(client, entry);
+            key
+        } else {
+            // like the production server, a wrong PIN gets a random key rather than an error
+            match self.pins.get_mut(&client) {
+                Some(entry) if entry.pin_secret == pin_secret => {
+                    entry.attempts = 0;
+                    entry.key.clone()
+                }
+                Some(entry) => {
+                    entry.attempts += 1;
+                    if entry.attempts >= MAX_ATTEMPTS {
+                        self.pins.remove(&client);
+                    }
+                    rand::random::<[u8; 32]>().to_vec()
+                }
+                None => rand::random::<[u8; 32]>().to_vec(),
+            }
+        };
+
+        let iv = rand::random::<[u8; 16]>();
+        let cipher = Aes256Cbc::new_from_slices(&derive(2, &shared_secret), &iv).ok()?;
+        let mut encrypted_key = iv.to_vec();
+        encrypted_key.extend(cipher.encrypt_vec(&key));
+        Some(json!({
+            "encrypted_key": encrypted_key.to_hex(),
+            "hmac": hmac(&derive(3, &shared_secret), &encrypted_key).to_hex(),
+        }))
+    }
+}
+
+fn derive(value: u8, shared_secret: &ecdh::SharedSecret) -> Vec<u8> {
+    hmac(&shared_secret[..], &[value])
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut engine: HmacEngine<sha256::Hash> = HmacEngine::new(key);
+    engine.input(data);
+    Hmac::from_engine(engine).into_inner().to_vec()
+}
+
+fn random_key() -> SecretKey {
+    SecretKey::from_slice(&rand::random::<[u8; 32]>()).unwrap()
+}
@@ -151,11 +151,11 @@ pub fn setup(
     let url = determine_electrum_url_from_net(&network).unwrap();
 
     info!("creating gdk session");
-    let mut session = ElectrumSession::create_session(network.clone(), &db_root, proxy, url);
+    let mut session = ElectrumSession::create_session(network.clone(), &db_root, proxy, url.into());
 
     let mnemonic: Mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string().into();
     info!("logging in gdk session");
-    let login_data = session.login(&mnemonic, None).unwrap();
+    let login_data = session.login(&mnemonic, None, None, None).unwrap();
     assert_eq!(network.name, ""); // network name contributes to wallet hash id
     assert_eq!(
         login_data.wallet_hash_id,
@@ -451,6 +451,49 @@ impl TestSession {
         self.session.get_transactions(&opt).unwrap().0
     }
 
+    /// check that the cursor pages cover the whole history and that the filters apply
+    pub fn tx_list_filters(&self, memo_txid: &str, memo: &str) {
+        let all = self.get_tx_list(0);
+        assert!(all.len() > 3);
+
+        let mut opt = GetTransactionsOpt::default();
+        opt.count = 3;
+        let mut paged: Vec<TxListItem> = vec![];
+        loop {
+            let page = self.session.get_transactions(&opt).unwrap().0;
+            match page.last() {
+                Some(last) => opt.cursor = Some(last.cursor.clone()),
+                None => break,
+            }
+            paged.extend(page);
+        }
+        let txids = |list: &[TxListItem]| list.iter().map(|t| t.txhash.clone()).collect::<Vec<_>>();
+        assert_eq!(txids(&paged), txids(&all));
+
+        let mut opt = GetTransactionsOpt::default();
+        opt.count = 100;
+        opt.sort = TxSort::Oldest;
+        let mut oldest = txids(&self.session.get_transactions(&opt).unwrap().0);
+        oldest.reverse();
+        assert_eq!(oldest, txids(&all));
+
+        let mut opt = GetTransactionsOpt::default();
+        opt.count = 100;
+        opt.memo = Some(memo.to_uppercase());
+        let found = self.session.get_transactions(&opt).unwrap().0;
+        assert_eq!(txids(&found), vec![memo_txid.to_string()]);
+
+        opt.memo = None;
+        opt.types = Some(vec!["incoming".into()]);
+        let incoming = self.session.get_transactions(&opt).unwrap().0;
+        assert!(!incoming.is_empty());
+        assert!(incoming.iter().all(|t| t.type_ == "incoming"));
+
+        opt.types = None;
+        opt.min_height = all.iter().map(|t| t.block_height).max().map(|h| h + 1);
+        assert!(self.session.get_transactions(&opt).unwrap().0.is_empty());
+    }
+
     pub fn get_tx_from_list(&self, subaccount: u32, txid: &str) -> TxListItem {
         let list = self.get_tx_list(subaccount);
         let filtered_list: Vec<TxListItem> =
@@ -906,6 +949,7 @@ impl TestSession {
             tor_proxy: None,
             timeout: None,
             encryption_key: Some("testing".to_string()),
+            server_type: None,
         };
         let param = SPVVerifyTxParams {
             txid: txid.to_string(),
@@ -956,6 +1000,14 @@ impl TestSession {
         self.session.refresh_assets(&opt)
     }
 
+    pub fn get_assets(&mut self, assets_id: Option<Vec<String>>) -> Result<Value, Error> {
+        let opt = GetAssetsOpt {
+            assets_id,
+            refresh: false,
+        };
+        self.session.get_assets(&opt)
+    }
+
     pub fn utxos(&self, subaccount: u32) -> GetUnspentOutputs {
         let utxo_opt = GetUnspentOpt {
             subaccount: subaccount,